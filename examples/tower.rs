@@ -0,0 +1,42 @@
+//! Serves a component behind host-side tower middleware, here a timeout, with hyper directly.
+//!
+//! `cargo run --example tower -- component.wasm`
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+use tower::{timeout::TimeoutLayer, ServiceBuilder};
+use wasi_http_runner::{Options, Runner, WasiHttpService};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let component = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "component.wasm".to_owned());
+    let runner = Arc::new(Runner::new(component, Options::default())?);
+
+    let service = ServiceBuilder::new()
+        .layer(TimeoutLayer::new(Duration::from_secs(10)))
+        .service(WasiHttpService::new(runner));
+
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 8080))).await?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(service.clone());
+
+        tokio::spawn(async move {
+            if let Err(err) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("Error serving connection: {err:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,44 @@
+#!/bin/sh
+# Builds the guest in wasi-http-guest into tests/fixtures/guest.wasm, the middleware in
+# wasi-http-middleware into tests/fixtures/middleware.wasm, and the standard library guest in
+# wasi-http-std into tests/fixtures/std.wasm, for tests/e2e.rs.
+#
+# Needs the wasm32-wasi target and wasm-tools:
+#   rustup target add wasm32-wasi
+#   cargo install wasm-tools
+# The preview 1 adapter is downloaded from the wasmtime release matching the runner's, unless
+# ADAPTER points at one already.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+WASMTIME_VERSION=15.0.0
+ADAPTER=${ADAPTER:-target/wasi_snapshot_preview1.reactor.wasm}
+
+if ! rustup target list --installed | grep -qx wasm32-wasi; then
+    echo "the wasm32-wasi target is not installed: rustup target add wasm32-wasi" >&2
+    exit 1
+fi
+
+if ! command -v wasm-tools >/dev/null; then
+    echo "wasm-tools is not installed: cargo install wasm-tools" >&2
+    exit 1
+fi
+
+if [ ! -f "$ADAPTER" ]; then
+    mkdir -p "$(dirname "$ADAPTER")"
+    curl -fsSL -o "$ADAPTER" \
+        "https://github.com/bytecodealliance/wasmtime/releases/download/v$WASMTIME_VERSION/wasi_snapshot_preview1.reactor.wasm"
+fi
+
+mkdir -p tests/fixtures
+
+for crate in guest middleware std; do
+    cargo build --manifest-path "wasi-http-$crate/Cargo.toml" --target wasm32-wasi --release
+
+    wasm-tools component new "target/wasm32-wasi/release/wasi_http_$crate.wasm" \
+        --adapt "wasi_snapshot_preview1=$ADAPTER" \
+        -o "tests/fixtures/$crate.wasm"
+
+    echo "built tests/fixtures/$crate.wasm"
+done
@@ -0,0 +1,194 @@
+use std::{
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ::http::{Method, Request};
+use http_body_util::Full;
+use hyper::body::Bytes;
+
+use crate::Runner;
+
+/// What [`bench`] sends and for how long.
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    /// Stops after this many requests, unless `duration` is set.
+    pub requests: u64,
+    /// Keeps sending requests until this much time has passed instead.
+    pub duration: Option<Duration>,
+    /// Requests in flight at once.
+    pub concurrency: usize,
+    pub path: String,
+    /// Sent with `POST` when set; requests are `GET`s otherwise.
+    pub body: Option<Bytes>,
+}
+
+/// Latencies are in microseconds.
+pub struct BenchReport {
+    pub requests: u64,
+    pub elapsed: Duration,
+    pub server_errors: u64,
+    pub traps: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    /// Mean time spent instantiating the component, per request.
+    pub instantiation: u64,
+    /// Mean time spent in the guest after it was instantiated, per request.
+    pub execution: u64,
+}
+
+/// Sends requests through `runner` in process, along the same path the server uses, and measures
+/// how it keeps up. Response bodies are read to the end.
+pub async fn bench(runner: Arc<Runner>, options: BenchOptions) -> anyhow::Result<BenchReport> {
+    let started_at = Instant::now();
+    let deadline = options.duration.map(|duration| started_at + duration);
+    let issued = Arc::new(AtomicU64::new(0));
+    let traps_before = runner.metrics().failures.get();
+    let instantiation_before = runner.metrics().instantiation_time.sum();
+    let execution_before = runner.metrics().execution_time.sum();
+
+    let mut workers = Vec::new();
+
+    for _ in 0..options.concurrency.max(1) {
+        let runner = runner.clone();
+        let options = options.clone();
+        let issued = issued.clone();
+
+        workers.push(tokio::task::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut server_errors = 0;
+
+            loop {
+                match deadline {
+                    Some(deadline) if Instant::now() >= deadline => break,
+                    Some(_) => {}
+                    None if issued.fetch_add(1, Ordering::Relaxed) >= options.requests => break,
+                    None => {}
+                }
+
+                let req = Request::builder()
+                    .method(if options.body.is_some() {
+                        Method::POST
+                    } else {
+                        Method::GET
+                    })
+                    .uri(options.path.as_str())
+                    .body(Full::new(options.body.clone().unwrap_or_default()))?;
+
+                let sent_at = Instant::now();
+                let status = runner.clone().invoke(req).await?.response.status();
+
+                latencies.push(sent_at.elapsed().as_micros() as u64);
+
+                if status.is_server_error() {
+                    server_errors += 1;
+                }
+            }
+
+            anyhow::Ok((latencies, server_errors))
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut server_errors = 0;
+
+    for worker in workers {
+        let (worker_latencies, worker_errors) = worker.await??;
+        latencies.extend(worker_latencies);
+        server_errors += worker_errors;
+    }
+
+    let elapsed = started_at.elapsed();
+    latencies.sort_unstable();
+
+    let requests = latencies.len() as u64;
+    let percentile = |p: u64| {
+        latencies
+            .get((requests * p / 100).min(requests.saturating_sub(1)) as usize)
+            .copied()
+            .unwrap_or_default()
+    };
+
+    let metrics = runner.metrics();
+    let instantiation = metrics.instantiation_time.sum() - instantiation_before;
+    let execution = metrics.execution_time.sum() - execution_before;
+
+    Ok(BenchReport {
+        requests,
+        elapsed,
+        server_errors,
+        traps: metrics.failures.get() - traps_before,
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max: latencies.last().copied().unwrap_or_default(),
+        instantiation: instantiation / requests.max(1),
+        execution: execution.saturating_sub(instantiation) / requests.max(1),
+    })
+}
+
+impl BenchReport {
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        let _ = write!(
+            json,
+            "{{\"requests\":{},\"elapsed_ms\":{},\"throughput\":{:.1},\"server_errors\":{},\
+             \"traps\":{},\"latency_us\":{{\"p50\":{},\"p90\":{},\"p99\":{},\"max\":{}}},\
+             \"instantiation_us\":{},\"execution_us\":{}}}",
+            self.requests,
+            self.elapsed.as_millis(),
+            self.throughput(),
+            self.server_errors,
+            self.traps,
+            self.p50,
+            self.p90,
+            self.p99,
+            self.max,
+            self.instantiation,
+            self.execution,
+        );
+
+        json
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |us: u64| us as f64 / 1000.0;
+
+        writeln!(
+            f,
+            "requests      {} in {:.2}s",
+            self.requests,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(f, "throughput    {:.1} req/s", self.throughput())?;
+        writeln!(f, "server errors {}", self.server_errors)?;
+        writeln!(f, "traps         {}", self.traps)?;
+        writeln!(
+            f,
+            "latency       p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms",
+            ms(self.p50),
+            ms(self.p90),
+            ms(self.p99),
+            ms(self.max)
+        )?;
+        write!(
+            f,
+            "per request   instantiate {:.2}ms  execute {:.2}ms",
+            ms(self.instantiation),
+            ms(self.execution)
+        )
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{future::poll_fn, task::noop_waker_ref};
+use hyper::body::Body;
+use wasmtime::component::Resource;
+
+use crate::{
+    bluezeeking,
+    http::BodyState,
+    wasi::io::streams::{InputStream, StreamError},
+    State,
+};
+
+/// The reader index of the stream originally returned by `incoming-body.stream`.
+pub const SOURCE: usize = 0;
+/// The reader index of the stream returned by `tee`.
+pub const BRANCH: usize = 1;
+
+/// Bytes pulled from a request body that have not yet been seen by both readers.
+pub struct Tee {
+    buf: VecDeque<u8>,
+    /// Absolute body offset of the first byte in `buf`.
+    start: usize,
+    cursors: [usize; 2],
+    eof: bool,
+}
+
+impl Tee {
+    pub fn available(&self, reader: usize) -> bool {
+        self.cursors[reader] < self.start + self.buf.len()
+    }
+
+    pub fn is_done(&self, reader: usize) -> bool {
+        self.eof && !self.available(reader)
+    }
+
+    fn take(&mut self, reader: usize, len: usize) -> Option<Vec<u8>> {
+        if !self.available(reader) {
+            return None;
+        }
+
+        let offset = self.cursors[reader] - self.start;
+        let len = len.min(self.buf.len() - offset);
+        let bytes = self.buf.range(offset..offset + len).copied().collect();
+
+        self.cursors[reader] += len;
+        self.trim();
+
+        Some(bytes)
+    }
+
+    pub fn release(&mut self, reader: usize) {
+        self.cursors[reader] = usize::MAX;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let end = self.start + self.buf.len();
+        let min = self.cursors.iter().copied().min().unwrap_or(end).min(end);
+
+        self.buf.drain(..min - self.start);
+        self.start = min;
+    }
+}
+
+impl State {
+    /// Returns the body and reader index backing `stream` if it takes part in a tee.
+    pub fn tee_reader(&self, stream: u32) -> Option<(u32, usize)> {
+        if let Some(body) = self.tees.get(&stream) {
+            return Some((*body, BRANCH));
+        }
+
+        self.incoming
+            .get(&stream)
+            .and_then(|resource| resource.tee.as_ref())
+            .map(|_| (stream, SOURCE))
+    }
+
+    pub fn read_tee(
+        &mut self,
+        body: u32,
+        reader: usize,
+        len: u64,
+        block: bool,
+    ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        let max_body_bytes = self.max_body_bytes;
+
+        loop {
+            let resource = self
+                .incoming
+                .get_mut(&body)
+                .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
+
+            let tee = resource
+                .tee
+                .as_mut()
+                .ok_or_else(|| wasmtime::Error::msg("Could not find tee"))?;
+
+            if let Some(bytes) = tee.take(reader, len as usize) {
+                return Ok(Ok(bytes));
+            }
+
+            if tee.eof {
+                return Ok(Err(StreamError::Closed));
+            }
+
+            let frame = match resource.last_frame.take() {
+                Some(frame) => frame,
+                None if resource.state.ended() => {
+                    tee.eof = true;
+                    continue;
+                }
+                None => {
+                    let res = if block {
+                        Poll::Ready(futures::executor::block_on(poll_fn(|cx| {
+                            Pin::new(&mut resource.incoming).poll_frame(cx)
+                        })))
+                    } else {
+                        Pin::new(&mut resource.incoming)
+                            .poll_frame(&mut Context::from_waker(noop_waker_ref()))
+                    };
+
+                    match res {
+                        Poll::Pending => return Ok(Ok(Vec::new())),
+                        Poll::Ready(None) => {
+                            resource.state = BodyState::Consumed;
+                            tee.eof = true;
+                            continue;
+                        }
+                        Poll::Ready(Some(frame)) => frame,
+                    }
+                }
+            };
+
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    tee.eof = true;
+                    return Ok(Err(StreamError::LastOperationFailed(
+                        self.handle_body_error(err),
+                    )));
+                }
+            };
+
+            match frame.into_data() {
+                Ok(data) => {
+                    if tee.buf.len() + data.len() > max_body_bytes {
+                        tee.eof = true;
+                        return Ok(Err(StreamError::LastOperationFailed(self.handle_io_error(
+                            std::io::Error::other("tee buffer exceeded the maximum body size"),
+                        ))));
+                    }
+
+                    tee.buf.extend(data);
+                }
+                Err(frame) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        resource.trailers = Some(trailers);
+                        resource.state = BodyState::Trailers;
+                    }
+
+                    tee.eof = true;
+                }
+            }
+        }
+    }
+}
+
+impl bluezeeking::service::body::Host for State {
+    fn tee(
+        &mut self,
+        stream: Resource<InputStream>,
+    ) -> wasmtime::Result<Result<Resource<InputStream>, ()>> {
+        let id = self.new_id();
+
+        let resource = self
+            .incoming
+            .get_mut(&stream.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        if resource.state != BodyState::Data || resource.tee.is_some() {
+            return Ok(Err(()));
+        }
+
+        // Data the source reader already pulled but has not consumed yet belongs to both readers.
+        let buf = match resource.last_frame.take() {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => VecDeque::from(data.to_vec()),
+                Err(frame) => {
+                    resource.last_frame = Some(Ok(frame));
+                    VecDeque::new()
+                }
+            },
+            other => {
+                resource.last_frame = other;
+                VecDeque::new()
+            }
+        };
+
+        resource.tee = Some(Tee {
+            buf,
+            start: 0,
+            cursors: [0, 0],
+            eof: false,
+        });
+
+        self.tees.insert(id, stream.rep());
+
+        Ok(Ok(Resource::new_own(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tee(bytes: &[u8]) -> Tee {
+        Tee {
+            buf: bytes.iter().copied().collect(),
+            start: 0,
+            cursors: [0, 0],
+            eof: true,
+        }
+    }
+
+    #[test]
+    fn both_readers_see_every_byte() {
+        let mut tee = tee(b"hello world");
+
+        assert_eq!(tee.take(SOURCE, 5).unwrap(), b"hello");
+        assert_eq!(tee.take(SOURCE, 64).unwrap(), b" world");
+        assert!(tee.is_done(SOURCE));
+
+        assert_eq!(tee.take(BRANCH, 64).unwrap(), b"hello world");
+        assert!(tee.is_done(BRANCH));
+    }
+
+    #[test]
+    fn bytes_are_kept_until_both_readers_have_them() {
+        let mut tee = tee(b"hello world");
+
+        tee.take(SOURCE, 6).unwrap();
+        assert_eq!(tee.buf.len(), 11);
+
+        tee.take(BRANCH, 3).unwrap();
+        assert_eq!((tee.start, tee.buf.len()), (3, 8));
+
+        // A dropped reader no longer holds bytes back.
+        tee.release(BRANCH);
+        assert_eq!((tee.start, tee.buf.len()), (6, 5));
+        assert_eq!(tee.take(SOURCE, 64).unwrap(), b"world");
+    }
+}
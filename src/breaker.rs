@@ -0,0 +1,199 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+
+/// Stops dispatching to a component that keeps failing, periodically letting a single probe
+/// request through to find out whether it recovered.
+pub struct CircuitBreaker {
+    circuit: Mutex<Circuit>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { next_probe: Instant },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Ticket {
+    Normal,
+    Probe,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of zero disables the breaker.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+            threshold,
+            cooldown,
+        }
+    }
+
+    pub fn admit(&self, metrics: &Metrics) -> Option<Ticket> {
+        let mut circuit = self.circuit.lock().unwrap();
+        let now = Instant::now();
+
+        match *circuit {
+            Circuit::Closed { .. } => Some(Ticket::Normal),
+            Circuit::Open { until } | Circuit::HalfOpen { next_probe: until } if now >= until => {
+                if let Circuit::Open { .. } = *circuit {
+                    info!("circuit half-open, sending probe request");
+                    metrics.circuit_state.set(2);
+                }
+
+                // A probe that never reports back (e.g. it was shed) must not wedge the circuit.
+                *circuit = Circuit::HalfOpen {
+                    next_probe: now + self.cooldown,
+                };
+
+                Some(Ticket::Probe)
+            }
+            _ => {
+                metrics.circuit_rejected.inc();
+                None
+            }
+        }
+    }
+
+    pub fn record(&self, ticket: Ticket, success: bool, metrics: &Metrics) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut circuit = self.circuit.lock().unwrap();
+
+        match (&mut *circuit, ticket) {
+            (Circuit::Closed { failures }, _) => {
+                if success {
+                    *failures = 0;
+                    return;
+                }
+
+                *failures += 1;
+
+                if *failures >= self.threshold {
+                    warn!(failures = *failures, "circuit opened");
+                    self.open(&mut circuit, metrics);
+                }
+            }
+            (Circuit::HalfOpen { .. }, Ticket::Probe) => {
+                if success {
+                    info!("circuit closed");
+                    *circuit = Circuit::Closed { failures: 0 };
+                    metrics.circuit_state.set(0);
+                } else {
+                    warn!("probe request failed, circuit reopened");
+                    self.open(&mut circuit, metrics);
+                }
+            }
+            // Results of requests admitted before the circuit opened say nothing new.
+            _ => {}
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.circuit.lock().unwrap(), Circuit::Closed { .. })
+    }
+
+    fn open(&self, circuit: &mut Circuit, metrics: &Metrics) {
+        *circuit = Circuit::Open {
+            until: Instant::now() + self.cooldown,
+        };
+
+        metrics.circuit_opened.inc();
+        metrics.circuit_state.set(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn fail(breaker: &CircuitBreaker, metrics: &Metrics, times: u32) {
+        for _ in 0..times {
+            let ticket = breaker.admit(metrics).unwrap();
+            breaker.record(ticket, false, metrics);
+        }
+    }
+
+    #[test]
+    fn closes_again_after_a_successful_probe() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        let metrics = Metrics::default();
+
+        fail(&breaker, &metrics, 2);
+        assert!(!breaker.is_open());
+
+        fail(&breaker, &metrics, 1);
+        assert!(breaker.is_open());
+        assert_eq!(metrics.circuit_state.get(), 1);
+        assert_eq!(metrics.circuit_opened.get(), 1);
+
+        // Open: everything is turned away until the cooldown passes.
+        assert!(breaker.admit(&metrics).is_none());
+        assert_eq!(metrics.circuit_rejected.get(), 1);
+
+        std::thread::sleep(COOLDOWN);
+
+        // Half-open: one probe goes through, the rest wait for its result.
+        assert!(breaker.admit(&metrics) == Some(Ticket::Probe));
+        assert_eq!(metrics.circuit_state.get(), 2);
+        assert!(breaker.admit(&metrics).is_none());
+
+        breaker.record(Ticket::Probe, true, &metrics);
+        assert!(!breaker.is_open());
+        assert_eq!(metrics.circuit_state.get(), 0);
+        assert!(breaker.admit(&metrics) == Some(Ticket::Normal));
+    }
+
+    #[test]
+    fn reopens_after_a_failed_probe() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let metrics = Metrics::default();
+
+        fail(&breaker, &metrics, 1);
+        std::thread::sleep(COOLDOWN);
+
+        let probe = breaker.admit(&metrics).unwrap();
+        breaker.record(probe, false, &metrics);
+
+        assert!(breaker.is_open());
+        assert_eq!(metrics.circuit_state.get(), 1);
+        assert_eq!(metrics.circuit_opened.get(), 2);
+        assert!(breaker.admit(&metrics).is_none());
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        let metrics = Metrics::default();
+
+        for _ in 0..3 {
+            fail(&breaker, &metrics, 1);
+            breaker.record(Ticket::Normal, true, &metrics);
+        }
+
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn a_zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        let metrics = Metrics::default();
+
+        fail(&breaker, &metrics, 100);
+
+        assert!(!breaker.is_open());
+        assert_eq!(metrics.circuit_opened.get(), 0);
+    }
+}
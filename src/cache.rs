@@ -0,0 +1,467 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ::http::{
+    header::{AGE, AUTHORIZATION, CACHE_CONTROL, SET_COOKIE, VARY},
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode,
+};
+
+use crate::http::Outgoing;
+
+/// An in-memory cache of guest responses that allow shared caching through `Cache-Control`.
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+    max_entry_bytes: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// The `Vary` header names of the last cached response for each resource.
+    vary: HashMap<Resource, Vec<HeaderName>>,
+    /// Keys ordered from least to most recently used.
+    lru: BTreeMap<u64, Key>,
+    size: usize,
+    tick: u64,
+}
+
+type Resource = (Method, String);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    resource: Resource,
+    vary: Vec<Option<HeaderValue>>,
+}
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    stored_at: Instant,
+    ttl: Duration,
+    tick: u64,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>()
+    }
+}
+
+/// A request that may be answered from the cache, or whose response may be stored in it.
+pub struct Lookup {
+    resource: Resource,
+    headers: HeaderMap,
+    /// The client asked for a fresh response, but the result may still be cached.
+    refresh: bool,
+}
+
+impl ResponseCache {
+    pub fn new(max_bytes: usize, max_entry_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            max_bytes,
+            max_entry_bytes,
+        }
+    }
+
+    /// Returns `None` for requests that must bypass the cache entirely.
+    pub fn lookup<B>(&self, req: &Request<B>) -> Option<Lookup> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD)
+            || req.headers().contains_key(AUTHORIZATION)
+        {
+            return None;
+        }
+
+        let directives = directives(req.headers());
+
+        if directives.iter().any(|(name, _)| name == "no-store") {
+            return None;
+        }
+
+        Some(Lookup {
+            resource: (req.method().clone(), req.uri().to_string()),
+            headers: req.headers().clone(),
+            refresh: directives.iter().any(|(name, _)| name == "no-cache"),
+        })
+    }
+
+    pub fn get(&self, lookup: &Lookup) -> Option<Response<Outgoing>> {
+        if lookup.refresh {
+            return None;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+
+        let names = inner.vary.get(&lookup.resource)?;
+        let key = key(lookup, names);
+
+        let entry = inner.entries.get_mut(&key)?;
+        let age = entry.stored_at.elapsed();
+
+        if age >= entry.ttl {
+            let entry = inner.entries.remove(&key)?;
+            inner.lru.remove(&entry.tick);
+            inner.size -= entry.size();
+
+            return None;
+        }
+
+        inner.tick += 1;
+        inner.lru.remove(&entry.tick);
+        inner.lru.insert(inner.tick, key);
+        entry.tick = inner.tick;
+
+        let mut res = Response::new(Outgoing::full(entry.body.clone()));
+        *res.status_mut() = entry.status;
+        *res.headers_mut() = entry.headers.clone();
+
+        let headers = res.headers_mut();
+        headers.insert(AGE, HeaderValue::from(age.as_secs()));
+        headers.insert("x-cache", HeaderValue::from_static("hit"));
+
+        Some(res)
+    }
+
+    /// Stores `res` if it is complete, small enough and allows shared caching.
+    pub fn put(&self, lookup: &Lookup, res: &mut Response<Outgoing>) {
+        res.headers_mut()
+            .insert("x-cache", HeaderValue::from_static("miss"));
+
+        let Some(ttl) = cacheable(res) else {
+            return;
+        };
+
+        let body = res.body();
+
+        if !body.done
+            || body.source.is_some()
+            || body.trailers.is_some()
+            || body.buf.len() > self.max_entry_bytes
+        {
+            return;
+        }
+
+        let names: Vec<HeaderName> = res
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+            .collect();
+
+        let mut headers = res.headers().clone();
+        headers.remove("x-cache");
+
+        let mut entry = Entry {
+            status: res.status(),
+            headers,
+            body: body.buf.iter().copied().collect(),
+            stored_at: Instant::now(),
+            ttl,
+            tick: 0,
+        };
+
+        let size = entry.size();
+
+        if size > self.max_bytes {
+            return;
+        }
+
+        let key = key(lookup, &names);
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.tick += 1;
+        entry.tick = inner.tick;
+
+        if let Some(old) = inner.entries.remove(&key) {
+            inner.lru.remove(&old.tick);
+            inner.size -= old.size();
+        }
+
+        inner.size += size;
+        inner.lru.insert(entry.tick, key.clone());
+        inner.entries.insert(key, entry);
+        inner.vary.insert(lookup.resource.clone(), names);
+
+        while inner.size > self.max_bytes {
+            let Some((_, key)) = inner.lru.pop_first() else {
+                break;
+            };
+
+            if let Some(evicted) = inner.entries.remove(&key) {
+                inner.size -= evicted.size();
+            }
+        }
+    }
+}
+
+fn key(lookup: &Lookup, names: &[HeaderName]) -> Key {
+    Key {
+        resource: lookup.resource.clone(),
+        vary: names
+            .iter()
+            .map(|name| lookup.headers.get(name).cloned())
+            .collect(),
+    }
+}
+
+/// Returns how long `res` may be served from a shared cache, if at all.
+fn cacheable(res: &Response<Outgoing>) -> Option<Duration> {
+    if !matches!(res.status().as_u16(), 200 | 203 | 204 | 301 | 404 | 410) {
+        return None;
+    }
+
+    let headers = res.headers();
+
+    if headers.contains_key(SET_COOKIE)
+        || headers
+            .get_all(VARY)
+            .iter()
+            .any(|value| value.to_str().is_ok_and(|value| value.trim() == "*"))
+    {
+        return None;
+    }
+
+    let mut max_age = None;
+    let mut shared_max_age = None;
+
+    for (name, value) in directives(headers) {
+        match name.as_str() {
+            "no-store" | "no-cache" | "private" => return None,
+            "max-age" => max_age = value.and_then(|value| value.parse().ok()),
+            "s-maxage" => shared_max_age = value.and_then(|value| value.parse().ok()),
+            _ => {}
+        }
+    }
+
+    // Without an explicit lifetime, even a `public` response is not worth guessing about.
+    shared_max_age
+        .or(max_age)
+        .filter(|ttl| *ttl > 0)
+        .map(Duration::from_secs)
+}
+
+fn directives(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_owned()),
+            ),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request<()> {
+        let mut req = Request::builder().method(method).uri("/page");
+
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+
+        req.body(()).unwrap()
+    }
+
+    fn get(headers: &[(&str, &str)]) -> Lookup {
+        ResponseCache::new(0, 0)
+            .lookup(&request(Method::GET, headers))
+            .unwrap()
+    }
+
+    fn response(headers: &[(&str, &str)], body: &str) -> Response<Outgoing> {
+        let mut res = Response::new(Outgoing::full(body.as_bytes().to_vec()));
+
+        for (name, value) in headers {
+            res.headers_mut().append(
+                HeaderName::try_from(*name).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+
+        res
+    }
+
+    fn body(res: &Response<Outgoing>) -> Vec<u8> {
+        res.body().buf.iter().copied().collect()
+    }
+
+    fn cache() -> ResponseCache {
+        ResponseCache::new(1 << 20, 1 << 16)
+    }
+
+    #[test]
+    fn stored_responses_are_hits() {
+        let cache = cache();
+        let lookup = get(&[]);
+
+        assert!(cache.get(&lookup).is_none());
+
+        let mut res = response(&[("cache-control", "max-age=60")], "hello");
+        cache.put(&lookup, &mut res);
+        assert_eq!(res.headers()["x-cache"], "miss");
+
+        let hit = cache.get(&lookup).unwrap();
+        assert_eq!(hit.headers()["x-cache"], "hit");
+        assert_eq!(hit.headers()[AGE], "0");
+        assert_eq!(body(&hit), b"hello");
+    }
+
+    #[test]
+    fn uncacheable_responses_are_misses() {
+        let cache = cache();
+        let lookup = get(&[]);
+
+        let cases: [&[(&str, &str)]; 6] = [
+            &[],
+            &[("cache-control", "max-age=0")],
+            &[("cache-control", "private, max-age=60")],
+            &[("cache-control", "no-store, max-age=60")],
+            &[("cache-control", "max-age=60"), ("set-cookie", "a=b")],
+            &[("cache-control", "max-age=60"), ("vary", "*")],
+        ];
+
+        for headers in cases {
+            cache.put(&lookup, &mut response(headers, "hello"));
+            assert!(cache.get(&lookup).is_none(), "{headers:?}");
+        }
+
+        let mut res = response(&[("cache-control", "max-age=60")], "hello");
+        *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        cache.put(&lookup, &mut res);
+        assert!(cache.get(&lookup).is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let cache = cache();
+        let lookup = get(&[]);
+
+        cache.put(
+            &lookup,
+            &mut response(&[("cache-control", "max-age=60")], "hello"),
+        );
+
+        for entry in cache.inner.lock().unwrap().entries.values_mut() {
+            entry.stored_at = entry
+                .stored_at
+                .checked_sub(Duration::from_secs(60))
+                .unwrap();
+        }
+
+        assert!(cache.get(&lookup).is_none());
+
+        let inner = cache.inner.lock().unwrap();
+        assert!(inner.entries.is_empty() && inner.lru.is_empty());
+        assert_eq!(inner.size, 0);
+    }
+
+    #[test]
+    fn shared_max_age_wins_over_max_age() {
+        let res = response(&[("cache-control", "max-age=5, s-maxage=60")], "");
+
+        assert_eq!(cacheable(&res), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn entries_vary_on_accept_encoding() {
+        let cache = cache();
+        let gzip = get(&[("accept-encoding", "gzip")]);
+        let identity = get(&[("accept-encoding", "identity")]);
+
+        cache.put(
+            &gzip,
+            &mut response(
+                &[("cache-control", "max-age=60"), ("vary", "Accept-Encoding")],
+                "compressed",
+            ),
+        );
+
+        assert!(cache.get(&identity).is_none());
+        assert_eq!(body(&cache.get(&gzip).unwrap()), b"compressed");
+
+        cache.put(
+            &identity,
+            &mut response(
+                &[("cache-control", "max-age=60"), ("vary", "accept-encoding")],
+                "plain",
+            ),
+        );
+
+        assert_eq!(body(&cache.get(&identity).unwrap()), b"plain");
+        assert_eq!(body(&cache.get(&gzip).unwrap()), b"compressed");
+        assert!(cache.get(&get(&[("accept-encoding", "br")])).is_none());
+    }
+
+    #[test]
+    fn some_requests_bypass_the_cache() {
+        let cache = cache();
+
+        assert!(cache.lookup(&request(Method::POST, &[])).is_none());
+        assert!(cache
+            .lookup(&request(Method::GET, &[("authorization", "Bearer x")]))
+            .is_none());
+        assert!(cache
+            .lookup(&request(Method::GET, &[("cache-control", "no-store")]))
+            .is_none());
+        assert!(cache.lookup(&request(Method::HEAD, &[])).is_some());
+    }
+
+    #[test]
+    fn no_cache_requests_refresh_the_entry() {
+        let cache = cache();
+        let lookup = get(&[]);
+
+        cache.put(
+            &lookup,
+            &mut response(&[("cache-control", "max-age=60")], "old"),
+        );
+
+        let refresh = get(&[("cache-control", "no-cache")]);
+        assert!(cache.get(&refresh).is_none());
+
+        cache.put(
+            &refresh,
+            &mut response(&[("cache-control", "max-age=60")], "new"),
+        );
+        assert_eq!(body(&cache.get(&lookup).unwrap()), b"new");
+    }
+
+    #[test]
+    fn streamed_and_oversized_bodies_are_not_stored() {
+        let cache = ResponseCache::new(1 << 20, 4);
+        let lookup = get(&[]);
+
+        cache.put(
+            &lookup,
+            &mut response(&[("cache-control", "max-age=60")], "hello"),
+        );
+        assert!(cache.get(&lookup).is_none());
+
+        let mut res = response(&[("cache-control", "max-age=60")], "hi");
+        res.body_mut().done = false;
+        cache.put(&lookup, &mut res);
+        assert!(cache.get(&lookup).is_none());
+    }
+}
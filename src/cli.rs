@@ -0,0 +1,173 @@
+// The stream plumbing stays compiled without the `cli` feature, since wasi:io dispatches on it.
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
+
+use wasmtime::component::Resource;
+
+use crate::{
+    io::{Ready, BUF_LIMIT},
+    wasi::{
+        self,
+        cli::{terminal_input::TerminalInput, terminal_output::TerminalOutput},
+        io::{
+            poll::Pollable,
+            streams::{InputStream, OutputStream},
+        },
+        logging::logging::{Host as _, Level},
+    },
+    State,
+};
+
+/// Which of the standard streams an id refers to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stdio {
+    /// Always at its end; requests reach the guest through `wasi:http` only.
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl State {
+    /// Accepts output written to stdout or stderr. Every complete line goes to the log like a
+    /// `wasi:logging` message, sharing its per-request budget.
+    pub fn stdio_write(&mut self, stream: Stdio, contents: &[u8]) -> wasmtime::Result<()> {
+        let (buf, level, context) = match stream {
+            Stdio::Stdout => (&mut self.stdout_line, Level::Info, "stdout"),
+            Stdio::Stderr => (&mut self.stderr_line, Level::Warn, "stderr"),
+            Stdio::Stdin => return Err(wasmtime::Error::msg("Could not find output stream")),
+        };
+
+        buf.extend_from_slice(contents);
+
+        let end = match buf.iter().rposition(|byte| *byte == b'\n') {
+            Some(end) => end + 1,
+            // A line that never ends would otherwise grow without bound.
+            None if buf.len() >= BUF_LIMIT => buf.len(),
+            None => return Ok(()),
+        };
+
+        let lines: Vec<u8> = buf.drain(..end).collect();
+
+        for line in String::from_utf8_lossy(&lines).lines() {
+            self.log(level, context.to_owned(), line.to_owned())?;
+        }
+
+        Ok(())
+    }
+
+    /// Logs what is left of an unterminated line on either stream once the request is over.
+    pub fn finish_stdio(&mut self) -> wasmtime::Result<()> {
+        for (buf, level, context) in [
+            (std::mem::take(&mut self.stdout_line), Level::Info, "stdout"),
+            (std::mem::take(&mut self.stderr_line), Level::Warn, "stderr"),
+        ] {
+            if !buf.is_empty() {
+                let line = String::from_utf8_lossy(&buf).into_owned();
+                self.log(level, context.to_owned(), line)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn stdio_subscribe(&mut self) -> Resource<Pollable> {
+        let id = self.new_id();
+        self.pollables.insert(id, Box::new(Ready));
+
+        Resource::new_own(id)
+    }
+
+    #[cfg(feature = "cli")]
+    fn new_stdio(&mut self, stream: Stdio) -> u32 {
+        let id = self.new_id();
+        self.stdio.insert(id, stream);
+        id
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::environment::Host for State {
+    fn get_environment(&mut self) -> wasmtime::Result<Vec<(String, String)>> {
+        Ok(Vec::new())
+    }
+
+    fn get_arguments(&mut self) -> wasmtime::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn initial_cwd(&mut self) -> wasmtime::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::exit::Host for State {
+    fn exit(&mut self, status: Result<(), ()>) -> wasmtime::Result<()> {
+        // There is no process to end; unwinding the guest is the closest equivalent.
+        Err(wasmtime::Error::msg(match status {
+            Ok(()) => "The component exited",
+            Err(()) => "The component exited with a failure status",
+        }))
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::stdin::Host for State {
+    fn get_stdin(&mut self) -> wasmtime::Result<Resource<InputStream>> {
+        Ok(Resource::new_own(self.new_stdio(Stdio::Stdin)))
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::stdout::Host for State {
+    fn get_stdout(&mut self) -> wasmtime::Result<Resource<OutputStream>> {
+        Ok(Resource::new_own(self.new_stdio(Stdio::Stdout)))
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::stderr::Host for State {
+    fn get_stderr(&mut self) -> wasmtime::Result<Resource<OutputStream>> {
+        Ok(Resource::new_own(self.new_stdio(Stdio::Stderr)))
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_input::Host for State {}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_input::HostTerminalInput for State {
+    fn drop(&mut self, _rep: Resource<TerminalInput>) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_output::Host for State {}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_output::HostTerminalOutput for State {
+    fn drop(&mut self, _rep: Resource<TerminalOutput>) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_stdin::Host for State {
+    fn get_terminal_stdin(&mut self) -> wasmtime::Result<Option<Resource<TerminalInput>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_stdout::Host for State {
+    fn get_terminal_stdout(&mut self) -> wasmtime::Result<Option<Resource<TerminalOutput>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl wasi::cli::terminal_stderr::Host for State {
+    fn get_terminal_stderr(&mut self) -> wasmtime::Result<Option<Resource<TerminalOutput>>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt::Debug,
+    sync::{Condvar, Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Longest a blocked guest sleeps before checking the clock again.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Where guests get the time from through `wasi:clocks`.
+pub trait ClockSource: Debug + Send + Sync {
+    /// Nanoseconds on the monotonic clock. Never goes backwards.
+    fn monotonic_now(&self) -> u64;
+
+    /// Time since the Unix epoch on the wall clock.
+    fn wall_now(&self) -> Duration;
+
+    /// Blocks the calling thread until the monotonic clock reaches `deadline`.
+    fn sleep_until(&self, deadline: u64);
+}
+
+/// The host's own clocks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    /// Counts from the first time any guest asked, saturating rather than wrapping after 584
+    /// years.
+    fn monotonic_now(&self) -> u64 {
+        static START: OnceLock<std::time::Instant> = OnceLock::new();
+
+        let elapsed = START.get_or_init(std::time::Instant::now).elapsed();
+
+        elapsed.as_nanos().try_into().unwrap_or(u64::MAX)
+    }
+
+    fn wall_now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn sleep_until(&self, deadline: u64) {
+        // Sleeping in slices keeps a deadline far in the future from overflowing the sleep.
+        loop {
+            let now = self.monotonic_now();
+
+            if now >= deadline {
+                return;
+            }
+
+            std::thread::sleep(Duration::from_nanos(deadline - now).min(MAX_SLEEP));
+        }
+    }
+}
+
+/// A clock that only moves when [`ManualClock::advance`] is called, for tests of guests that
+/// wait or measure time. Guests blocked on it wake as soon as it passes their deadline.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<ManualTime>,
+    advanced: Condvar,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    monotonic: u64,
+    wall: Duration,
+    sleepers: usize,
+}
+
+impl ManualClock {
+    /// A clock whose monotonic time starts at zero and whose wall time starts at `wall`.
+    pub fn new(wall: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(ManualTime {
+                monotonic: 0,
+                wall: wall.duration_since(UNIX_EPOCH).unwrap_or_default(),
+                sleepers: 0,
+            }),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Moves both clocks forward by `by`, waking guests whose deadline has come.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        let nanos = by.as_nanos().try_into().unwrap_or(u64::MAX);
+
+        now.monotonic = now.monotonic.saturating_add(nanos);
+        now.wall = now.wall.saturating_add(by);
+
+        self.advanced.notify_all();
+    }
+
+    /// How many guests are blocked waiting for the clock, so a test can advance it once they all
+    /// are.
+    pub fn sleepers(&self) -> usize {
+        self.now.lock().unwrap().sleepers
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(UNIX_EPOCH)
+    }
+}
+
+impl ClockSource for ManualClock {
+    fn monotonic_now(&self) -> u64 {
+        self.now.lock().unwrap().monotonic
+    }
+
+    fn wall_now(&self) -> Duration {
+        self.now.lock().unwrap().wall
+    }
+
+    fn sleep_until(&self, deadline: u64) {
+        let mut now = self.now.lock().unwrap();
+        now.sleepers += 1;
+
+        let mut now = self
+            .advanced
+            .wait_while(now, |now| now.monotonic < deadline)
+            .unwrap();
+        now.sleepers -= 1;
+    }
+}
@@ -1,31 +1,145 @@
+use wasmtime::component::Resource;
+
 use crate::{
+    io::PollableIndividual,
     wasi::{
         self,
-        clocks::monotonic_clock::{Duration, Instant, Pollable},
+        clocks::{
+            monotonic_clock::{Duration, Instant, Pollable},
+            wall_clock::Datetime,
+        },
     },
     State,
 };
 
+/// Ready once the monotonic clock reaches `deadline`, so at once for an instant in the past.
+struct Deadline {
+    deadline: Instant,
+}
+
+impl PollableIndividual for Deadline {
+    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
+        Ok(state.clock.monotonic_now() >= self.deadline)
+    }
+
+    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
+        state.clock.sleep_until(self.deadline);
+
+        Ok(())
+    }
+}
+
 impl wasi::clocks::monotonic_clock::Host for State {
     fn now(&mut self) -> wasmtime::Result<Instant> {
-        todo!()
+        Ok(self.clock.monotonic_now())
     }
 
     fn resolution(&mut self) -> wasmtime::Result<Duration> {
-        todo!()
+        Ok(1)
+    }
+
+    fn subscribe_instant(&mut self, when: Instant) -> wasmtime::Result<Resource<Pollable>> {
+        let id = self.new_id();
+        self.pollables
+            .insert(id, Box::new(Deadline { deadline: when }));
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn subscribe_duration(&mut self, when: Duration) -> wasmtime::Result<Resource<Pollable>> {
+        let now = self.clock.monotonic_now();
+        self.subscribe_instant(now.saturating_add(when))
+    }
+}
+
+impl wasi::clocks::wall_clock::Host for State {
+    fn now(&mut self) -> wasmtime::Result<Datetime> {
+        let since_epoch = self.clock.wall_now();
+
+        Ok(Datetime {
+            seconds: since_epoch.as_secs(),
+            nanoseconds: since_epoch.subsec_nanos(),
+        })
+    }
+
+    fn resolution(&mut self) -> wasmtime::Result<Datetime> {
+        Ok(Datetime {
+            seconds: 0,
+            nanoseconds: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration as StdDuration};
+
+    use crate::{
+        wasi::{clocks::monotonic_clock::Host, io::poll::HostPollable},
+        ManualClock,
+    };
+
+    use super::*;
+
+    fn ready(state: &mut State, pollable: &Resource<Pollable>) -> bool {
+        HostPollable::ready(state, Resource::new_borrow(pollable.rep())).unwrap()
     }
 
-    fn subscribe_instant(
-        &mut self,
-        when: Instant,
-    ) -> wasmtime::Result<wasmtime::component::Resource<Pollable>> {
-        todo!()
+    fn block(state: &mut State, pollable: &Resource<Pollable>) {
+        HostPollable::block(state, Resource::new_borrow(pollable.rep())).unwrap()
     }
 
-    fn subscribe_duration(
-        &mut self,
-        when: Duration,
-    ) -> wasmtime::Result<wasmtime::component::Resource<Pollable>> {
-        todo!()
+    #[test]
+    fn past_instants_are_ready_at_once() {
+        let mut state = State::default();
+        let now = state.now().unwrap();
+
+        for instant in [0, now.saturating_sub(1), now] {
+            let pollable = state.subscribe_instant(instant).unwrap();
+            assert!(ready(&mut state, &pollable), "{instant}");
+
+            let started_at = std::time::Instant::now();
+            block(&mut state, &pollable);
+            assert!(started_at.elapsed() < StdDuration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn near_instants_are_waited_for() {
+        let mut state = State::default();
+        let pollable = state.subscribe_duration(20_000_000).unwrap();
+        assert!(!ready(&mut state, &pollable));
+
+        let started_at = std::time::Instant::now();
+        block(&mut state, &pollable);
+        let waited = started_at.elapsed();
+
+        assert!(waited >= StdDuration::from_millis(15), "{waited:?}");
+        assert!(waited < StdDuration::from_secs(1), "{waited:?}");
+        assert!(ready(&mut state, &pollable));
+    }
+
+    #[test]
+    fn far_deadlines_saturate() {
+        let clock = Arc::new(ManualClock::default());
+        clock.advance(StdDuration::from_secs(1));
+
+        let mut state = State {
+            clock: clock.clone(),
+            ..Default::default()
+        };
+
+        // Adding the longest duration to a nonzero instant would wrap to one already passed.
+        let pollable = state.subscribe_duration(Duration::MAX).unwrap();
+        assert!(!ready(&mut state, &pollable));
+
+        clock.advance(StdDuration::from_secs(365 * 24 * 60 * 60));
+        assert!(!ready(&mut state, &pollable));
+
+        // The clock saturates too, reaching the deadline instead of wrapping past it.
+        clock.advance(StdDuration::MAX);
+        assert_eq!(state.now().unwrap(), Instant::MAX);
+        assert!(ready(&mut state, &pollable));
+        block(&mut state, &pollable);
     }
 }
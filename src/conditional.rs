@@ -0,0 +1,224 @@
+use std::time::SystemTime;
+
+use ::http::{
+    header::{
+        CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        TRANSFER_ENCODING,
+    },
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+};
+
+use crate::http::Outgoing;
+
+/// The validators a `GET` or `HEAD` request carried.
+pub struct Validators {
+    /// Every `If-None-Match` field line, joined into one list.
+    if_none_match: Option<String>,
+    if_modified_since: Option<HeaderValue>,
+}
+
+impl Validators {
+    pub fn from_request<B>(req: &Request<B>) -> Option<Self> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return None;
+        }
+
+        let validators = Self {
+            if_none_match: req
+                .headers()
+                .get_all(IF_NONE_MATCH)
+                .iter()
+                .map(|value| value.to_str().ok())
+                .collect::<Option<Vec<_>>>()
+                .filter(|values| !values.is_empty())
+                .map(|values| values.join(",")),
+            if_modified_since: req.headers().get(IF_MODIFIED_SINCE).cloned(),
+        };
+
+        (validators.if_none_match.is_some() || validators.if_modified_since.is_some())
+            .then_some(validators)
+    }
+
+    /// Whether the client's copy of the representation in `headers` is still current.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        // If-Modified-Since is only evaluated without If-None-Match (RFC 9110 section 13.2.2).
+        if let Some(if_none_match) = &self.if_none_match {
+            let Some(etag) = headers
+                .get(ETAG)
+                .and_then(|etag| EntityTag::parse(etag.to_str().ok()?))
+            else {
+                return if_none_match.trim() == "*";
+            };
+
+            return none_match_fails(if_none_match, &etag);
+        }
+
+        let since = self.if_modified_since.as_ref().and_then(parse_date);
+        let modified = headers.get(LAST_MODIFIED).and_then(parse_date);
+
+        matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+    }
+
+    /// Turns a successful response the client already has into `304 Not Modified`.
+    pub fn apply(&self, res: &mut Response<Outgoing>) {
+        if res.status() != StatusCode::OK || !self.matches(res.headers()) {
+            return;
+        }
+
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        *res.body_mut() = Outgoing::full(Vec::new());
+
+        let headers = res.headers_mut();
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(CONTENT_TYPE);
+        headers.remove(TRANSFER_ENCODING);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct EntityTag<'a> {
+    pub weak: bool,
+    /// The quoted tag.
+    pub opaque: &'a str,
+}
+
+impl<'a> EntityTag<'a> {
+    pub fn parse(value: &'a str) -> Option<Self> {
+        let value = value.trim();
+
+        let (weak, opaque) = match value.strip_prefix("W/") {
+            Some(opaque) => (true, opaque),
+            None => (false, value),
+        };
+
+        let valid = opaque.len() >= 2
+            && opaque.starts_with('"')
+            && opaque.ends_with('"')
+            && !opaque[1..opaque.len() - 1].contains('"');
+
+        valid.then_some(Self { weak, opaque })
+    }
+
+    /// The tags are identical apart from weakness. `If-None-Match` never needs the strong
+    /// comparison, which would also require both tags to be strong.
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.opaque == other.opaque
+    }
+}
+
+/// Whether an `If-None-Match` value matches `etag`, i.e. the condition is false and the client's
+/// copy is current. This uses the weak comparison.
+fn none_match_fails(if_none_match: &str, etag: &EntityTag) -> bool {
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+
+    split_tags(if_none_match)
+        .filter_map(EntityTag::parse)
+        .any(|candidate| candidate.weak_eq(etag))
+}
+
+/// Splits a list of entity tags on the commas between them, not those inside quotes.
+fn split_tags(value: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+
+    value
+        .split(move |c| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+
+            c == ',' && !quoted
+        })
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+}
+
+fn parse_date(value: &HeaderValue) -> Option<SystemTime> {
+    httpdate::parse_http_date(value.to_str().ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(value: &str) -> EntityTag {
+        EntityTag::parse(value).unwrap()
+    }
+
+    #[test]
+    fn entity_tags_parse() {
+        assert_eq!(
+            EntityTag::parse(" \"v1\" "),
+            Some(EntityTag {
+                weak: false,
+                opaque: "\"v1\""
+            })
+        );
+        assert_eq!(
+            EntityTag::parse("W/\"v1\""),
+            Some(EntityTag {
+                weak: true,
+                opaque: "\"v1\""
+            })
+        );
+        assert_eq!(EntityTag::parse("\"\"").map(|tag| tag.opaque), Some("\"\""));
+
+        for invalid in ["v1", "\"v1", "w/\"v1\"", "\"a\"b\"", "\""] {
+            assert_eq!(EntityTag::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn weak_comparison_ignores_weakness() {
+        assert!(tag("\"v1\"").weak_eq(&tag("\"v1\"")));
+        assert!(tag("W/\"v1\"").weak_eq(&tag("\"v1\"")));
+        assert!(tag("\"v1\"").weak_eq(&tag("W/\"v1\"")));
+        assert!(tag("W/\"v1\"").weak_eq(&tag("W/\"v1\"")));
+        assert!(!tag("\"v1\"").weak_eq(&tag("\"v2\"")));
+    }
+
+    #[test]
+    fn any_tag_matches_a_star() {
+        assert!(none_match_fails("*", &tag("\"v1\"")));
+        assert!(none_match_fails(" * ", &tag("W/\"v1\"")));
+    }
+
+    #[test]
+    fn any_of_several_tags_matches() {
+        assert!(none_match_fails("\"v0\", W/\"v1\"", &tag("\"v1\"")));
+        assert!(none_match_fails("\"v0\",\"v1\"", &tag("W/\"v1\"")));
+        assert!(!none_match_fails("\"v0\", \"v2\"", &tag("\"v1\"")));
+        // Malformed members are skipped rather than failing the whole list.
+        assert!(none_match_fails("v0, \"v1\"", &tag("\"v1\"")));
+    }
+
+    #[test]
+    fn commas_inside_quotes_stay_in_the_tag() {
+        assert_eq!(
+            split_tags("\"a,b\", W/\"c\",,\"d\"").collect::<Vec<_>>(),
+            ["\"a,b\"", "W/\"c\"", "\"d\""]
+        );
+        assert!(none_match_fails("\"x\", \"a,b\"", &tag("\"a,b\"")));
+        assert!(!none_match_fails("\"a\", \"b\"", &tag("\"a,b\"")));
+    }
+
+    #[test]
+    fn if_modified_since_is_ignored_alongside_if_none_match() {
+        let req = Request::get("/")
+            .header(IF_NONE_MATCH, "\"v2\"")
+            .header(IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body(())
+            .unwrap();
+        let validators = Validators::from_request(&req).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+        headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+
+        assert!(!validators.matches(&headers));
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use crate::{
+    wasi::{self, config::store::Error},
+    State,
+};
+
+/// Values guests read through `wasi:config/store`. Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct GuestConfig {
+    values: Arc<BTreeMap<String, String>>,
+    /// Keys whose values are masked when the config is logged.
+    secrets: Arc<BTreeSet<String>>,
+}
+
+impl GuestConfig {
+    pub fn set(mut self, key: &str, value: &str) -> Self {
+        Arc::make_mut(&mut self.values).insert(key.to_owned(), value.to_owned());
+        Arc::make_mut(&mut self.secrets).remove(key);
+        self
+    }
+
+    /// Like [`GuestConfig::set`], but the value never shows up in the log.
+    pub fn secret(mut self, key: &str, value: &str) -> Self {
+        Arc::make_mut(&mut self.values).insert(key.to_owned(), value.to_owned());
+        Arc::make_mut(&mut self.secrets).insert(key.to_owned());
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+}
+
+impl fmt::Debug for GuestConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.values.iter().map(|(key, value)| {
+                let value = if self.secrets.contains(key) {
+                    "***"
+                } else {
+                    value.as_str()
+                };
+
+                (key, value)
+            }))
+            .finish()
+    }
+}
+
+impl wasi::config::store::Host for State {
+    fn get(&mut self, key: String) -> wasmtime::Result<Result<Option<String>, Error>> {
+        Ok(Ok(self.config.get(&key).map(str::to_owned)))
+    }
+
+    fn get_all(&mut self) -> wasmtime::Result<Result<Vec<(String, String)>, Error>> {
+        Ok(Ok(self
+            .config
+            .values
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked_in_debug_output() {
+        let config = GuestConfig::default()
+            .set("api-base", "https://api.example.com")
+            .secret("api-token", "hunter2");
+
+        assert_eq!(
+            format!("{config:?}"),
+            r#"{"api-base": "https://api.example.com", "api-token": "***"}"#
+        );
+        assert_eq!(config.get("api-token"), Some("hunter2"));
+    }
+
+    #[test]
+    fn setting_a_secret_again_in_the_clear_unmasks_it() {
+        let config = GuestConfig::default()
+            .secret("token", "hunter2")
+            .set("token", "public");
+
+        assert_eq!(format!("{config:?}"), r#"{"token": "public"}"#);
+    }
+}
@@ -0,0 +1,392 @@
+use std::{str::FromStr, time::Duration};
+
+use ::http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+        ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+    },
+    HeaderMap, HeaderName, HeaderValue, Method, Request,
+};
+
+/// Cross-origin access the host grants on a component's behalf, so components don't each have to
+/// implement CORS.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    pub origins: Vec<AllowedOrigin>,
+    /// Methods a preflight may ask for.
+    pub methods: Vec<Method>,
+    /// Request headers a preflight may ask for.
+    pub headers: Vec<HeaderName>,
+    /// Lets browsers send cookies and read the response. Can't be combined with `*`.
+    pub credentials: bool,
+    /// How long browsers may cache a preflight's answer.
+    pub max_age: Option<Duration>,
+    /// Answers requests from origins that aren't allowed with 403, instead of passing them on and
+    /// leaving the browser to withhold the response. Same-origin `POST`s carry an `Origin` too, so
+    /// the component's own origin has to be listed.
+    pub strict: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+            strict: false,
+        }
+    }
+}
+
+/// An origin allowed to make cross-origin requests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllowedOrigin {
+    /// `*`, every origin.
+    Any,
+    /// Exactly this origin, such as `https://example.com`.
+    Exact(String),
+    /// `<scheme>://*.<domain>`: every subdomain of the domain, at any depth, but not the domain
+    /// itself.
+    Subdomains { scheme: String, domain: String },
+}
+
+impl AllowedOrigin {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            Self::Subdomains { scheme, domain } => {
+                let Some(host) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+
+                host.len() > domain.len() + 1
+                    && host.ends_with(domain.as_str())
+                    && host[..host.len() - domain.len()].ends_with('.')
+            }
+        }
+    }
+}
+
+impl FromStr for AllowedOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Self::Any);
+        }
+
+        let invalid = || format!("expected *, an origin or <scheme>://*.<domain>, got {s}");
+
+        let (scheme, host) = s.split_once("://").ok_or_else(invalid)?;
+
+        if scheme.is_empty() || host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => Ok(Self::Subdomains {
+                scheme: scheme.to_ascii_lowercase(),
+                domain: domain.to_ascii_lowercase(),
+            }),
+            Some(_) => Err(invalid()),
+            None if host.contains('*') => Err(invalid()),
+            None => Ok(Self::Exact(s.to_owned())),
+        }
+    }
+}
+
+/// Whether a request is a CORS preflight, which the host answers without the guest.
+pub fn is_preflight<B>(req: &Request<B>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(ORIGIN)
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+impl Cors {
+    /// Refuses settings browsers would reject anyway.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.credentials && self.origins.contains(&AllowedOrigin::Any) {
+            return Err("CORS credentials can't be allowed for every origin (*)".to_owned());
+        }
+
+        Ok(())
+    }
+
+    pub fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        origin
+            .to_str()
+            .is_ok_and(|origin| self.origins.iter().any(|allowed| allowed.matches(origin)))
+    }
+
+    /// Whether the method and headers a preflight asks for are all allowed.
+    fn allows_preflight(&self, headers: &HeaderMap) -> bool {
+        let method_allowed = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+            .is_some_and(|method| self.methods.contains(&method));
+
+        let headers_allowed = headers
+            .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|names| names.to_str().ok())
+            .flat_map(|names| names.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .is_ok_and(|name| self.headers.contains(&name))
+            });
+
+        method_allowed && headers_allowed
+    }
+
+    /// The headers answering a preflight, or `None` when the origin, method or headers it asks
+    /// for aren't allowed. [`Cors::apply`] adds the origin.
+    pub fn preflight(&self, headers: &HeaderMap) -> Option<HeaderMap> {
+        let origin = headers.get(ORIGIN)?;
+
+        if !self.allows_origin(origin) || !self.allows_preflight(headers) {
+            return None;
+        }
+
+        let mut answer = HeaderMap::new();
+        answer.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+
+        if !self.headers.is_empty() {
+            answer.insert(ACCESS_CONTROL_ALLOW_HEADERS, join(&self.headers));
+        }
+
+        if let Some(max_age) = self.max_age {
+            answer.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+
+        Some(answer)
+    }
+
+    /// Adds the `Access-Control-Allow-*` headers for a request from `origin` to a response. Unless
+    /// every origin gets the same answer, the response varies by origin even when there is none,
+    /// so caches keep the answers apart.
+    pub fn apply(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+        let wildcard = self.origins.contains(&AllowedOrigin::Any) && !self.credentials;
+
+        if !wildcard {
+            vary_by_origin(headers);
+        }
+
+        let Some(origin) = origin.filter(|origin| self.allows_origin(origin)) else {
+            return;
+        };
+
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            if wildcard {
+                HeaderValue::from_static("*")
+            } else {
+                origin.clone()
+            },
+        );
+
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+fn vary_by_origin(headers: &mut HeaderMap) {
+    let varies = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|vary| vary.to_str().ok())
+        .flat_map(|vary| vary.split(','))
+        .map(str::trim)
+        .any(|name| name == "*" || name.eq_ignore_ascii_case("origin"));
+
+    if !varies {
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+fn join<T: AsRef<str>>(values: &[T]) -> HeaderValue {
+    let joined = values
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    HeaderValue::from_str(&joined).expect("methods and header names are tokens")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors() -> Cors {
+        Cors {
+            origins: vec![
+                "https://app.example.com".parse().unwrap(),
+                "https://*.example.org".parse().unwrap(),
+            ],
+            methods: vec![Method::GET, Method::PUT],
+            headers: vec![HeaderName::from_static("content-type")],
+            credentials: true,
+            max_age: Some(Duration::from_secs(600)),
+            strict: false,
+        }
+    }
+
+    fn preflight(origin: &str, method: &str, request_headers: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, origin.parse().unwrap());
+        headers.insert(ACCESS_CONTROL_REQUEST_METHOD, method.parse().unwrap());
+
+        if !request_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_REQUEST_HEADERS,
+                request_headers.parse().unwrap(),
+            );
+        }
+
+        headers
+    }
+
+    #[test]
+    fn origins_match_exactly_or_by_subdomain() {
+        let cors = cors();
+        let allows = |origin: &str| cors.allows_origin(&HeaderValue::from_str(origin).unwrap());
+
+        assert!(allows("https://app.example.com"));
+        assert!(allows("HTTPS://APP.EXAMPLE.COM"));
+        assert!(allows("https://api.example.org"));
+        assert!(allows("https://a.b.example.org"));
+
+        // The domain itself isn't one of its subdomains, nor is another scheme or a lookalike.
+        assert!(!allows("https://example.org"));
+        assert!(!allows("http://api.example.org"));
+        assert!(!allows("https://evilexample.org"));
+        assert!(!allows("https://app.example.com.evil.com"));
+        assert!(!allows("https://evil.com"));
+
+        let any = Cors {
+            origins: vec![AllowedOrigin::Any],
+            ..cors
+        };
+        assert!(any.allows_origin(&HeaderValue::from_static("https://evil.com")));
+    }
+
+    #[test]
+    fn preflights_may_only_ask_for_allowed_methods_and_headers() {
+        let cors = cors();
+
+        // (method, requested headers, allowed)
+        let matrix = [
+            ("PUT", "content-type", true),
+            ("GET", "", true),
+            ("PUT", "Content-Type", true),
+            ("PUT", " content-type , ", true),
+            ("DELETE", "", false),
+            ("put", "", false),
+            ("PUT", "content-type, x-secret", false),
+        ];
+
+        for (method, request_headers, allowed) in matrix {
+            let headers = preflight("https://app.example.com", method, request_headers);
+
+            assert_eq!(
+                cors.allows_preflight(&headers),
+                allowed,
+                "{method} {request_headers}"
+            );
+        }
+
+        let answer = cors
+            .preflight(&preflight("https://app.example.com", "PUT", "content-type"))
+            .unwrap();
+        assert_eq!(answer[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(answer[ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+        assert_eq!(answer[ACCESS_CONTROL_MAX_AGE], "600");
+
+        // An allowed method from an origin that isn't allowed gets no answer either.
+        assert!(cors
+            .preflight(&preflight("https://evil.com", "GET", ""))
+            .is_none());
+    }
+
+    #[test]
+    fn responses_vary_by_origin_unless_every_origin_is_allowed() {
+        let cors = cors();
+        let origin = HeaderValue::from_static("https://app.example.com");
+
+        let mut headers = HeaderMap::new();
+        cors.apply(Some(&origin), &mut headers);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[VARY], "Origin");
+
+        // Other origins get nothing, but caches still have to keep the answers apart.
+        let mut headers = HeaderMap::new();
+        cors.apply(
+            Some(&HeaderValue::from_static("https://evil.com")),
+            &mut headers,
+        );
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(headers[VARY], "Origin");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding, origin"));
+        cors.apply(Some(&origin), &mut headers);
+        assert_eq!(headers.get_all(VARY).iter().count(), 1);
+
+        let any = Cors {
+            origins: vec![AllowedOrigin::Any],
+            credentials: false,
+            ..cors
+        };
+        let mut headers = HeaderMap::new();
+        any.apply(Some(&origin), &mut headers);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        assert!(!headers.contains_key(VARY));
+    }
+
+    #[test]
+    fn credentials_need_explicit_origins() {
+        let wildcard = Cors {
+            origins: vec![AllowedOrigin::Any],
+            ..cors()
+        };
+        assert!(wildcard.validate().is_err());
+
+        let anonymous = Cors {
+            credentials: false,
+            ..wildcard
+        };
+        assert!(anonymous.validate().is_ok());
+
+        for origin in [
+            "https://*",
+            "https://a.*.com",
+            "example.com",
+            "https://x.com/path",
+        ] {
+            assert!(origin.parse::<AllowedOrigin>().is_err(), "{origin}");
+        }
+
+        assert_eq!(
+            "HTTPS://*.Example.org".parse::<AllowedOrigin>(),
+            Ok(AllowedOrigin::Subdomains {
+                scheme: "https".to_owned(),
+                domain: "example.org".to_owned(),
+            })
+        );
+    }
+}
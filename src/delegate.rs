@@ -0,0 +1,56 @@
+use wasmtime::component::Linker;
+use wasmtime_wasi::preview2::{bindings, Table, WasiCtx, WasiCtxBuilder, WasiView};
+
+use crate::State;
+
+/// Host state for the interfaces wasmtime-wasi serves on this crate's behalf. Only interfaces
+/// that hand out no `wasi:io` resources are delegated, since streams and pollables live in
+/// `State`'s own tables and the two implementations can't share them.
+pub struct Delegated {
+    table: Table,
+    ctx: WasiCtx,
+}
+
+impl Default for Delegated {
+    fn default() -> Self {
+        Self {
+            table: Table::new(),
+            ctx: WasiCtxBuilder::new().build(),
+        }
+    }
+}
+
+impl WasiView for State {
+    fn table(&self) -> &Table {
+        &self.delegated.table
+    }
+
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.delegated.table
+    }
+
+    fn ctx(&self) -> &WasiCtx {
+        &self.delegated.ctx
+    }
+
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.delegated.ctx
+    }
+}
+
+/// Adds the interfaces served by wasmtime-wasi in place of this crate's own.
+pub fn add_to_linker(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    let get = |state: &mut State| state;
+
+    bindings::random::random::add_to_linker(linker, get)?;
+    bindings::random::insecure::add_to_linker(linker, get)?;
+    bindings::random::insecure_seed::add_to_linker(linker, get)?;
+    #[cfg(feature = "cli")]
+    {
+        bindings::cli::environment::add_to_linker(linker, get)?;
+        bindings::cli::exit::add_to_linker(linker, get)?;
+    }
+    bindings::clocks::wall_clock::add_to_linker(linker, get)?;
+
+    Ok(())
+}
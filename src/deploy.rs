@@ -0,0 +1,197 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use ::http::{header::COOKIE, HeaderMap, HeaderName};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use tracing::info;
+use wasmtime::component::InstancePre;
+
+use crate::{breaker::CircuitBreaker, metrics::Metrics, Options, State};
+
+/// A named, pre-linked component version.
+pub struct Version {
+    pub slot: String,
+    pub(crate) pre: InstancePre<State>,
+    pub(crate) breaker: CircuitBreaker,
+    metrics: Metrics,
+}
+
+impl Version {
+    pub(crate) fn new(slot: &str, pre: InstancePre<State>, options: &Options) -> Self {
+        Self {
+            slot: slot.to_owned(),
+            pre,
+            breaker: CircuitBreaker::new(options.failure_threshold, options.circuit_cooldown),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Requests, failures, latencies and circuit state of this version alone.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+/// Which part of a request keeps a client on the same side of a split.
+#[derive(Clone, Debug)]
+pub enum Sticky {
+    Header(HeaderName),
+    Cookie(String),
+}
+
+impl Sticky {
+    fn key<'a>(&self, headers: &'a HeaderMap) -> Option<&'a [u8]> {
+        match self {
+            Sticky::Header(name) => headers.get(name).map(|value| value.as_bytes()),
+            Sticky::Cookie(name) => headers
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    (key == name).then_some(value.as_bytes())
+                }),
+        }
+    }
+}
+
+/// Sends `weight` percent of requests to a canary version instead of the active one.
+pub struct Split {
+    canary: Arc<Version>,
+    weight: u8,
+    sticky: Option<Sticky>,
+}
+
+impl Split {
+    fn picks_canary(&self, headers: &HeaderMap) -> bool {
+        let roll = match self.sticky.as_ref().and_then(|sticky| sticky.key(headers)) {
+            // `DefaultHasher::new` uses fixed keys, so a key lands on the same side every time.
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish() % 100
+            }
+            None => rand::random::<u64>() % 100,
+        };
+
+        roll < u64::from(self.weight)
+    }
+}
+
+/// The versions loaded for a mount and the one currently receiving traffic.
+///
+/// Requests take a snapshot of the active version when they start, so switching only affects
+/// requests that arrive afterwards.
+pub struct Slots {
+    active: ArcSwap<Version>,
+    split: ArcSwapOption<Split>,
+    history: Mutex<History>,
+}
+
+struct History {
+    loaded: HashMap<String, Arc<Version>>,
+    previous: Option<Arc<Version>>,
+}
+
+impl Slots {
+    pub fn new(version: Version) -> Self {
+        let version = Arc::new(version);
+
+        Self {
+            active: ArcSwap::new(version.clone()),
+            split: ArcSwapOption::empty(),
+            history: Mutex::new(History {
+                loaded: HashMap::from([(version.slot.clone(), version)]),
+                previous: None,
+            }),
+        }
+    }
+
+    pub fn active(&self) -> Arc<Version> {
+        self.active.load_full()
+    }
+
+    /// Picks the version a request goes to, along with the active version if that is a different
+    /// one so the caller can fall back to it.
+    pub fn route(&self, headers: &HeaderMap) -> (Arc<Version>, Option<Arc<Version>>) {
+        let active = self.active();
+
+        match &*self.split.load() {
+            Some(split) if split.picks_canary(headers) => (split.canary.clone(), Some(active)),
+            _ => (active, None),
+        }
+    }
+
+    pub fn set_split(&self, canary: Arc<Version>, weight: u8, sticky: Option<Sticky>) {
+        info!(canary = %canary.slot, weight, "splitting traffic");
+
+        self.split.store(Some(Arc::new(Split {
+            canary,
+            weight: weight.min(100),
+            sticky,
+        })));
+    }
+
+    pub fn clear_split(&self) {
+        if self.split.swap(None).is_some() {
+            info!("stopped splitting traffic");
+        }
+    }
+
+    pub fn insert(&self, version: Version) {
+        let mut history = self.history.lock().unwrap();
+
+        history
+            .loaded
+            .insert(version.slot.clone(), Arc::new(version));
+    }
+
+    pub fn get(&self, slot: &str) -> Option<Arc<Version>> {
+        self.history.lock().unwrap().loaded.get(slot).cloned()
+    }
+
+    pub fn versions(&self) -> Vec<Arc<Version>> {
+        let mut versions: Vec<_> = self
+            .history
+            .lock()
+            .unwrap()
+            .loaded
+            .values()
+            .cloned()
+            .collect();
+        versions.sort_by(|a, b| a.slot.cmp(&b.slot));
+        versions
+    }
+
+    /// Switches traffic to `version`, remembering the current version for [`Slots::rollback`].
+    pub fn activate(&self, version: Arc<Version>) {
+        let mut history = self.history.lock().unwrap();
+        self.clear_split();
+
+        let previous = self.active.swap(version.clone());
+        info!(from = %previous.slot, to = %version.slot, "activated component version");
+
+        if !Arc::ptr_eq(&previous, &version) {
+            history.previous = Some(previous);
+        }
+    }
+
+    /// Switches back to the version that was active before the last activation.
+    pub fn rollback(&self) -> Option<Arc<Version>> {
+        let mut history = self.history.lock().unwrap();
+
+        let previous = history.previous.take()?;
+        self.clear_split();
+
+        let current = self.active.swap(previous.clone());
+        info!(from = %current.slot, to = %previous.slot, "rolled back component version");
+
+        history.previous = Some(current);
+
+        Some(previous)
+    }
+}
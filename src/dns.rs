@@ -0,0 +1,477 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use ::http::{uri::Authority, Uri};
+use futures::{stream::FuturesUnordered, StreamExt};
+use hyper::rt::ReadBufCursor;
+use hyper_util::{
+    client::legacy::connect::{Connected, Connection, HttpConnector},
+    rt::TokioIo,
+};
+use tokio::{net::TcpStream, time::Instant};
+use tower::{Service, ServiceExt};
+
+use crate::{
+    http::BoxError,
+    proxy::{self, ProxyOptions},
+    wasi::http::types::{DnsErrorPayload, ErrorCode},
+};
+
+/// How long a connection attempt gets before the next address is tried alongside it, as in
+/// Happy Eyeballs (RFC 8305).
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+tokio::task_local! {
+    /// When the request being sent has to be connected by, from its `connect-timeout`. Name
+    /// resolution counts against it.
+    pub static CONNECT_DEADLINE: Option<Instant>;
+}
+
+/// How outbound requests find the addresses of the hosts they name.
+#[derive(Clone, Debug, Default)]
+pub struct DnsOptions {
+    pub resolver: DnsResolver,
+    /// Hosts answered from this map instead of DNS, port included, such as `api.example.com` to
+    /// `127.0.0.1:8080`. Handy for pointing guests at local servers in tests.
+    pub hosts: HashMap<String, SocketAddr>,
+    pub prefer: IpPreference,
+}
+
+/// Where names are looked up.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DnsResolver {
+    /// The host's own resolver, through `getaddrinfo`.
+    #[default]
+    System,
+    /// These nameservers, queried directly.
+    Nameservers(Vec<SocketAddr>),
+}
+
+impl FromStr for DnsResolver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "system" {
+            return Ok(Self::System);
+        }
+
+        let nameservers = s
+            .split(',')
+            .map(|server| {
+                server
+                    .parse()
+                    .or_else(|_| server.parse().map(|ip: IpAddr| SocketAddr::new(ip, 53)))
+                    .map_err(|_| format!("expected system or <ip>[:<port>],..., got {s}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        if cfg!(not(feature = "hickory-dns")) {
+            return Err(format!(
+                "querying nameservers needs the hickory-dns feature, got {s}"
+            ));
+        }
+
+        Ok(Self::Nameservers(nameservers))
+    }
+}
+
+/// Which address family connections try first when a host has both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Whichever the resolver listed first, alternating between families after that.
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for IpPreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "ipv4" => Ok(Self::Ipv4),
+            "ipv6" => Ok(Self::Ipv6),
+            _ => Err(format!("expected auto, ipv4 or ipv6, got {s}")),
+        }
+    }
+}
+
+impl IpPreference {
+    /// Puts `addrs` in the order they are tried in: the preferred family first, alternating
+    /// with the other so one broken family can't hold up the connection for long.
+    fn order(self, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        let first_v6 = match self {
+            Self::Auto => addrs.first().is_some_and(IpAddr::is_ipv6),
+            Self::Ipv4 => false,
+            Self::Ipv6 => true,
+        };
+
+        let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+            .into_iter()
+            .partition(|addr| addr.is_ipv6() == first_v6);
+        preferred.reverse();
+        other.reverse();
+
+        let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+
+        while let Some(addr) = preferred.pop() {
+            ordered.push(addr);
+            ordered.extend(other.pop());
+        }
+
+        ordered.extend(other.into_iter().rev());
+        ordered
+    }
+}
+
+/// Why a connection could not be made, as the guest is told it.
+#[derive(Debug)]
+pub struct ConnectFailure(pub ErrorCode);
+
+impl fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::error::Error for ConnectFailure {}
+
+enum Lookup {
+    System,
+    #[cfg(feature = "hickory-dns")]
+    Hickory(hickory_resolver::TokioAsyncResolver),
+}
+
+impl Lookup {
+    fn new(resolver: &DnsResolver) -> Self {
+        match resolver {
+            DnsResolver::System => Self::System,
+            #[cfg(feature = "hickory-dns")]
+            DnsResolver::Nameservers(nameservers) => {
+                use hickory_resolver::config::{
+                    LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts,
+                };
+
+                let servers = nameservers
+                    .iter()
+                    .flat_map(|&addr| {
+                        [
+                            NameServerConfig::new(addr, Protocol::Udp),
+                            NameServerConfig::new(addr, Protocol::Tcp),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut opts = ResolverOpts::default();
+                opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+
+                Self::Hickory(hickory_resolver::TokioAsyncResolver::tokio(
+                    ResolverConfig::from_parts(None, Vec::new(), servers),
+                    opts,
+                ))
+            }
+            // Refused when the option is parsed.
+            #[cfg(not(feature = "hickory-dns"))]
+            DnsResolver::Nameservers(_) => Self::System,
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, ErrorCode> {
+        let addrs = match self {
+            Self::System => tokio::net::lookup_host((host, 0))
+                .await
+                .map_err(|_| dns_error(None))?
+                .map(|addr| addr.ip())
+                .collect::<Vec<_>>(),
+            #[cfg(feature = "hickory-dns")]
+            Self::Hickory(resolver) => {
+                use hickory_resolver::error::ResolveErrorKind;
+
+                match resolver.lookup_ip(host).await {
+                    Ok(lookup) => lookup.iter().collect(),
+                    Err(error) => {
+                        return Err(match error.kind() {
+                            ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                                dns_error(Some(format!("{response_code:?}").to_uppercase()))
+                            }
+                            ResolveErrorKind::Timeout => ErrorCode::DnsTimeout,
+                            _ => dns_error(None),
+                        })
+                    }
+                }
+            }
+        };
+
+        if addrs.is_empty() {
+            // The name exists but has no addresses.
+            return Err(dns_error(Some("NOERROR".to_owned())));
+        }
+
+        Ok(addrs)
+    }
+}
+
+fn dns_error(rcode: Option<String>) -> ErrorCode {
+    ErrorCode::DnsError(DnsErrorPayload {
+        rcode,
+        info_code: None,
+    })
+}
+
+/// Connects outbound requests to the addresses [`DnsOptions`] gives for their host, racing
+/// attempts Happy Eyeballs style, or to the proxy [`ProxyOptions`] routes them through. Cloning
+/// is cheap.
+#[derive(Clone)]
+pub struct Connector {
+    lookup: Arc<Lookup>,
+    hosts: Arc<HashMap<String, SocketAddr>>,
+    prefer: IpPreference,
+    proxy: Arc<ProxyOptions>,
+    http: HttpConnector,
+}
+
+impl Connector {
+    pub fn new(options: &DnsOptions, proxy: &ProxyOptions) -> Self {
+        Self {
+            lookup: Arc::new(Lookup::new(&options.resolver)),
+            hosts: Arc::new(
+                options
+                    .hosts
+                    .iter()
+                    .map(|(host, addr)| (host.to_ascii_lowercase(), *addr))
+                    .collect(),
+            ),
+            prefer: options.prefer,
+            proxy: Arc::new(proxy.clone()),
+            http: HttpConnector::new(),
+        }
+    }
+
+    async fn addrs(&self, uri: &Uri) -> Result<Vec<SocketAddr>, ErrorCode> {
+        let host = uri
+            .host()
+            .ok_or(ErrorCode::HttpRequestUriInvalid)?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+
+        if let Some(addr) = self.hosts.get(&host) {
+            return Ok(vec![*addr]);
+        }
+
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+
+        let ips = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => self.prefer.order(self.lookup.lookup(&host).await?),
+        };
+
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    async fn connect(self, uri: Uri, deadline: Option<Instant>) -> Result<Conn, BoxError> {
+        let route = self
+            .proxy
+            .route(&uri)
+            .map(|(proxy, tunnel)| (proxy.clone(), tunnel));
+
+        let to = match &route {
+            Some((proxy, _)) => proxy.uri(),
+            None => uri.clone(),
+        };
+
+        let addrs = within(deadline, ErrorCode::DnsTimeout, self.addrs(&to))
+            .await?
+            .map_err(ConnectFailure)?;
+        let io = within(
+            deadline,
+            ErrorCode::ConnectionTimeout,
+            race(&self.http, addrs),
+        )
+        .await??;
+
+        let Some((proxy, tunnel)) = route else {
+            return Ok(Conn { io, proxied: false });
+        };
+
+        if !tunnel {
+            return Ok(Conn { io, proxied: true });
+        }
+
+        let host = uri
+            .host()
+            .ok_or(ConnectFailure(ErrorCode::HttpRequestUriInvalid))?;
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+        let target: Authority = format!("{host}:{port}")
+            .parse()
+            .map_err(|_| ConnectFailure(ErrorCode::HttpRequestUriInvalid))?;
+
+        let mut stream = io.into_inner();
+        within(
+            deadline,
+            ErrorCode::ConnectionTimeout,
+            proxy::tunnel(&mut stream, &target, proxy.auth()),
+        )
+        .await?
+        .map_err(ConnectFailure)?;
+
+        // Through the tunnel, requests go in origin form as if the connection were direct.
+        Ok(Conn {
+            io: TokioIo::new(stream),
+            proxied: false,
+        })
+    }
+}
+
+/// Runs `future`, failing with `code` if it is still going at `deadline`.
+async fn within<T>(
+    deadline: Option<Instant>,
+    code: ErrorCode,
+    future: impl Future<Output = T>,
+) -> Result<T, ConnectFailure> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| ConnectFailure(code)),
+        None => Ok(future.await),
+    }
+}
+
+/// Connects to the first of `addrs` that answers, starting the next attempt whenever the ones
+/// in flight have taken [`ATTEMPT_DELAY`] or failed.
+async fn race(
+    http: &HttpConnector,
+    addrs: Vec<SocketAddr>,
+) -> Result<TokioIo<TcpStream>, BoxError> {
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if attempts.is_empty() {
+            let Some(addr) = addrs.next() else {
+                return Err(last_error
+                    .unwrap_or_else(|| ConnectFailure(ErrorCode::ConnectionRefused).into()));
+            };
+
+            attempts.push(attempt(http.clone(), addr));
+        }
+
+        tokio::select! {
+            Some(res) = attempts.next() => match res {
+                Ok(stream) => return Ok(stream),
+                Err(error) => last_error = Some(error),
+            },
+            () = tokio::time::sleep(ATTEMPT_DELAY), if addrs.len() > 0 => {
+                attempts.extend(addrs.next().map(|addr| attempt(http.clone(), addr)));
+            }
+        }
+    }
+}
+
+async fn attempt(http: HttpConnector, addr: SocketAddr) -> Result<TokioIo<TcpStream>, BoxError> {
+    // An address as the host skips the connector's own resolution.
+    let uri = Uri::try_from(format!("http://{addr}"))?;
+
+    http.oneshot(uri).await.map_err(Into::into)
+}
+
+/// A connection to an origin, or to a proxy that takes requests in absolute form.
+pub struct Conn {
+    io: TokioIo<TcpStream>,
+    proxied: bool,
+}
+
+impl Connection for Conn {
+    fn connected(&self) -> Connected {
+        self.io.inner().connected().proxy(self.proxied)
+    }
+}
+
+impl hyper::rt::Read for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_read(cx, buf)
+    }
+}
+
+impl hyper::rt::Write for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// The client starts connections from the requesting task, so its deadline can be read
+    /// here even if the connection is finished in the background.
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let deadline = CONNECT_DEADLINE
+            .try_with(|deadline| *deadline)
+            .ok()
+            .flatten();
+
+        Box::pin(self.clone().connect(uri, deadline))
+    }
+}
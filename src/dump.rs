@@ -0,0 +1,195 @@
+use std::{
+    fmt::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ::http::{
+    header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE},
+    HeaderMap, HeaderName,
+};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use pin_project::{pin_project, pinned_drop};
+use tracing::debug;
+
+use crate::http::{BoxError, RequestBody};
+
+/// Passes a request body through unchanged while keeping its first `limit` bytes for the log.
+#[pin_project(PinnedDrop)]
+pub struct DumpBody {
+    #[pin]
+    inner: RequestBody,
+    captured: Vec<u8>,
+    total: usize,
+    limit: usize,
+    logged: bool,
+    redact: Vec<HeaderName>,
+}
+
+impl DumpBody {
+    pub fn new(inner: RequestBody, limit: usize, redact: Vec<HeaderName>) -> Self {
+        Self {
+            inner,
+            captured: Vec::new(),
+            total: 0,
+            limit,
+            logged: false,
+            redact,
+        }
+    }
+}
+
+impl Body for DumpBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.project();
+        let res = this.inner.poll_frame(cx);
+
+        match &res {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    let room = this.limit.saturating_sub(this.captured.len());
+                    this.captured
+                        .extend_from_slice(&data[..room.min(data.len())]);
+                    *this.total += data.len();
+                }
+
+                if let Some(trailers) = frame.trailers_ref() {
+                    dump_trailers("request", trailers, this.redact);
+                }
+            }
+            Poll::Ready(None) if !*this.logged => {
+                *this.logged = true;
+                dump("request", this.captured, *this.total);
+            }
+            _ => {}
+        }
+
+        res
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for DumpBody {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+
+        // The guest did not read the body to the end.
+        if !*this.logged {
+            dump("request", this.captured, *this.total);
+        }
+    }
+}
+
+/// Headers whose values are left out of dumps and recordings unless configured otherwise.
+pub fn sensitive_headers() -> Vec<HeaderName> {
+    vec![AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE]
+}
+
+/// Formats header fields for the log, with the values of those in `redact` left out.
+pub fn format_headers(headers: &HeaderMap, redact: &[HeaderName]) -> String {
+    let mut out = String::new();
+
+    for (name, value) in headers {
+        let separator = if out.is_empty() { "" } else { ", " };
+
+        if redact.contains(name) {
+            let _ = write!(out, "{separator}{name}: [redacted]");
+        } else {
+            let _ = write!(
+                out,
+                "{separator}{name}: {}",
+                String::from_utf8_lossy(value.as_bytes())
+            );
+        }
+    }
+
+    out
+}
+
+pub fn dump_trailers(direction: &str, trailers: &HeaderMap, redact: &[HeaderName]) {
+    debug!(
+        direction,
+        trailers = %format_headers(trailers, redact),
+        "trailers dump"
+    );
+}
+
+/// Logs `captured` as text if it is valid UTF-8 and as hex otherwise, noting how much of the
+/// `total` bytes was left out.
+pub fn dump(direction: &str, captured: &[u8], total: usize) {
+    let text = match std::str::from_utf8(captured) {
+        Ok(text) => Some(text),
+        // A character cut in half by the limit does not make the body binary.
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&captured[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    };
+
+    let body = match text {
+        Some(text) => format!("{text:?}"),
+        None => captured.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }),
+    };
+
+    let truncated = total - captured.len();
+
+    debug!(direction, len = total, truncated, %body, "body dump");
+}
+
+#[cfg(test)]
+mod tests {
+    use ::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn redacted_headers_keep_their_name_but_not_their_values() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        headers.append(COOKIE, HeaderValue::from_static("a=secret"));
+        headers.append(COOKIE, HeaderValue::from_static("b=secret"));
+        headers.insert("x-request-id", HeaderValue::from_static("42"));
+
+        let dumped = format_headers(&headers, &sensitive_headers());
+
+        assert_eq!(
+            dumped,
+            "authorization: [redacted], cookie: [redacted], cookie: [redacted], x-request-id: 42"
+        );
+    }
+
+    #[test]
+    fn configured_redactions_replace_the_defaults() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        headers.insert("x-api-key", HeaderValue::from_static("secret"));
+
+        let redact = [HeaderName::from_static("x-api-key")];
+
+        assert_eq!(
+            format_headers(&headers, &redact),
+            "authorization: Bearer token, x-api-key: [redacted]"
+        );
+        assert_eq!(
+            format_headers(&headers, &[]),
+            "authorization: Bearer token, x-api-key: secret"
+        );
+    }
+}
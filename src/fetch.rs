@@ -0,0 +1,180 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ::http::{
+    header::{ETAG, IF_NONE_MATCH},
+    Request, StatusCode, Uri,
+};
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::body::Bytes;
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use sha2::{Digest, Sha256};
+
+/// How to download a component given as a URL.
+#[derive(Clone, Debug)]
+pub struct Fetch {
+    /// Longest the whole download may take.
+    pub timeout: Duration,
+    pub max_bytes: usize,
+    /// The expected SHA-256 digest of the component, in hex.
+    pub sha256: Option<String>,
+    /// Downloads are kept here, along with their `ETag`, so a restart can revalidate them.
+    pub cache_dir: PathBuf,
+}
+
+impl Default for Fetch {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_bytes: 256 * 1024 * 1024,
+            sha256: None,
+            cache_dir: std::env::temp_dir().join("wasi-http-runner"),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+impl Fetch {
+    /// Whether `component` names a URL rather than a file.
+    pub fn is_url(component: &str) -> bool {
+        component.starts_with("http://") || component.starts_with("https://")
+    }
+
+    /// Whether `component` names an OCI artifact, as `oci://<registry>/<repository>:<tag>` or
+    /// `...@sha256:<digest>`. Pulling one needs the `oci` feature.
+    pub fn is_oci(component: &str) -> bool {
+        component.starts_with("oci://")
+    }
+
+    /// Downloads the component at `url` into the cache and returns its path there. A cached copy
+    /// is reused if it still matches the pinned digest, or if the server answers `304` to its
+    /// `ETag`.
+    pub async fn component(&self, url: &str) -> anyhow::Result<PathBuf> {
+        let uri: Uri = url.parse()?;
+
+        if uri.scheme_str() == Some("https") {
+            return Err(anyhow::Error::msg(format!(
+                "Can't download {url}: HTTPS is not supported, serve the component over HTTP or \
+                 download it first"
+            )));
+        }
+
+        std::fs::create_dir_all(&self.cache_dir)?;
+
+        // Keep the extension, as it decides whether the file is loaded as precompiled.
+        let extension = Path::new(uri.path())
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("wasm");
+        let name = hex(url.as_bytes());
+        let path = self.cache_dir.join(format!("{name}.{extension}"));
+        let etag_path = self.cache_dir.join(format!("{name}.etag"));
+
+        let cached = std::fs::read(&path).ok();
+
+        if let (Some(cached), Some(_)) = (&cached, &self.sha256) {
+            if self.verify(cached).is_ok() {
+                return Ok(path);
+            }
+        }
+
+        let etag = cached
+            .is_some()
+            .then(|| std::fs::read_to_string(&etag_path).ok())
+            .flatten();
+
+        let download = tokio::time::timeout(self.timeout, self.download(uri, etag.as_deref()))
+            .await
+            .map_err(|_| {
+                anyhow::Error::msg(format!(
+                    "Downloading {url} took longer than {:?}",
+                    self.timeout
+                ))
+            })??;
+
+        let Some((bytes, etag)) = download else {
+            // Not modified, so the cached copy is current.
+            self.verify(cached.as_deref().unwrap_or_default())?;
+            return Ok(path);
+        };
+
+        self.verify(&bytes)?;
+
+        // Written aside and renamed, so a crash never leaves a truncated component in the cache.
+        let partial = self.cache_dir.join(format!("{name}.partial"));
+        std::fs::write(&partial, &bytes)?;
+        std::fs::rename(&partial, &path)?;
+
+        match etag {
+            Some(etag) => std::fs::write(&etag_path, etag)?,
+            None => {
+                let _ = std::fs::remove_file(&etag_path);
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// Returns the body and `ETag` of a fresh copy, or `None` if the cached one is current.
+    async fn download(
+        &self,
+        uri: Uri,
+        etag: Option<&str>,
+    ) -> anyhow::Result<Option<(Bytes, Option<String>)>> {
+        let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+
+        let mut req = Request::get(uri.clone());
+
+        if let Some(etag) = etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+
+        let res = client.request(req.body(Empty::new())?).await?;
+
+        if res.status() == StatusCode::NOT_MODIFIED && etag.is_some() {
+            return Ok(None);
+        }
+
+        if !res.status().is_success() {
+            return Err(anyhow::Error::msg(format!(
+                "Downloading {uri} failed with {}",
+                res.status()
+            )));
+        }
+
+        let etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned);
+
+        let body = Limited::new(res.into_body(), self.max_bytes)
+            .collect()
+            .await
+            .map_err(|err| anyhow::Error::msg(format!("Downloading {uri} failed: {err}")))?
+            .to_bytes();
+
+        Ok(Some((body, etag)))
+    }
+
+    fn verify(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        let Some(expected) = &self.sha256 else {
+            return Ok(());
+        };
+
+        let actual = hex(bytes);
+
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(anyhow::Error::msg(format!(
+                "The component's SHA-256 is {actual}, expected {expected}"
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use wasmtime::component::Resource;
+
+use crate::{
+    wasi::{
+        self,
+        filesystem::types::{
+            Advice, Descriptor, DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry,
+            DirectoryEntryStream, ErrorCode, Filesize, MetadataHashValue, NewTimestamp, OpenFlags,
+            PathFlags,
+        },
+        io::streams::{Error, InputStream, OutputStream},
+    },
+    State,
+};
+
+// Guests get no preopened directories, so no descriptor can ever exist. The descriptor methods
+// only satisfy the imports of components built against the full WASI command world.
+
+impl wasi::filesystem::preopens::Host for State {
+    fn get_directories(&mut self) -> wasmtime::Result<Vec<(Resource<Descriptor>, String)>> {
+        Ok(Vec::new())
+    }
+}
+
+impl wasi::filesystem::types::Host for State {
+    fn filesystem_error_code(
+        &mut self,
+        _err: Resource<Error>,
+    ) -> wasmtime::Result<Option<ErrorCode>> {
+        Ok(None)
+    }
+}
+
+impl wasi::filesystem::types::HostDescriptor for State {
+    fn read_via_stream(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _offset: Filesize,
+    ) -> wasmtime::Result<Result<Resource<InputStream>, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn write_via_stream(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _offset: Filesize,
+    ) -> wasmtime::Result<Result<Resource<OutputStream>, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn append_via_stream(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<Resource<OutputStream>, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn advise(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _offset: Filesize,
+        _length: Filesize,
+        _advice: Advice,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn sync_data(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn get_flags(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<DescriptorFlags, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn get_type(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<DescriptorType, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn set_size(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _size: Filesize,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn set_times(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _data_access_timestamp: NewTimestamp,
+        _data_modification_timestamp: NewTimestamp,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn read(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _length: Filesize,
+        _offset: Filesize,
+    ) -> wasmtime::Result<Result<(Vec<u8>, bool), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn write(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _buffer: Vec<u8>,
+        _offset: Filesize,
+    ) -> wasmtime::Result<Result<Filesize, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn read_directory(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<Resource<DirectoryEntryStream>, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn sync(&mut self, _self_: Resource<Descriptor>) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn create_directory_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn stat(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<DescriptorStat, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn stat_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        _path: String,
+    ) -> wasmtime::Result<Result<DescriptorStat, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn set_times_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        _path: String,
+        _data_access_timestamp: NewTimestamp,
+        _data_modification_timestamp: NewTimestamp,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn link_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _old_path_flags: PathFlags,
+        _old_path: String,
+        _new_descriptor: Resource<Descriptor>,
+        _new_path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn open_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        _path: String,
+        _open_flags: OpenFlags,
+        _flags: DescriptorFlags,
+    ) -> wasmtime::Result<Result<Resource<Descriptor>, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn readlink_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path: String,
+    ) -> wasmtime::Result<Result<String, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn remove_directory_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn rename_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _old_path: String,
+        _new_descriptor: Resource<Descriptor>,
+        _new_path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn symlink_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _old_path: String,
+        _new_path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn unlink_file_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path: String,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn is_same_object(
+        &mut self,
+        self_: Resource<Descriptor>,
+        other: Resource<Descriptor>,
+    ) -> wasmtime::Result<bool> {
+        Ok(self_.rep() == other.rep())
+    }
+
+    fn metadata_hash(
+        &mut self,
+        _self_: Resource<Descriptor>,
+    ) -> wasmtime::Result<Result<MetadataHashValue, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn metadata_hash_at(
+        &mut self,
+        _self_: Resource<Descriptor>,
+        _path_flags: PathFlags,
+        _path: String,
+    ) -> wasmtime::Result<Result<MetadataHashValue, ErrorCode>> {
+        Ok(Err(ErrorCode::BadDescriptor))
+    }
+
+    fn drop(&mut self, _rep: Resource<Descriptor>) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
+
+impl wasi::filesystem::types::HostDirectoryEntryStream for State {
+    fn read_directory_entry(
+        &mut self,
+        _self_: Resource<DirectoryEntryStream>,
+    ) -> wasmtime::Result<Result<Option<DirectoryEntry>, ErrorCode>> {
+        Ok(Ok(None))
+    }
+
+    fn drop(&mut self, _rep: Resource<DirectoryEntryStream>) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    bluezeeking::service::log::{Host, Level},
+    State,
+};
+
+/// A guest's fields, shown as `key=value` pairs.
+struct Fields(Vec<(String, String)>);
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+
+            write!(f, "{key}={value:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Host for State {
+    fn log(
+        &mut self,
+        level: Level,
+        message: String,
+        fields: Vec<(String, String)>,
+    ) -> wasmtime::Result<()> {
+        // Shares the budget of wasi:logging, so switching interfaces doesn't double it.
+        if self.log_budget == 0 {
+            self.logs_suppressed += 1;
+            return Ok(());
+        }
+
+        self.log_budget -= 1;
+
+        let fields = Fields(fields);
+
+        match level {
+            Level::Trace => trace!(target: "guest", %fields, "{message}"),
+            Level::Debug => debug!(target: "guest", %fields, "{message}"),
+            Level::Info => info!(target: "guest", %fields, "{message}"),
+            Level::Warn => warn!(target: "guest", %fields, "{message}"),
+            Level::Error => error!(target: "guest", %fields, "{message}"),
+        }
+
+        Ok(())
+    }
+}
@@ -1,26 +1,35 @@
 use std::{
     collections::VecDeque,
-    convert::Infallible,
     pin::Pin,
-    task::{Context, Poll, Waker},
+    str::FromStr,
+    task::{ready, Context, Poll, Waker},
     thread::Thread,
 };
 
-use crate::{io::PollableIndividual, wasi::http::types::Duration};
+use crate::{
+    body::Tee,
+    io::PollableIndividual,
+    pipe::{Pipe, PipeBody},
+    upstream::BodyFailure,
+};
 
 use super::wasi::{
     self,
     http::types::{
-        ErrorCode, FieldKey, FieldValue, Fields, FutureIncomingResponse, FutureTrailers,
-        HeaderError, Headers, IncomingBody, IncomingRequest, IncomingResponse, InputStream,
-        IoError, Method, OutgoingBody, OutgoingRequest, OutgoingResponse, OutputStream,
-        RequestOptions, ResponseOutparam, Scheme, StatusCode, Trailers,
+        ErrorCode, FieldKey, FieldValue, Fields, FutureTrailers, HeaderError, Headers,
+        IncomingBody, IncomingRequest, InputStream, IoError, Method, OutgoingBody,
+        OutgoingResponse, OutputStream, ResponseOutparam, Scheme, StatusCode, Trailers,
     },
     io::poll::Pollable,
 };
 use futures::{future::poll_fn, task::noop_waker_ref};
-use http::{header::Entry, HeaderMap, HeaderName, HeaderValue, Response};
-use hyper::body::{Body, Bytes, Frame, Incoming};
+use http::{
+    header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderMap, HeaderName, HeaderValue, Response,
+};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt};
+use hyper::body::{Body, Bytes, Frame};
+use tracing::warn;
 use wasmtime::component::Resource;
 
 use super::State;
@@ -32,45 +41,89 @@ impl wasi::http::types::Host for State {
             .get(&err.rep())
             .ok_or_else(|| wasmtime::Error::msg("Unable to find error resource"))?;
 
+        // Upstream bodies that were cut off carry the code the guest should see.
+        if let Some(BodyFailure(code)) = val.get_ref().and_then(|err| err.downcast_ref()) {
+            return Ok(Some(code.clone()));
+        }
+
         Ok(Some(ErrorCode::InternalError(Some(format!("{}", val)))))
     }
 }
 
+fn parse_name(name: FieldKey) -> Result<HeaderName, HeaderError> {
+    // Pseudo-headers are derived from the request itself and must never be set by the guest.
+    if name.starts_with(':') {
+        return Err(HeaderError::Forbidden);
+    }
+
+    HeaderName::try_from(name).map_err(|_| HeaderError::InvalidSyntax)
+}
+
+/// Checks a name and its values. `from-list`, `set` and `append` all go through here, so they
+/// accept and refuse exactly the same fields.
+fn parse_field(
+    name: FieldKey,
+    values: Vec<FieldValue>,
+) -> Result<(HeaderName, Vec<HeaderValue>), HeaderError> {
+    let name = parse_name(name)?;
+    let values = values
+        .into_iter()
+        .map(|value| HeaderValue::try_from(value).map_err(|_| HeaderError::InvalidSyntax))
+        .collect::<Result<_, _>>()?;
+
+    Ok((name, values))
+}
+
+impl State {
+    /// Hands `headers` to the guest as a `fields` resource. Immutable ones refuse every edit with
+    /// `header-error.immutable`, as the headers of requests and responses do.
+    pub(crate) fn new_fields(&mut self, headers: HeaderMap, immutable: bool) -> Resource<Fields> {
+        let id = self.new_id();
+        self.fields.insert(id, (immutable, headers));
+        Resource::new_own(id)
+    }
+
+    fn mutable_fields(
+        &mut self,
+        fields: &Resource<Fields>,
+    ) -> wasmtime::Result<Result<&mut HeaderMap, HeaderError>> {
+        let (immutable, headers) = self
+            .fields
+            .get_mut(&fields.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
+
+        if *immutable {
+            return Ok(Err(HeaderError::Immutable));
+        }
+
+        Ok(Ok(headers))
+    }
+}
+
 impl wasi::http::types::HostFields for State {
     fn new(&mut self) -> wasmtime::Result<Resource<Fields>> {
-        let id = self.new_id();
-        self.fields.insert(id, (false, HeaderMap::new()));
-        Ok(Resource::new_own(id))
+        Ok(self.new_fields(HeaderMap::new(), false))
     }
 
+    /// Repeated names keep every value, in order. Nothing is added if any entry is refused.
     fn from_list(
         &mut self,
         entries: Vec<(FieldKey, FieldValue)>,
     ) -> wasmtime::Result<Result<Resource<Fields>, HeaderError>> {
-        let id = self.new_id();
-        self.fields.insert(id, (false, HeaderMap::new()));
-        let (_, resource) = self.fields.get_mut(&id).unwrap();
-
-        let headers = entries
-            .into_iter()
-            .map(|(k, v)| -> Result<(HeaderName, HeaderValue), HeaderError> {
-                Ok((
-                    HeaderName::try_from(k).map_err(|_| HeaderError::InvalidSyntax)?,
-                    HeaderValue::from_bytes(&v).map_err(|_| HeaderError::InvalidSyntax)?,
-                ))
-            })
-            .collect::<Result<Vec<_>, _>>();
+        let mut headers = HeaderMap::new();
 
-        let headers = match headers {
-            Ok(val) => val,
-            Err(err) => return Ok(Err(err)),
-        };
+        for (name, value) in entries {
+            let (name, values) = match parse_field(name, vec![value]) {
+                Ok(field) => field,
+                Err(err) => return Ok(Err(err)),
+            };
 
-        for (name, value) in headers {
-            resource.append(name, value);
+            for value in values {
+                headers.append(&name, value);
+            }
         }
 
-        Ok(Ok(Resource::new_own(id)))
+        Ok(Ok(self.new_fields(headers, false)))
     }
 
     fn get(
@@ -97,38 +150,21 @@ impl wasi::http::types::HostFields for State {
         name: FieldKey,
         value: Vec<FieldValue>,
     ) -> wasmtime::Result<Result<(), HeaderError>> {
-        let (immutable, resourse) = self
-            .fields
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
-
-        if *immutable {
-            return Ok(Err(HeaderError::Immutable));
-        }
-
-        let name = match HeaderName::try_from(name) {
-            Ok(val) => val,
-            Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
+        let headers = match self.mutable_fields(&self_)? {
+            Ok(headers) => headers,
+            Err(err) => return Ok(Err(err)),
         };
 
-        let mut vals = value.into_iter().map(|val| HeaderValue::try_from(val));
+        // Every value is checked up front, so a bad one leaves the existing values untouched.
+        let (name, values) = match parse_field(name, value) {
+            Ok(field) => field,
+            Err(err) => return Ok(Err(err)),
+        };
 
-        if let Some(val) = vals.next() {
-            let val = match val {
-                Ok(v) => v,
-                Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
-            };
-            resourse.insert(name.clone(), val);
-        } else {
-            resourse.remove(name.clone());
-        }
+        headers.remove(&name);
 
-        for val in vals {
-            let val = match val {
-                Ok(v) => v,
-                Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
-            };
-            resourse.append(name.clone(), val);
+        for value in values {
+            headers.append(&name, value);
         }
 
         Ok(Ok(()))
@@ -139,21 +175,18 @@ impl wasi::http::types::HostFields for State {
         self_: Resource<Fields>,
         name: FieldKey,
     ) -> wasmtime::Result<Result<(), HeaderError>> {
-        let (immutable, resource) = self
-            .fields
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
+        let headers = match self.mutable_fields(&self_)? {
+            Ok(headers) => headers,
+            Err(err) => return Ok(Err(err)),
+        };
 
-        if *immutable {
-            return Ok(Err(HeaderError::Immutable));
+        match parse_name(name) {
+            Ok(name) => {
+                headers.remove(&name);
+                Ok(Ok(()))
+            }
+            Err(err) => Ok(Err(err)),
         }
-
-        resource.remove(&match HeaderName::try_from(name) {
-            Ok(val) => val,
-            Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
-        });
-
-        Ok(Ok(()))
     }
 
     fn append(
@@ -162,30 +195,18 @@ impl wasi::http::types::HostFields for State {
         name: FieldKey,
         value: FieldValue,
     ) -> wasmtime::Result<Result<(), HeaderError>> {
-        let (immutable, resource) = self
-            .fields
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
-
-        if *immutable {
-            return Ok(Err(HeaderError::Immutable));
-        }
+        let headers = match self.mutable_fields(&self_)? {
+            Ok(headers) => headers,
+            Err(err) => return Ok(Err(err)),
+        };
 
-        let value = match HeaderValue::try_from(value) {
-            Ok(val) => val,
-            Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
+        let (name, values) = match parse_field(name, vec![value]) {
+            Ok(field) => field,
+            Err(err) => return Ok(Err(err)),
         };
 
-        match resource.entry(match HeaderName::try_from(name) {
-            Ok(val) => val,
-            Err(_) => return Ok(Err(HeaderError::InvalidSyntax)),
-        }) {
-            Entry::Occupied(mut entry) => {
-                entry.append(value);
-            }
-            Entry::Vacant(entry) => {
-                entry.insert(value);
-            }
+        for value in values {
+            headers.append(&name, value);
         }
 
         Ok(Ok(()))
@@ -200,24 +221,24 @@ impl wasi::http::types::HostFields for State {
             .get(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
 
+        // `HeaderMap` keeps the values of a repeated field in the order they were added and groups
+        // them under the first occurrence of the name. Only the relative order of values sharing
+        // a name is significant in HTTP, so that order survives `from-list`/`entries` round trips.
         Ok(resource
             .iter()
             .map(|(key, value)| (key.to_string(), value.as_bytes().to_vec()))
             .collect())
     }
 
+    /// The copy is mutable even when the original is not, like one built from its `entries`.
     fn clone(&mut self, self_: Resource<Fields>) -> wasmtime::Result<Resource<Fields>> {
-        let id = self.new_id();
-
-        let resource = self
+        let (_, headers) = self
             .fields
             .get(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?
-            .clone();
-
-        self.fields.insert(id, resource);
+            .ok_or_else(|| wasmtime::Error::msg("Could not find field"))?;
+        let headers = headers.clone();
 
-        Ok(Resource::new_own(id))
+        Ok(self.new_fields(headers, false))
     }
 
     fn drop(&mut self, rep: Resource<Fields>) -> wasmtime::Result<()> {
@@ -227,6 +248,46 @@ impl wasi::http::types::HostFields for State {
     }
 }
 
+pub fn method_to_wasi(method: &http::Method) -> Method {
+    if method == http::Method::GET {
+        Method::Get
+    } else if method == http::Method::HEAD {
+        Method::Head
+    } else if method == http::Method::POST {
+        Method::Post
+    } else if method == http::Method::PUT {
+        Method::Put
+    } else if method == http::Method::DELETE {
+        Method::Delete
+    } else if method == http::Method::CONNECT {
+        Method::Connect
+    } else if method == http::Method::OPTIONS {
+        Method::Options
+    } else if method == http::Method::TRACE {
+        Method::Trace
+    } else if method == http::Method::PATCH {
+        Method::Patch
+    } else {
+        Method::Other(method.to_string())
+    }
+}
+
+/// The standard method `method` spells, whatever its casing.
+fn standard_method(method: &http::Method) -> Option<http::Method> {
+    let upper = http::Method::from_bytes(method.as_str().to_ascii_uppercase().as_bytes()).ok()?;
+
+    (!matches!(method_to_wasi(&upper), Method::Other(_))).then_some(upper)
+}
+
+/// The method a guest sees for a request that arrived with `method`. Standard methods are matched
+/// whatever their casing, unless `preserve_case` keeps any other spelling as it arrived.
+fn incoming_method_to_wasi(method: &http::Method, preserve_case: bool) -> Method {
+    match standard_method(method) {
+        Some(standard) if !preserve_case => method_to_wasi(&standard),
+        _ => method_to_wasi(method),
+    }
+}
+
 impl wasi::http::types::HostIncomingRequest for State {
     fn method(&mut self, self_: Resource<IncomingRequest>) -> wasmtime::Result<Method> {
         let resource = self
@@ -234,29 +295,10 @@ impl wasi::http::types::HostIncomingRequest for State {
             .get(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find request"))?;
 
-        let method = resource.method();
-
-        if method == http::Method::GET {
-            Ok(Method::Get)
-        } else if method == http::Method::HEAD {
-            Ok(Method::Head)
-        } else if method == http::Method::POST {
-            Ok(Method::Post)
-        } else if method == http::Method::PUT {
-            Ok(Method::Put)
-        } else if method == http::Method::DELETE {
-            Ok(Method::Delete)
-        } else if method == http::Method::CONNECT {
-            Ok(Method::Connect)
-        } else if method == http::Method::OPTIONS {
-            Ok(Method::Options)
-        } else if method == http::Method::TRACE {
-            Ok(Method::Trace)
-        } else if method == http::Method::PATCH {
-            Ok(Method::Patch)
-        } else {
-            Ok(Method::Other(method.to_string()))
-        }
+        Ok(incoming_method_to_wasi(
+            resource.method(),
+            self.preserve_method_case,
+        ))
     }
 
     fn path_with_query(
@@ -297,27 +339,25 @@ impl wasi::http::types::HostIncomingRequest for State {
         Ok(resource.uri().authority().map(|val| val.to_string()))
     }
 
+    /// When the body's length is known but the client didn't send a `Content-Length`, as over
+    /// HTTP/2 or after spooling, the guest's copy gets one so it can size its reads. Bodies of
+    /// unknown length have none and must be read until they end.
     fn headers(&mut self, self_: Resource<IncomingRequest>) -> wasmtime::Result<Resource<Headers>> {
-        let id = self.new_id();
         let resource = self
             .requests
-            .get_mut(&self_.rep())
+            .get(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find request"))?;
 
-        self.fields.insert(
-            id,
-            (
-                true,
-                HeaderMap::from_iter(
-                    resource
-                        .headers()
-                        .iter()
-                        .map(|(key, val)| (key.to_owned(), val.to_owned())),
-                ),
-            ),
-        );
+        let mut headers = resource.headers().clone();
 
-        Ok(Resource::new_own(id))
+        if !headers.contains_key(CONTENT_LENGTH) && !headers.contains_key(TRANSFER_ENCODING) {
+            // Bodiless requests, such as most `GET`s, are left alone.
+            if let Some(len) = resource.body().size_hint().exact().filter(|len| *len > 0) {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+            }
+        }
+
+        Ok(self.new_fields(headers, true))
     }
 
     fn consume(
@@ -340,8 +380,11 @@ impl wasi::http::types::HostIncomingRequest for State {
             IncomingBodyWrapper {
                 incoming: resource.into_body(),
                 state: BodyState::New,
+                stream: StreamHandle::NotTaken,
                 trailers: None,
                 last_frame: None,
+                tee: None,
+                trailers_taken: false,
             },
         );
 
@@ -349,27 +392,310 @@ impl wasi::http::types::HostIncomingRequest for State {
     }
 
     fn drop(&mut self, rep: Resource<IncomingRequest>) -> wasmtime::Result<()> {
-        self.requests.remove(&rep.rep());
+        if let Some(req) = self.requests.remove(&rep.rep()) {
+            self.keep_unread(rep.rep(), req.into_body());
+        }
 
         Ok(())
     }
 }
 
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The body of a request handed to the guest, boxed so the host can also synthesize requests.
+pub type RequestBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// The body of a response leaving the runner, boxed so response hooks can wrap it.
+pub type ResponseBody = UnsyncBoxBody<Bytes, BoxError>;
+
 pub struct IncomingBodyWrapper {
-    pub incoming: Incoming,
+    pub incoming: RequestBody,
     pub state: BodyState,
+    pub stream: StreamHandle,
     pub trailers: Option<HeaderMap>,
-    pub last_frame: Option<Result<Frame<Bytes>, hyper::Error>>,
+    pub last_frame: Option<Result<Frame<Bytes>, BoxError>>,
+    pub tee: Option<Tee>,
+    /// Whether `future-trailers.get` has already answered with the trailers.
+    pub trailers_taken: bool,
 }
 
+/// How far the body itself has been read.
 #[derive(PartialEq)]
 pub enum BodyState {
+    /// Nothing has been read.
     New,
     Data,
+    /// The trailers arrived and are kept in `trailers` until the guest asks for them.
     Trailers,
+    /// The body ended.
     Consumed,
 }
 
+impl BodyState {
+    /// Whether the body has no data left to read.
+    pub fn ended(&self) -> bool {
+        matches!(self, BodyState::Trailers | BodyState::Consumed)
+    }
+}
+
+/// What happens to the part of a request body the guest leaves unread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnreadBody {
+    /// Read and discard it in the background, so an HTTP/1 connection can be reused.
+    #[default]
+    Drain,
+    /// Discard it and close the connection after the response.
+    Close,
+}
+
+impl FromStr for UnreadBody {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drain" => Ok(Self::Drain),
+            "close" => Ok(Self::Close),
+            _ => Err(format!("expected drain or close, got {s}")),
+        }
+    }
+}
+
+/// What happens to a response whose body is longer or shorter than its `Content-Length`, which
+/// would otherwise leave the client waiting for bytes that never come or misreading the next
+/// response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LengthMismatch {
+    /// Answer with a 500 in its place. A body still streaming when the head went out is cut off
+    /// short instead, which aborts the connection.
+    #[default]
+    Fail,
+    /// Send what was written, with the `Content-Length` corrected. A body still streaming when
+    /// the head went out is cut off at its declared length instead.
+    Correct,
+}
+
+impl FromStr for LengthMismatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "correct" => Ok(Self::Correct),
+            _ => Err(format!("expected fail or correct, got {s}")),
+        }
+    }
+}
+
+/// The `Content-Length` of a response whose body was still streaming when its head went out,
+/// counted against the bytes that follow. The head can't be changed by then, so a mismatch can
+/// only end the body early.
+pub struct DeclaredLength {
+    declared: u64,
+    sent: u64,
+    policy: LengthMismatch,
+    /// Set once a mismatch ended the body.
+    ended: bool,
+}
+
+impl DeclaredLength {
+    pub fn new(declared: u64, policy: LengthMismatch) -> Self {
+        Self {
+            declared,
+            sent: 0,
+            policy,
+            ended: false,
+        }
+    }
+
+    /// Counts the body against `declared` instead, for a response cut down before any of it
+    /// was sent.
+    pub fn redeclare(&mut self, declared: u64) {
+        self.declared = declared;
+    }
+
+    /// Passes `frame` on as long as the body keeps to its length. Bytes past it are never sent,
+    /// as the client would read them as the next response, and a body that falls short is left
+    /// short, so hyper aborts the connection.
+    fn check(
+        &mut self,
+        frame: Poll<Option<Result<Frame<VecDeque<u8>>, BoxError>>>,
+    ) -> Poll<Option<Result<Frame<VecDeque<u8>>, BoxError>>> {
+        let frame = match frame {
+            Poll::Ready(Some(Ok(frame))) => frame,
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => {
+                if self.sent < self.declared {
+                    self.mismatch(self.sent);
+                }
+
+                return Poll::Ready(None);
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut data = match frame.into_data() {
+            Ok(data) => data,
+            Err(_) if self.sent < self.declared => {
+                self.mismatch(self.sent);
+                return Poll::Ready(None);
+            }
+            Err(trailers) => return Poll::Ready(Some(Ok(trailers))),
+        };
+
+        let room = self.declared - self.sent;
+
+        if data.len() as u64 <= room {
+            self.sent += data.len() as u64;
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+
+        self.mismatch(self.sent + data.len() as u64);
+
+        match self.policy {
+            LengthMismatch::Fail => Poll::Ready(None),
+            LengthMismatch::Correct => {
+                data.truncate(room as usize);
+                self.sent = self.declared;
+
+                Poll::Ready(Some(Ok(Frame::data(data))))
+            }
+        }
+    }
+
+    /// Ends the body, `written` being as many bytes as are known to have been written.
+    fn mismatch(&mut self, written: u64) {
+        self.ended = true;
+
+        let declared = self.declared;
+
+        match self.policy {
+            LengthMismatch::Fail => warn!(
+                declared,
+                written, "cutting off a streamed body that misses its content-length"
+            ),
+            LengthMismatch::Correct => warn!(
+                declared,
+                written, "ending a streamed body at its content-length"
+            ),
+        }
+    }
+}
+
+/// Which request methods are passed to the guest. Others are answered with `405 Method Not
+/// Allowed` before it runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MethodPolicy {
+    #[default]
+    Any,
+    /// Only these methods.
+    Allow(Vec<http::Method>),
+    /// Every method but these.
+    Deny(Vec<http::Method>),
+}
+
+impl MethodPolicy {
+    /// Standard methods are matched whatever their casing, as the guest would see them, so
+    /// `trace` can't get past a denied `TRACE`. Extension methods are matched exactly.
+    pub fn allows(&self, method: &http::Method) -> bool {
+        let method = standard_method(method).unwrap_or_else(|| method.clone());
+
+        match self {
+            Self::Any => true,
+            Self::Allow(methods) => methods.contains(&method),
+            Self::Deny(methods) => !methods.contains(&method),
+        }
+    }
+
+    /// The `Allow` header of a `405`. A denylist can't name every method a client might try, so
+    /// it lists the standard ones it lets through.
+    pub fn allow_header(&self) -> HeaderValue {
+        let methods = match self {
+            Self::Allow(methods) => methods.clone(),
+            Self::Any | Self::Deny(_) => [
+                http::Method::GET,
+                http::Method::HEAD,
+                http::Method::POST,
+                http::Method::PUT,
+                http::Method::DELETE,
+                http::Method::CONNECT,
+                http::Method::OPTIONS,
+                http::Method::TRACE,
+                http::Method::PATCH,
+            ]
+            .into_iter()
+            .filter(|method| self.allows(method))
+            .collect(),
+        };
+
+        let methods = methods
+            .iter()
+            .map(http::Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        HeaderValue::from_str(&methods).expect("methods are tokens")
+    }
+}
+
+/// Reads `body` to the end in the background. Giving up after `limit` bytes drops the body,
+/// which makes hyper close the connection.
+pub fn drain(mut body: RequestBody, limit: usize) {
+    tokio::spawn(async move {
+        let mut read = 0;
+
+        while let Some(Ok(frame)) = body.frame().await {
+            if let Some(data) = frame.data_ref() {
+                read += data.len();
+
+                if read > limit {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// The lifecycle of the single input stream an incoming body hands out.
+#[derive(PartialEq)]
+pub enum StreamHandle {
+    NotTaken,
+    Open,
+    /// The stream was dropped, possibly before the body ended, so `finish` may be called.
+    Dropped,
+}
+
+impl IncomingBodyWrapper {
+    /// Skips the data the guest left unread until the trailers or the end of the body. An error
+    /// is kept in `last_frame` for the caller to report.
+    pub fn poll_trailers(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.state == BodyState::New {
+            self.state = BodyState::Data;
+        }
+
+        loop {
+            if self.state.ended() || matches!(self.last_frame, Some(Err(_))) {
+                return Poll::Ready(());
+            }
+
+            let frame = match self.last_frame.take() {
+                Some(frame) => Some(frame),
+                None => ready!(Pin::new(&mut self.incoming).poll_frame(cx)),
+            };
+
+            match frame {
+                Some(Ok(frame)) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        self.trailers = Some(trailers);
+                        self.state = BodyState::Trailers;
+                    }
+                }
+                Some(Err(err)) => self.last_frame = Some(Err(err)),
+                None => self.state = BodyState::Consumed,
+            }
+        }
+    }
+}
+
 impl wasi::http::types::HostIncomingBody for State {
     fn stream(
         &mut self,
@@ -380,13 +706,16 @@ impl wasi::http::types::HostIncomingBody for State {
             .get_mut(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
 
-        if resource.state == BodyState::New {
-            resource.state = BodyState::Data;
-
-            Ok(Ok(Resource::new_own(self_.rep())))
-        } else {
-            Ok(Err(()))
+        // The body has only one stream, even once it has been dropped. `Err(())` is the only
+        // error the interface allows for this.
+        if resource.stream != StreamHandle::NotTaken {
+            return Ok(Err(()));
         }
+
+        resource.stream = StreamHandle::Open;
+        resource.state = BodyState::Data;
+
+        Ok(Ok(Resource::new_own(self_.rep())))
     }
 
     fn finish(
@@ -398,15 +727,21 @@ impl wasi::http::types::HostIncomingBody for State {
             .get_mut(&this.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
 
-        if resource.state != BodyState::Trailers {
-            return Err(wasmtime::Error::msg("The body is not ready for trailers"));
+        if resource.stream == StreamHandle::Open {
+            return Err(wasmtime::Error::msg(
+                "The body's stream must be dropped before the body is finished",
+            ));
         }
 
         Ok(Resource::new_own(this.rep()))
     }
 
     fn drop(&mut self, rep: Resource<IncomingBody>) -> wasmtime::Result<()> {
-        self.incoming.remove(&rep.rep());
+        if let Some(body) = self.incoming.remove(&rep.rep()) {
+            if !body.state.ended() {
+                self.keep_unread(rep.rep(), body.incoming);
+            }
+        }
 
         Ok(())
     }
@@ -419,9 +754,58 @@ pub struct Outgoing {
     pub done: bool,
     pub new: bool,
     pub thread: Option<Thread>,
+    /// A body produced by the host instead of the guest, forwarded as is.
+    pub source: Option<UnsyncBoxBody<Bytes, BoxError>>,
+    /// Called once the whole body has been sent.
+    pub on_end: Option<Box<dyn FnOnce() + Send>>,
+    /// Set when the body was still streaming as its head went out with a `Content-Length`.
+    pub length: Option<DeclaredLength>,
+}
+
+impl Default for Outgoing {
+    fn default() -> Self {
+        Self {
+            buf: VecDeque::new(),
+            waker: None,
+            trailers: None,
+            done: false,
+            new: true,
+            thread: None,
+            source: None,
+            on_end: None,
+            length: None,
+        }
+    }
 }
 
 impl Outgoing {
+    pub fn into_response_body(self) -> ResponseBody {
+        self.into_body()
+    }
+
+    pub fn into_body(self) -> UnsyncBoxBody<Bytes, BoxError> {
+        self.map_frame(|frame| frame.map_data(|data| Bytes::from(Vec::from(data))))
+            .boxed_unsync()
+    }
+
+    pub fn full(data: Vec<u8>) -> Self {
+        Self {
+            buf: VecDeque::from(data),
+            done: true,
+            new: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn stream(source: UnsyncBoxBody<Bytes, BoxError>) -> Self {
+        Self {
+            done: true,
+            new: false,
+            source: Some(source),
+            ..Default::default()
+        }
+    }
+
     pub fn wake(&self) {
         if let Some(waker) = &self.waker {
             waker.wake_by_ref();
@@ -432,7 +816,7 @@ impl Outgoing {
 impl Body for Outgoing {
     type Data = VecDeque<u8>;
 
-    type Error = Infallible;
+    type Error = BoxError;
 
     fn poll_frame(
         self: Pin<&mut Self>,
@@ -440,25 +824,82 @@ impl Body for Outgoing {
     ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
         let data = Pin::into_inner(self);
 
-        if let Some(thread) = data.thread.take() {
+        let ended = data.length.as_ref().is_some_and(|length| length.ended);
+        let frame = if ended {
+            Poll::Ready(None)
+        } else {
+            data.next_frame(cx)
+        };
+        let frame = match &mut data.length {
+            Some(length) if !ended => length.check(frame),
+            _ => frame,
+        };
+
+        // hyper stops polling once the body says it has ended, so this may come before `None`.
+        if matches!(frame, Poll::Ready(None)) || data.is_end_stream() {
+            if let Some(on_end) = data.on_end.take() {
+                on_end();
+            }
+        }
+
+        frame
+    }
+
+    /// Lets h2 end the stream with the last frame, and hyper skip polling a finished body.
+    fn is_end_stream(&self) -> bool {
+        if self.length.as_ref().is_some_and(|length| length.ended) {
+            return true;
+        }
+
+        match &self.source {
+            Some(source) => source.is_end_stream(),
+            None => self.done && self.buf.is_empty() && self.trailers.is_none(),
+        }
+    }
+}
+
+impl Outgoing {
+    fn next_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<VecDeque<u8>>, BoxError>>> {
+        if let Some(thread) = self.thread.take() {
             thread.unpark();
         }
 
-        if !data.buf.is_empty() {
-            return Poll::Ready(Some(Ok(Frame::data(std::mem::take(&mut data.buf)))));
+        if let Some(source) = &mut self.source {
+            return match ready!(Pin::new(source).poll_frame(cx)) {
+                Some(Ok(frame)) => Poll::Ready(Some(Ok(
+                    frame.map_data(|bytes| VecDeque::from(Vec::from(bytes)))
+                ))),
+                // An error makes hyper abort the connection, or reset the stream over HTTP/2,
+                // which is all a client can be told once the head is sent.
+                Some(Err(err)) => {
+                    self.source = None;
+                    Poll::Ready(Some(Err(err)))
+                }
+                None => {
+                    self.source = None;
+                    Poll::Ready(None)
+                }
+            };
+        }
+
+        if !self.buf.is_empty() {
+            return Poll::Ready(Some(Ok(Frame::data(std::mem::take(&mut self.buf)))));
         }
 
-        if let Some(trailers) = data.trailers.take() {
-            data.done = true;
+        if let Some(trailers) = self.trailers.take() {
+            self.done = true;
 
             return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
         }
 
-        if data.done {
+        if self.done {
             return Poll::Ready(None);
         }
 
-        data.waker = Some(cx.waker().clone());
+        self.waker = Some(cx.waker().clone());
 
         Poll::Pending
     }
@@ -469,19 +910,7 @@ impl wasi::http::types::HostOutgoingBody for State {
         &mut self,
         self_: Resource<OutgoingBody>,
     ) -> wasmtime::Result<Result<Resource<OutputStream>, ()>> {
-        let resource = self
-            .responses
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?
-            .body_mut();
-
-        if !resource.new {
-            Ok(Err(()))
-        } else {
-            resource.new = false;
-
-            Ok(Ok(Resource::new_own(self_.rep())))
-        }
+        self.body_write(self_.rep())
     }
 
     fn finish(
@@ -489,26 +918,47 @@ impl wasi::http::types::HostOutgoingBody for State {
         this: Resource<OutgoingBody>,
         trailers: Option<Resource<Trailers>>,
     ) -> wasmtime::Result<Result<(), ErrorCode>> {
-        let resource = self
-            .responses
-            .get_mut(&this.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?
-            .body_mut();
+        if self.is_request_body(this.rep()) {
+            return self.request_body_finish(this.rep(), trailers);
+        }
 
-        resource.done = true;
-        if let Some(trailers) = trailers {
-            resource.trailers = Some(
+        let pipe = self
+            .response_bodies
+            .get(&this.rep())
+            .cloned()
+            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
+
+        let trailers = trailers
+            .map(|trailers| {
                 self.fields
                     .remove(&trailers.rep())
-                    .ok_or_else(|| wasmtime::Error::msg("Could not find trailers"))?
-                    .1,
-            );
-        }
+                    .map(|(_, trailers)| trailers)
+                    .ok_or_else(|| wasmtime::Error::msg("Could not find trailers"))
+            })
+            .transpose()?;
+
+        let mut pipe = pipe.lock().unwrap();
+        pipe.body.done = true;
+        pipe.body.trailers = trailers;
+        pipe.body.wake();
 
         Ok(Ok(()))
     }
 
-    fn drop(&mut self, _rep: Resource<OutgoingBody>) -> wasmtime::Result<()> {
+    /// A body dropped without `finish` can't be sent whole, so it is cut off.
+    fn drop(&mut self, rep: Resource<OutgoingBody>) -> wasmtime::Result<()> {
+        if self.is_request_body(rep.rep()) {
+            return self.request_body_drop(rep.rep());
+        }
+
+        if let Some(pipe) = self.response_bodies.get(&rep.rep()) {
+            let mut pipe = pipe.lock().unwrap();
+
+            if !pipe.body.done {
+                pipe.abort();
+            }
+        }
+
         Ok(())
     }
 }
@@ -524,31 +974,9 @@ impl PollableIndividual for TrailerPollable {
             .get_mut(&self.id)
             .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
 
-        let Poll::Ready(res) =
-            Pin::new(&mut resource.incoming).poll_frame(&mut Context::from_waker(noop_waker_ref()))
-        else {
-            return Ok(false);
-        };
-
-        if let Some(frame) = res {
-            let frame = match frame {
-                Ok(frame) => frame,
-                Err(_) => {
-                    return Ok(true);
-                }
-            };
-
-            if frame.is_data() {
-                return Ok(false);
-            } else {
-                let trailers = frame.into_trailers().unwrap();
-                resource.trailers = Some(trailers);
-                return Ok(true);
-            }
-        } else {
-            resource.state = BodyState::Consumed;
-            Ok(true)
-        }
+        Ok(resource
+            .poll_trailers(&mut Context::from_waker(noop_waker_ref()))
+            .is_ready())
     }
 
     fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
@@ -557,29 +985,9 @@ impl PollableIndividual for TrailerPollable {
             .get_mut(&self.id)
             .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
 
-        loop {
-            let res = futures::executor::block_on(poll_fn(|cx| {
-                Pin::new(&mut resource.incoming).poll_frame(cx)
-            }));
-
-            if let Some(frame) = res {
-                let frame = match frame {
-                    Ok(frame) => frame,
-                    Err(_) => {
-                        return Ok(());
-                    }
-                };
+        futures::executor::block_on(poll_fn(|cx| resource.poll_trailers(cx)));
 
-                if frame.is_trailers() {
-                    let trailers = frame.into_trailers().unwrap();
-                    resource.trailers = Some(trailers);
-                    return Ok(());
-                }
-            } else {
-                resource.state = BodyState::Consumed;
-                return Ok(());
-            }
-        }
+        Ok(())
     }
 }
 
@@ -600,46 +1008,39 @@ impl wasi::http::types::HostFutureTrailers for State {
         &mut self,
         self_: Resource<FutureTrailers>,
     ) -> wasmtime::Result<Option<Result<Option<Resource<Trailers>>, ErrorCode>>> {
-        let id = self.new_id();
-
         let resource = self
             .incoming
             .get_mut(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find the body"))?;
 
-        if let Some(trailers) = resource.trailers.take() {
-            self.fields.insert(id, (true, trailers));
-
-            return Ok(Some(Ok(Some(Resource::new_own(id)))));
+        // `get` hands the trailers out once. The future is spent after that, which the guest is
+        // told rather than trapped for.
+        if resource.trailers_taken {
+            return Ok(Some(Err(ErrorCode::InternalError(Some(
+                "trailers already taken".into(),
+            )))));
         }
 
-        let Poll::Ready(res) =
-            Pin::new(&mut resource.incoming).poll_frame(&mut Context::from_waker(noop_waker_ref()))
-        else {
+        if resource
+            .poll_trailers(&mut Context::from_waker(noop_waker_ref()))
+            .is_pending()
+        {
             return Ok(None);
-        };
+        }
 
-        if let Some(frame) = res {
-            let frame = match frame {
-                Ok(frame) => frame,
-                Err(err) => {
-                    return Ok(Some(Err(ErrorCode::InternalError(Some(format!(
-                        "{}",
-                        err
-                    ))))));
-                }
-            };
+        resource.trailers_taken = true;
 
-            if frame.is_data() {
-                return Ok(None);
-            } else {
-                let trailers = frame.into_trailers().unwrap();
-                self.fields.insert(id, (true, trailers));
-                return Ok(Some(Ok(Some(Resource::new_own(id)))));
-            }
-        } else {
+        if let Some(Err(err)) = resource.last_frame.take() {
             resource.state = BodyState::Consumed;
-            Ok(Some(Ok(None)))
+
+            return Ok(Some(Err(ErrorCode::InternalError(Some(err.to_string())))));
+        }
+
+        resource.state = BodyState::Consumed;
+
+        match resource.trailers.take() {
+            Some(trailers) => Ok(Some(Ok(Some(self.new_fields(trailers, true))))),
+            None => Ok(Some(Ok(None))),
         }
     }
 
@@ -652,14 +1053,7 @@ impl wasi::http::types::HostOutgoingResponse for State {
     fn new(&mut self, headers: Resource<Headers>) -> wasmtime::Result<Resource<OutgoingResponse>> {
         let id = self.new_id();
 
-        let mut response = Response::new(Outgoing {
-            buf: VecDeque::new(),
-            waker: None,
-            trailers: None,
-            done: false,
-            new: true,
-            thread: None,
-        });
+        let mut response = Response::new(());
 
         let mut headers = self
             .fields
@@ -669,6 +1063,8 @@ impl wasi::http::types::HostOutgoingResponse for State {
         std::mem::swap(response.headers_mut(), &mut headers.1);
 
         self.responses.insert(id, response);
+        self.response_bodies
+            .insert(id, Pipe::shared(self.max_response_buffer_bytes));
 
         Ok(Resource::new_own(id))
     }
@@ -706,15 +1102,14 @@ impl wasi::http::types::HostOutgoingResponse for State {
         &mut self,
         self_: Resource<OutgoingResponse>,
     ) -> wasmtime::Result<Resource<Headers>> {
-        let id = self.new_id();
-        let resource = self
+        let headers = self
             .responses
             .get(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
-
-        self.fields.insert(id, (true, resource.headers().clone()));
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?
+            .headers()
+            .clone();
 
-        Ok(Resource::new_own(id))
+        Ok(self.new_fields(headers, true))
     }
 
     fn body(
@@ -731,23 +1126,77 @@ impl wasi::http::types::HostOutgoingResponse for State {
     }
 }
 
+impl State {
+    /// Puts a response the guest set back together with its body. A body the guest has not
+    /// finished is sent as it is written if the response can be handed off now, and otherwise
+    /// left for it to go on writing and taken once it returns. `None` once handed off.
+    fn take_response(&mut self, id: u32) -> wasmtime::Result<Option<Response<Outgoing>>> {
+        let head = self
+            .responses
+            .remove(&id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+        let pipe = self
+            .response_bodies
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
+        let mut body = pipe.lock().unwrap();
+
+        if body.body.done && !body.aborted {
+            // Whatever is written from now on finds the body ended.
+            let body = std::mem::replace(&mut body.body, Outgoing::full(Vec::new()));
+
+            return Ok(Some(head.map(|()| body)));
+        }
+
+        // An aborted body is left for the guest's return to turn into an error response.
+        let handoff = (!body.aborted).then(|| self.handoff.take()).flatten();
+
+        let Some(handoff) = handoff else {
+            drop(body);
+            self.response_pipe = Some(pipe);
+
+            return Ok(Some(head.map(|()| Outgoing::default())));
+        };
+
+        body.streaming = true;
+        drop(body);
+        self.response_pipe = Some(pipe.clone());
+
+        // If the request was given up on, the body is dropped with the response, which closes it
+        // and fails the guest's next write.
+        let _ = handoff.send(head.map(|()| Outgoing::stream(PipeBody(pipe).boxed_unsync())));
+
+        Ok(None)
+    }
+}
+
 impl wasi::http::types::HostResponseOutparam for State {
     fn set(
         &mut self,
         param: Resource<ResponseOutparam>,
         response: Result<Resource<OutgoingResponse>, ErrorCode>,
     ) -> wasmtime::Result<()> {
-        let res = response.unwrap().rep();
+        let response = match response {
+            Ok(res) => match self.take_response(res.rep())? {
+                Some(res) => res,
+                None => return Ok(()),
+            },
+            Err(code) => {
+                warn!(?code, "guest responded with an error");
+                crate::error_response(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "guest-error",
+                    format!("The component responded with an error: {code:?}"),
+                )
+            }
+        };
+
         let resource = self
             .full_responses
             .get_mut(&param.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find full response"))?;
 
-        let response = self
-            .responses
-            .remove(&res)
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
-
         *resource = Some(response);
 
         Ok(())
@@ -760,173 +1209,733 @@ impl wasi::http::types::HostResponseOutparam for State {
     }
 }
 
-impl wasi::http::types::HostRequestOptions for State {
-    fn new(&mut self) -> wasmtime::Result<Resource<RequestOptions>> {
-        unimplemented!();
-    }
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
 
-    fn connect_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-    ) -> wasmtime::Result<Option<Duration>> {
-        unimplemented!();
-    }
+    use proptest::{prelude::*, sample::Index};
 
-    fn set_connect_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-        ms: Option<Duration>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!();
-    }
+    use crate::{io::BUF_LIMIT, wasi::http::types::HostFields};
 
-    fn first_byte_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-    ) -> wasmtime::Result<Option<Duration>> {
-        unimplemented!();
-    }
+    use super::*;
 
-    fn set_first_byte_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-        ms: Option<Duration>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!();
+    /// Another handle on `fields`, as the guest would pass when borrowing it.
+    fn borrow(fields: &Resource<Fields>) -> Resource<Fields> {
+        Resource::new_borrow(fields.rep())
     }
 
-    fn between_bytes_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-    ) -> wasmtime::Result<Option<Duration>> {
-        unimplemented!();
-    }
+    #[test]
+    fn pseudo_headers_are_forbidden() {
+        for name in [":path", ":method", ":authority", ":scheme"] {
+            assert!(
+                matches!(parse_name(name.to_owned()), Err(HeaderError::Forbidden)),
+                "{name}"
+            );
+        }
 
-    fn set_between_bytes_timeout_ms(
-        &mut self,
-        self_: Resource<RequestOptions>,
-        ms: Option<Duration>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!();
+        let mut state = State::default();
+        let fields = HostFields::new(&mut state).unwrap();
+
+        assert!(matches!(
+            HostFields::set(
+                &mut state,
+                borrow(&fields),
+                ":path".into(),
+                vec![b"/".to_vec()]
+            ),
+            Ok(Err(HeaderError::Forbidden))
+        ));
+        assert!(matches!(
+            HostFields::append(&mut state, borrow(&fields), ":path".into(), b"/".to_vec()),
+            Ok(Err(HeaderError::Forbidden))
+        ));
+        assert!(matches!(
+            HostFields::from_list(&mut state, vec![(":path".into(), b"/".to_vec())]),
+            Ok(Err(HeaderError::Forbidden))
+        ));
+
+        // Nothing reached the fields, so nothing reaches the wire.
+        assert!(HostFields::entries(&mut state, fields).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeated_fields_keep_their_order() {
+        let mut state = State::default();
+        let entry = |name: &str, value: &str| (name.to_owned(), value.as_bytes().to_vec());
+
+        let fields = HostFields::from_list(
+            &mut state,
+            vec![
+                entry("x-hop", "first"),
+                entry("via", "1.1 a"),
+                entry("x-hop", "second"),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+        HostFields::append(
+            &mut state,
+            borrow(&fields),
+            "x-hop".into(),
+            b"third".to_vec(),
+        )
+        .unwrap()
+        .unwrap();
+        HostFields::append(&mut state, borrow(&fields), "via".into(), b"1.1 b".to_vec())
+            .unwrap()
+            .unwrap();
+
+        let expected = vec![
+            entry("x-hop", "first"),
+            entry("x-hop", "second"),
+            entry("x-hop", "third"),
+            entry("via", "1.1 a"),
+            entry("via", "1.1 b"),
+        ];
+        let entries = HostFields::entries(&mut state, borrow(&fields)).unwrap();
+        assert_eq!(entries, expected);
+
+        // `get` and a copy made from the entries see the same order.
+        assert_eq!(
+            HostFields::get(&mut state, borrow(&fields), "x-hop".into()).unwrap(),
+            [b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]
+        );
+
+        let copy = HostFields::from_list(&mut state, entries).unwrap().unwrap();
+        assert_eq!(HostFields::entries(&mut state, copy).unwrap(), expected);
+
+        // `set` replaces every value, keeping the order it was given.
+        HostFields::set(
+            &mut state,
+            borrow(&fields),
+            "x-hop".into(),
+            vec![b"only".to_vec(), b"also".to_vec()],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            HostFields::get(&mut state, fields, "x-hop".into()).unwrap(),
+            [b"only".to_vec(), b"also".to_vec()]
+        );
     }
 
-    fn drop(&mut self, rep: Resource<RequestOptions>) -> wasmtime::Result<()> {
-        unimplemented!();
+    /// What a `fields` resource should hold: every entry, in the order it was added.
+    #[derive(Clone, Debug, Default)]
+    struct Model {
+        immutable: bool,
+        entries: Vec<(String, Vec<u8>)>,
     }
-}
 
-impl wasi::http::types::HostOutgoingRequest for State {
-    fn new(&mut self, headers: Resource<Headers>) -> wasmtime::Result<Resource<OutgoingRequest>> {
-        unimplemented!()
+    impl Model {
+        /// Names are stored lower case, as `HeaderName` keeps them.
+        fn name(name: &str) -> Result<String, &'static str> {
+            const TCHARS: &[u8] = b"!#$%&'*+-.^_`|~";
+
+            if name.starts_with(':') {
+                return Err("forbidden");
+            }
+
+            if name.is_empty()
+                || !name
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || TCHARS.contains(&byte))
+            {
+                return Err("invalid-syntax");
+            }
+
+            Ok(name.to_ascii_lowercase())
+        }
+
+        fn value(value: &[u8]) -> Result<(), &'static str> {
+            if value
+                .iter()
+                .all(|&byte| byte == b'\t' || (byte >= 0x20 && byte != 0x7f))
+            {
+                Ok(())
+            } else {
+                Err("invalid-syntax")
+            }
+        }
+
+        fn from_list(entries: &[(String, Vec<u8>)]) -> Result<Self, &'static str> {
+            let mut model = Self::default();
+
+            for (name, value) in entries {
+                let name = Self::name(name)?;
+                Self::value(value)?;
+                model.entries.push((name, value.clone()));
+            }
+
+            Ok(model)
+        }
+
+        fn mutable(&mut self) -> Result<&mut Vec<(String, Vec<u8>)>, &'static str> {
+            if self.immutable {
+                return Err("immutable");
+            }
+
+            Ok(&mut self.entries)
+        }
+
+        fn get(&self, name: &str) -> Vec<Vec<u8>> {
+            let Ok(name) = Self::name(name) else {
+                return Vec::new();
+            };
+
+            self.entries
+                .iter()
+                .filter(|(key, _)| *key == name)
+                .map(|(_, value)| value.clone())
+                .collect()
+        }
+
+        fn set(&mut self, name: &str, values: &[Vec<u8>]) -> Result<(), &'static str> {
+            let entries = self.mutable()?;
+            let name = Self::name(name)?;
+            values.iter().try_for_each(|value| Self::value(value))?;
+
+            entries.retain(|(key, _)| *key != name);
+            entries.extend(values.iter().map(|value| (name.clone(), value.clone())));
+            Ok(())
+        }
+
+        fn delete(&mut self, name: &str) -> Result<(), &'static str> {
+            let entries = self.mutable()?;
+            let name = Self::name(name)?;
+
+            entries.retain(|(key, _)| *key != name);
+            Ok(())
+        }
+
+        fn append(&mut self, name: &str, value: &[u8]) -> Result<(), &'static str> {
+            let entries = self.mutable()?;
+            let name = Self::name(name)?;
+            Self::value(value)?;
+
+            entries.push((name, value.to_vec()));
+            Ok(())
+        }
+
+        /// Copies are mutable, whatever they were copied from.
+        fn copy(&self) -> Self {
+            Self {
+                immutable: false,
+                entries: self.entries.clone(),
+            }
+        }
     }
 
-    fn body(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-    ) -> wasmtime::Result<Result<Resource<OutgoingBody>, ()>> {
-        unimplemented!()
+    /// Checks the host's `entries` against the model's as far as `HeaderMap` keeps their order.
+    /// It lists the values of a name together and in the order they were added, but not the names
+    /// themselves in insertion order: a value is grouped under the first occurrence of its name,
+    /// and removing a name can move another into its place. So the host must keep each name's
+    /// values together, and only the order within a name is compared.
+    fn same_entries(host: &[(String, Vec<u8>)], model: &Model) -> Result<(), TestCaseError> {
+        let mut seen = HashSet::new();
+        let together = host
+            .chunk_by(|(a, _), (b, _)| a == b)
+            .all(|run| seen.insert(&run[0].0));
+        prop_assert!(together, "the values of a name are split up in {:?}", host);
+
+        prop_assert_eq!(by_name(host), by_name(&model.entries));
+
+        Ok(())
     }
 
-    fn method(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Method> {
-        unimplemented!()
+    fn by_name(entries: &[(String, Vec<u8>)]) -> BTreeMap<&str, Vec<&[u8]>> {
+        let mut names = BTreeMap::<_, Vec<_>>::new();
+
+        for (name, value) in entries {
+            names
+                .entry(name.as_str())
+                .or_default()
+                .push(value.as_slice());
+        }
+
+        names
     }
 
-    fn set_method(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-        method: Method,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!()
+    fn error_kind(err: HeaderError) -> &'static str {
+        match err {
+            HeaderError::InvalidSyntax => "invalid-syntax",
+            HeaderError::Forbidden => "forbidden",
+            HeaderError::Immutable => "immutable",
+        }
     }
 
-    fn path_with_query(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-    ) -> wasmtime::Result<Option<String>> {
-        unimplemented!()
+    #[derive(Clone, Debug)]
+    enum Op {
+        New,
+        FromList(Vec<(String, Vec<u8>)>),
+        /// The headers of a request or response, which the guest may not edit.
+        Immutable(Vec<(String, Vec<u8>)>),
+        Get(Index, String),
+        Set(Index, String, Vec<Vec<u8>>),
+        Delete(Index, String),
+        Append(Index, String, Vec<u8>),
+        Entries(Index),
+        Clone(Index),
+        Drop(Index),
     }
 
-    fn set_path_with_query(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-        path_with_query: Option<String>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!()
+    /// Valid names, one differing only in case, and ones refused for each reason.
+    fn any_name() -> impl Strategy<Value = String> {
+        prop::sample::select(vec!["x-a", "x-b", "via", "X-A", ":path", "bad name", ""])
+            .prop_map(str::to_owned)
     }
 
-    fn scheme(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Option<Scheme>> {
-        unimplemented!()
+    fn any_value() -> impl Strategy<Value = Vec<u8>> {
+        prop::sample::select(vec!["1", "two", "", "bad\nvalue"])
+            .prop_map(|value| value.as_bytes().to_vec())
     }
 
-    fn set_scheme(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-        scheme: Option<Scheme>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!()
+    fn valid_entries() -> impl Strategy<Value = Vec<(String, Vec<u8>)>> {
+        let name = prop::sample::select(vec!["x-a", "x-b", "via"]).prop_map(str::to_owned);
+        let value =
+            prop::sample::select(vec!["1", "two", ""]).prop_map(|value| value.as_bytes().to_vec());
+
+        prop::collection::vec((name, value), 0..4)
     }
 
-    fn authority(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Option<String>> {
-        unimplemented!()
+    fn any_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            Just(Op::New),
+            prop::collection::vec((any_name(), any_value()), 0..4).prop_map(Op::FromList),
+            valid_entries().prop_map(Op::Immutable),
+            (any::<Index>(), any_name()).prop_map(|(at, name)| Op::Get(at, name)),
+            (
+                any::<Index>(),
+                any_name(),
+                prop::collection::vec(any_value(), 0..3)
+            )
+                .prop_map(|(at, name, values)| Op::Set(at, name, values)),
+            (any::<Index>(), any_name()).prop_map(|(at, name)| Op::Delete(at, name)),
+            (any::<Index>(), any_name(), any_value())
+                .prop_map(|(at, name, value)| Op::Append(at, name, value)),
+            any::<Index>().prop_map(Op::Entries),
+            any::<Index>().prop_map(Op::Clone),
+            any::<Index>().prop_map(Op::Drop),
+        ]
+    }
+
+    proptest! {
+        /// Runs the same operations on the host and on the model, which must agree on every
+        /// result and on the contents of every live resource after each step.
+        #[test]
+        fn fields_behave_like_a_list_of_entries(ops in prop::collection::vec(any_op(), 1..40)) {
+            let mut state = State::default();
+            let mut live: Vec<(Resource<Fields>, Model)> = Vec::new();
+
+            for op in ops {
+                match op {
+                    Op::New => live.push((HostFields::new(&mut state).unwrap(), Model::default())),
+                    Op::FromList(entries) => {
+                        let model = Model::from_list(&entries);
+                        let host = HostFields::from_list(&mut state, entries)
+                            .unwrap()
+                            .map_err(error_kind);
+
+                        prop_assert_eq!(host.as_ref().err(), model.as_ref().err());
+
+                        if let (Ok(fields), Ok(model)) = (host, model) {
+                            live.push((fields, model));
+                        }
+                    }
+                    Op::Immutable(entries) => {
+                        let mut headers = HeaderMap::new();
+
+                        for (name, value) in &entries {
+                            headers.append(
+                                HeaderName::try_from(name.as_str()).unwrap(),
+                                HeaderValue::try_from(value.clone()).unwrap(),
+                            );
+                        }
+
+                        let model = Model {
+                            immutable: true,
+                            ..Model::from_list(&entries).unwrap()
+                        };
+                        live.push((state.new_fields(headers, true), model));
+                    }
+                    Op::Get(at, name) if !live.is_empty() => {
+                        let (fields, model) = &live[at.index(live.len())];
+                        let values = HostFields::get(&mut state, borrow(fields), name.clone());
+
+                        prop_assert_eq!(values.unwrap(), model.get(&name));
+                    }
+                    Op::Set(at, name, values) if !live.is_empty() => {
+                        let len = live.len();
+                        let (fields, model) = &mut live[at.index(len)];
+                        let host = HostFields::set(
+                            &mut state,
+                            borrow(fields),
+                            name.clone(),
+                            values.clone(),
+                        );
+
+                        prop_assert_eq!(
+                            host.unwrap().map_err(error_kind),
+                            model.set(&name, &values)
+                        );
+                    }
+                    Op::Delete(at, name) if !live.is_empty() => {
+                        let len = live.len();
+                        let (fields, model) = &mut live[at.index(len)];
+                        let host = HostFields::delete(&mut state, borrow(fields), name.clone());
+
+                        prop_assert_eq!(host.unwrap().map_err(error_kind), model.delete(&name));
+                    }
+                    Op::Append(at, name, value) if !live.is_empty() => {
+                        let len = live.len();
+                        let (fields, model) = &mut live[at.index(len)];
+                        let host = HostFields::append(
+                            &mut state,
+                            borrow(fields),
+                            name.clone(),
+                            value.clone(),
+                        );
+
+                        prop_assert_eq!(
+                            host.unwrap().map_err(error_kind),
+                            model.append(&name, &value)
+                        );
+                    }
+                    Op::Entries(at) if !live.is_empty() => {
+                        let (fields, model) = &live[at.index(live.len())];
+                        let entries = HostFields::entries(&mut state, borrow(fields)).unwrap();
+
+                        same_entries(&entries, model)?;
+                    }
+                    Op::Clone(at) if !live.is_empty() => {
+                        let (fields, model) = &live[at.index(live.len())];
+                        let copy = HostFields::clone(&mut state, borrow(fields)).unwrap();
+                        let model = model.copy();
+
+                        live.push((copy, model));
+                    }
+                    Op::Drop(at) if !live.is_empty() => {
+                        let (fields, _) = live.remove(at.index(live.len()));
+                        let rep = fields.rep();
+
+                        HostFields::drop(&mut state, fields).unwrap();
+                        prop_assert!(!state.fields.contains_key(&rep));
+                    }
+                    // There is nothing to operate on yet.
+                    _ => {}
+                }
+
+                // Edits reach only the resource they were made on, not its copies or originals.
+                for (fields, model) in &live {
+                    let entries = HostFields::entries(&mut state, borrow(fields)).unwrap();
+
+                    same_entries(&entries, model)?;
+                }
+            }
+        }
     }
 
-    fn set_authority(
-        &mut self,
-        self_: Resource<OutgoingRequest>,
-        authority: Option<String>,
-    ) -> wasmtime::Result<Result<(), ()>> {
-        unimplemented!()
+    #[test]
+    fn method_policies_match_standard_methods_in_any_case() {
+        let method = |name: &str| ::http::Method::from_bytes(name.as_bytes()).unwrap();
+
+        let deny = MethodPolicy::Deny(vec![::http::Method::TRACE]);
+        assert!(!deny.allows(&method("TRACE")));
+        assert!(!deny.allows(&method("trace")));
+        assert!(!deny.allows(&method("Trace")));
+        assert!(deny.allows(&method("get")));
+
+        let allow = MethodPolicy::Allow(vec![::http::Method::GET]);
+        assert!(allow.allows(&method("get")));
+        assert!(!allow.allows(&method("post")));
+
+        // Only standard methods have a casing to normalize to.
+        let deny = MethodPolicy::Deny(vec![method("PURGE")]);
+        assert!(!deny.allows(&method("PURGE")));
+        assert!(deny.allows(&method("purge")));
     }
 
-    fn headers(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Resource<Headers>> {
-        unimplemented!()
+    #[test]
+    fn method_not_allowed_lists_the_methods_let_through() {
+        let allow = MethodPolicy::Allow(vec![::http::Method::GET, ::http::Method::HEAD]);
+        assert_eq!(allow.allow_header(), "GET, HEAD");
+
+        // A denylist names the standard methods it doesn't deny.
+        let deny = MethodPolicy::Deny(vec![::http::Method::TRACE, ::http::Method::CONNECT]);
+        assert_eq!(
+            deny.allow_header(),
+            "GET, HEAD, POST, PUT, DELETE, OPTIONS, PATCH"
+        );
+
+        assert_eq!(
+            MethodPolicy::Any.allow_header(),
+            "GET, HEAD, POST, PUT, DELETE, CONNECT, OPTIONS, TRACE, PATCH"
+        );
     }
 
-    fn drop(&mut self, rep: Resource<OutgoingRequest>) -> wasmtime::Result<()> {
-        unimplemented!()
+    #[test]
+    fn trailers_are_only_handed_out_once() {
+        use crate::wasi::http::types::{HostFutureTrailers, HostIncomingBody, HostIncomingRequest};
+
+        let mut state = State::default();
+        let id = state.new_id();
+        let body = http_body_util::StreamBody::new(futures::stream::iter([
+            Ok::<_, BoxError>(Frame::data(Bytes::from_static(b"body"))),
+            Ok(Frame::trailers(HeaderMap::from_iter([(
+                HeaderName::from_static("x-checksum"),
+                HeaderValue::from_static("abc"),
+            )]))),
+        ]));
+        state
+            .requests
+            .insert(id, ::http::Request::new(body.boxed_unsync()));
+
+        let body = HostIncomingRequest::consume(&mut state, Resource::new_borrow(id))
+            .unwrap()
+            .unwrap();
+        let trailers = HostIncomingBody::finish(&mut state, body).unwrap();
+        let get = |state: &mut State| {
+            HostFutureTrailers::get(state, Resource::new_borrow(trailers.rep())).unwrap()
+        };
+
+        let Some(Ok(Some(fields))) = get(&mut state) else {
+            panic!("the trailers weren't handed out");
+        };
+        assert_eq!(
+            HostFields::get(&mut state, fields, "x-checksum".into()).unwrap(),
+            [b"abc".to_vec()]
+        );
+
+        // Spent, which the guest sees as an error rather than a trap.
+        let Some(Err(ErrorCode::InternalError(Some(message)))) = get(&mut state) else {
+            panic!("taking the trailers again didn't fail");
+        };
+        assert_eq!(message, "trailers already taken");
     }
-}
 
-impl wasi::http::types::HostIncomingResponse for State {
-    fn status(&mut self, self_: Resource<IncomingResponse>) -> wasmtime::Result<StatusCode> {
-        unimplemented!()
+    #[test]
+    fn finished_bodies_end_with_their_last_frame() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use futures::executor::block_on;
+
+        let ended = Arc::new(AtomicUsize::new(0));
+        let on_end = {
+            let ended = ended.clone();
+            move || {
+                ended.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+
+        let mut body = Outgoing {
+            on_end: Some(Box::new(on_end)),
+            ..Default::default()
+        };
+        body.buf.extend(b"streamed");
+        assert!(!body.is_end_stream());
+
+        body.done = true;
+        assert!(!body.is_end_stream());
+
+        let frame = block_on(body.frame()).unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), b"streamed");
+
+        // Nothing is left, so h2 can end the stream with that frame rather than an empty one.
+        assert!(body.is_end_stream());
+        assert_eq!(ended.load(Ordering::Relaxed), 1);
+        assert!(block_on(body.frame()).is_none());
+        assert_eq!(ended.load(Ordering::Relaxed), 1);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+
+        let mut body = Outgoing {
+            trailers: Some(trailers),
+            done: true,
+            ..Default::default()
+        };
+        assert!(!body.is_end_stream());
+
+        let frame = block_on(body.frame()).unwrap().unwrap();
+        assert_eq!(frame.into_trailers().unwrap()["x-checksum"], "abc");
+        assert!(body.is_end_stream());
+        assert!(block_on(body.frame()).is_none());
     }
 
-    fn headers(
-        &mut self,
-        self_: Resource<IncomingResponse>,
-    ) -> wasmtime::Result<Resource<Headers>> {
-        unimplemented!()
+    #[test]
+    fn responses_set_before_their_body_is_finished_stream() {
+        use futures::executor::block_on;
+        use tokio::sync::oneshot;
+
+        use crate::wasi::{
+            http::types::{HostOutgoingBody, HostOutgoingResponse, HostResponseOutparam},
+            io::streams::HostOutputStream,
+        };
+
+        let mut state = State::default();
+        let (handoff, mut handed_off) = oneshot::channel();
+        state.handoff = Some(handoff);
+
+        let headers = HostFields::new(&mut state).unwrap();
+        let res = HostOutgoingResponse::new(&mut state, headers).unwrap();
+        let body = HostOutgoingResponse::body(&mut state, Resource::new_borrow(res.rep()))
+            .unwrap()
+            .unwrap();
+        let stream = HostOutgoingBody::write(&mut state, Resource::new_borrow(body.rep()))
+            .unwrap()
+            .unwrap();
+        let write = |state: &mut State, contents: &[u8]| {
+            HostOutputStream::write(state, Resource::new_borrow(stream.rep()), contents.to_vec())
+        };
+
+        assert!(matches!(write(&mut state, b"head "), Ok(Ok(()))));
+
+        let param = state.new_id();
+        state.full_responses.insert(param, None);
+        HostResponseOutparam::set(&mut state, Resource::new_own(param), Ok(res)).unwrap();
+
+        // The response went out with what was written so far, and nothing is left to send once
+        // the guest returns.
+        let mut sent = handed_off.try_recv().unwrap().into_body();
+        assert!(state.full_responses[&param].is_none());
+
+        let frame = block_on(sent.frame()).unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), b"head ");
+
+        // The rest is sent as it is written, the guest only getting a window ahead of it.
+        assert!(matches!(write(&mut state, b"tail"), Ok(Ok(()))));
+        assert!(matches!(
+            HostOutputStream::check_write(&mut state, Resource::new_borrow(stream.rep())),
+            Ok(Ok(room)) if room == (BUF_LIMIT - 4) as u64
+        ));
+
+        let frame = block_on(sent.frame()).unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), b"tail");
+
+        drop(stream);
+        assert!(matches!(
+            HostOutgoingBody::finish(&mut state, body, None),
+            Ok(Ok(()))
+        ));
+        assert!(block_on(sent.frame()).is_none());
     }
 
-    fn consume(
-        &mut self,
-        self_: Resource<IncomingResponse>,
-    ) -> wasmtime::Result<Result<Resource<IncomingBody>, ()>> {
-        unimplemented!()
+    #[test]
+    fn bodies_dropped_unfinished_fail_once_streamed() {
+        use futures::executor::block_on;
+
+        let pipe = Pipe::shared(BUF_LIMIT);
+        pipe.lock().unwrap().streaming = true;
+        pipe.lock().unwrap().body.buf.extend(b"partial");
+
+        let mut sent = Outgoing::stream(PipeBody(pipe.clone()).boxed_unsync());
+        let frame = block_on(sent.frame()).unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), b"partial");
+
+        // An error, so the client can't take the part that was sent for the whole body.
+        pipe.lock().unwrap().abort();
+        assert!(block_on(sent.frame()).unwrap().is_err());
+        assert!(block_on(sent.frame()).is_none());
+
+        // Once the client is gone, the guest's writes find the body closed.
+        drop(sent);
+        assert!(pipe.lock().unwrap().closed);
     }
 
-    fn drop(&mut self, rep: Resource<IncomingResponse>) -> wasmtime::Result<()> {
-        unimplemented!()
+    /// The data frames sent of a body streaming `chunks` under a `Content-Length` of `declared`.
+    fn sent_of(chunks: &[&'static str], declared: u64, policy: LengthMismatch) -> Vec<String> {
+        use futures::executor::block_on;
+        use http_body_util::StreamBody;
+
+        let frames = chunks
+            .iter()
+            .map(|&chunk| Ok::<_, BoxError>(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+            .collect::<Vec<_>>();
+        let mut body =
+            Outgoing::stream(StreamBody::new(futures::stream::iter(frames)).boxed_unsync());
+        body.length = Some(DeclaredLength::new(declared, policy));
+
+        let mut sent = Vec::new();
+
+        while let Some(frame) = block_on(body.frame()) {
+            let data = frame.unwrap().into_data().unwrap();
+            sent.push(String::from_utf8(Vec::from(data)).unwrap());
+        }
+
+        assert!(body.is_end_stream());
+        sent
     }
-}
 
-impl wasi::http::types::HostFutureIncomingResponse for State {
-    fn subscribe(
-        &mut self,
-        self_: Resource<FutureIncomingResponse>,
-    ) -> wasmtime::Result<Resource<Pollable>> {
-        unimplemented!()
+    #[test]
+    fn streamed_bodies_are_held_to_their_declared_length() {
+        for policy in [LengthMismatch::Fail, LengthMismatch::Correct] {
+            assert_eq!(sent_of(&["12345", "67890"], 10, policy), ["12345", "67890"]);
+
+            // An under-write ends short, which is all hyper needs to abort the connection.
+            assert_eq!(sent_of(&["12345"], 10, policy), ["12345"]);
+        }
+
+        // An over-write is cut off before the frame that overflows, or at the declared length.
+        assert_eq!(
+            sent_of(&["12345", "67890"], 7, LengthMismatch::Fail),
+            ["12345"]
+        );
+        assert_eq!(
+            sent_of(&["12345", "67890", "abc"], 7, LengthMismatch::Correct),
+            ["12345", "67"]
+        );
     }
 
-    fn get(
-        &mut self,
-        self_: Resource<FutureIncomingResponse>,
-    ) -> wasmtime::Result<Option<Result<Result<Resource<IncomingResponse>, ErrorCode>, ()>>> {
-        unimplemented!()
+    /// The `content-length` a guest sees on a request whose body is `body`.
+    fn guest_content_length(body: RequestBody, headers: &[(&str, &str)]) -> Vec<Vec<u8>> {
+        use crate::wasi::http::types::HostIncomingRequest;
+
+        let mut state = State::default();
+        let id = state.new_id();
+        let mut req = ::http::Request::new(body);
+
+        for (name, value) in headers {
+            req.headers_mut()
+                .insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+
+        state.requests.insert(id, req);
+
+        let headers = HostIncomingRequest::headers(&mut state, Resource::new_borrow(id)).unwrap();
+        HostFields::get(&mut state, headers, "content-length".into()).unwrap()
     }
 
-    fn drop(&mut self, rep: Resource<FutureIncomingResponse>) -> wasmtime::Result<()> {
-        unimplemented!()
+    #[test]
+    fn guests_see_the_length_of_bodies_that_have_one() {
+        let fixed = || {
+            http_body_util::Full::new(Bytes::from_static(b"12345"))
+                .map_err(|never| match never {})
+                .boxed_unsync()
+        };
+
+        // Sent by the client, or worked out from the body, as over HTTP/2.
+        assert_eq!(
+            guest_content_length(fixed(), &[("content-length", "5")]),
+            [b"5".to_vec()]
+        );
+        assert_eq!(guest_content_length(fixed(), &[]), [b"5".to_vec()]);
+
+        // A chunked body's length is unknown until it ends.
+        assert!(guest_content_length(fixed(), &[("transfer-encoding", "chunked")]).is_empty());
+
+        let streamed = http_body_util::StreamBody::new(futures::stream::iter([Ok::<_, BoxError>(
+            Frame::data(Bytes::from_static(b"12345")),
+        )]))
+        .boxed_unsync();
+        assert!(guest_content_length(streamed, &[]).is_empty());
+
+        // Nor does a request without a body get one.
+        let empty = http_body_util::Empty::new()
+            .map_err(|never| match never {})
+            .boxed_unsync();
+        assert!(guest_content_length(empty, &[]).is_empty());
     }
 }
@@ -0,0 +1,312 @@
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+use crate::{engine, problem::escape, provided_interfaces, Options};
+
+/// The export a component needs to be served.
+pub const HANDLER: &str = "wasi:http/incoming-handler@0.2.0-rc-2023-11-10";
+
+/// What a component imports and exports, and whether this build can serve it.
+pub struct Inspection {
+    pub imports: Vec<Import>,
+    pub exports: Vec<String>,
+}
+
+pub struct Import {
+    /// The interface name with its version, such as `wasi:io/poll@0.2.0-rc-2023-11-10`.
+    pub name: String,
+    /// Whether this build provides the interface, whatever the version.
+    pub provided: bool,
+}
+
+impl Inspection {
+    /// Reads the imports and exports of a component binary. Those of nested components and
+    /// modules are satisfied inside the component, so only the outermost ones are listed.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let provided = provided_interfaces();
+        let mut inspection = Self {
+            imports: Vec::new(),
+            exports: Vec::new(),
+        };
+        let mut depth = 0;
+
+        for payload in wasmparser::Parser::new(0).parse_all(bytes) {
+            match payload? {
+                wasmparser::Payload::Version { .. } => depth += 1,
+                wasmparser::Payload::End(_) => depth -= 1,
+                wasmparser::Payload::ComponentImportSection(imports) if depth == 1 => {
+                    for import in imports {
+                        let name = import?.name.0;
+                        let interface = name.split('@').next().unwrap_or(name);
+
+                        inspection.imports.push(Import {
+                            name: name.to_owned(),
+                            provided: provided.contains(&interface),
+                        });
+                    }
+                }
+                wasmparser::Payload::ComponentExportSection(exports) if depth == 1 => {
+                    for export in exports {
+                        inspection.exports.push(export?.name.0.to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(inspection)
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.imports
+            .iter()
+            .filter(|import| !import.provided)
+            .map(|import| import.name.as_str())
+    }
+
+    pub fn has_handler(&self) -> bool {
+        self.exports.iter().any(|export| export == HANDLER)
+    }
+
+    /// Whether the runner could serve the component.
+    pub fn servable(&self) -> bool {
+        self.has_handler() && self.missing().next().is_none()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"imports\":[");
+
+        for (i, import) in self.imports.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}{{\"name\":\"{}\",\"provided\":{}}}",
+                if i == 0 { "" } else { "," },
+                escape(&import.name),
+                import.provided,
+            );
+        }
+
+        json.push_str("],\"exports\":[");
+
+        for (i, export) in self.exports.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\"{}\"",
+                if i == 0 { "" } else { "," },
+                escape(export)
+            );
+        }
+
+        let _ = write!(
+            json,
+            "],\"handler\":{},\"servable\":{}}}",
+            self.has_handler(),
+            self.servable()
+        );
+
+        json
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "imports:")?;
+
+        for import in &self.imports {
+            let mark = if import.provided { "ok" } else { "missing" };
+            writeln!(f, "  {:<8} {}", mark, import.name)?;
+        }
+
+        writeln!(f, "exports:")?;
+
+        for export in &self.exports {
+            writeln!(f, "  {export}")?;
+        }
+
+        if !self.has_handler() {
+            writeln!(f, "does not export {HANDLER}")?;
+        }
+
+        if self.servable() {
+            write!(f, "the component can be served")
+        } else {
+            write!(f, "the component can't be served")
+        }
+    }
+}
+
+/// Inspects the component at `path` without serving it. The component is also compiled, so a
+/// malformed one fails here rather than when the runner starts.
+pub fn inspect(path: impl AsRef<Path>) -> anyhow::Result<Inspection> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+
+    if !bytes.starts_with(b"\0asm") {
+        return Err(anyhow::Error::msg(format!(
+            "{} is not a component binary",
+            path.display()
+        )));
+    }
+
+    let inspection = Inspection::parse(&bytes)?;
+    wasmtime::component::Component::from_binary(&engine(&Options::default())?, &bytes)?;
+
+    Ok(inspection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION: &str = "0.2.0-rc-2023-11-10";
+
+    /// A component that imports each interface as an empty instance.
+    fn importing(interfaces: &[&str]) -> Vec<u8> {
+        let imports = interfaces
+            .iter()
+            .map(|interface| format!("(import \"{interface}@{VERSION}\" (instance))"))
+            .collect::<String>();
+
+        wat::parse_str(format!("(component {imports})")).unwrap()
+    }
+
+    /// A component that exports each interface as an empty instance.
+    fn exporting(interfaces: &[&str]) -> Vec<u8> {
+        let exports = interfaces
+            .iter()
+            .enumerate()
+            .map(|(i, interface)| {
+                format!("(instance $i{i}) (export \"{interface}@{VERSION}\" (instance $i{i}))")
+            })
+            .collect::<String>();
+
+        wat::parse_str(format!("(component {exports})")).unwrap()
+    }
+
+    #[test]
+    fn handlers_using_provided_imports_are_servable() {
+        let bytes = wat::parse_str(format!(
+            "(component (import \"wasi:http/types@{VERSION}\" (instance)) \
+             (instance $h) (export \"{HANDLER}\" (instance $h)))"
+        ))
+        .unwrap();
+        let inspection = Inspection::parse(&bytes).unwrap();
+
+        assert!(inspection.has_handler());
+        assert_eq!(inspection.missing().count(), 0);
+        assert!(inspection.servable());
+        assert_eq!(
+            inspection.to_json(),
+            format!(
+                "{{\"imports\":[{{\"name\":\"wasi:http/types@{VERSION}\",\"provided\":true}}],\
+                 \"exports\":[\"{HANDLER}\"],\"handler\":true,\"servable\":true}}"
+            )
+        );
+        assert!(inspection
+            .to_string()
+            .ends_with("the component can be served"));
+    }
+
+    #[test]
+    fn commands_are_not_servable() {
+        let inspection = Inspection::parse(&exporting(&["wasi:cli/run"])).unwrap();
+
+        assert!(!inspection.has_handler());
+        assert!(!inspection.servable());
+
+        let report = inspection.to_string();
+        assert!(
+            report.contains(&format!("does not export {HANDLER}")),
+            "{report}"
+        );
+        assert!(
+            report.ends_with("the component can't be served"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn unimplemented_imports_are_listed() {
+        let bytes = wat::parse_str(format!(
+            "(component (import \"wasi:http/types@{VERSION}\" (instance)) \
+             (import \"example:unknown/thing@1.0.0\" (instance)) \
+             (instance $h) (export \"{HANDLER}\" (instance $h)))"
+        ))
+        .unwrap();
+        let inspection = Inspection::parse(&bytes).unwrap();
+
+        assert_eq!(
+            inspection.missing().collect::<Vec<_>>(),
+            ["example:unknown/thing@1.0.0"]
+        );
+        assert!(inspection.has_handler());
+        assert!(!inspection.servable());
+        assert!(inspection
+            .to_string()
+            .contains("missing  example:unknown/thing@1.0.0"));
+        assert!(inspection
+            .to_json()
+            .contains("{\"name\":\"example:unknown/thing@1.0.0\",\"provided\":false}"));
+    }
+
+    #[test]
+    fn inspecting_rejects_files_that_are_not_components() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "not wasm").unwrap();
+
+        let err = inspect(file.path()).err().unwrap().to_string();
+        assert!(err.ends_with("is not a component binary"), "{err}");
+    }
+
+    #[test]
+    fn disabled_interfaces_are_missing() {
+        let bytes = importing(&[
+            "wasi:http/types",
+            "wasi:cli/stdout",
+            "wasi:filesystem/preopens",
+            "wasi:sockets/tcp",
+        ]);
+        let inspection = Inspection::parse(&bytes).unwrap();
+        let missing = inspection.missing().collect::<Vec<_>>();
+
+        assert!(!missing.contains(&format!("wasi:http/types@{VERSION}").as_str()));
+        assert_eq!(
+            missing.contains(&format!("wasi:cli/stdout@{VERSION}").as_str()),
+            cfg!(not(feature = "cli"))
+        );
+        assert_eq!(
+            missing.contains(&format!("wasi:filesystem/preopens@{VERSION}").as_str()),
+            cfg!(not(feature = "filesystem"))
+        );
+        assert_eq!(
+            missing.contains(&format!("wasi:sockets/tcp@{VERSION}").as_str()),
+            cfg!(not(feature = "sockets"))
+        );
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "cli", feature = "filesystem", feature = "sockets")))]
+    fn http_only_builds_reject_other_imports() {
+        use crate::check_imports;
+
+        assert!(check_imports(&importing(&["wasi:http/types", "wasi:io/streams"]), &[]).is_ok());
+
+        for interface in [
+            "wasi:cli/stdout",
+            "wasi:filesystem/preopens",
+            "wasi:sockets/tcp",
+        ] {
+            let err = check_imports(&importing(&["wasi:http/types", interface]), &[])
+                .unwrap_err()
+                .to_string();
+
+            assert!(
+                err.starts_with("The component imports interfaces this build does not provide")
+                    && err.contains(interface),
+                "{err}"
+            );
+        }
+    }
+}
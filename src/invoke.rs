@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use ::http::{HeaderMap, Request, Response};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+
+use crate::{problem::HostError, Runner};
+
+/// The outcome of a request sent through a runner in process.
+pub struct Invocation {
+    /// The response with its whole body.
+    pub response: Response<Bytes>,
+    pub trailers: Option<HeaderMap>,
+    /// The host answered because the guest trapped.
+    pub trapped: bool,
+}
+
+impl Runner {
+    /// Sends one request along the same path the server uses, without a listener, and reads the
+    /// response to the end.
+    pub async fn invoke(self: Arc<Self>, req: Request<Full<Bytes>>) -> anyhow::Result<Invocation> {
+        let res = self.service_fn(req).await?;
+
+        let trapped = res
+            .extensions()
+            .get::<HostError>()
+            .is_some_and(|error| error.kind == "trap");
+
+        let (parts, body) = res.into_parts();
+        let collected = body.collect().await.map_err(anyhow::Error::msg)?;
+        let trailers = collected.trailers().cloned();
+
+        Ok(Invocation {
+            response: Response::from_parts(parts, collected.to_bytes()),
+            trailers,
+            trapped,
+        })
+    }
+}
@@ -1,17 +1,17 @@
 use futures::{future::poll_fn, task::noop_waker_ref};
-use hyper::body::Body;
+use hyper::body::{Body, Bytes, Frame};
 use std::{
-    collections::VecDeque,
     io::ErrorKind,
     pin::Pin,
     task::{Context, Poll},
-    thread,
+    time::Instant,
 };
 
 use wasmtime::component::Resource;
 
 use crate::{
-    http::BodyState,
+    body::{BRANCH, SOURCE},
+    http::{BodyState, BoxError, IncomingBodyWrapper, StreamHandle},
     wasi::{
         self,
         io::{
@@ -27,43 +27,75 @@ pub trait PollableIndividual {
 
     fn block(&mut self, state: &mut State) -> wasmtime::Result<()>;
 
-    fn destroy(&mut self, state: &mut State) -> wasmtime::Result<()> {
+    fn destroy(&mut self, _state: &mut State) -> wasmtime::Result<()> {
         Ok(())
     }
 }
 
 impl wasi::io::poll::Host for State {
+    /// Returns the index of every pollable that is ready, waiting until at least one is. A
+    /// pollable listed more than once is reported at each of its indices.
     fn poll(&mut self, in_: Vec<Resource<Pollable>>) -> wasmtime::Result<Vec<u32>> {
+        if in_.is_empty() {
+            return Err(wasmtime::Error::msg("Nothing to poll"));
+        }
+
+        let reps = in_.iter().map(|val| val.rep()).collect::<Vec<_>>();
         let mut resources = Vec::new();
 
-        for index in in_.into_iter().map(|val| val.rep()) {
-            resources.push((
-                index,
-                self.pollables
-                    .remove(&index)
-                    .ok_or_else(|| wasmtime::Error::msg("Could not find pollable"))?,
-            ));
+        for &rep in &reps {
+            if resources.iter().any(|(taken, _)| *taken == rep) {
+                continue;
+            }
+
+            match self.pollables.remove(&rep) {
+                Some(pollable) => resources.push((rep, pollable)),
+                None => {
+                    self.pollables.extend(resources);
+                    return Err(wasmtime::Error::msg("Could not find pollable"));
+                }
+            }
         }
 
-        let mut ready = Vec::new();
+        let waited_at = Instant::now();
+        let ready = self.wait_ready(&mut resources);
+        self.timings.waited += waited_at.elapsed();
+        self.pollables.extend(resources);
+        let ready = ready?;
+
+        Ok(reps
+            .iter()
+            .enumerate()
+            .filter(|(_, rep)| ready.contains(rep))
+            .map(|(index, _)| index as u32)
+            .collect())
+    }
+}
 
+impl State {
+    fn wait_ready(
+        &mut self,
+        resources: &mut [(u32, Box<dyn PollableIndividual>)],
+    ) -> wasmtime::Result<Vec<u32>> {
         loop {
-            let mut should_break = false;
-            for (index, (_, val)) in resources.iter_mut().enumerate() {
-                if val.ready(self)? {
-                    should_break = true;
-                    ready.push(index as u32);
+            let mut ready = Vec::new();
+
+            for (rep, pollable) in resources.iter_mut() {
+                if pollable.ready(self)? {
+                    ready.push(*rep);
                 }
             }
 
-            if should_break {
-                break;
+            if !ready.is_empty() {
+                return Ok(ready);
             }
-        }
 
-        self.pollables.extend(resources.into_iter());
-
-        Ok(ready)
+            // A lone pollable can be waited on directly; several are checked again in turn.
+            match resources {
+                [(_, pollable)] => pollable.block(self)?,
+                _ => std::thread::yield_now(),
+            }
+        }
     }
 }
 
@@ -87,7 +119,9 @@ impl wasi::io::poll::HostPollable for State {
             .remove(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find pollable"))?;
 
+        let waited_at = Instant::now();
         let res = resourse.block(self);
+        self.timings.waited += waited_at.elapsed();
 
         self.pollables.insert(self_.rep(), resourse);
 
@@ -133,165 +167,223 @@ impl wasi::io::error::HostError for State {
 impl wasi::io::streams::Host for State {}
 
 impl State {
-    fn handle_hyper_error(&mut self, error: hyper::Error) -> Resource<Error> {
+    pub fn handle_body_error(&mut self, error: BoxError) -> Resource<Error> {
         let id = self.new_id();
 
-        self.errors
-            .insert(id, std::io::Error::new(ErrorKind::Other, error));
+        self.errors.insert(id, std::io::Error::other(error));
 
         Resource::new_own(id)
     }
-}
 
-impl wasi::io::streams::HostInputStream for State {
-    fn read(
+    pub fn handle_io_error(&mut self, error: std::io::Error) -> Resource<Error> {
+        let id = self.new_id();
+
+        self.errors.insert(id, error);
+
+        Resource::new_own(id)
+    }
+
+    /// Hands out up to `len` bytes of `frame`, keeping the rest for the next read.
+    fn read_frame(
         &mut self,
-        self_: wasmtime::component::Resource<InputStream>,
+        stream: u32,
+        frame: Result<Frame<Bytes>, BoxError>,
         len: u64,
     ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => {
+                return Ok(Err(StreamError::LastOperationFailed(
+                    self.handle_body_error(err),
+                )))
+            }
+        };
+
         let resource = self
             .incoming
-            .get_mut(&self_.rep())
+            .get_mut(&stream)
             .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
 
-        if resource.state == BodyState::Consumed {
-            return Ok(Err(StreamError::Closed));
-        }
+        match frame.into_data() {
+            Ok(mut bytes) => {
+                let read = bytes.split_to((len as usize).min(bytes.len()));
 
-        if let Some(frame) = resource.last_frame.take() {
-            let mut frame = match frame {
-                Ok(v) => v,
-                Err(e) => {
-                    return Ok(Err(StreamError::LastOperationFailed(
-                        self.handle_hyper_error(e),
-                    )))
+                if !bytes.is_empty() {
+                    resource.last_frame = Some(Ok(Frame::data(bytes)));
                 }
-            };
 
-            if frame.is_trailers() {
-                resource.trailers = Some(frame.into_trailers().unwrap());
-                return Ok(Err(StreamError::Closed));
+                Ok(Ok(read.to_vec()))
             }
-
-            let bytes = frame.data_mut().unwrap();
-            let mut new = bytes.split_off(len as usize);
-
-            std::mem::swap(bytes, &mut new);
-
-            resource.last_frame = Some(Ok(frame));
-
-            return Ok(Ok(new.to_vec()));
+            Err(frame) => match frame.into_trailers() {
+                Ok(trailers) => {
+                    resource.trailers = Some(trailers);
+                    resource.state = BodyState::Trailers;
+                    Ok(Err(StreamError::Closed))
+                }
+                Err(_) => {
+                    resource.state = BodyState::Consumed;
+                    Ok(Err(StreamError::LastOperationFailed(self.handle_io_error(
+                        std::io::Error::new(ErrorKind::InvalidData, "Unexpected body frame"),
+                    ))))
+                }
+            },
         }
+    }
 
-        let Poll::Ready(res) =
-            Pin::new(&mut resource.incoming).poll_frame(&mut Context::from_waker(noop_waker_ref()))
-        else {
-            return Ok(Ok(Vec::new()));
+    /// Reads frames into one buffer until the body ends or `limit` bytes arrived, for guests that
+    /// want the whole body at once. Trailers or an error after some data are left for the next
+    /// read.
+    fn read_to_end(
+        &mut self,
+        stream: u32,
+        limit: usize,
+    ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        let resource = self
+            .incoming
+            .get_mut(&stream)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        let buffered = match &resource.last_frame {
+            Some(Ok(frame)) => frame.data_ref().map_or(0, Bytes::len),
+            _ => 0,
         };
+        let expected = buffered + resource.incoming.size_hint().lower() as usize;
+        let mut buf = Vec::with_capacity(expected.min(limit));
+
+        while buf.len() < limit && !resource.state.ended() {
+            let frame = match resource.last_frame.take() {
+                Some(frame) => frame,
+                None => match futures::executor::block_on(poll_fn(|cx| {
+                    Pin::new(&mut resource.incoming).poll_frame(cx)
+                })) {
+                    None => {
+                        resource.state = BodyState::Consumed;
+                        break;
+                    }
+                    Some(frame) => frame,
+                },
+            };
 
-        if let Some(frame) = res {
-            let mut frame = match frame {
-                Ok(frame) => frame,
-                Err(err) => {
-                    return Ok(Err(StreamError::LastOperationFailed(
-                        self.handle_hyper_error(err),
-                    )))
+            let mut data = match frame {
+                Ok(frame) if frame.is_data() => frame.into_data().unwrap_or_default(),
+                frame if buf.is_empty() => return self.read_frame(stream, frame, 0),
+                frame => {
+                    resource.last_frame = Some(frame);
+                    break;
                 }
             };
 
-            if frame.is_data() {
-                let bytes = frame.data_mut().unwrap();
-                let mut new = bytes.split_off(len as usize);
+            let read = data.split_to((limit - buf.len()).min(data.len()));
 
-                std::mem::swap(bytes, &mut new);
+            if !data.is_empty() {
+                resource.last_frame = Some(Ok(Frame::data(data)));
+            }
 
-                resource.last_frame = Some(Ok(frame));
+            buf.extend_from_slice(&read);
+        }
 
-                return Ok(Ok(new.to_vec()));
-            } else {
-                let trailers = frame.into_trailers().unwrap();
-                resource.trailers = Some(trailers);
-                resource.state = BodyState::Trailers;
-                Ok(Err(StreamError::Closed))
-            }
-        } else {
-            resource.state = BodyState::Consumed;
-            Ok(Err(StreamError::Closed))
+        if buf.is_empty() && resource.state.ended() {
+            return Ok(Err(StreamError::Closed));
         }
+
+        Ok(Ok(buf))
     }
+}
 
-    fn blocking_read(
+impl wasi::io::streams::HostInputStream for State {
+    fn read(
         &mut self,
         self_: wasmtime::component::Resource<InputStream>,
         len: u64,
     ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        if self.tcp_readers.contains_key(&self_.rep()) {
+            return self.tcp_read(self_.rep(), len, false);
+        }
+
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(Err(StreamError::Closed));
+        }
+
+        if let Some((body, reader)) = self.tee_reader(self_.rep()) {
+            return self.read_tee(body, reader, len, false);
+        }
+
         let resource = self
             .incoming
             .get_mut(&self_.rep())
             .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
 
-        if resource.state == BodyState::Consumed {
+        if resource.state.ended() {
             return Ok(Err(StreamError::Closed));
         }
 
-        if let Some(frame) = resource.last_frame.take() {
-            let mut frame = match frame {
-                Ok(v) => v,
-                Err(e) => {
-                    return Ok(Err(StreamError::LastOperationFailed(
-                        self.handle_hyper_error(e),
-                    )))
+        let frame = match resource.last_frame.take() {
+            Some(frame) => frame,
+            None => match Pin::new(&mut resource.incoming)
+                .poll_frame(&mut Context::from_waker(noop_waker_ref()))
+            {
+                Poll::Pending => return Ok(Ok(Vec::new())),
+                Poll::Ready(None) => {
+                    resource.state = BodyState::Consumed;
+                    return Ok(Err(StreamError::Closed));
                 }
-            };
-
-            if frame.is_trailers() {
-                resource.trailers = Some(frame.into_trailers().unwrap());
-                return Ok(Err(StreamError::Closed));
-            }
-
-            let bytes = frame.data_mut().unwrap();
-            let mut new = bytes.split_off(len as usize);
+                Poll::Ready(Some(frame)) => frame,
+            },
+        };
 
-            std::mem::swap(bytes, &mut new);
+        self.read_frame(self_.rep(), frame, len)
+    }
 
-            resource.last_frame = Some(Ok(frame));
+    fn blocking_read(
+        &mut self,
+        self_: wasmtime::component::Resource<InputStream>,
+        len: u64,
+    ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        if self.tcp_readers.contains_key(&self_.rep()) {
+            return self.tcp_read(self_.rep(), len, true);
+        }
 
-            return Ok(Ok(new.to_vec()));
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(Err(StreamError::Closed));
         }
 
-        let res = futures::executor::block_on(poll_fn(|cx| {
-            Pin::new(&mut resource.incoming).poll_frame(cx)
-        }));
+        if let Some((body, reader)) = self.tee_reader(self_.rep()) {
+            return self.read_tee(body, reader, len, true);
+        }
 
-        if let Some(frame) = res {
-            let mut frame = match frame {
-                Ok(frame) => frame,
-                Err(err) => {
-                    return Ok(Err(StreamError::LastOperationFailed(
-                        self.handle_hyper_error(err),
-                    )))
-                }
-            };
+        // Asking for more than the host would ever buffer means the guest wants the whole body.
+        if self.max_body_bytes > 0 && len >= self.max_body_bytes as u64 {
+            return self.read_to_end(self_.rep(), self.max_body_bytes);
+        }
 
-            if frame.is_data() {
-                let bytes = frame.data_mut().unwrap();
-                let mut new = bytes.split_off(len as usize);
+        let resource = self
+            .incoming
+            .get_mut(&self_.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
 
-                std::mem::swap(bytes, &mut new);
+        if resource.state.ended() {
+            return Ok(Err(StreamError::Closed));
+        }
 
-                resource.last_frame = Some(Ok(frame));
+        let waited_at = Instant::now();
+        let frame = match resource.last_frame.take() {
+            Some(frame) => Some(frame),
+            None => futures::executor::block_on(poll_fn(|cx| {
+                Pin::new(&mut resource.incoming).poll_frame(cx)
+            })),
+        };
 
-                return Ok(Ok(new.to_vec()));
-            } else {
-                let trailers = frame.into_trailers().unwrap();
-                resource.trailers = Some(trailers);
-                resource.state = BodyState::Trailers;
-                Ok(Err(StreamError::Closed))
-            }
-        } else {
+        if frame.is_none() {
             resource.state = BodyState::Consumed;
-            Ok(Err(StreamError::Closed))
         }
+
+        self.timings.waited += waited_at.elapsed();
+
+        let Some(frame) = frame else {
+            return Ok(Err(StreamError::Closed));
+        };
+
+        self.read_frame(self_.rep(), frame, len)
     }
 
     fn skip(
@@ -316,28 +408,75 @@ impl wasi::io::streams::HostInputStream for State {
         &mut self,
         self_: wasmtime::component::Resource<InputStream>,
     ) -> wasmtime::Result<wasmtime::component::Resource<Pollable>> {
+        if self.tcp_readers.contains_key(&self_.rep()) {
+            return Ok(self.tcp_subscribe(self_.rep()));
+        }
+
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(self.stdio_subscribe());
+        }
+
         let id = self.new_id();
+        let (body, reader) = self
+            .tee_reader(self_.rep())
+            .unwrap_or((self_.rep(), SOURCE));
 
         self.pollables
-            .insert(id, Box::new(InputStreamReady { id: self_.rep() }));
+            .insert(id, Box::new(InputStreamReady { id: body, reader }));
 
         Ok(Resource::new_own(id))
     }
 
     fn drop(&mut self, rep: wasmtime::component::Resource<InputStream>) -> wasmtime::Result<()> {
+        if self.tcp_readers.remove(&rep.rep()).is_some() || self.stdio.remove(&rep.rep()).is_some()
+        {
+            return Ok(());
+        }
+
+        if let Some(body) = self.tees.remove(&rep.rep()) {
+            if let Some(tee) = self
+                .incoming
+                .get_mut(&body)
+                .and_then(|resource| resource.tee.as_mut())
+            {
+                tee.release(BRANCH);
+            }
+
+            return Ok(());
+        }
+
         let resource = self
             .incoming
             .get_mut(&rep.rep())
             .ok_or_else(|| wasmtime::Error::msg("Cannot find stream"))?;
 
-        resource.state = BodyState::Trailers;
+        if let Some(tee) = resource.tee.as_mut() {
+            tee.release(SOURCE);
+        }
+
+        // Whatever was left unread stays in the body for `finish` to skip.
+        resource.stream = StreamHandle::Dropped;
 
         Ok(())
     }
 }
 
+/// Keeps the frame a pollable became ready for. Data waits for the next read; trailers end the
+/// stream right away, so the read that follows reports `closed` instead of the guest taking the
+/// readiness for data.
+fn keep_frame(resource: &mut IncomingBodyWrapper, frame: Result<Frame<Bytes>, BoxError>) {
+    match frame {
+        Ok(frame) if frame.is_trailers() => {
+            resource.trailers = frame.into_trailers().ok();
+            resource.state = BodyState::Trailers;
+        }
+        frame => resource.last_frame = Some(frame),
+    }
+}
+
 struct InputStreamReady {
     id: u32,
+    reader: usize,
 }
 
 impl PollableIndividual for InputStreamReady {
@@ -347,19 +486,34 @@ impl PollableIndividual for InputStreamReady {
             .get_mut(&self.id)
             .ok_or_else(|| wasmtime::Error::msg("Cannot find stream"))?;
 
-        let Poll::Ready(res) =
-            Pin::new(&mut resource.incoming).poll_frame(&mut Context::from_waker(noop_waker_ref()))
-        else {
-            return Ok(false);
-        };
+        if let Some(tee) = resource.tee.as_ref() {
+            if tee.available(self.reader) || tee.is_done(self.reader) {
+                return Ok(true);
+            }
+        }
 
-        if let Some(frame) = res {
-            resource.last_frame = Some(frame);
-        } else {
-            resource.state = BodyState::Consumed;
+        // Data left over from a short read must not be replaced by the next frame.
+        if resource.last_frame.is_some() || resource.state.ended() {
+            return Ok(true);
         }
 
-        Ok(true)
+        loop {
+            let Poll::Ready(res) = Pin::new(&mut resource.incoming)
+                .poll_frame(&mut Context::from_waker(noop_waker_ref()))
+            else {
+                return Ok(false);
+            };
+
+            match res {
+                // Empty data frames (common under h2) would only wake the guest up to an empty
+                // read, which it can't tell apart from a pending one.
+                Some(Ok(frame)) if is_empty_data(&frame) => continue,
+                Some(frame) => keep_frame(resource, frame),
+                None => resource.state = BodyState::Consumed,
+            }
+
+            return Ok(true);
+        }
     }
 
     fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
@@ -368,34 +522,65 @@ impl PollableIndividual for InputStreamReady {
             .get_mut(&self.id)
             .ok_or_else(|| wasmtime::Error::msg("Cannot find stream"))?;
 
-        let res = futures::executor::block_on(poll_fn(|cx| {
-            Pin::new(&mut resource.incoming).poll_frame(cx)
-        }));
+        if let Some(tee) = resource.tee.as_ref() {
+            if tee.available(self.reader) || tee.is_done(self.reader) {
+                return Ok(());
+            }
+        }
 
-        if let Some(frame) = res {
-            resource.last_frame = Some(frame);
-        } else {
-            resource.state = BodyState::Consumed;
+        if resource.last_frame.is_some() || resource.state.ended() {
+            return Ok(());
         }
 
-        Ok(())
+        loop {
+            let res = futures::executor::block_on(poll_fn(|cx| {
+                Pin::new(&mut resource.incoming).poll_frame(cx)
+            }));
+
+            match res {
+                Some(Ok(frame)) if is_empty_data(&frame) => continue,
+                Some(frame) => keep_frame(resource, frame),
+                None => resource.state = BodyState::Consumed,
+            }
+
+            return Ok(());
+        }
     }
 }
 
-const BUF_LIMIT: usize = 4096;
+fn is_empty_data(frame: &Frame<Bytes>) -> bool {
+    frame.data_ref().is_some_and(|data| data.is_empty())
+}
+
+pub const BUF_LIMIT: usize = 4096;
+
+/// A pollable for streams that never block.
+pub struct Ready;
+
+impl PollableIndividual for Ready {
+    fn ready(&mut self, _state: &mut State) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+
+    fn block(&mut self, _state: &mut State) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
 
 impl wasi::io::streams::HostOutputStream for State {
     fn check_write(
         &mut self,
         self_: wasmtime::component::Resource<OutputStream>,
     ) -> wasmtime::Result<Result<u64, StreamError>> {
-        let resource = self
-            .responses
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response body"))?
-            .body_mut();
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return self.tcp_check_write(self_.rep());
+        }
+
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(Ok(BUF_LIMIT as u64));
+        }
 
-        Ok(Ok((BUF_LIMIT - resource.buf.len()) as u64))
+        self.body_check_write(self_.rep())
     }
 
     fn write(
@@ -403,16 +588,15 @@ impl wasi::io::streams::HostOutputStream for State {
         self_: wasmtime::component::Resource<OutputStream>,
         contents: Vec<u8>,
     ) -> wasmtime::Result<Result<(), StreamError>> {
-        let resource = self
-            .responses
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response body"))?
-            .body_mut();
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return self.tcp_write(self_.rep(), contents, false);
+        }
 
-        resource.buf.append(&mut VecDeque::from(contents));
-        resource.wake();
+        if let Some(stream) = self.stdio.get(&self_.rep()).copied() {
+            return self.stdio_write(stream, &contents).map(Ok);
+        }
 
-        Ok(Ok(()))
+        self.body_append(self_.rep(), contents)
     }
 
     fn blocking_write_and_flush(
@@ -420,21 +604,25 @@ impl wasi::io::streams::HostOutputStream for State {
         self_: wasmtime::component::Resource<OutputStream>,
         contents: Vec<u8>,
     ) -> wasmtime::Result<Result<(), StreamError>> {
-        let resource = self
-            .responses
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response body"))?
-            .body_mut();
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return self.tcp_write(self_.rep(), contents, true);
+        }
 
-        resource.buf.append(&mut VecDeque::from(contents));
+        if let Some(stream) = self.stdio.get(&self_.rep()).copied() {
+            return self.stdio_write(stream, &contents).map(Ok);
+        }
 
-        self.blocking_flush(self_)
+        self.body_blocking_write(self_.rep(), contents)
     }
 
     fn flush(
         &mut self,
-        _self_: wasmtime::component::Resource<OutputStream>,
+        self_: wasmtime::component::Resource<OutputStream>,
     ) -> wasmtime::Result<Result<(), StreamError>> {
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return self.tcp_flush(self_.rep(), false);
+        }
+
         Ok(Ok(()))
     }
 
@@ -442,29 +630,30 @@ impl wasi::io::streams::HostOutputStream for State {
         &mut self,
         self_: wasmtime::component::Resource<OutputStream>,
     ) -> wasmtime::Result<Result<(), StreamError>> {
-        let resource = self
-            .responses
-            .get_mut(&self_.rep())
-            .ok_or_else(|| wasmtime::Error::msg("Could not find response body"))?
-            .body_mut();
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return self.tcp_flush(self_.rep(), true);
+        }
 
-        while resource.buf.len() > 0 {
-            resource.wake();
-            thread::park();
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(Ok(()));
         }
 
-        Ok(Ok(()))
+        self.body_flush(self_.rep())
     }
 
     fn subscribe(
         &mut self,
         self_: wasmtime::component::Resource<OutputStream>,
     ) -> wasmtime::Result<wasmtime::component::Resource<Pollable>> {
-        let id = self.new_id();
-        self.pollables
-            .insert(id, Box::new(OutputPollable { id: self_.rep() }));
+        if self.tcp_writers.contains_key(&self_.rep()) {
+            return Ok(self.tcp_subscribe(self_.rep()));
+        }
 
-        Ok(Resource::new_own(id))
+        if self.stdio.contains_key(&self_.rep()) {
+            return Ok(self.stdio_subscribe());
+        }
+
+        self.body_subscribe(self_.rep())
     }
 
     fn write_zeroes(
@@ -472,6 +661,13 @@ impl wasi::io::streams::HostOutputStream for State {
         self_: wasmtime::component::Resource<OutputStream>,
         len: u64,
     ) -> wasmtime::Result<Result<(), StreamError>> {
+        // No write can exceed the buffer limit, so reject before allocating.
+        if len > BUF_LIMIT as u64 {
+            return Err(wasmtime::Error::msg(
+                "Attempted to write more than check-write permitted",
+            ));
+        }
+
         self.write(self_, vec![0; len as usize])
     }
 
@@ -480,58 +676,491 @@ impl wasi::io::streams::HostOutputStream for State {
         self_: wasmtime::component::Resource<OutputStream>,
         len: u64,
     ) -> wasmtime::Result<Result<(), StreamError>> {
+        if len > BUF_LIMIT as u64 {
+            return Err(wasmtime::Error::msg(
+                "Attempted to write more than 4096 bytes with blocking-write-zeroes-and-flush",
+            ));
+        }
+
         self.blocking_write_and_flush(self_, vec![0; len as usize])
     }
 
+    /// Reads from `src` what it has ready, up to what `check-write` permits, and writes it.
     fn splice(
         &mut self,
         self_: wasmtime::component::Resource<OutputStream>,
         src: wasmtime::component::Resource<InputStream>,
         len: u64,
     ) -> wasmtime::Result<Result<u64, StreamError>> {
-        todo!()
+        let permitted = match self.check_write(Resource::new_borrow(self_.rep()))? {
+            Ok(permitted) => permitted.min(len),
+            Err(err) => return Ok(Err(err)),
+        };
+
+        if permitted == 0 {
+            return Ok(Ok(0));
+        }
+
+        let contents = match wasi::io::streams::HostInputStream::read(self, src, permitted)? {
+            Ok(contents) => contents,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        let read = contents.len() as u64;
+
+        if read > 0 {
+            if let Err(err) = self.write(self_, contents)? {
+                return Ok(Err(err));
+            }
+        }
+
+        Ok(Ok(read))
     }
 
+    /// Waits for `src` to have something, then writes it and waits for it to be flushed.
     fn blocking_splice(
         &mut self,
         self_: wasmtime::component::Resource<OutputStream>,
         src: wasmtime::component::Resource<InputStream>,
         len: u64,
     ) -> wasmtime::Result<Result<u64, StreamError>> {
-        todo!()
+        // Bounded like blocking-write-and-flush, which the bytes go through.
+        let len = len.min(BUF_LIMIT as u64);
+
+        let contents = match wasi::io::streams::HostInputStream::blocking_read(self, src, len)? {
+            Ok(contents) => contents,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        let read = contents.len() as u64;
+
+        if read > 0 {
+            if let Err(err) = self.blocking_write_and_flush(self_, contents)? {
+                return Ok(Err(err));
+            }
+        }
+
+        Ok(Ok(read))
     }
 
     fn drop(&mut self, rep: wasmtime::component::Resource<OutputStream>) -> wasmtime::Result<()> {
+        // Dropping the write half shuts down the sending side of a socket.
+        self.tcp_writers.remove(&rep.rep());
+        self.stdio.remove(&rep.rep());
+
         Ok(())
     }
 }
 
-struct OutputPollable {
-    id: u32,
-}
+#[cfg(test)]
+mod tests {
+    use ::http::{HeaderMap, Request};
+    use http_body_util::{BodyExt, StreamBody};
+
+    use crate::{
+        pipe::Pipe,
+        wasi::{
+            http::types::{
+                HostFields, HostFutureTrailers, HostIncomingBody, HostIncomingRequest,
+                HostOutgoingBody, IncomingBody,
+            },
+            io::streams::{HostInputStream, HostOutputStream},
+        },
+    };
 
-impl PollableIndividual for OutputPollable {
-    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
-        let resource = state
-            .responses
-            .get(&self.id)
-            .ok_or_else(|| wasmtime::Error::msg("Could not find output body"))?;
+    use super::*;
+
+    /// The input stream of a request whose body sends `frames`.
+    fn stream_of(
+        state: &mut State,
+        frames: Vec<Result<Frame<Bytes>, BoxError>>,
+    ) -> Resource<InputStream> {
+        let id = state.new_id();
+        let body = StreamBody::new(futures::stream::iter(frames)).boxed_unsync();
+        state.requests.insert(id, Request::new(body));
+
+        let body = HostIncomingRequest::consume(state, Resource::new_own(id))
+            .unwrap()
+            .unwrap();
 
-        Ok(resource.body().buf.len() < BUF_LIMIT)
+        HostIncomingBody::stream(state, body).unwrap().unwrap()
     }
 
-    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
-        let resource = state
-            .responses
-            .get_mut(&self.id)
-            .ok_or_else(|| wasmtime::Error::msg("Could not find output body"))?
-            .body_mut();
+    fn read(state: &mut State, stream: &Resource<InputStream>) -> Result<Vec<u8>, StreamError> {
+        HostInputStream::read(state, Resource::new_borrow(stream.rep()), 64).unwrap()
+    }
+
+    #[test]
+    fn body_errors_fail_the_read() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![Ok(Frame::data(Bytes::from("ok"))), Err("reset".into())],
+        );
+
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes == b"ok"));
+        assert!(matches!(
+            read(&mut state, &stream),
+            Err(StreamError::LastOperationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn data_after_trailers_is_never_read() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![
+                Ok(Frame::trailers(HeaderMap::new())),
+                Ok(Frame::data(Bytes::from("late"))),
+            ],
+        );
+
+        assert!(matches!(
+            read(&mut state, &stream),
+            Err(StreamError::Closed)
+        ));
+        assert!(matches!(
+            read(&mut state, &stream),
+            Err(StreamError::Closed)
+        ));
+        assert!(matches!(
+            HostInputStream::blocking_read(&mut state, Resource::new_borrow(stream.rep()), 64),
+            Ok(Err(StreamError::Closed))
+        ));
+    }
+
+    #[test]
+    fn empty_data_frames_read_as_nothing() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![
+                Ok(Frame::data(Bytes::new())),
+                Ok(Frame::data(Bytes::from("x"))),
+            ],
+        );
+
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes.is_empty()));
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes == b"x"));
+        assert!(matches!(
+            read(&mut state, &stream),
+            Err(StreamError::Closed)
+        ));
+    }
+
+    #[test]
+    fn reading_to_the_end_leaves_an_error_for_the_next_read() {
+        let mut state = State::default();
+        let len = state.max_body_bytes as u64;
+        let stream = stream_of(
+            &mut state,
+            vec![Ok(Frame::data(Bytes::from("ab"))), Err("reset".into())],
+        );
+        let mut read_all =
+            || HostInputStream::blocking_read(&mut state, Resource::new_borrow(stream.rep()), len);
+
+        assert!(matches!(read_all(), Ok(Ok(bytes)) if bytes == b"ab"));
+        assert!(matches!(
+            read_all(),
+            Ok(Err(StreamError::LastOperationFailed(_)))
+        ));
+    }
+
+    #[test]
+    fn one_large_blocking_read_returns_the_whole_body() {
+        let mut state = State::default();
+        let json = format!("[{}0]", "1234567,".repeat(128 * 1024));
+        let frames = json
+            .as_bytes()
+            .chunks(16 * 1024)
+            .map(|chunk| Ok(Frame::data(Bytes::copy_from_slice(chunk))))
+            .collect();
+        let stream = stream_of(&mut state, frames);
+        let mut read_all = || {
+            HostInputStream::blocking_read(&mut state, Resource::new_borrow(stream.rep()), u64::MAX)
+        };
+
+        assert!(matches!(read_all(), Ok(Ok(bytes)) if bytes == json.as_bytes()));
+        assert!(matches!(read_all(), Ok(Err(StreamError::Closed))));
+    }
 
-        while resource.buf.len() >= BUF_LIMIT {
-            resource.thread = Some(thread::current());
-            thread::park();
+    #[test]
+    fn large_blocking_reads_stop_at_max_body_bytes() {
+        let mut state = State::default();
+        state.max_body_bytes = 1000;
+        let stream = stream_of(
+            &mut state,
+            vec![
+                Ok(Frame::data(Bytes::from(vec![b'a'; 600]))),
+                Ok(Frame::data(Bytes::from(vec![b'b'; 600]))),
+            ],
+        );
+        let mut read_all = || {
+            HostInputStream::blocking_read(&mut state, Resource::new_borrow(stream.rep()), u64::MAX)
+        };
+
+        // What is left of the frame that crossed the limit comes with the next read.
+        assert!(matches!(read_all(), Ok(Ok(bytes)) if bytes.len() == 1000 && bytes[999] == b'b'));
+        assert!(matches!(read_all(), Ok(Ok(bytes)) if bytes == [b'b'; 200]));
+        assert!(matches!(read_all(), Ok(Err(StreamError::Closed))));
+    }
+
+    /// What has been written to the body `id` and not yet sent.
+    fn written(state: &State, id: u32) -> Vec<u8> {
+        let pipe = state.response_bodies[&id].lock().unwrap();
+        pipe.body.buf.iter().copied().collect()
+    }
+
+    #[test]
+    fn bodies_stay_within_their_buffer_until_sent() {
+        let mut state = State::default();
+        let id = state.new_id();
+        let limit = 64 * 1024;
+        state.response_bodies.insert(id, Pipe::shared(limit));
+
+        let stream = || Resource::<OutputStream>::new_borrow(id);
+        let mut total = 0;
+
+        // Nothing reads the body yet, so once the buffer is full the guest is told so instead of
+        // being let in for more.
+        loop {
+            match HostOutputStream::check_write(&mut state, stream()) {
+                Ok(Ok(permitted)) => {
+                    assert!(permitted > 0);
+
+                    let contents = vec![0; permitted as usize];
+                    assert!(matches!(
+                        HostOutputStream::write(&mut state, stream(), contents),
+                        Ok(Ok(()))
+                    ));
+
+                    total += permitted as usize;
+                    assert!(total <= limit);
+                }
+                Ok(Err(StreamError::LastOperationFailed(_))) => break,
+                _ => panic!("check-write failed"),
+            }
         }
 
-        Ok(())
+        assert_eq!(total, limit);
+        assert!(matches!(
+            HostOutputStream::blocking_write_and_flush(&mut state, stream(), vec![0; 1]),
+            Ok(Err(StreamError::LastOperationFailed(_)))
+        ));
+        assert_eq!(written(&state, id).len(), limit);
+    }
+
+    #[test]
+    fn writes_after_finish_report_a_closed_stream() {
+        let mut state = State::default();
+        let id = state.new_id();
+        let pipe = Pipe::shared(BUF_LIMIT);
+        pipe.lock().unwrap().streaming = true;
+        state.response_bodies.insert(id, pipe);
+
+        assert!(matches!(
+            HostOutputStream::write(&mut state, Resource::new_borrow(id), b"sent".to_vec()),
+            Ok(Ok(()))
+        ));
+        assert!(matches!(
+            HostOutgoingBody::finish(&mut state, Resource::new_borrow(id), None),
+            Ok(Ok(()))
+        ));
+
+        let stream = || Resource::<OutputStream>::new_borrow(id);
+
+        assert!(matches!(
+            HostOutputStream::check_write(&mut state, stream()),
+            Ok(Err(StreamError::Closed))
+        ));
+        assert!(matches!(
+            HostOutputStream::write(&mut state, stream(), b"lost".to_vec()),
+            Ok(Err(StreamError::Closed))
+        ));
+        assert!(matches!(
+            HostOutputStream::blocking_write_and_flush(&mut state, stream(), b"lost".to_vec()),
+            Ok(Err(StreamError::Closed))
+        ));
+
+        // A guest waiting to write is woken to find the stream closed, not left blocked.
+        let pollable = HostOutputStream::subscribe(&mut state, stream()).unwrap();
+        let mut ready = state.pollables.remove(&pollable.rep()).unwrap();
+        assert!(ready.ready(&mut state).unwrap());
+
+        assert_eq!(written(&state, id), b"sent");
+    }
+
+    #[test]
+    fn splices_move_what_the_source_has_ready() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![
+                Ok(Frame::data(Bytes::from_static(b"hello "))),
+                Ok(Frame::data(Bytes::from_static(b"world"))),
+            ],
+        );
+        let id = state.new_id();
+        state.response_bodies.insert(id, Pipe::shared(BUF_LIMIT));
+
+        let splice = |state: &mut State, len| {
+            HostOutputStream::splice(
+                state,
+                Resource::new_borrow(id),
+                Resource::new_borrow(stream.rep()),
+                len,
+            )
+            .unwrap()
+        };
+
+        // Never more than `len` at a time, and what is left of a frame comes next.
+        assert!(matches!(splice(&mut state, 3), Ok(3)));
+        assert!(matches!(splice(&mut state, 64), Ok(3)));
+        assert!(matches!(splice(&mut state, 64), Ok(5)));
+        assert!(matches!(splice(&mut state, 64), Err(StreamError::Closed)));
+
+        assert_eq!(written(&state, id), b"hello world");
+    }
+
+    #[test]
+    fn splices_stop_at_a_full_destination() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![Ok(Frame::data(Bytes::from_static(b"more")))],
+        );
+        let id = state.new_id();
+        // Only a body that is being sent waits for room.
+        let pipe = Pipe::shared(BUF_LIMIT);
+        pipe.lock().unwrap().streaming = true;
+        state.response_bodies.insert(id, pipe);
+
+        assert!(matches!(
+            HostOutputStream::write(&mut state, Resource::new_borrow(id), vec![0; BUF_LIMIT]),
+            Ok(Ok(()))
+        ));
+
+        // Nothing is read when nothing could be written, so no bytes are lost.
+        assert!(matches!(
+            HostOutputStream::splice(
+                &mut state,
+                Resource::new_borrow(id),
+                Resource::new_borrow(stream.rep()),
+                64
+            ),
+            Ok(Ok(0))
+        ));
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes == b"more"));
+    }
+
+    /// The body `stream` was taken from, which shares its handle.
+    fn body_of(stream: &Resource<InputStream>) -> Resource<IncomingBody> {
+        Resource::new_borrow(stream.rep())
+    }
+
+    fn trailers() -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        trailers
+    }
+
+    #[test]
+    fn a_body_has_only_one_stream() {
+        let mut state = State::default();
+        let stream = stream_of(&mut state, vec![Ok(Frame::data(Bytes::from("ab")))]);
+
+        assert!(matches!(
+            HostIncomingBody::stream(&mut state, body_of(&stream)),
+            Ok(Err(()))
+        ));
+
+        HostInputStream::drop(&mut state, Resource::new_own(stream.rep())).unwrap();
+
+        assert!(matches!(
+            HostIncomingBody::stream(&mut state, body_of(&stream)),
+            Ok(Err(()))
+        ));
+    }
+
+    #[test]
+    fn finishing_needs_the_stream_dropped() {
+        let mut state = State::default();
+        let stream = stream_of(&mut state, vec![Ok(Frame::data(Bytes::from("ab")))]);
+
+        assert!(HostIncomingBody::finish(&mut state, Resource::new_own(stream.rep())).is_err());
+    }
+
+    #[test]
+    fn trailers_arrive_after_a_stream_dropped_early() {
+        let mut state = State::default();
+        let stream = stream_of(
+            &mut state,
+            vec![
+                Ok(Frame::data(Bytes::from("unread"))),
+                Ok(Frame::data(Bytes::from("data"))),
+                Ok(Frame::trailers(trailers())),
+            ],
+        );
+
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes == b"unread"));
+        assert!(!state.incoming[&stream.rep()].state.ended());
+
+        HostInputStream::drop(&mut state, Resource::new_own(stream.rep())).unwrap();
+        let future = HostIncomingBody::finish(&mut state, Resource::new_own(stream.rep())).unwrap();
+
+        let Ok(Some(Ok(Some(trailers)))) =
+            HostFutureTrailers::get(&mut state, Resource::new_borrow(future.rep()))
+        else {
+            panic!("the trailers were lost");
+        };
+
+        let entries = HostFields::entries(&mut state, trailers).unwrap();
+        assert_eq!(entries, [("x-checksum".to_owned(), b"abc".to_vec())]);
+        assert!(state.incoming[&stream.rep()].state == BodyState::Consumed);
+    }
+
+    #[test]
+    fn finishing_a_body_without_trailers() {
+        let mut state = State::default();
+        let stream = stream_of(&mut state, vec![Ok(Frame::data(Bytes::from("ab")))]);
+
+        assert!(matches!(read(&mut state, &stream), Ok(bytes) if bytes == b"ab"));
+        HostInputStream::drop(&mut state, Resource::new_own(stream.rep())).unwrap();
+
+        let future = HostIncomingBody::finish(&mut state, Resource::new_own(stream.rep())).unwrap();
+        assert!(matches!(
+            HostFutureTrailers::get(&mut state, Resource::new_borrow(future.rep())),
+            Ok(Some(Ok(None)))
+        ));
+
+        // The future is spent once it answered.
+        assert!(HostFutureTrailers::get(&mut state, Resource::new_borrow(future.rep())).is_err());
+    }
+
+    #[test]
+    fn polls_report_every_ready_pollable() {
+        use crate::wasi::{clocks::monotonic_clock::Host as _, io::poll::Host as _};
+
+        let mut state = State::default();
+        let first = state.subscribe_instant(0).unwrap();
+        let pending = state.subscribe_duration(u64::MAX).unwrap();
+        let second = state.subscribe_instant(0).unwrap();
+        let borrow = |pollable: &Resource<Pollable>| Resource::new_borrow(pollable.rep());
+
+        // Both ready ones come back from the one call, and a repeat at each of its indices.
+        let ready = state
+            .poll(vec![
+                borrow(&first),
+                borrow(&pending),
+                borrow(&second),
+                borrow(&first),
+            ])
+            .unwrap();
+        assert_eq!(ready, [0, 2, 3]);
+
+        // The pollables are handed back, so they can be polled again.
+        let ready = state.poll(vec![borrow(&second), borrow(&first)]).unwrap();
+        assert_eq!(ready, [0, 1]);
     }
 }
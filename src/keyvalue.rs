@@ -0,0 +1,367 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+use wasmtime::component::Resource;
+
+use crate::{
+    wasi::{
+        self,
+        keyvalue::store::{Bucket, Error, KeyResponse},
+    },
+    State,
+};
+
+/// Keys returned by one `list-keys` call.
+const PAGE_SIZE: usize = 1000;
+
+/// Storage for the `wasi:keyvalue` interfaces. Namespaces keep the buckets apart.
+pub trait KvBackend: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    fn set(&self, namespace: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()>;
+
+    fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()>;
+
+    fn exists(&self, namespace: &str, key: &str) -> anyhow::Result<bool> {
+        Ok(self.get(namespace, key)?.is_some())
+    }
+
+    /// Returns up to `limit` keys in order, starting after `cursor`.
+    fn list_keys(
+        &self,
+        namespace: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>>;
+
+    /// Adds `delta` to the decimal number stored under `key`, treating a missing key as zero.
+    fn increment(&self, namespace: &str, key: &str, delta: u64) -> anyhow::Result<u64>;
+}
+
+/// The buckets guests may open and the backend holding them.
+pub struct KeyValue {
+    backend: Arc<dyn KvBackend>,
+    /// Bucket names mapped to the namespace they are stored under.
+    buckets: HashMap<String, String>,
+}
+
+impl KeyValue {
+    pub fn new(backend: impl KvBackend + 'static) -> Self {
+        Self::shared(Arc::new(backend))
+    }
+
+    /// Like [`KeyValue::new`], for a backend that several runners store their buckets in.
+    pub fn shared(backend: Arc<dyn KvBackend>) -> Self {
+        Self {
+            backend,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Allows guests to open `name`, storing its keys under `namespace`.
+    pub fn bucket(mut self, name: &str, namespace: &str) -> Self {
+        self.buckets.insert(name.to_owned(), namespace.to_owned());
+        self
+    }
+}
+
+/// Keeps every bucket in memory, shared by all requests until the runner exits.
+#[derive(Default)]
+pub struct MemoryBackend {
+    namespaces: RwLock<HashMap<String, BTreeMap<String, Vec<u8>>>>,
+}
+
+impl KvBackend for MemoryBackend {
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let namespaces = self.namespaces.read().unwrap();
+
+        Ok(namespaces
+            .get(namespace)
+            .and_then(|entries| entries.get(key))
+            .cloned())
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        self.namespaces
+            .write()
+            .unwrap()
+            .entry(namespace.to_owned())
+            .or_default()
+            .insert(key.to_owned(), value);
+
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        if let Some(entries) = self.namespaces.write().unwrap().get_mut(namespace) {
+            entries.remove(key);
+        }
+
+        Ok(())
+    }
+
+    fn list_keys(
+        &self,
+        namespace: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let namespaces = self.namespaces.read().unwrap();
+
+        let Some(entries) = namespaces.get(namespace) else {
+            return Ok(Vec::new());
+        };
+
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+
+        Ok(entries
+            .range::<str, _>((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    fn increment(&self, namespace: &str, key: &str, delta: u64) -> anyhow::Result<u64> {
+        let mut namespaces = self.namespaces.write().unwrap();
+        let entries = namespaces.entry(namespace.to_owned()).or_default();
+
+        let value = add(entries.get(key).map(Vec::as_slice), delta)?;
+        entries.insert(key.to_owned(), value.to_string().into_bytes());
+
+        Ok(value)
+    }
+}
+
+/// Keeps every bucket in a redb database file, one table per namespace.
+#[cfg(feature = "redb")]
+pub struct RedbBackend {
+    db: redb::Database,
+}
+
+#[cfg(feature = "redb")]
+impl RedbBackend {
+    pub fn open(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: redb::Database::create(path)?,
+        })
+    }
+}
+
+#[cfg(feature = "redb")]
+fn table(namespace: &str) -> redb::TableDefinition<&str, &[u8]> {
+    redb::TableDefinition::new(namespace)
+}
+
+#[cfg(feature = "redb")]
+impl KvBackend for RedbBackend {
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+
+        let table = match txn.open_table(table(namespace)) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let value = table.get(key)?;
+
+        Ok(value.map(|value| value.value().to_vec()))
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(table(namespace))?
+            .insert(key, value.as_slice())?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(table(namespace))?.remove(key)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn list_keys(
+        &self,
+        namespace: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let txn = self.db.begin_read()?;
+
+        let table = match txn.open_table(table(namespace)) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+
+        let mut keys = Vec::new();
+
+        for entry in table.range::<&str>((start, Bound::Unbounded))?.take(limit) {
+            let (key, _) = entry?;
+            keys.push(key.value().to_owned());
+        }
+
+        Ok(keys)
+    }
+
+    fn increment(&self, namespace: &str, key: &str, delta: u64) -> anyhow::Result<u64> {
+        let txn = self.db.begin_write()?;
+
+        let value = {
+            let mut table = txn.open_table(table(namespace))?;
+
+            let current = table.get(key)?.map(|value| value.value().to_vec());
+            let value = add(current.as_deref(), delta)?;
+
+            table.insert(key, value.to_string().as_bytes())?;
+
+            value
+        };
+
+        txn.commit()?;
+
+        Ok(value)
+    }
+}
+
+fn add(current: Option<&[u8]>, delta: u64) -> anyhow::Result<u64> {
+    let current = match current {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| anyhow::Error::msg("The value is not a number"))?,
+        None => 0,
+    };
+
+    current
+        .checked_add(delta)
+        .ok_or_else(|| anyhow::Error::msg("The value would overflow"))
+}
+
+impl State {
+    fn bucket(&self, bucket: &Resource<Bucket>) -> wasmtime::Result<(&dyn KvBackend, &str)> {
+        let namespace = self
+            .buckets
+            .get(&bucket.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find bucket"))?;
+
+        // A bucket can only be opened while a store is configured.
+        let kv = self
+            .kv
+            .as_ref()
+            .ok_or_else(|| wasmtime::Error::msg("No key-value store is configured"))?;
+
+        Ok((&*kv.backend, namespace))
+    }
+}
+
+fn other(err: anyhow::Error) -> Error {
+    Error::Other(err.to_string())
+}
+
+impl wasi::keyvalue::store::Host for State {
+    fn open(&mut self, identifier: String) -> wasmtime::Result<Result<Resource<Bucket>, Error>> {
+        let Some(kv) = &self.kv else {
+            return Ok(Err(Error::NoSuchStore));
+        };
+
+        let Some(namespace) = kv.buckets.get(&identifier).cloned() else {
+            return Ok(Err(Error::AccessDenied));
+        };
+
+        let id = self.new_id();
+        self.buckets.insert(id, namespace);
+
+        Ok(Ok(Resource::new_own(id)))
+    }
+}
+
+impl wasi::keyvalue::store::HostBucket for State {
+    fn get(
+        &mut self,
+        self_: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<Option<Vec<u8>>, Error>> {
+        let (backend, namespace) = self.bucket(&self_)?;
+
+        Ok(backend.get(namespace, &key).map_err(other))
+    }
+
+    fn set(
+        &mut self,
+        self_: Resource<Bucket>,
+        key: String,
+        value: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let (backend, namespace) = self.bucket(&self_)?;
+
+        Ok(backend.set(namespace, &key, value).map_err(other))
+    }
+
+    fn delete(
+        &mut self,
+        self_: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let (backend, namespace) = self.bucket(&self_)?;
+
+        Ok(backend.delete(namespace, &key).map_err(other))
+    }
+
+    fn exists(
+        &mut self,
+        self_: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<bool, Error>> {
+        let (backend, namespace) = self.bucket(&self_)?;
+
+        Ok(backend.exists(namespace, &key).map_err(other))
+    }
+
+    fn list_keys(
+        &mut self,
+        self_: Resource<Bucket>,
+        cursor: Option<String>,
+    ) -> wasmtime::Result<Result<KeyResponse, Error>> {
+        let (backend, namespace) = self.bucket(&self_)?;
+
+        Ok(backend
+            .list_keys(namespace, cursor.as_deref(), PAGE_SIZE)
+            .map(|keys| KeyResponse {
+                cursor: (keys.len() == PAGE_SIZE)
+                    .then(|| keys.last().cloned())
+                    .flatten(),
+                keys,
+            })
+            .map_err(other))
+    }
+
+    fn drop(&mut self, rep: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.buckets.remove(&rep.rep());
+
+        Ok(())
+    }
+}
+
+impl wasi::keyvalue::atomics::Host for State {
+    fn increment(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        delta: u64,
+    ) -> wasmtime::Result<Result<u64, Error>> {
+        let (backend, namespace) = self.bucket(&bucket)?;
+
+        Ok(backend.increment(namespace, &key, delta).map_err(other))
+    }
+}
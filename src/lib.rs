@@ -1,25 +1,152 @@
-use std::{collections::HashMap, sync::OnceLock, time::Instant};
+use std::{
+    any::Any,
+    collections::HashMap,
+    ops::ControlFlow,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
-use ::http::{HeaderMap, HeaderValue, Request, Response};
-use http::{IncomingBodyWrapper, Outgoing};
-use hyper::body::Incoming;
+use ::http::{
+    header::{
+        ALLOW, CONNECTION, CONTENT_LENGTH, DATE, ORIGIN, RETRY_AFTER, SERVER, TRANSFER_ENCODING,
+    },
+    request::Parts,
+    response, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri,
+};
+use cache::ResponseCache;
+use conditional::Validators;
+use deploy::Slots;
+use dump::DumpBody;
+use futures::future::BoxFuture;
+use http::{drain, BodyState, DeclaredLength, IncomingBodyWrapper, Outgoing};
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    body::{Body, Bytes},
+    ext::ReasonPhrase,
+};
 use io::PollableIndividual;
+use limits::MemoryLimiter;
+use metrics::Timings;
+use pipe::Pipe;
+use pool::GuestPool;
+use problem::HostError;
+use queue::{Queue, Shed};
+use range::RangeRequest;
+use record::ResponseCopy;
+use rewrite::Substitutions;
+use shared_cache::SharedCache;
+use spool::SpoolBody;
+use tokio::sync::oneshot;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 use wasmtime::{
-    component::{bindgen, Component, Linker, Resource},
-    AsContext, AsContextMut, Config, Engine, Store,
+    component::{bindgen, Component, InstancePre, Linker, Resource},
+    AsContextMut, Config, Engine, Store, StoreContextMut,
 };
 
 bindgen!();
 
+mod bench;
+mod body;
+mod breaker;
+mod cache;
+mod cli;
+mod clock;
+#[cfg(feature = "clocks")]
 mod clocks;
+mod conditional;
+mod config;
+mod cors;
+#[cfg(feature = "wasmtime-wasi-impl")]
+mod delegate;
+mod deploy;
+mod dns;
+mod dump;
+mod fetch;
+#[cfg(feature = "filesystem")]
+mod filesystem;
+#[cfg(feature = "host-log")]
+mod host_log;
 mod http;
+mod inspect;
+mod invoke;
 mod io;
+mod keyvalue;
+mod limits;
+mod logging;
+mod metrics;
+mod mirror;
+mod mount;
+#[cfg(feature = "oci")]
+mod oci;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod outbound;
+mod paths;
+mod pipe;
+mod pool;
+mod problem;
+mod proxy;
+mod queue;
+mod random;
+mod range;
+mod ratelimit;
+mod record;
+mod rewrite;
+mod send_file;
+mod service;
+mod shared_cache;
+mod sockets;
+mod spool;
+mod static_files;
+mod subscriber;
+pub mod testing;
+mod trace;
+mod upstream;
+
+pub use bench::{bench, BenchOptions, BenchReport};
+pub use clock::{ClockSource, ManualClock, SystemClock};
+pub use config::GuestConfig;
+pub use cors::{AllowedOrigin, Cors};
+pub use deploy::{Sticky, Version};
+pub use dns::{DnsOptions, DnsResolver, IpPreference};
+pub use fetch::Fetch;
+pub use http::{BoxError, LengthMismatch, MethodPolicy, RequestBody, ResponseBody, UnreadBody};
+pub use inspect::{inspect, Import, Inspection};
+pub use invoke::Invocation;
+#[cfg(feature = "redb")]
+pub use keyvalue::RedbBackend;
+pub use keyvalue::{KeyValue, KvBackend, MemoryBackend};
+pub use metrics::{Counter, Gauge, Histogram, Metrics};
+pub use mirror::Mirror;
+pub use mount::Mounts;
+pub use outbound::OutboundMock;
+pub use paths::{PathFilter, PathGlob};
+pub use problem::ErrorFormat;
+pub use proxy::{NoProxy, Proxy, ProxyOptions};
+pub use ratelimit::{ClientAddr, RateLimit};
+pub use record::{Recorded, RecordedResponse, Recording};
+pub use rewrite::{HeaderEdits, HeaderRules, HeaderTemplate};
+pub use service::WasiHttpService;
+pub use sockets::EgressRule;
+pub use static_files::StaticDir;
+pub use subscriber::{LogFormat, LogOptions};
+pub use trace::TraceContext;
 
 pub struct State {
     errors: HashMap<u32, std::io::Error>,
     fields: HashMap<u32, (bool, HeaderMap<HeaderValue>)>,
-    requests: HashMap<u32, Request<hyper::body::Incoming>>,
-    responses: HashMap<u32, Response<Outgoing>>,
+    requests: HashMap<u32, Request<RequestBody>>,
+    /// The heads of responses the guest is building. Their bodies are kept apart, as the guest
+    /// may go on writing one after setting the response.
+    responses: HashMap<u32, Response<()>>,
+    response_bodies: HashMap<u32, Arc<Mutex<Pipe>>>,
+    /// The body of the response that was set, if the guest had not finished it by then.
+    response_pipe: Option<Arc<Mutex<Pipe>>>,
+    /// Where a response set before its body is finished goes, so it is sent while the guest
+    /// writes the rest.
+    handoff: Option<Handoff>,
 
     incoming: HashMap<u32, IncomingBodyWrapper>,
 
@@ -27,6 +154,58 @@ pub struct State {
 
     full_responses: HashMap<u32, Option<Response<Outgoing>>>,
 
+    /// The request being handled, whose body is kept if the guest drops it before the end.
+    request_id: Option<u32>,
+    unread_body: Option<RequestBody>,
+
+    tees: HashMap<u32, u32>,
+
+    max_body_bytes: usize,
+    max_response_buffer_bytes: usize,
+    preserve_method_case: bool,
+    /// Directories `bluezeeking:service/files` may send files from.
+    file_dirs: Vec<PathBuf>,
+
+    config: GuestConfig,
+    kv: Option<Arc<KeyValue>>,
+    shared_cache: Arc<SharedCache>,
+    /// Open buckets and the namespace each is stored under.
+    buckets: HashMap<u32, String>,
+
+    tcp_egress: Vec<EgressRule>,
+    tcp_sockets: HashMap<u32, sockets::TcpConn>,
+    tcp_readers: HashMap<u32, sockets::TcpReader>,
+    tcp_writers: HashMap<u32, sockets::TcpWriter>,
+    resolvers: HashMap<u32, sockets::Resolver>,
+
+    outbound: Option<outbound::Outbound>,
+    /// The span of the request being handled, which outbound requests are sent from.
+    trace: Option<TraceContext>,
+    tracestate: Option<HeaderValue>,
+    outgoing_requests: HashMap<u32, outbound::OutboundRequest>,
+    outgoing_responses: HashMap<u32, outbound::FutureResponse>,
+    incoming_responses: HashMap<u32, Response<RequestBody>>,
+    request_options: HashMap<u32, outbound::Timeouts>,
+    upstream_limits: upstream::UpstreamLimits,
+
+    /// How many more guest log messages this request may emit.
+    log_budget: usize,
+    logs_suppressed: usize,
+
+    timings: Timings,
+    limiter: MemoryLimiter,
+    clock: Arc<dyn ClockSource>,
+    /// The components each middleware wraps, by the middleware's position in the chain.
+    layers: Vec<Option<Service>>,
+
+    stdio: HashMap<u32, cli::Stdio>,
+    /// Output written to stdout and stderr since the last newline.
+    stdout_line: Vec<u8>,
+    stderr_line: Vec<u8>,
+
+    #[cfg(feature = "wasmtime-wasi-impl")]
+    delegated: delegate::Delegated,
+
     current_id: u32,
 }
 
@@ -37,9 +216,47 @@ impl Default for State {
             fields: HashMap::new(),
             requests: HashMap::new(),
             responses: HashMap::new(),
+            response_bodies: HashMap::new(),
+            response_pipe: None,
+            handoff: None,
             incoming: HashMap::new(),
             pollables: HashMap::new(),
             full_responses: HashMap::new(),
+            request_id: None,
+            unread_body: None,
+            tees: HashMap::new(),
+            max_body_bytes: Options::default().max_body_bytes,
+            max_response_buffer_bytes: Options::default().max_response_buffer_bytes,
+            preserve_method_case: false,
+            file_dirs: Vec::new(),
+            config: GuestConfig::default(),
+            kv: None,
+            shared_cache: Arc::new(SharedCache::new(0)),
+            buckets: HashMap::new(),
+            tcp_egress: Vec::new(),
+            tcp_sockets: HashMap::new(),
+            tcp_readers: HashMap::new(),
+            tcp_writers: HashMap::new(),
+            resolvers: HashMap::new(),
+            outbound: None,
+            trace: None,
+            tracestate: None,
+            outgoing_requests: HashMap::new(),
+            outgoing_responses: HashMap::new(),
+            incoming_responses: HashMap::new(),
+            request_options: HashMap::new(),
+            upstream_limits: upstream::UpstreamLimits::default(),
+            log_budget: Options::default().max_guest_logs,
+            logs_suppressed: 0,
+            limiter: MemoryLimiter::default(),
+            clock: Arc::new(SystemClock),
+            layers: Vec::new(),
+            stdio: HashMap::new(),
+            stdout_line: Vec::new(),
+            stderr_line: Vec::new(),
+            #[cfg(feature = "wasmtime-wasi-impl")]
+            delegated: delegate::Delegated::default(),
+            timings: Timings::default(),
             current_id: 0,
         }
     }
@@ -50,65 +267,1991 @@ impl State {
         self.current_id += 1;
         self.current_id
     }
+
+    /// Takes back the request body if the guest never read from it and never responded.
+    fn take_unread_body(&mut self, req_id: u32, res_id: u32) -> Option<RequestBody> {
+        if let Some(Some(_)) = self.full_responses.get(&res_id) {
+            return None;
+        }
+
+        if let Some(req) = self.requests.remove(&req_id) {
+            return Some(req.into_body());
+        }
+
+        let body = self.incoming.remove(&req_id)?;
+
+        (body.state == BodyState::New && body.last_frame.is_none()).then_some(body.incoming)
+    }
+
+    fn keep_unread(&mut self, id: u32, body: RequestBody) {
+        if self.request_id == Some(id) && !body.is_end_stream() {
+            self.unread_body = Some(body);
+        }
+    }
+
+    /// What is left of the request body once the guest has responded, whether it dropped the
+    /// body or still holds it.
+    fn take_remaining_body(&mut self, req_id: u32) -> Option<RequestBody> {
+        let body = match self.requests.remove(&req_id) {
+            Some(req) => req.into_body(),
+            None => match self.incoming.remove(&req_id) {
+                Some(body) if body.state.ended() => return None,
+                Some(body) => body.incoming,
+                None => self.unread_body.take()?,
+            },
+        };
+
+        (!body.is_end_stream()).then_some(body)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Maximum number of requests running inside the guest at once. No more than `guest_threads`
+    /// ever do.
+    pub max_concurrency: usize,
+    /// Threads set aside for running the guest.
+    pub guest_threads: usize,
+    /// Maximum number of requests waiting for a free slot before new ones are shed.
+    pub queue_depth: usize,
+    /// Longest a request may wait in the queue before it is shed.
+    pub max_queue_wait: Duration,
+    /// Maximum number of request body bytes the host will buffer on behalf of the guest.
+    pub max_body_bytes: usize,
+    /// Most of a response body the host holds before the response is sent. Writing more before
+    /// then fails.
+    pub max_response_buffer_bytes: usize,
+    /// Consecutive guest failures after which the circuit opens. Zero disables the breaker.
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a probe request is let through.
+    pub circuit_cooldown: Duration,
+    /// How many times an idempotent request is retried on a fresh instance after a trap.
+    pub max_retries: u32,
+    /// No retry is started once a request has been running for this long.
+    pub retry_budget: Duration,
+    /// Paths requested with `GET` on a component version before it is activated.
+    pub warmup: Vec<String>,
+    /// Logs up to this many bytes of every request and response body at debug level. Zero
+    /// disables dumping.
+    pub dump_bodies: usize,
+    /// Logs the head and trailers of every request as the guest saw it and of every response as
+    /// it produced it, at debug level.
+    pub dump_heads: bool,
+    /// Headers whose values are left out of dumps.
+    pub dump_redact: Vec<HeaderName>,
+    /// Total size of the response cache. Zero disables caching.
+    pub cache_max_bytes: usize,
+    /// Responses with larger bodies are never cached.
+    pub cache_max_entry_bytes: usize,
+    /// Directories served by the host for matching path prefixes, before the guest is consulted.
+    pub static_dirs: Vec<StaticDir>,
+    /// Directories guests may stream response bodies from through `bluezeeking:service/files`,
+    /// without the bytes passing through the guest.
+    pub file_dirs: Vec<PathBuf>,
+    /// Request bodies that may be larger than this are copied to a temp file as they arrive and
+    /// read from there by the guest. Zero disables spooling.
+    pub spool_threshold: usize,
+    /// Loads `.cwasm` files as components precompiled by [`compile`]. They are deserialized
+    /// without validation, so only enable this for artifacts from a trusted build.
+    pub allow_precompiled: bool,
+    /// How the bodies of responses the host produces in place of the guest are written.
+    pub error_format: ErrorFormat,
+    /// What happens to request bodies the guest responds without reading.
+    pub unread_body: UnreadBody,
+    /// Unread request bodies larger than this are not drained but close the connection.
+    pub max_drain_bytes: usize,
+    /// What happens to a response whose body is longer or shorter than its `Content-Length`.
+    pub length_mismatch: LengthMismatch,
+    /// Which request methods reach the guest. The rest get `405 Method Not Allowed`.
+    pub methods: MethodPolicy,
+    /// Middleware components the main one is wrapped in, outermost first. Each exports
+    /// `wasi:http/incoming-handler` and imports it to pass requests on to the next.
+    pub middleware: Vec<PathBuf>,
+    /// Which request paths reach the guest. The rest get `404 Not Found`.
+    pub paths: PathFilter,
+    /// Answers CORS preflights on the host and adds `Access-Control-Allow-*` headers to responses.
+    pub cors: Option<Cors>,
+    /// Header edits made to requests before the guest sees them and to its responses.
+    pub headers: HeaderRules,
+    /// The time guests see through `wasi:clocks`. A [`ManualClock`] makes it deterministic.
+    pub clock: Arc<dyn ClockSource>,
+    /// Fuel each request starts with; running out traps the guest. `None` turns metering off.
+    pub fuel: Option<u64>,
+    /// Reports the fuel a response took in an `x-wasm-fuel-used` header.
+    pub fuel_header: bool,
+    /// Entries the `bluezeeking:service/cache` interface holds before refusing new keys.
+    pub shared_cache_entries: usize,
+    /// Linear memory each instance may grow to, across all of its memories. `None` is no cap.
+    pub max_memory_bytes: Option<usize>,
+    /// Messages a guest may log through `wasi:logging` per request. Later ones are counted and
+    /// reported once the request ends.
+    pub max_guest_logs: usize,
+    /// Shows guests a request's method token exactly as it arrived, so a lowercase `get` is
+    /// `other("get")` rather than `get`.
+    pub preserve_method_case: bool,
+    /// Values guests read through `wasi:config/store`.
+    pub guest_config: GuestConfig,
+    /// Answers `Range` requests from full responses of known length, for guests that don't.
+    pub ranges: bool,
+    /// Addresses guests may open TCP connections to through `wasi:sockets`. Empty denies all.
+    pub tcp_egress: Vec<EgressRule>,
+    /// Authorities guests may send HTTP requests to through `wasi:http/outgoing-handler`, as
+    /// `host` or `host:port`. Empty denies all.
+    pub http_egress: Vec<String>,
+    /// How the hosts of outbound HTTP requests are resolved.
+    pub dns: DnsOptions,
+    /// Proxies outbound HTTP requests go through.
+    pub proxy: ProxyOptions,
+    /// Upstream response bodies longer than this fail the guest's read with
+    /// `http-response-body-size`. `None` is no cap.
+    pub max_upstream_body_bytes: Option<u64>,
+    /// Response body bytes all of a guest request's outbound requests may receive together.
+    pub max_upstream_total_bytes: Option<u64>,
+    /// Adds a `Date` header to guest responses that lack one.
+    pub date_header: bool,
+    /// Sent as the `Server` header of guest responses that lack one.
+    pub server_header: Option<HeaderValue>,
+    /// Requests whose target is longer than this get `414 URI Too Long`.
+    pub max_uri_bytes: usize,
+    /// Requests whose header fields add up to more than this get `431 Request Header Fields Too
+    /// Large`. Each field counts its name, its value and four bytes of framing.
+    pub max_header_bytes: usize,
+    /// Requests with more header fields than this also get `431`, so the copy the guest sees
+    /// stays small. Repeated names count once per value.
+    pub max_headers: usize,
+    /// Limits how fast each client IP may send requests. Requests over the limit get `429 Too
+    /// Many Requests` before the guest runs.
+    pub rate_limit: Option<RateLimit>,
+    /// Records every request the guest handles, with its response, for replaying later.
+    pub record: Option<Recording>,
+}
+
+impl Options {
+    /// Limits suited to a fallback component, which never sees a request body. It runs within
+    /// the primary's concurrency slot when the primary fails, and within its own, which are
+    /// fewer, while the primary's circuit is open.
+    pub fn fallback() -> Self {
+        Self {
+            max_concurrency: 8,
+            queue_depth: 16,
+            max_body_bytes: 0,
+            failure_threshold: 0,
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Limits suited to a shadow component: no queue, so a slow shadow sheds mirrored requests
+    /// instead of piling them up.
+    pub fn shadow() -> Self {
+        Self {
+            max_concurrency: 8,
+            queue_depth: 0,
+            failure_threshold: 0,
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 64,
+            guest_threads: std::thread::available_parallelism().map_or(4, |threads| threads.get()),
+            queue_depth: 128,
+            max_queue_wait: Duration::from_secs(5),
+            max_body_bytes: 16 * 1024 * 1024,
+            max_response_buffer_bytes: 16 * 1024 * 1024,
+            failure_threshold: 5,
+            circuit_cooldown: Duration::from_secs(30),
+            max_retries: 0,
+            retry_budget: Duration::from_secs(1),
+            warmup: Vec::new(),
+            dump_bodies: 0,
+            dump_heads: false,
+            dump_redact: dump::sensitive_headers(),
+            cache_max_bytes: 0,
+            cache_max_entry_bytes: 1024 * 1024,
+            static_dirs: Vec::new(),
+            file_dirs: Vec::new(),
+            spool_threshold: 0,
+            allow_precompiled: false,
+            error_format: ErrorFormat::Text,
+            unread_body: UnreadBody::Drain,
+            max_drain_bytes: 1024 * 1024,
+            length_mismatch: LengthMismatch::Fail,
+            methods: MethodPolicy::Any,
+            middleware: Vec::new(),
+            paths: PathFilter::default(),
+            cors: None,
+            headers: HeaderRules::default(),
+            clock: Arc::new(SystemClock),
+            fuel: None,
+            fuel_header: false,
+            shared_cache_entries: 10_000,
+            max_memory_bytes: None,
+            max_guest_logs: 1000,
+            preserve_method_case: false,
+            guest_config: GuestConfig::default(),
+            ranges: false,
+            tcp_egress: Vec::new(),
+            http_egress: Vec::new(),
+            dns: DnsOptions::default(),
+            proxy: ProxyOptions::default(),
+            max_upstream_body_bytes: None,
+            max_upstream_total_bytes: None,
+            date_header: true,
+            server_header: None,
+            max_uri_bytes: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            rate_limit: None,
+            record: None,
+        }
+    }
 }
 
-pub async fn service_fn(req: Request<Incoming>) -> anyhow::Result<Response<Outgoing>> {
-    tokio::task::spawn_blocking(move || blocking_service(req))
-        .await
-        .unwrap()
+type ResponseHook =
+    Box<dyn Fn(Response<ResponseBody>, &RequestCtx) -> Response<ResponseBody> + Send + Sync>;
+
+/// What a response hook knows about the request the response answers.
+pub struct RequestCtx {
+    pub method: Method,
+    pub uri: Uri,
+    pub version: ::http::Version,
+    pub headers: HeaderMap,
 }
 
-fn blocking_service(req: Request<Incoming>) -> anyhow::Result<Response<Outgoing>> {
-    let (service, mut store) = instantiate()?;
-    let (req_id, res_id) = {
+type RequestHook = Box<
+    dyn for<'a> Fn(&'a mut Request<RequestBody>) -> BoxFuture<'a, ControlFlow<Response<Bytes>>>
+        + Send
+        + Sync,
+>;
+
+pub struct Runner {
+    engine: Engine,
+    linker: Linker<State>,
+    slots: Slots,
+    options: Options,
+    queue: Queue,
+    pool: GuestPool,
+    metrics: Metrics,
+    request_hooks: Vec<RequestHook>,
+    response_hooks: Vec<ResponseHook>,
+    fallback: Option<Box<Runner>>,
+    mirror: Option<Mirror>,
+    cache: Option<ResponseCache>,
+    kv: Option<Arc<KeyValue>>,
+    shared_cache: Arc<SharedCache>,
+    outbound: Option<outbound::Outbound>,
+    limiter: Option<ratelimit::Limiter>,
+    recorder: Option<record::Recorder>,
+    /// The components named by [`Options::middleware`], outermost first.
+    middleware: Vec<InstancePre<State>>,
+}
+
+impl Runner {
+    pub fn new(path: impl AsRef<Path>, options: Options) -> anyhow::Result<Self> {
+        let engine = engine(&options)?;
+        let component = read_component(&engine, path.as_ref(), &options, &[])?;
+
+        Self::with_component(engine, component, options)
+    }
+
+    /// Like [`Runner::new`], for a component that is already in memory, such as one embedded with
+    /// `include_bytes!`.
+    pub fn from_bytes(bytes: &[u8], options: Options) -> anyhow::Result<Self> {
+        check_imports(bytes, &[])?;
+
+        let engine = engine(&options)?;
+        let component = Component::from_binary(&engine, bytes)?;
+
+        Self::with_component(engine, component, options)
+    }
+
+    fn with_component(
+        engine: Engine,
+        component: Component,
+        options: Options,
+    ) -> anyhow::Result<Self> {
+        if let Some(cors) = &options.cors {
+            cors.validate().map_err(anyhow::Error::msg)?;
+        }
+
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker)?;
+
+        let middleware = options
+            .middleware
+            .iter()
+            .enumerate()
+            .map(|(layer, path)| {
+                let component = read_component(&engine, path, &options, &[inspect::HANDLER])?;
+
+                let mut linker = Linker::new(&engine);
+                add_to_linker(&mut linker)?;
+                link_layer(&mut linker, layer)?;
+
+                instantiate_pre(&linker, &component)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let slots = Slots::new(Version::new(
+            "default",
+            instantiate_pre(&linker, &component)?,
+            &options,
+        ));
+
+        // Requests beyond the pool's threads wait in the queue, where they can be shed.
+        let pool = GuestPool::new(options.guest_threads)?;
+        let queue = Queue::new(
+            options.max_concurrency.min(pool.threads()),
+            options.queue_depth,
+            options.max_queue_wait,
+        );
+
+        let cache = (options.cache_max_bytes > 0)
+            .then(|| ResponseCache::new(options.cache_max_bytes, options.cache_max_entry_bytes));
+
+        let outbound = (!options.http_egress.is_empty()).then(|| {
+            outbound::Outbound::new(options.http_egress.clone(), &options.dns, &options.proxy)
+        });
+        let limiter = options.rate_limit.map(ratelimit::Limiter::new);
+        let shared_cache = Arc::new(SharedCache::new(options.shared_cache_entries));
+        let recorder = options
+            .record
+            .clone()
+            .map(record::Recorder::new)
+            .transpose()?;
+
+        Ok(Self {
+            engine,
+            linker,
+            slots,
+            options,
+            queue,
+            pool,
+            metrics: Metrics::default(),
+            request_hooks: Vec::new(),
+            response_hooks: Vec::new(),
+            fallback: None,
+            mirror: None,
+            cache,
+            kv: None,
+            shared_cache,
+            outbound,
+            limiter,
+            recorder,
+            middleware,
+        })
+    }
+
+    /// Sets a component that answers in place of this one when it fails or its circuit is open.
+    pub fn with_fallback(mut self, fallback: Runner) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    pub fn fallback(&self) -> Option<&Runner> {
+        self.fallback.as_deref()
+    }
+
+    /// Sends a sample of requests to a shadow component as well, discarding its responses.
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// Gives guests the `wasi:keyvalue` buckets of `kv`, shared by every request.
+    pub fn with_key_value(mut self, kv: KeyValue) -> Self {
+        self.kv = Some(Arc::new(kv));
+        self
+    }
+
+    pub fn mirror_target(&self) -> Option<&Runner> {
+        self.mirror.as_ref().map(Mirror::runner)
+    }
+
+    /// Answers the guest's outbound HTTP requests with `mock` wherever it returns a response,
+    /// without touching the network. Mocked requests bypass the egress rules.
+    pub fn with_outbound_mock(
+        mut self,
+        mock: impl Fn(&Request<Bytes>) -> Option<Response<Bytes>> + Send + Sync + 'static,
+    ) -> Self {
+        let outbound = self.outbound.take().unwrap_or_else(|| {
+            outbound::Outbound::new(Vec::new(), &self.options.dns, &self.options.proxy)
+        });
+
+        self.outbound = Some(outbound.with_mock(Arc::new(mock)));
+        self
+    }
+
+    /// Installs the runner's log subscriber for the whole process. Embedders that set up their
+    /// own leave this out.
+    pub fn with_logging(self, options: &LogOptions) -> anyhow::Result<Self> {
+        options.init()?;
+        Ok(self)
+    }
+
+    /// Registers a hook that can rewrite a request before the guest sees it, or answer it itself
+    /// with `ControlFlow::Break`, in which case neither the guest nor later hooks run. Hooks run
+    /// in the order they were registered, before the request is queued.
+    pub fn on_request(
+        mut self,
+        hook: impl Fn(&mut Request<RequestBody>) -> ControlFlow<Response<Bytes>> + Send + Sync + 'static,
+    ) -> Self {
+        self.request_hooks.push(Box::new(request_hook(move |req| {
+            Box::pin(std::future::ready(hook(req)))
+        })));
+        self
+    }
+
+    /// Like [`Runner::on_request`], for a hook that has to wait, such as on an auth service.
+    pub fn on_request_async(
+        mut self,
+        hook: impl for<'a> Fn(&'a mut Request<RequestBody>) -> BoxFuture<'a, ControlFlow<Response<Bytes>>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.request_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Registers a hook that can rewrite the status and headers of every response, once its head
+    /// is known and before any of its body is sent. Response hooks run in the order they were
+    /// registered.
+    pub fn on_response(
+        mut self,
+        hook: impl Fn(&mut response::Parts, &RequestCtx) + Send + Sync + 'static,
+    ) -> Self {
+        self.response_hooks.push(Box::new(move |res, ctx| {
+            let (mut parts, body) = res.into_parts();
+            hook(&mut parts, ctx);
+            Response::from_parts(parts, body)
+        }));
+        self
+    }
+
+    /// Registers a response hook that replaces the body, such as with one that counts or
+    /// compresses what passes through it.
+    pub fn wrap_response_body(
+        mut self,
+        hook: impl Fn(ResponseBody, &RequestCtx) -> ResponseBody + Send + Sync + 'static,
+    ) -> Self {
+        self.response_hooks
+            .push(Box::new(move |res, ctx| res.map(|body| hook(body, ctx))));
+        self
+    }
+
+    /// Compiles the component at `path` and keeps it under `slot` without sending it traffic.
+    pub fn load(&self, path: impl AsRef<Path>, slot: &str) -> anyhow::Result<()> {
+        let component = read_component(&self.engine, path.as_ref(), &self.options, &[])?;
+        let pre = instantiate_pre(&self.linker, &component)?;
+
+        self.slots.insert(Version::new(slot, pre, &self.options));
+
+        Ok(())
+    }
+
+    /// Sends all new requests to the version loaded under `slot`, once it instantiates and answers
+    /// every warmup request without a server error.
+    pub fn activate(&self, slot: &str) -> anyhow::Result<()> {
+        let version = self
+            .slots
+            .get(slot)
+            .ok_or_else(|| anyhow::Error::msg(format!("No component is loaded as {slot}")))?;
+
+        self.warm_up(&version)?;
+        self.slots.activate(version);
+
+        Ok(())
+    }
+
+    /// Switches back to the version that was active before the last activation.
+    pub fn rollback(&self) -> anyhow::Result<()> {
+        self.slots
+            .rollback()
+            .map(|_| ())
+            .ok_or_else(|| anyhow::Error::msg("There is no previous version to roll back to"))
+    }
+
+    /// Stops admitting requests and waits for those in the guest to finish, so that every store
+    /// and its linear memory has been dropped when it returns. Later requests are shed with 503.
+    pub async fn shutdown(&self) {
+        let mut runners = vec![self];
+
+        while let Some(runner) = runners.pop() {
+            runner.queue.close().await;
+            runners.extend(runner.fallback.as_deref());
+            runners.extend(runner.mirror_target());
+        }
+    }
+
+    /// Instantiates the active version once and sends it the warmup requests, so the first real
+    /// request does not pay for any one-time initialization.
+    pub fn warm_up_active(&self) -> anyhow::Result<()> {
+        self.warm_up(&self.slots.active())?;
+
+        if let Some(fallback) = &self.fallback {
+            fallback.warm_up_active()?;
+        }
+
+        if let Some(shadow) = self.mirror_target() {
+            shadow.warm_up_active()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn active_slot(&self) -> String {
+        self.slots.active().slot.clone()
+    }
+
+    pub fn versions(&self) -> Vec<Arc<Version>> {
+        self.slots.versions()
+    }
+
+    /// Sends `weight` percent of new requests to the version loaded under `slot`. With `sticky`,
+    /// requests carrying the same key always go to the same version.
+    pub fn split(&self, slot: &str, weight: u8, sticky: Option<Sticky>) -> anyhow::Result<()> {
+        let canary = self
+            .slots
+            .get(slot)
+            .ok_or_else(|| anyhow::Error::msg(format!("No component is loaded as {slot}")))?;
+
+        self.warm_up(&canary)?;
+        self.slots.set_split(canary, weight, sticky);
+
+        Ok(())
+    }
+
+    pub fn clear_split(&self) {
+        self.slots.clear_split();
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Whether the runner should currently receive traffic.
+    pub fn is_ready(&self) -> bool {
+        !self.slots.active().breaker.is_open()
+    }
+
+    pub async fn service_fn<B>(
+        self: Arc<Self>,
+        req: Request<B>,
+    ) -> anyhow::Result<Response<ResponseBody>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let ctx = (!self.response_hooks.is_empty()).then(|| RequestCtx {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+        });
+
+        let origin = self
+            .options
+            .cors
+            .as_ref()
+            .and_then(|_| req.headers().get(ORIGIN).cloned());
+
+        let mut res = self.clone().respond(req).await?;
+
+        if let Some(cors) = &self.options.cors {
+            cors.apply(origin.as_ref(), res.headers_mut());
+        }
+
+        let mut res = res.map(Outgoing::into_response_body);
+
+        if let Some(ctx) = ctx {
+            for hook in &self.response_hooks {
+                res = hook(res, &ctx);
+            }
+        }
+
+        Ok(res)
+    }
+
+    async fn respond<B>(self: Arc<Self>, req: Request<B>) -> anyhow::Result<Response<Outgoing>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        if let Some(mut res) = self
+            .check_head_size(&req)
+            .or_else(|| self.check_cors(&req))
+            .or_else(|| self.check_method(&req))
+            .or_else(|| self.check_path(&req))
+            .or_else(|| self.check_rate(&req))
+        {
+            self.options.error_format.render(&mut res);
+
+            return Ok(res);
+        }
+
+        let mut req = req.map(|body| body.map_err(Into::into).boxed_unsync());
+
+        for hook in &self.request_hooks {
+            if let ControlFlow::Break(res) = hook(&mut req).await {
+                return Ok(res.map(|body| Outgoing::full(body.to_vec())));
+            }
+        }
+
+        let validators = Validators::from_request(&req);
+        let range = self
+            .options
+            .ranges
+            .then(|| RangeRequest::from_request(&req))
+            .flatten();
+
+        if let Some(mut res) = self.serve_static(req.method(), req.uri().path()).await {
+            if let Some(validators) = validators {
+                validators.apply(&mut res);
+            }
+
+            if let Some(range) = range {
+                range.apply(&mut res);
+            }
+
+            self.options.error_format.render(&mut res);
+
+            return Ok(res);
+        }
+
+        let (req, mirrored) = self.mirror(req);
+        let (mut req, recorded) = self.record(req);
+
+        let trace = TraceContext::for_request(req.headers());
+
+        let span = info_span!(
+            "request",
+            otel.kind = "server",
+            otel.name = %req.method(),
+            http.request.method = %req.method(),
+            url.path = %req.uri().path(),
+            http.response.status_code = field::Empty,
+            trace_id = field::Empty,
+            span_id = field::Empty,
+            parent_id = trace.parent_id.map(|id| field::display(format!("{id:016x}"))),
+            queue_us = field::Empty,
+            exec_us = field::Empty,
+            instantiation_us = field::Empty,
+            guest_us = field::Empty,
+            wait_us = field::Empty,
+            drain_us = field::Empty,
+            fuel_used = field::Empty,
+            peak_memory_bytes = field::Empty,
+            retries = field::Empty,
+            version = field::Empty,
+        );
+
+        // Exported spans get their ids from OpenTelemetry, and outbound requests have to name
+        // those.
+        #[cfg(feature = "otel")]
+        let trace = otel::join(&span, trace, req.headers().get(trace::TRACESTATE));
+
+        span.record("trace_id", format_args!("{:032x}", trace.trace_id));
+        span.record("span_id", format_args!("{:016x}", trace.span_id));
+
+        // The guest sees the request's own `traceparent`, if any; its outbound requests carry
+        // this span's.
+        req.extensions_mut().insert(trace);
+
+        let error_format = self.options.error_format;
+        let mut res = async move {
+            self.metrics.requests.inc();
+
+            let lookup = self.cache.as_ref().and_then(|cache| cache.lookup(&req));
+
+            if let Some(res) = lookup
+                .as_ref()
+                .and_then(|lookup| self.cache.as_ref()?.get(lookup))
+            {
+                self.metrics.cache_hits.inc();
+                return res;
+            }
+
+            let (version, stable) = self.slots.route(req.headers());
+            let (version, ticket) = match (version.breaker.admit(version.metrics()), stable) {
+                (Some(ticket), _) => (version, Some(ticket)),
+                // A canary whose circuit opened hands its share back to the active version.
+                (None, Some(stable)) => {
+                    let ticket = stable.breaker.admit(stable.metrics());
+                    (stable, ticket)
+                }
+                (None, None) => (version, None),
+            };
+
+            Span::current().record("version", version.slot.as_str());
+
+            let Some(ticket) = ticket else {
+                let res = self
+                    .clone()
+                    .fail_over(clone_head(&req), "circuit-open")
+                    .await;
+
+                return res.unwrap_or_else(|| self.circuit_open());
+            };
+
+            let queued_at = Instant::now();
+            let permit = match self.queue.admit(&self.metrics.queue_depth).await {
+                Ok(permit) => permit,
+                Err(reason) => {
+                    warn!(%reason, "shedding request");
+                    self.metrics.shed.inc();
+
+                    return self.unavailable(reason);
+                }
+            };
+
+            let queue_time = queued_at.elapsed();
+            Span::current().record("queue_us", queue_time.as_micros() as u64);
+            self.metrics.queue_time.observe_duration(queue_time);
+
+            let span = Span::current();
+            let runner = self.clone();
+            let started_at = Instant::now();
+            let served_by = version.clone();
+            let vars = Substitutions::for_request(&req);
+            let bodiless = req.method() == Method::HEAD;
+            let (handoff, mut handed_off) = oneshot::channel();
+
+            let job = self.pool.run(move || {
+                let _permit = permit;
+                let _busy = runner.metrics.guest_threads_busy.track();
+                span.in_scope(|| {
+                    let head = runner.fallback.is_some().then(|| clone_head(&req));
+
+                    let res = runner.blocking_service(req, &version, Some(handoff));
+                    version
+                        .breaker
+                        .record(ticket, res.is_ok(), version.metrics());
+
+                    match res {
+                        Ok(res) => res,
+                        Err(failure) => {
+                            error!(
+                                error = ?failure.error,
+                                reason = failure.reason,
+                                "guest failed to handle request"
+                            );
+                            runner.metrics.failures.inc();
+                            version.metrics().failures.inc();
+
+                            // The client already has the head and part of the body, which is cut
+                            // off.
+                            if failure.streamed {
+                                return None;
+                            }
+
+                            let res = head
+                                .and_then(|head| runner.serve_fallback(&head, failure.reason))
+                                .unwrap_or_else(|| {
+                                    error_response(
+                                        StatusCode::INTERNAL_SERVER_ERROR,
+                                        failure.reason,
+                                        failure.detail(),
+                                    )
+                                });
+
+                            Some(res)
+                        }
+                    }
+                })
+            });
+            tokio::pin!(job);
+
+            // A response handed off goes out while the guest is still writing its body; the job
+            // goes on without being awaited.
+            let res = tokio::select! {
+                biased;
+                Ok(mut res) = &mut handed_off => {
+                    match self.finish_response(&mut res, &vars, bodiless) {
+                        Ok(()) => Some(res),
+                        Err(failure) => Some(error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            failure.reason,
+                            failure.detail(),
+                        )),
+                    }
+                }
+                res = &mut job => res.unwrap_or_else(|payload| {
+                    // The guest call catches its own panics, so this is the host's glue around it.
+                    error!(
+                        panic = panic_message(&*payload),
+                        "host panicked while handling request"
+                    );
+                    self.metrics.failures.inc();
+
+                    Some(error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "host-panic",
+                        "The host panicked while handling the request",
+                    ))
+                }),
+            };
+
+            // The job only returns nothing once it handed the response off, which is taken first.
+            let mut res = res.expect("a streamed response was handed off");
+
+            let exec_time = started_at.elapsed();
+            Span::current().record("exec_us", exec_time.as_micros() as u64);
+            self.metrics.execution_time.observe_duration(exec_time);
+            served_by
+                .metrics()
+                .execution_time
+                .observe_duration(exec_time);
+
+            let returned_at = Instant::now();
+            let span = Span::current();
+            let runner = self.clone();
+
+            res.body_mut().on_end = Some(Box::new(move || {
+                let drain_time = returned_at.elapsed();
+                span.record("drain_us", drain_time.as_micros() as u64);
+                runner.metrics.drain_time.observe_duration(drain_time);
+            }));
+
+            if let (Some(cache), Some(lookup)) = (&self.cache, &lookup) {
+                self.metrics.cache_misses.inc();
+                cache.put(lookup, &mut res);
+            }
+
+            res
+        }
+        .instrument(span.clone())
+        .await;
+
+        if let Some(validators) = validators {
+            validators.apply(&mut res);
+        }
+
+        if let Some(range) = range {
+            range.apply(&mut res);
+        }
+
+        if let Some(status) = mirrored {
+            let _ = status.send(res.status());
+        }
+
+        error_format.render(&mut res);
+        span.record("http.response.status_code", res.status().as_u16());
+        span.in_scope(|| info!(target: ACCESS_LOG, "request handled"));
+
+        if let Some(recorded) = recorded {
+            let _ = recorded.send(ResponseCopy::new(&res));
+        }
+
+        Ok(res)
+    }
+
+    async fn serve_static(&self, method: &Method, path: &str) -> Option<Response<Outgoing>> {
+        for dir in &self.options.static_dirs {
+            if let Some(rest) = dir.strip(path) {
+                return Some(dir.serve(method, rest).await);
+            }
+        }
+
+        None
+    }
+
+    fn unavailable(&self, reason: Shed) -> Response<Outgoing> {
+        let mut res = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded",
+            format!("The request was shed: {reason}"),
+        );
+
+        let retry_after = self.options.max_queue_wait.as_secs().max(1);
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+        res
+    }
+
+    fn circuit_open(&self) -> Response<Outgoing> {
+        let mut res = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "circuit-open",
+            "The component failed repeatedly and is not receiving requests for now",
+        );
+        *res.body_mut() = Outgoing::full(b"Service Unavailable: circuit open".to_vec());
+
+        let retry_after = self.options.circuit_cooldown.as_secs().max(1);
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+        res
+    }
+
+    async fn fail_over(
+        self: Arc<Self>,
+        head: Parts,
+        reason: &'static str,
+    ) -> Option<Response<Outgoing>> {
+        let fallback = self.fallback.as_ref()?;
+
+        // With the circuit open every request ends up here, so the fallback's own queue sheds
+        // them once it is busy.
+        let permit = match fallback.queue.admit(&fallback.metrics.queue_depth).await {
+            Ok(permit) => permit,
+            Err(reason) => {
+                warn!(%reason, "shedding request to the fallback");
+                fallback.metrics.shed.inc();
+
+                return Some(fallback.unavailable(reason));
+            }
+        };
+
+        let span = Span::current();
+
+        let runner = self.clone();
+
+        fallback
+            .pool
+            .run(move || {
+                let _permit = permit;
+                let fallback = runner.fallback.as_deref()?;
+                let _busy = fallback.metrics.guest_threads_busy.track();
+
+                span.in_scope(|| runner.serve_fallback(&head, reason))
+            })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Runs the fallback component with a body-less copy of the failed request.
+    fn serve_fallback(&self, head: &Parts, reason: &'static str) -> Option<Response<Outgoing>> {
+        let fallback = self.fallback.as_ref()?;
+
+        let mut req = Request::new(empty_body());
+        *req.method_mut() = head.method.clone();
+        *req.uri_mut() = head.uri.clone();
+        *req.version_mut() = head.version;
+        *req.headers_mut() = head.headers.clone();
+
+        let headers = req.headers_mut();
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(TRANSFER_ENCODING);
+        headers.insert("x-fallback-reason", HeaderValue::from_static(reason));
+
+        fallback.metrics.requests.inc();
+
+        match fallback.blocking_service(req, &fallback.slots.active(), None) {
+            Ok(res) => res,
+            Err(failure) => {
+                error!(error = ?failure.error, "fallback component failed to handle request");
+                fallback.metrics.failures.inc();
+
+                None
+            }
+        }
+    }
+
+    /// Has `version` handle `req`. With a `handoff`, a response the guest sets before finishing
+    /// its body goes there as is, to be finished with [`Runner::finish_response`], and `None` is
+    /// returned once the guest is done.
+    fn blocking_service(
+        &self,
+        req: Request<RequestBody>,
+        version: &Version,
+        handoff: Option<Handoff>,
+    ) -> Result<Option<Response<Outgoing>>, GuestFailure> {
+        let started_at = Instant::now();
+        version.metrics().requests.inc();
+        let mut req = req;
+        let mut retries = 0;
+
+        if self.options.spool_threshold > 0
+            && SpoolBody::wanted(req.body(), self.options.spool_threshold)
+        {
+            let (parts, body) = req.into_parts();
+
+            let body = SpoolBody::spool(body)
+                .map_err(|error| GuestFailure::new("spool-failed", error.into()))?;
+
+            req = Request::from_parts(parts, body.boxed_unsync());
+        }
+
+        let vars = Substitutions::for_request(&req);
+        self.options.headers.request.apply(req.headers_mut(), &vars);
+
+        if self.options.dump_heads {
+            debug!(
+                direction = "request",
+                method = %req.method(),
+                uri = %req.uri(),
+                version = ?req.version(),
+                headers = %dump::format_headers(req.headers(), &self.options.dump_redact),
+                "head dump"
+            );
+        }
+
+        if self.options.dump_bodies > 0 {
+            let limit = self.options.dump_bodies;
+            let redact = self.options.dump_redact.clone();
+            req = req.map(|body| DumpBody::new(body, limit, redact).boxed_unsync());
+        }
+
+        // A `HEAD` response declares the length of the body it leaves out.
+        let bodiless = req.method() == Method::HEAD;
+
+        let mut handoff = handoff;
+
+        loop {
+            match self.call_guest(&version.pre, req, &mut handoff) {
+                Ok(Some(mut res)) => {
+                    self.finish_response(&mut res, &vars, bodiless)?;
+
+                    return Ok(Some(res));
+                }
+                Ok(None) => return Ok(None),
+                Err(GuestFailure {
+                    error,
+                    request: Some(retry),
+                    ..
+                }) if retries < self.options.max_retries
+                    && started_at.elapsed() < self.options.retry_budget =>
+                {
+                    retries += 1;
+                    Span::current().record("retries", retries);
+                    self.metrics.retries.inc();
+                    warn!(error = ?error, retries, "guest trapped, retrying with a fresh instance");
+
+                    req = retry;
+                }
+                Err(failure) => return Err(failure),
+            }
+        }
+    }
+
+    /// Applies the server's own headers and checks to a response from the guest, whether it
+    /// comes back with the guest or is handed off while its body is still being written.
+    fn finish_response(
+        &self,
+        res: &mut Response<Outgoing>,
+        vars: &Substitutions,
+        bodiless: bool,
+    ) -> Result<(), GuestFailure> {
+        self.add_standard_headers(res.headers_mut());
+        self.options.headers.response.apply(res.headers_mut(), vars);
+
+        // Framing belongs to the server: hyper picks chunked encoding for h1 bodies of unknown
+        // length, and h2 has none. A guest's own value would contradict what is actually sent.
+        res.headers_mut().remove(TRANSFER_ENCODING);
+        apply_reason_phrase(res);
+        apply_connection_close(res);
+
+        if !bodiless {
+            check_content_length(res, self.options.length_mismatch)?;
+        }
+
+        if self.options.dump_heads {
+            let redact = &self.options.dump_redact;
+
+            debug!(
+                direction = "response",
+                status = %res.status(),
+                headers = %dump::format_headers(res.headers(), redact),
+                "head dump"
+            );
+
+            if let Some(trailers) = &res.body().trailers {
+                dump::dump_trailers("response", trailers, redact);
+            }
+        }
+
+        if self.options.dump_bodies > 0 {
+            let body = &res.body().buf;
+            let captured: Vec<u8> = body
+                .iter()
+                .take(self.options.dump_bodies)
+                .copied()
+                .collect();
+
+            dump::dump("response", &captured, body.len());
+        }
+
+        Ok(())
+    }
+
+    /// Answers a CORS preflight, and in strict mode rejects requests from origins that aren't
+    /// allowed.
+    fn check_cors<B>(&self, req: &Request<B>) -> Option<Response<Outgoing>> {
+        let cors = self.options.cors.as_ref()?;
+
+        if cors::is_preflight(req) {
+            let answer = cors.preflight(req.headers());
+
+            if answer.is_none() && cors.strict {
+                return Some(error_response(
+                    StatusCode::FORBIDDEN,
+                    "cors-denied",
+                    "This cross-origin request is not allowed",
+                ));
+            }
+
+            let mut res = Response::new(Outgoing::full(Vec::new()));
+            *res.status_mut() = StatusCode::NO_CONTENT;
+            res.headers_mut().extend(answer.unwrap_or_default());
+
+            return Some(res);
+        }
+
+        let origin = req.headers().get(ORIGIN)?;
+
+        (cors.strict && !cors.allows_origin(origin)).then(|| {
+            error_response(
+                StatusCode::FORBIDDEN,
+                "cors-denied",
+                "Requests from this origin are not allowed",
+            )
+        })
+    }
+
+    /// Rejects a request whose method the guest isn't meant to see.
+    fn check_method<B>(&self, req: &Request<B>) -> Option<Response<Outgoing>> {
+        if self.options.methods.allows(req.method()) {
+            return None;
+        }
+
+        let mut res = error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method-not-allowed",
+            format!("{} requests are not allowed", req.method()),
+        );
+        res.headers_mut()
+            .insert(ALLOW, self.options.methods.allow_header());
+
+        Some(res)
+    }
+
+    /// Hides paths the guest isn't meant to serve, as though they didn't exist.
+    fn check_path<B>(&self, req: &Request<B>) -> Option<Response<Outgoing>> {
+        (!self.options.paths.allows(req.uri().path())).then(|| {
+            error_response(
+                StatusCode::NOT_FOUND,
+                "not-found",
+                "Nothing is served at this path",
+            )
+        })
+    }
+
+    /// Rejects a request from a client over its rate limit.
+    fn check_rate<B>(&self, req: &Request<B>) -> Option<Response<Outgoing>> {
+        let limiter = self.limiter.as_ref()?;
+        let ClientAddr(addr) = req.extensions().get::<ClientAddr>()?;
+        let wait = limiter.check(addr.ip()).err()?;
+
+        self.metrics.rate_limited.inc();
+
+        let mut res = error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate-limited",
+            "This client is sending requests faster than allowed",
+        );
+
+        // Whole seconds, rounded up so the client doesn't come back too early.
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+
+        Some(res)
+    }
+
+    /// Rejects a request whose target or headers exceed the configured limits before anything
+    /// else looks at it.
+    fn check_head_size<B>(&self, req: &Request<B>) -> Option<Response<Outgoing>> {
+        let uri_bytes = req
+            .uri()
+            .path_and_query()
+            .map_or(0, |path| path.as_str().len());
+
+        if uri_bytes > self.options.max_uri_bytes {
+            return Some(error_response(
+                StatusCode::URI_TOO_LONG,
+                "uri-too-long",
+                format!("The request target is {uri_bytes} bytes long"),
+            ));
+        }
+
+        let header_count = req.headers().len();
+
+        if header_count > self.options.max_headers {
+            return Some(error_response(
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "too-many-headers",
+                format!("The request has {header_count} header fields"),
+            ));
+        }
+
+        let header_bytes: usize = req
+            .headers()
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum();
+
+        (header_bytes > self.options.max_header_bytes).then(|| {
+            error_response(
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "headers-too-large",
+                format!("The request header fields take up {header_bytes} bytes"),
+            )
+        })
+    }
+
+    fn add_standard_headers(&self, headers: &mut HeaderMap) {
+        if self.options.date_header && !headers.contains_key(DATE) {
+            let now = httpdate::fmt_http_date(SystemTime::now());
+            headers.insert(
+                DATE,
+                HeaderValue::try_from(now).expect("HTTP dates are plain ASCII"),
+            );
+        }
+
+        if let Some(server) = &self.options.server_header {
+            headers.entry(SERVER).or_insert_with(|| server.clone());
+        }
+    }
+
+    /// Calls the guest with `req`. A response it sets before finishing the body goes to
+    /// `handoff`, which is then taken, and `None` is returned once the guest has finished it.
+    fn call_guest(
+        &self,
+        pre: &InstancePre<State>,
+        req: Request<RequestBody>,
+        handoff: &mut Option<Handoff>,
+    ) -> Result<Option<Response<Outgoing>>, GuestFailure> {
+        let retryable = self.options.max_retries > 0
+            && matches!(
+                *req.method(),
+                ::http::Method::GET | ::http::Method::HEAD | ::http::Method::OPTIONS
+            );
+        let head = retryable.then(|| clone_head(&req));
+        let offered = handoff.is_some();
+
+        let instantiated_at = Instant::now();
+        let (service, mut store) = self
+            .instantiate(pre)
+            .map_err(|error| GuestFailure::new("instantiation-failed", error))?;
+        store.data_mut().timings.instantiation = instantiated_at.elapsed();
+        let (req_id, res_id) = {
+            let state = store.data_mut();
+
+            let req_id = state.new_id();
+            let res_id = state.new_id();
+
+            state.trace = req.extensions().get().copied();
+            // Vendor data only travels with the trace it belongs to.
+            state.tracestate = state
+                .trace
+                .and_then(|trace| trace.parent_id)
+                .and_then(|_| req.headers().get(trace::TRACESTATE).cloned());
+            state.request_id = Some(req_id);
+            state.requests.insert(req_id, req);
+            state.full_responses.insert(res_id, None);
+            state.handoff = handoff.take();
+
+            (req_id, res_id)
+        };
+
+        // A host bug triggered by the guest must fail this request rather than the worker thread.
+        let called_at = Instant::now();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            service.wasi_http_incoming_handler().call_handle(
+                store.as_context_mut(),
+                Resource::new_own(req_id),
+                Resource::new_own(res_id),
+            )
+        }));
+
+        let timings = &mut store.data_mut().timings;
+        timings.guest = called_at.elapsed().saturating_sub(timings.waited);
+        self.record_timings(*timings);
+
+        // Counted per attempt, so a retry's fuel isn't added to the trap before it.
+        let fuel_used = self
+            .options
+            .fuel
+            .map(|fuel| fuel - store.get_fuel().unwrap_or(0));
+
+        if let Some(fuel_used) = fuel_used {
+            self.metrics.fuel_used.observe(fuel_used);
+        }
+
+        self.record_memory(&store.data().limiter);
+
+        let res = res.map_err(|payload| {
+            GuestFailure::new(
+                "host-panic",
+                anyhow::Error::msg(format!("Host panicked: {}", panic_message(&*payload))),
+            )
+        });
+
+        // The last line a guest printed may lack its newline.
+        let _ = store.data_mut().finish_stdio();
+
+        let suppressed = store.data().logs_suppressed;
+
+        if suppressed > 0 {
+            warn!(suppressed, "suppressed {suppressed} guest log messages");
+        }
+
+        *handoff = store.data_mut().handoff.take();
+
+        if offered && handoff.is_none() {
+            return self.end_stream(store.data_mut(), req_id, res);
+        }
+
+        let res = res?;
+
+        if let Err(error) = res {
+            let request = head.and_then(|head| {
+                let body = store.data_mut().take_unread_body(req_id, res_id)?;
+                Some(Request::from_parts(head, body))
+            });
+
+            return Err(GuestFailure {
+                reason: "trap",
+                error,
+                request,
+                streamed: false,
+            });
+        }
+
         let state = store.data_mut();
 
-        let req_id = state.new_id();
-        let res_id = state.new_id();
+        let mut res = state
+            .full_responses
+            .remove(&res_id)
+            .flatten()
+            .ok_or_else(|| {
+                GuestFailure::new(
+                    "no-response",
+                    anyhow::Error::msg("The guest did not set a response"),
+                )
+            })?;
 
-        state.requests.insert(req_id, req);
-        state.full_responses.insert(res_id, None);
+        // Nothing writes to the body once the guest has returned, so the client would wait for
+        // the rest of one it never finished.
+        if let Some(pipe) = state.response_pipe.take() {
+            let mut pipe = pipe.lock().unwrap();
 
-        (req_id, res_id)
-    };
+            if !pipe.body.done || pipe.aborted {
+                return Err(GuestFailure::new(
+                    "unfinished-body",
+                    anyhow::Error::msg("The guest returned without finishing the response body"),
+                ));
+            }
+
+            *res.body_mut() = std::mem::replace(&mut pipe.body, Outgoing::full(Vec::new()));
+        }
+
+        if let Some(body) = state.take_remaining_body(req_id) {
+            self.discard_unread(body, &mut res);
+        }
+
+        if let Some(fuel_used) = fuel_used {
+            Span::current().record("fuel_used", fuel_used);
 
-    service
-        .wasi_http_incoming_handler()
-        .call_handle(
-            store.as_context_mut(),
-            Resource::new_own(req_id),
-            Resource::new_own(res_id),
-        )
-        .unwrap();
+            if self.options.fuel_header {
+                res.headers_mut()
+                    .insert(FUEL_USED, HeaderValue::from(fuel_used));
+            }
+        }
+
+        Ok(Some(res))
+    }
+
+    /// Ends the body of a response that was handed off before the guest returned. It only counts
+    /// as sent if the guest finished it; otherwise the client sees it cut off.
+    fn end_stream(
+        &self,
+        state: &mut State,
+        req_id: u32,
+        res: Result<wasmtime::Result<()>, GuestFailure>,
+    ) -> Result<Option<Response<Outgoing>>, GuestFailure> {
+        let finished = state.response_pipe.take().is_none_or(|pipe| {
+            let mut pipe = pipe.lock().unwrap();
 
-    let state = store.data_mut();
+            if !pipe.body.done {
+                pipe.abort();
+            }
 
-    let res = state.full_responses.remove(&res_id).unwrap().unwrap();
+            !pipe.aborted
+        });
+
+        // The head is gone, so there is no asking for the connection to be closed. hyper closes
+        // it anyway if the body is left unread.
+        if let Some(body) = state.take_remaining_body(req_id) {
+            let limit = self.options.max_drain_bytes;
+
+            if self.options.unread_body == UnreadBody::Drain
+                && body.size_hint().lower() <= limit as u64
+            {
+                drain(body, limit);
+            }
+        }
+
+        let failure = match res {
+            Ok(Ok(())) if finished => return Ok(None),
+            Ok(Ok(())) => GuestFailure::new(
+                "unfinished-body",
+                anyhow::Error::msg("The guest returned without finishing the response body"),
+            ),
+            Ok(Err(error)) => GuestFailure::new("trap", error),
+            Err(failure) => failure,
+        };
+
+        Err(GuestFailure {
+            streamed: true,
+            ..failure
+        })
+    }
+
+    /// Deals with the request body the guest responded without reading, as configured. A body
+    /// known to be larger than the drain limit closes the connection straight away.
+    fn discard_unread(&self, body: RequestBody, res: &mut Response<Outgoing>) {
+        let limit = self.options.max_drain_bytes;
+
+        match self.options.unread_body {
+            UnreadBody::Drain if body.size_hint().lower() <= limit as u64 => drain(body, limit),
+            _ => {
+                res.headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+            }
+        }
+    }
+
+    fn record_timings(&self, timings: Timings) {
+        let span = Span::current();
+        span.record("instantiation_us", timings.instantiation.as_micros() as u64);
+        span.record("guest_us", timings.guest.as_micros() as u64);
+        span.record("wait_us", timings.waited.as_micros() as u64);
+
+        self.metrics
+            .instantiation_time
+            .observe_duration(timings.instantiation);
+        self.metrics.guest_time.observe_duration(timings.guest);
+        self.metrics.wait_time.observe_duration(timings.waited);
+    }
+
+    fn record_memory(&self, limiter: &MemoryLimiter) {
+        Span::current().record("peak_memory_bytes", limiter.peak_memory_bytes as u64);
+
+        self.metrics
+            .memory_bytes
+            .observe(limiter.memory_bytes as u64);
+        self.metrics
+            .peak_memory_bytes
+            .observe(limiter.peak_memory_bytes as u64);
+        self.metrics.memory_grows_denied.add(limiter.denied);
+    }
 
-    Ok(res)
+    fn warm_up(&self, version: &Version) -> anyhow::Result<()> {
+        // Instantiating once catches components that link but fail while starting up.
+        self.instantiate(&version.pre)?;
+
+        for path in &self.options.warmup {
+            let req = Request::get(path.as_str()).body(empty_body())?;
+
+            let res = self
+                .call_guest(&version.pre, req, &mut None)
+                .map_err(|failure| {
+                    failure
+                        .error
+                        .context(format!("Warmup request to {path} failed"))
+                })?;
+
+            if let Some(res) = res.filter(|res| res.status().is_server_error()) {
+                return Err(anyhow::Error::msg(format!(
+                    "Warmup request to {path} returned {}",
+                    res.status()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn instantiate(&self, pre: &InstancePre<State>) -> wasmtime::Result<(Service, Store<State>)> {
+        let mut state = State::default();
+        state.max_body_bytes = self.options.max_body_bytes;
+        state.max_response_buffer_bytes = self.options.max_response_buffer_bytes;
+        state.preserve_method_case = self.options.preserve_method_case;
+        state.file_dirs = self.options.file_dirs.clone();
+        state.log_budget = self.options.max_guest_logs;
+        state.kv = self.kv.clone();
+        state.shared_cache = self.shared_cache.clone();
+        state.config = self.options.guest_config.clone();
+        state.tcp_egress = self.options.tcp_egress.clone();
+        state.outbound = self.outbound.clone();
+        state.upstream_limits = upstream::UpstreamLimits::new(
+            self.options.max_upstream_body_bytes,
+            self.options.max_upstream_total_bytes,
+        );
+        state.limiter = MemoryLimiter::new(self.options.max_memory_bytes);
+        state.clock = self.options.clock.clone();
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limiter);
+
+        if let Some(fuel) = self.options.fuel {
+            store.set_fuel(fuel)?;
+        }
+
+        let (mut bindings, _) = Service::instantiate_pre(&mut store, pre)?;
+
+        // Innermost first, so each middleware's import has something to call.
+        store
+            .data_mut()
+            .layers
+            .resize_with(self.middleware.len(), || None);
+
+        for (layer, middleware) in self.middleware.iter().enumerate().rev() {
+            store.data_mut().layers[layer] = Some(bindings);
+            (bindings, _) = Service::instantiate_pre(&mut store, middleware)?;
+        }
+
+        Ok((bindings, store))
+    }
 }
 
-static COMPONENT: OnceLock<(Engine, Component, Linker<State>)> = OnceLock::new();
+/// Compiles the component at `input` and writes the result to `output`, for a runner started with
+/// [`Options::allow_precompiled`] to load. Only runners that meter fuel the same way can.
+pub fn compile(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let component = Component::from_file(&engine(options)?, input)?;
+    std::fs::write(output, component.serialize()?)?;
+
+    Ok(())
+}
 
-fn instantiate_lazy() -> wasmtime::Result<(Engine, Component, Linker<State>)> {
+fn engine(options: &Options) -> anyhow::Result<Engine> {
     let mut config = Config::new();
     config.wasm_component_model(true);
-    let engine = Engine::new(&config)?;
+    config.consume_fuel(options.fuel.is_some());
+
+    Engine::new(&config)
+}
+
+/// Reads and compiles a component. It may import the interfaces in `bridged` besides those this
+/// build provides.
+fn read_component(
+    engine: &Engine,
+    path: &Path,
+    options: &Options,
+    bridged: &[&str],
+) -> anyhow::Result<Component> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "cwasm")
+    {
+        if !options.allow_precompiled {
+            return Err(anyhow::Error::msg(format!(
+                "{} is precompiled, which must be allowed explicitly",
+                path.display()
+            )));
+        }
+
+        // SAFETY: the operator vouched for the file by allowing precompiled components, and
+        // wasmtime rejects artifacts built for a different engine configuration.
+        return unsafe { Component::deserialize_file(engine, path) };
+    }
+
+    let bytes = std::fs::read(path)?;
+    check_imports(&bytes, bridged)?;
+
+    Component::new(engine, bytes)
+}
+
+/// Interfaces [`add_to_linker`] registers, without their versions.
+fn provided_interfaces() -> Vec<&'static str> {
+    let mut provided = vec![
+        "bluezeeking:service/body",
+        "bluezeeking:service/cache",
+        "bluezeeking:service/files",
+        "wasi:logging/logging",
+        "wasi:keyvalue/store",
+        "wasi:keyvalue/atomics",
+        "wasi:config/store",
+        "wasi:http/types",
+        "wasi:http/outgoing-handler",
+        "wasi:io/error",
+        "wasi:io/poll",
+        "wasi:io/streams",
+        "wasi:random/random",
+        "wasi:random/insecure",
+        "wasi:random/insecure-seed",
+    ];
+
+    #[cfg(feature = "sockets")]
+    provided.extend([
+        "wasi:sockets/network",
+        "wasi:sockets/instance-network",
+        "wasi:sockets/tcp-create-socket",
+        "wasi:sockets/tcp",
+        "wasi:sockets/ip-name-lookup",
+    ]);
+    #[cfg(feature = "cli")]
+    provided.extend([
+        "wasi:cli/environment",
+        "wasi:cli/exit",
+        "wasi:cli/stdin",
+        "wasi:cli/stdout",
+        "wasi:cli/stderr",
+        "wasi:cli/terminal-input",
+        "wasi:cli/terminal-output",
+        "wasi:cli/terminal-stdin",
+        "wasi:cli/terminal-stdout",
+        "wasi:cli/terminal-stderr",
+    ]);
+    #[cfg(feature = "filesystem")]
+    provided.extend(["wasi:filesystem/types", "wasi:filesystem/preopens"]);
+    #[cfg(feature = "clocks")]
+    provided.push("wasi:clocks/monotonic-clock");
+    #[cfg(feature = "host-log")]
+    provided.push("bluezeeking:service/log");
+    #[cfg(any(feature = "clocks", feature = "wasmtime-wasi-impl"))]
+    provided.push("wasi:clocks/wall-clock");
+
+    provided
+}
+
+/// Fails with every interface the component imports that this build does not provide, rather
+/// than only the first one instantiation trips over.
+fn check_imports(bytes: &[u8], bridged: &[&str]) -> anyhow::Result<()> {
+    // Text components are checked when they are instantiated instead.
+    if !bytes.starts_with(b"\0asm") {
+        return Ok(());
+    }
+
+    let inspection = Inspection::parse(bytes)?;
+    let missing = inspection
+        .missing()
+        .filter(|name| !bridged.contains(name))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow::Error::msg(format!(
+        "The component imports interfaces this build does not provide: {}",
+        missing.join(", ")
+    )))
+}
+
+/// Satisfies a middleware's `wasi:http/incoming-handler` import with the component it wraps,
+/// instantiated in the same store. Requests and responses are host resources, so the handles the
+/// middleware passes on mean the same thing to the inner component.
+fn link_layer(linker: &mut Linker<State>, layer: usize) -> wasmtime::Result<()> {
+    use wasi::http::types::{IncomingRequest, ResponseOutparam};
+
+    type Params = (Resource<IncomingRequest>, Resource<ResponseOutparam>);
+
+    linker.instance(inspect::HANDLER)?.func_wrap(
+        "handle",
+        move |mut store: StoreContextMut<'_, State>, (request, response_out): Params| {
+            // Taken for the call, so a layer can't be entered again while it runs.
+            let inner = store.data_mut().layers[layer].take().ok_or_else(|| {
+                wasmtime::Error::msg("The wrapped component is already handling a request")
+            })?;
+
+            let handler = inner.wasi_http_incoming_handler();
+            let res = handler.call_handle(&mut store, request, response_out);
+            store.data_mut().layers[layer] = Some(inner);
+
+            res
+        },
+    )
+}
+
+/// Registers the interfaces this build provides. Anything else the component imports makes
+/// [`instantiate_pre`] fail.
+fn add_to_linker(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    fn get(state: &mut State) -> &mut State {
+        state
+    }
+
+    bluezeeking::service::body::add_to_linker(linker, get)?;
+    bluezeeking::service::cache::add_to_linker(linker, get)?;
+    bluezeeking::service::files::add_to_linker(linker, get)?;
+    wasi::logging::logging::add_to_linker(linker, get)?;
+    wasi::keyvalue::store::add_to_linker(linker, get)?;
+    wasi::keyvalue::atomics::add_to_linker(linker, get)?;
+    wasi::config::store::add_to_linker(linker, get)?;
+    wasi::http::types::add_to_linker(linker, get)?;
+    wasi::http::outgoing_handler::add_to_linker(linker, get)?;
+    wasi::io::error::add_to_linker(linker, get)?;
+    wasi::io::poll::add_to_linker(linker, get)?;
+    wasi::io::streams::add_to_linker(linker, get)?;
+
+    #[cfg(feature = "sockets")]
+    {
+        wasi::sockets::network::add_to_linker(linker, get)?;
+        wasi::sockets::instance_network::add_to_linker(linker, get)?;
+        wasi::sockets::tcp_create_socket::add_to_linker(linker, get)?;
+        wasi::sockets::tcp::add_to_linker(linker, get)?;
+        wasi::sockets::ip_name_lookup::add_to_linker(linker, get)?;
+    }
+
+    #[cfg(feature = "cli")]
+    {
+        wasi::cli::stdin::add_to_linker(linker, get)?;
+        wasi::cli::stdout::add_to_linker(linker, get)?;
+        wasi::cli::stderr::add_to_linker(linker, get)?;
+        wasi::cli::terminal_input::add_to_linker(linker, get)?;
+        wasi::cli::terminal_output::add_to_linker(linker, get)?;
+        wasi::cli::terminal_stdin::add_to_linker(linker, get)?;
+        wasi::cli::terminal_stdout::add_to_linker(linker, get)?;
+        wasi::cli::terminal_stderr::add_to_linker(linker, get)?;
+    }
+
+    #[cfg(feature = "filesystem")]
+    {
+        wasi::filesystem::types::add_to_linker(linker, get)?;
+        wasi::filesystem::preopens::add_to_linker(linker, get)?;
+    }
+
+    #[cfg(feature = "clocks")]
+    wasi::clocks::monotonic_clock::add_to_linker(linker, get)?;
+
+    #[cfg(feature = "host-log")]
+    bluezeeking::service::log::add_to_linker(linker, get)?;
 
-    let component = Component::from_file(&engine, "./component.wasm").unwrap();
+    // These are served by wasmtime-wasi instead when it is enabled.
+    #[cfg(not(feature = "wasmtime-wasi-impl"))]
+    {
+        #[cfg(feature = "cli")]
+        {
+            wasi::cli::environment::add_to_linker(linker, get)?;
+            wasi::cli::exit::add_to_linker(linker, get)?;
+        }
+        wasi::random::random::add_to_linker(linker, get)?;
+        wasi::random::insecure::add_to_linker(linker, get)?;
+        wasi::random::insecure_seed::add_to_linker(linker, get)?;
+        #[cfg(feature = "clocks")]
+        wasi::clocks::wall_clock::add_to_linker(linker, get)?;
+    }
+
+    #[cfg(feature = "wasmtime-wasi-impl")]
+    delegate::add_to_linker(linker)?;
+
+    Ok(())
+}
+
+/// The target of the event logged once per request, which `--log-level` can turn off on its own.
+pub const ACCESS_LOG: &str = "wasi_http_runner::access";
+
+/// Carries the fuel a response took when [`Options::fuel_header`] is on.
+const FUEL_USED: &str = "x-wasm-fuel-used";
+
+/// A guest sets a custom reason phrase for the HTTP/1 status line with this header, which is not
+/// sent on. HTTP/2 has no reason phrase, so it is dropped there.
+const REASON_PHRASE: &str = "x-reason-phrase";
+
+fn apply_reason_phrase<B>(res: &mut Response<B>) {
+    let Some(reason) = res.headers_mut().remove(REASON_PHRASE) else {
+        return;
+    };
+
+    match ReasonPhrase::try_from(reason.as_bytes().to_vec()) {
+        Ok(reason) => {
+            res.extensions_mut().insert(reason);
+        }
+        Err(_) => warn!(?reason, "ignoring invalid reason phrase"),
+    }
+}
 
-    let mut linker = Linker::new(&engine);
-    Service::add_to_linker(&mut linker, |state: &mut State| state)?;
+/// A guest closes an HTTP/1 connection after its response by sending `Connection: close`, or
+/// this header with any value, which is not sent on. HTTP/2 has no way to end the connection with
+/// one response, so there it is ignored.
+const CLOSE_CONNECTION: &str = "x-close-connection";
 
-    Ok((engine, component, linker))
+/// Reduces the guest's `Connection` header to the one thing it can ask for. The other options
+/// describe the connection, which belongs to the server.
+fn apply_connection_close<B>(res: &mut Response<B>) {
+    let headers = res.headers_mut();
+    let reserved = headers.remove(CLOSE_CONNECTION).is_some();
+    let close = headers.get_all(CONNECTION).iter().any(|value| {
+        value.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+        })
+    });
+
+    headers.remove(CONNECTION);
+
+    if reserved || close {
+        headers.insert(CONNECTION, HeaderValue::from_static("close"));
+    }
 }
 
-fn instantiate() -> wasmtime::Result<(Service, Store<State>)> {
-    let (engine, component, linker) = COMPONENT.get_or_init(|| instantiate_lazy().unwrap());
+/// Holds a guest's `Content-Length` against its body. A body the guest wrote is complete once it
+/// returns, so it is checked before the head goes out; one the host streams is counted as it is
+/// sent instead.
+fn check_content_length(
+    res: &mut Response<Outgoing>,
+    policy: LengthMismatch,
+) -> Result<(), GuestFailure> {
+    let status = res.status();
+
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return Ok(());
+    }
+
+    let Some(declared) = res
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+    else {
+        return Ok(());
+    };
+
+    let body = res.body_mut();
+
+    if body.source.is_some() {
+        body.length = Some(DeclaredLength::new(declared, policy));
+        return Ok(());
+    }
+
+    let written = body.buf.len() as u64;
+
+    if declared == written {
+        return Ok(());
+    }
+
+    match policy {
+        LengthMismatch::Fail => Err(GuestFailure::new(
+            "content-length-mismatch",
+            anyhow::Error::msg(format!(
+                "The component declared a {declared} byte body but wrote {written} bytes"
+            )),
+        )),
+        LengthMismatch::Correct => {
+            warn!(
+                declared,
+                written, "correcting the component's content-length"
+            );
+            res.headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(written));
+
+            Ok(())
+        }
+    }
+}
+
+fn instantiate_pre(
+    linker: &Linker<State>,
+    component: &Component,
+) -> anyhow::Result<InstancePre<State>> {
+    linker.instantiate_pre(component).map_err(|err| {
+        err.context("The component imports an interface that is not enabled in this build")
+    })
+}
+
+/// Takes a response the guest set before finishing its body, so the head goes out while the
+/// guest writes the rest.
+type Handoff = oneshot::Sender<Response<Outgoing>>;
+
+struct GuestFailure {
+    reason: &'static str,
+    error: anyhow::Error,
+    /// The original request, if the guest failed without observing its body or responding.
+    request: Option<Request<RequestBody>>,
+    /// Set when the response already went out, so nothing can be sent in its place.
+    streamed: bool,
+}
+
+impl GuestFailure {
+    fn new(reason: &'static str, error: anyhow::Error) -> Self {
+        Self {
+            reason,
+            error,
+            request: None,
+            streamed: false,
+        }
+    }
+
+    /// A description of the failure that is safe to show to clients.
+    fn detail(&self) -> &'static str {
+        match self.reason {
+            "instantiation-failed" => "The component could not be instantiated",
+            "trap" => "The component trapped while handling the request",
+            "no-response" => "The component returned without setting a response",
+            "unfinished-body" => "The component returned without finishing its response body",
+            "host-panic" => "The host panicked while running the component",
+            "spool-failed" => "The request body could not be spooled to disk",
+            "content-length-mismatch" => "The component's response body did not match its length",
+            _ => "The component failed to handle the request",
+        }
+    }
+}
+
+fn clone_head<B>(req: &Request<B>) -> Parts {
+    let mut head = Request::new(());
+
+    *head.method_mut() = req.method().clone();
+    *head.uri_mut() = req.uri().clone();
+    *head.version_mut() = req.version();
+    *head.headers_mut() = req.headers().clone();
+
+    head.into_parts().0
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+fn empty_body() -> RequestBody {
+    Empty::<Bytes>::new()
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+/// Pins down the signature of a hook closure, which inference can't work out on its own for a
+/// future borrowing the request.
+fn request_hook<F>(hook: F) -> F
+where
+    F: for<'a> Fn(&'a mut Request<RequestBody>) -> BoxFuture<'a, ControlFlow<Response<Bytes>>>,
+{
+    hook
+}
 
-    let mut store = Store::new(&engine, State::default());
+/// A response produced by the host itself, which [`ErrorFormat::render`] rewrites as configured.
+fn error_response(
+    status: StatusCode,
+    kind: &'static str,
+    detail: impl Into<String>,
+) -> Response<Outgoing> {
+    let reason = status.canonical_reason().unwrap_or_default();
 
-    let (bindings, _) = Service::instantiate(&mut store, &component, &linker)?;
+    let mut res = Response::new(Outgoing::full(reason.as_bytes().to_vec()));
+    *res.status_mut() = status;
+    res.extensions_mut().insert(HostError {
+        kind,
+        detail: detail.into(),
+    });
 
-    Ok((bindings, store))
+    res
 }
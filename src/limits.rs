@@ -0,0 +1,56 @@
+use wasmtime::ResourceLimiter;
+
+/// Watches every linear memory a store creates or grows, refusing growth past an optional cap.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryLimiter {
+    max_memory_bytes: Option<usize>,
+    /// Bytes of linear memory across all of the instance's memories.
+    pub memory_bytes: usize,
+    pub peak_memory_bytes: usize,
+    /// Grows refused for going over `max_memory_bytes`.
+    pub denied: u64,
+}
+
+impl MemoryLimiter {
+    pub fn new(max_memory_bytes: Option<usize>) -> Self {
+        Self {
+            max_memory_bytes,
+            ..Default::default()
+        }
+    }
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        if maximum.is_some_and(|maximum| desired > maximum) {
+            return Ok(false);
+        }
+
+        // Creating a memory counts as growing it from nothing.
+        let total = self.memory_bytes - current + desired;
+
+        if self.max_memory_bytes.is_some_and(|max| total > max) {
+            self.denied += 1;
+            return Ok(false);
+        }
+
+        self.memory_bytes = total;
+        self.peak_memory_bytes = self.peak_memory_bytes.max(total);
+
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        Ok(maximum.is_none_or(|maximum| desired <= maximum))
+    }
+}
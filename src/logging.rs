@@ -0,0 +1,144 @@
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    wasi::{self, logging::logging::Level},
+    State,
+};
+
+impl wasi::logging::logging::Host for State {
+    fn log(&mut self, level: Level, context: String, message: String) -> wasmtime::Result<()> {
+        // A guest logging in a loop must not flood the host's log.
+        if self.log_budget == 0 {
+            self.logs_suppressed += 1;
+            return Ok(());
+        }
+
+        self.log_budget -= 1;
+
+        match level {
+            Level::Trace => trace!(target: "guest", %context, "{message}"),
+            Level::Debug => debug!(target: "guest", %context, "{message}"),
+            Level::Info => info!(target: "guest", %context, "{message}"),
+            Level::Warn => warn!(target: "guest", %context, "{message}"),
+            Level::Error => error!(target: "guest", %context, "{message}"),
+            Level::Critical => error!(target: "guest", %context, critical = true, "{message}"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        Layer,
+    };
+
+    use crate::wasi::logging::logging::Host;
+
+    use super::*;
+
+    /// The level, target and fields of every event.
+    type Events = Vec<(tracing::Level, String, BTreeMap<String, String>)>;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Events>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            struct Fields(BTreeMap<String, String>);
+
+            impl Visit for Fields {
+                fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                    self.0.insert(field.name().to_owned(), format!("{value:?}"));
+                }
+            }
+
+            let mut fields = Fields(BTreeMap::new());
+            event.record(&mut fields);
+
+            let metadata = event.metadata();
+            self.0.lock().unwrap().push((
+                *metadata.level(),
+                metadata.target().to_owned(),
+                fields.0,
+            ));
+        }
+    }
+
+    fn logged(state: &mut State, messages: &[(Level, &str)]) -> Events {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            for (level, message) in messages {
+                state
+                    .log(*level, "app".to_owned(), (*message).to_owned())
+                    .unwrap();
+            }
+        });
+
+        let events = capture.0.lock().unwrap();
+        events.clone()
+    }
+
+    #[test]
+    fn levels_map_to_tracing_levels() {
+        let mut state = State::default();
+        let events = logged(
+            &mut state,
+            &[
+                (Level::Trace, "a"),
+                (Level::Debug, "b"),
+                (Level::Info, "c"),
+                (Level::Warn, "d"),
+                (Level::Error, "e"),
+                (Level::Critical, "f"),
+            ],
+        );
+
+        let levels = events.iter().map(|(level, ..)| *level).collect::<Vec<_>>();
+        assert_eq!(
+            levels,
+            [
+                tracing::Level::TRACE,
+                tracing::Level::DEBUG,
+                tracing::Level::INFO,
+                tracing::Level::WARN,
+                tracing::Level::ERROR,
+                tracing::Level::ERROR,
+            ]
+        );
+
+        for ((_, target, fields), message) in events.iter().zip(["a", "b", "c", "d", "e", "f"]) {
+            assert_eq!(target, "guest");
+            assert_eq!(fields["message"], message);
+            assert_eq!(fields["context"], "app");
+        }
+
+        assert_eq!(events[5].2["critical"], "true");
+        assert!(!events[4].2.contains_key("critical"));
+    }
+
+    #[test]
+    fn messages_beyond_the_budget_are_counted_instead() {
+        let mut state = State::default();
+        state.log_budget = 2;
+
+        let events = logged(&mut state, &[(Level::Info, "kept"); 5]);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!((state.log_budget, state.logs_suppressed), (0, 3));
+    }
+}
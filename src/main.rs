@@ -1,32 +1,1254 @@
-use std::net::SocketAddr;
-use tracing::info;
+use std::{
+    convert::Infallible,
+    io::{Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, info_span, warn, Instrument};
 
-use hyper::{server::conn::http1, service::service_fn};
-use hyper_util::rt::TokioIo;
-use tokio::net::TcpListener;
+use clap::{Parser, Subcommand};
+use http::{
+    header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+    sync::Semaphore,
+    task::JoinSet,
+};
+#[cfg(feature = "redb")]
+use wasi_http_runner::RedbBackend;
+use wasi_http_runner::{
+    BenchOptions, ClientAddr, Cors, DnsOptions, DnsResolver, EgressRule, ErrorFormat, Fetch,
+    GuestConfig, HeaderEdits, HeaderRules, IpPreference, KeyValue, KvBackend, LengthMismatch,
+    LogFormat, LogOptions, MemoryBackend, MethodPolicy, Mirror, Mounts, NoProxy, Options,
+    PathFilter, PathGlob, Proxy, ProxyOptions, RateLimit, Recorded, Recording, Runner, StaticDir,
+    Sticky, UnreadBody,
+};
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The component to serve, or an `http://` URL or `oci://` reference to download it from at
+    /// startup
+    #[arg(long, default_value = "./component.wasm")]
+    component: PathBuf,
+
+    /// The SHA-256 digest, in hex, a downloaded component must have
+    #[arg(long)]
+    component_sha256: Option<String>,
+
+    /// Longest downloading the component may take, in seconds
+    #[arg(long, default_value_t = Fetch::default().timeout.as_secs())]
+    component_timeout_secs: u64,
+
+    /// Largest component that will be downloaded, in bytes
+    #[arg(long, default_value_t = Fetch::default().max_bytes)]
+    component_max_bytes: usize,
+
+    /// Where downloaded components are kept between restarts
+    #[arg(long, default_value_os_t = Fetch::default().cache_dir)]
+    component_cache: PathBuf,
+
+    /// Credentials for pulling an `oci://` component, as `<username>:<password>`. Without them,
+    /// the Docker config is consulted
+    #[cfg(feature = "oci")]
+    #[arg(long)]
+    registry_auth: Option<String>,
+
+    /// Load `.cwasm` components produced by `--compile`. These are trusted to be valid machine
+    /// code, so only use artifacts from your own builds
+    #[arg(long)]
+    allow_precompiled: bool,
+
+    /// Compile a component ahead of time and exit, as `--compile <in.wasm> <out.cwasm>`
+    #[arg(long, num_args = 2, value_names = ["IN", "OUT"])]
+    compile: Option<Vec<PathBuf>>,
+
+    /// A TOML file whose `[guest-config]` and `[guest-secrets]` tables are offered to the
+    /// component through `wasi:config/store`. Each `[mounts."<prefix>"]` table serves another
+    /// `component` below the prefix, optionally with its own `max-concurrency`, `guest-threads`,
+    /// `queue-depth`, `max-body-bytes`, `dump-http`, `fuel`, `max-memory-bytes`, `tcp-allow`,
+    /// `http-allow`, `rate-limit`, `kv-buckets`, `cors`, `allow-methods` or `deny-methods`, and
+    /// `allow-paths` and `deny-paths`. Top-level `log-format` and `log-level` keys stand in for the
+    /// flags, and a `[cors]` table sets up CORS for the main component with `origins`, `methods`,
+    /// `headers`, `credentials`, `max-age` and `strict`. `[headers.request]` and
+    /// `[headers.response]` tables `remove`, `rename` and `set` headers, with `${remote_addr}` and
+    /// `${request_id}` substituted in set values; mounts take a `headers` table too. A
+    /// `[dns-hosts]` table maps hosts of outbound requests to `<ip>:<port>` addresses, and a
+    /// `[server.socket]` table sets `nodelay`, `keepalive-secs`, `keepalive-interval-secs`,
+    /// `keepalive-retries`, `send-buffer-size`, `recv-buffer-size` and `backlog`, which the flags
+    /// override
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// How the log is written: `text`, or `json` with one object per line [default: text]
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+
+    /// Which events are logged, as `tracing` filter directives such as
+    /// `info,wasi_http_runner::access=off` [default: info]
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// A `wasi:config/store` value, as `<key>=<value>` (repeatable, overrides the config file)
+    #[arg(long, value_parser = parse_pair)]
+    guest_config: Vec<(String, String)>,
+
+    /// Like `--guest-config`, but the value is masked in the log
+    #[arg(long, value_parser = parse_pair)]
+    guest_secret: Vec<(String, String)>,
+
+    /// A middleware component to wrap the main one in (repeatable, outermost first). It exports
+    /// `wasi:http/incoming-handler` and imports it to pass requests on
+    #[arg(long = "compose")]
+    middleware: Vec<PathBuf>,
+
+    /// A component that serves requests when the main one fails
+    #[arg(long)]
+    fallback_component: Option<PathBuf>,
+
+    /// A component that receives a copy of sampled requests; its responses are discarded
+    #[arg(long)]
+    shadow_component: Option<PathBuf>,
+
+    /// Percentage of requests copied to the shadow component
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    shadow_percent: u8,
+
+    /// Requests with larger bodies are not copied to the shadow component
+    #[arg(long, default_value_t = 64 * 1024)]
+    shadow_max_body_bytes: usize,
+
+    /// An address to listen on (repeatable). An unspecified IPv6 address such as `[::]:3000`
+    /// accepts IPv4 connections too unless `--ipv6-only` is set. Ignored when sockets are passed
+    /// in by systemd socket activation
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    addr: Vec<SocketAddr>,
+
+    /// Accept connections on this many threads, each with its own runtime and its own
+    /// `SO_REUSEPORT` socket per address for the kernel to balance between
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+
+    /// Do not accept IPv4 connections on IPv6 listeners
+    #[arg(long)]
+    ipv6_only: bool,
+
+    /// Leave Nagle's algorithm on for accepted connections, which can hold back small responses
+    /// until the client acknowledges earlier writes
+    #[arg(long)]
+    no_tcp_nodelay: bool,
+
+    /// The send buffer size, in bytes, of accepted connections. The system default if unset
+    #[arg(long)]
+    tcp_send_buffer: Option<usize>,
+
+    /// The receive buffer size, in bytes, of accepted connections. The system default if unset
+    #[arg(long)]
+    tcp_recv_buffer: Option<usize>,
+
+    /// Send TCP keepalive probes on accepted connections once they have been idle this many
+    /// seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    tcp_keepalive_secs: Option<u64>,
+
+    /// Seconds between TCP keepalive probes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    tcp_keepalive_interval_secs: Option<u64>,
+
+    /// Unanswered TCP keepalive probes after which a connection is dropped
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    tcp_keepalive_retries: Option<u32>,
+
+    /// Connections each listener queues before they are accepted. 1024 if unset
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    listen_backlog: Option<i32>,
+
+    /// Skip instantiating the component before accepting connections
+    #[arg(long)]
+    no_warmup: bool,
+
+    /// Maximum number of requests running inside the guest at once
+    #[arg(long, default_value_t = Options::default().max_concurrency)]
+    max_concurrency: usize,
+
+    /// Threads that run the guest, which also caps `--max-concurrency`. Defaults to the number of
+    /// cores
+    #[arg(long, default_value_t = Options::default().guest_threads)]
+    guest_threads: usize,
+
+    /// Maximum number of requests waiting for a free slot
+    #[arg(long, default_value_t = Options::default().queue_depth)]
+    queue_depth: usize,
+
+    /// Longest a request may wait in the queue, in milliseconds
+    #[arg(long, default_value_t = Options::default().max_queue_wait.as_millis() as u64)]
+    max_queue_wait_ms: u64,
+
+    /// Maximum number of request body bytes buffered on behalf of the guest
+    #[arg(long, default_value_t = Options::default().max_body_bytes)]
+    max_body_bytes: usize,
+
+    /// Most of a response body buffered before the response is sent; a component writing more
+    /// before then gets an error
+    #[arg(long, default_value_t = Options::default().max_response_buffer_bytes)]
+    max_response_buffer_bytes: usize,
+
+    /// Consecutive guest failures after which requests are rejected (0 disables)
+    #[arg(long, default_value_t = Options::default().failure_threshold)]
+    failure_threshold: u32,
+
+    /// How long the circuit stays open before probing the guest again, in milliseconds
+    #[arg(long, default_value_t = Options::default().circuit_cooldown.as_millis() as u64)]
+    circuit_cooldown_ms: u64,
+
+    /// How many times a trapping GET/HEAD/OPTIONS request is retried on a fresh instance
+    #[arg(long, default_value_t = Options::default().max_retries)]
+    max_retries: u32,
+
+    /// No retry is started after a request has run for this long, in milliseconds
+    #[arg(long, default_value_t = Options::default().retry_budget.as_millis() as u64)]
+    retry_budget_ms: u64,
+
+    /// A path requested before a new component version is activated (repeatable)
+    #[arg(long)]
+    warmup: Vec<String>,
+
+    /// A recording, or a directory of them, sent through the server in order once the components
+    /// are instantiated and before it reports ready
+    #[arg(long)]
+    warmup_requests: Option<PathBuf>,
+
+    /// Exit if a `--warmup-requests` request fails or gets a server error, instead of logging it
+    #[arg(long, requires = "warmup_requests")]
+    warmup_strict: bool,
+
+    /// Log up to this many bytes of each request and response body at debug level
+    #[arg(long, default_value_t = 0, num_args = 0..=1, default_missing_value = "4096")]
+    dump_bodies: usize,
+
+    /// Log the head and trailers of each request and response at debug level, and their bodies
+    /// up to `--dump-bodies` bytes (4096 unless given)
+    #[arg(long)]
+    dump_http: bool,
+
+    /// A header whose values are left out of dumps (repeatable). Replaces the default list
+    #[arg(
+        long,
+        default_values = ["authorization", "proxy-authorization", "cookie", "set-cookie"]
+    )]
+    dump_redact: Vec<HeaderName>,
+
+    /// Total size of the response cache in bytes (0 disables caching)
+    #[arg(long, default_value_t = Options::default().cache_max_bytes)]
+    cache_max_bytes: usize,
+
+    /// Responses with larger bodies are never cached
+    #[arg(long, default_value_t = Options::default().cache_max_entry_bytes)]
+    cache_max_entry_bytes: usize,
+
+    /// Copy request bodies that may be larger than this many bytes to a temp file before the
+    /// component reads them (0 disables spooling)
+    #[arg(long, default_value_t = Options::default().spool_threshold)]
+    spool_threshold: usize,
+
+    /// Body of the errors the runner answers with itself: `text`, `problem` (RFC 7807
+    /// `application/problem+json`) or `empty`
+    #[arg(long, default_value = "text")]
+    error_format: ErrorFormat,
+
+    /// What to do with a request body the component responds without reading: `drain` it so the
+    /// connection can be reused, or `close` the connection
+    #[arg(long, default_value = "drain")]
+    unread_body: UnreadBody,
+
+    /// What to do with a response whose body doesn't match its `Content-Length`: `fail` with a
+    /// 500, or `correct` the header and send the body as written
+    #[arg(long, default_value = "fail")]
+    content_length_mismatch: LengthMismatch,
+
+    /// Unread request bodies larger than this close the connection instead of being drained
+    #[arg(long, default_value_t = Options::default().max_drain_bytes)]
+    max_drain_bytes: usize,
+
+    /// Meter guest execution, giving each request this much fuel. Components compiled with
+    /// `--compile` must be compiled with the same setting
+    #[arg(long)]
+    fuel: Option<u64>,
+
+    /// Report the fuel each response took in an `x-wasm-fuel-used` header
+    #[arg(long, requires = "fuel")]
+    fuel_header: bool,
+
+    /// Linear memory each instance may grow to, in bytes, across all of its memories
+    #[arg(long)]
+    max_memory_bytes: Option<usize>,
+
+    /// Keys the `bluezeeking:service/cache` interface holds before refusing new ones
+    #[arg(long, default_value_t = Options::default().shared_cache_entries)]
+    shared_cache_entries: usize,
+
+    /// Messages a component may log through `wasi:logging` per request before the rest are
+    /// suppressed
+    #[arg(long, default_value_t = Options::default().max_guest_logs)]
+    max_guest_logs: usize,
+
+    /// A `wasi:keyvalue` bucket the component may open, as `<name>` or `<name>=<namespace>`
+    /// (repeatable)
+    #[arg(long = "kv-bucket", value_parser = parse_bucket)]
+    kv_buckets: Vec<(String, String)>,
+
+    /// Keep key-value buckets in this redb database instead of in memory
+    #[cfg(feature = "redb")]
+    #[arg(long)]
+    kv_path: Option<PathBuf>,
+
+    /// An address the component may open TCP connections to through `wasi:sockets`, as
+    /// `<ip>[/<prefix>][:<port>]` (repeatable). Without any, every connection is refused
+    #[arg(long)]
+    tcp_allow: Vec<EgressRule>,
+
+    /// A `host` or `host:port` the component may send HTTP requests to through
+    /// `wasi:http/outgoing-handler` (repeatable). Without any, every request is denied
+    #[arg(long)]
+    http_allow: Vec<String>,
+
+    /// Where the hosts of outbound HTTP requests are looked up: `system`, or nameservers to query
+    /// directly as `<ip>[:<port>],...`, which needs the `hickory-dns` feature [default: system]
+    #[arg(long)]
+    dns_resolver: Option<DnsResolver>,
+
+    /// Resolve a host of outbound HTTP requests to a fixed address, port included, as
+    /// `<host>=<ip>:<port>` (repeatable, overrides the config file)
+    #[arg(long, value_parser = parse_dns_host)]
+    dns_host: Vec<(String, SocketAddr)>,
+
+    /// Which address family outbound connections try first when a host has both: `auto`, `ipv4`
+    /// or `ipv6`. The other is tried too if the first is slow to connect [default: auto]
+    #[arg(long)]
+    dns_prefer: Option<IpPreference>,
+
+    /// A proxy for outbound `http://` requests, as `http://[<user>:<password>@]<host>[:<port>]`
+    /// [default: $http_proxy]
+    #[arg(long)]
+    http_proxy: Option<Proxy>,
+
+    /// A proxy outbound `https://` requests are tunneled through with `CONNECT`
+    /// [default: $https_proxy]
+    #[arg(long)]
+    https_proxy: Option<Proxy>,
+
+    /// Hosts outbound requests reach without the proxy, separated by commas: domains, which
+    /// cover their subdomains, `<ip>[/<prefix>][:<port>]` ranges, or `*` [default: $no_proxy]
+    #[arg(long)]
+    no_proxy: Option<NoProxy>,
+
+    /// Tunnel outbound `http://` requests through the proxy with `CONNECT` too, instead of
+    /// sending them to it in absolute form
+    #[arg(long)]
+    proxy_tunnel: bool,
+
+    /// Fail reads of an outbound response body once it goes over this many bytes
+    #[arg(long)]
+    max_upstream_body_bytes: Option<u64>,
+
+    /// Fail reads of outbound response bodies once they add up to more than this many bytes
+    /// within one request
+    #[arg(long)]
+    max_upstream_total_bytes: Option<u64>,
+
+    /// Leave out the `Date` header on responses whose component did not set one
+    #[arg(long)]
+    no_date_header: bool,
+
+    /// Show the component each request's method exactly as it arrived, rather than matching
+    /// standard methods whatever their casing
+    #[arg(long)]
+    preserve_method_case: bool,
+
+    /// A `Server` header added to responses whose component did not set one
+    #[arg(long)]
+    server_header: Option<HeaderValue>,
+
+    /// Requests with a longer target are answered with 414
+    #[arg(long, default_value_t = Options::default().max_uri_bytes)]
+    max_uri_bytes: usize,
+
+    /// Requests whose header fields take up more bytes are answered with 431
+    #[arg(long, default_value_t = Options::default().max_header_bytes)]
+    max_header_bytes: usize,
+
+    /// Requests with more header fields are answered with 431
+    #[arg(long, default_value_t = Options::default().max_headers)]
+    max_headers: usize,
+
+    /// A request method passed to the component (repeatable). Others are answered with 405
+    #[arg(long = "allow-method", conflicts_with = "denied_methods")]
+    allowed_methods: Vec<Method>,
+
+    /// A request method answered with 405 instead of being passed to the component (repeatable)
+    #[arg(long = "deny-method")]
+    denied_methods: Vec<Method>,
+
+    /// A path pattern passed to the component, where `*` stands for part of a segment and `**` for
+    /// any number of segments (repeatable). Other paths are answered with 404
+    #[arg(long = "allow-path")]
+    allowed_paths: Vec<PathGlob>,
+
+    /// A path pattern answered with 404 instead of being passed to the component, even if it is
+    /// also allowed (repeatable)
+    #[arg(long = "deny-path")]
+    denied_paths: Vec<PathGlob>,
+
+    /// Close connections that take longer than this to send a request head, so slow clients can't
+    /// hold them open. 0 waits forever
+    #[arg(long, default_value_t = 30)]
+    header_read_timeout_secs: u64,
+
+    /// Concurrent HTTP/2 streams a client may open on one connection
+    #[arg(long, default_value_t = 100)]
+    h2_max_streams: u32,
+
+    /// Requests from one connection handled at once. Further HTTP/2 streams wait their turn, so a
+    /// single client can't take every guest thread
+    #[arg(long, default_value_t = 8)]
+    max_connection_concurrency: usize,
+
+    /// Answer requests with 429 once a client IP sends more than this many per second, as
+    /// `<per-second>[/<burst>]`
+    #[arg(long)]
+    rate_limit: Option<RateLimit>,
+
+    /// Record every request the component handles, with its response, as a file in this
+    /// directory for the `replay` command
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Recorded bodies are cut off after this many bytes
+    #[arg(long, default_value_t = Recording::new("").max_body_bytes)]
+    record_max_body_bytes: usize,
+
+    /// A header whose values are left out of recordings (repeatable). Replaces the default list
+    #[arg(
+        long,
+        default_values = ["authorization", "proxy-authorization", "cookie", "set-cookie"]
+    )]
+    record_redact: Vec<HeaderName>,
+
+    /// Answer `Range` requests by slicing full responses of known length
+    #[arg(long)]
+    ranges: bool,
+
+    /// Serve files from a directory for a path prefix without invoking the component, as
+    /// `<prefix>=<dir>` (repeatable)
+    #[arg(long = "static", value_parser = parse_static)]
+    static_dirs: Vec<(String, PathBuf)>,
+
+    /// File served for requests naming a directory below a static prefix
+    #[arg(long)]
+    index_file: Option<String>,
+
+    /// Let the component stream response bodies from files inside a directory through
+    /// `bluezeeking:service/files` (repeatable)
+    #[arg(long = "file-dir")]
+    file_dirs: Vec<PathBuf>,
+
+    /// Read `load <path> as <slot>`, `activate <slot>`, `rollback`, `split <slot> <percent>
+    /// [header|cookie <name>]`, `unsplit` and `status` commands from stdin
+    #[arg(long)]
+    admin_stdin: bool,
+
+    /// Serve `GET /metrics`, `GET /healthz` and `POST /reload` on this address, as `<ip>:<port>`
+    /// or `unix:<path>`, apart from guest traffic
+    #[arg(long)]
+    admin_addr: Option<AdminAddr>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report what a component imports and exports and whether it can be served, then exit.
+    /// Exits non-zero if it can't
+    Validate {
+        component: PathBuf,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send requests to a component in process, without a listener, and report how it kept up
+    Bench {
+        #[arg(long, default_value = "./component.wasm")]
+        component: PathBuf,
+
+        /// Requests to send in total
+        #[arg(long, default_value_t = 10_000)]
+        requests: u64,
+
+        /// Keep sending requests for this many seconds instead of a fixed number
+        #[arg(long, conflicts_with = "requests")]
+        duration: Option<u64>,
+
+        /// Requests in flight at once
+        #[arg(long, default_value_t = 32)]
+        concurrency: usize,
+
+        #[arg(long, default_value = "/")]
+        path: String,
+
+        /// A file sent as the body of every request, which makes them `POST`s
+        #[arg(long)]
+        body: Option<PathBuf>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send one request to a component in process and print the response, like curl. Exits 0
+    /// for 1xx-3xx, 4 for 4xx, 5 for 5xx and 6 when the component trapped
+    Invoke {
+        #[arg(long, default_value = "./component.wasm")]
+        component: PathBuf,
+
+        method: Method,
+
+        /// The request target, such as `/users/42?expand=true`
+        path: String,
+
+        /// A request header, as `<name>: <value>` (repeatable)
+        #[arg(short = 'H', long = "header", value_parser = parse_header)]
+        headers: Vec<(HeaderName, HeaderValue)>,
+
+        /// The request body: inline text, `@<file>`, or `@-` for stdin
+        #[arg(short, long)]
+        body: Option<String>,
+
+        /// Write the response body to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Print the status line, headers and trailers with the body
+        #[arg(short, long)]
+        include: bool,
+
+        /// Print the request and response heads to stderr
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Send recorded requests to a component in process and compare its responses with the
+    /// recorded ones. Exits 1 if any differ
+    Replay {
+        #[arg(long, default_value = "./component.wasm")]
+        component: PathBuf,
+
+        /// A recording, or a directory of them to replay in the order they were made
+        recordings: PathBuf,
+
+        /// Wait between requests as long as passed between them when they were recorded
+        #[arg(long)]
+        timing: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    info!("listening");
+    let args = Args::parse();
+    let file = config_file(&args)?;
+    let tuning = socket_tuning(&args, &file)?;
+    log_options(&args, &file)?.init()?;
+
+    if let Some(Command::Validate { component, json }) = &args.command {
+        let inspection = wasi_http_runner::inspect(component)?;
+
+        if *json {
+            println!("{}", inspection.to_json());
+        } else {
+            println!("{inspection}");
+        }
+
+        if !inspection.servable() {
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Invoke {
+        component,
+        method,
+        path,
+        headers,
+        body,
+        output,
+        include,
+        verbose,
+    }) = &args.command
+    {
+        let runner = Runner::new(
+            component,
+            Options {
+                allow_precompiled: args.allow_precompiled,
+                ..Default::default()
+            },
+        )?;
+
+        let body = match body.as_deref() {
+            Some("@-") => {
+                let mut buf = Vec::new();
+                std::io::stdin().read_to_end(&mut buf)?;
+                buf
+            }
+            Some(body) => match body.strip_prefix('@') {
+                Some(file) => std::fs::read(file)?,
+                None => body.as_bytes().to_vec(),
+            },
+            None => Vec::new(),
+        };
+
+        let mut req = http::Request::builder()
+            .method(method.clone())
+            .uri(path.as_str())
+            .body(Full::new(Bytes::from(body)))?;
+        req.headers_mut().extend(headers.iter().cloned());
+
+        if *verbose {
+            eprintln!("> {} {}", req.method(), req.uri());
+            print_headers(&mut std::io::stderr(), "> ", req.headers())?;
+        }
+
+        let invocation = Arc::new(runner).invoke(req).await?;
+        let res = &invocation.response;
+
+        // Heads go to stdout with the body for -i, to stderr for -v.
+        let mut head: Option<Box<dyn Write>> = match (*include, *verbose) {
+            (true, _) => Some(Box::new(std::io::stdout())),
+            (false, true) => Some(Box::new(std::io::stderr())),
+            (false, false) => None,
+        };
+        let prefix = if *include { "" } else { "< " };
+
+        if let Some(head) = &mut head {
+            writeln!(head, "{prefix}{:?} {}", res.version(), res.status())?;
+            print_headers(head, prefix, res.headers())?;
+            writeln!(head)?;
+            head.flush()?;
+        }
+
+        match output {
+            Some(output) => std::fs::write(output, res.body())?,
+            None => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(res.body())?;
+                stdout.flush()?;
+            }
+        }
 
-    let listener = TcpListener::bind(addr).await?;
+        if let Some(trailers) = &invocation.trailers {
+            let mut head = head.unwrap_or_else(|| Box::new(std::io::stderr()));
+            writeln!(head)?;
+            print_headers(&mut head, prefix, trailers)?;
+        }
 
+        let code = if invocation.trapped {
+            6
+        } else if res.status().is_server_error() {
+            5
+        } else if res.status().is_client_error() {
+            4
+        } else {
+            0
+        };
+
+        std::process::exit(code);
+    }
+
+    if let Some(Command::Replay {
+        component,
+        recordings,
+        timing,
+    }) = &args.command
+    {
+        let runner = Runner::new(
+            component,
+            Options {
+                allow_precompiled: args.allow_precompiled,
+                ..Default::default()
+            },
+        )?;
+
+        if !replay(Arc::new(runner), recordings, *timing).await? {
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Bench {
+        component,
+        requests,
+        duration,
+        concurrency,
+        path,
+        body,
+        json,
+    }) = &args.command
+    {
+        let runner = Runner::new(
+            component,
+            Options {
+                max_concurrency: *concurrency,
+                allow_precompiled: args.allow_precompiled,
+                ..Default::default()
+            },
+        )?;
+
+        let report = wasi_http_runner::bench(
+            Arc::new(runner),
+            BenchOptions {
+                requests: *requests,
+                duration: duration.map(Duration::from_secs),
+                concurrency: *concurrency,
+                path: path.clone(),
+                body: body
+                    .as_ref()
+                    .map(std::fs::read)
+                    .transpose()?
+                    .map(Into::into),
+            },
+        )
+        .await?;
+
+        if *json {
+            println!("{}", report.to_json());
+        } else {
+            println!("{report}");
+        }
+
+        return Ok(());
+    }
+
+    if let Some([input, output]) = args.compile.as_deref() {
+        let options = Options {
+            fuel: args.fuel,
+            ..Default::default()
+        };
+        wasi_http_runner::compile(input, output, &options)?;
+        info!(output = %output.display(), "compiled");
+
+        return Ok(());
+    }
+
+    let guest_config = guest_config(&args, &file)?;
+    info!(config = ?guest_config, "guest config");
+    let dns = dns_options(&args, &file)?;
+    let proxy = proxy_options(&args)?;
+
+    let options = Options {
+        max_concurrency: args.max_concurrency,
+        guest_threads: args.guest_threads,
+        queue_depth: args.queue_depth,
+        max_queue_wait: Duration::from_millis(args.max_queue_wait_ms),
+        max_body_bytes: args.max_body_bytes,
+        max_response_buffer_bytes: args.max_response_buffer_bytes,
+        failure_threshold: args.failure_threshold,
+        circuit_cooldown: Duration::from_millis(args.circuit_cooldown_ms),
+        max_retries: args.max_retries,
+        retry_budget: Duration::from_millis(args.retry_budget_ms),
+        warmup: args.warmup,
+        dump_bodies: match (args.dump_http, args.dump_bodies) {
+            (true, 0) => 4096,
+            (_, dump_bodies) => dump_bodies,
+        },
+        dump_heads: args.dump_http,
+        dump_redact: args.dump_redact.clone(),
+        cache_max_bytes: args.cache_max_bytes,
+        cache_max_entry_bytes: args.cache_max_entry_bytes,
+        spool_threshold: args.spool_threshold,
+        allow_precompiled: args.allow_precompiled,
+        error_format: args.error_format,
+        unread_body: args.unread_body,
+        max_drain_bytes: args.max_drain_bytes,
+        length_mismatch: args.content_length_mismatch,
+        methods: method_policy(&args),
+        middleware: args.middleware.clone(),
+        paths: PathFilter {
+            allow: args.allowed_paths.clone(),
+            deny: args.denied_paths.clone(),
+        },
+        cors: file
+            .get("cors")
+            .map(|cors| cors_config(cors, "[cors]"))
+            .transpose()?,
+        fuel: args.fuel,
+        fuel_header: args.fuel_header,
+        max_memory_bytes: args.max_memory_bytes,
+        shared_cache_entries: args.shared_cache_entries,
+        max_guest_logs: args.max_guest_logs,
+        guest_config: guest_config.clone(),
+        ranges: args.ranges,
+        max_uri_bytes: args.max_uri_bytes,
+        max_header_bytes: args.max_header_bytes,
+        max_headers: args.max_headers,
+        rate_limit: args.rate_limit,
+        record: args.record.as_ref().map(|dir| Recording {
+            dir: dir.clone(),
+            max_body_bytes: args.record_max_body_bytes,
+            redact: args.record_redact.clone(),
+        }),
+        tcp_egress: args.tcp_allow.clone(),
+        http_egress: args.http_allow.clone(),
+        dns: dns.clone(),
+        proxy: proxy.clone(),
+        max_upstream_body_bytes: args.max_upstream_body_bytes,
+        max_upstream_total_bytes: args.max_upstream_total_bytes,
+        date_header: !args.no_date_header,
+        preserve_method_case: args.preserve_method_case,
+        server_header: args.server_header.clone(),
+        static_dirs: args
+            .static_dirs
+            .iter()
+            .map(|(prefix, dir)| StaticDir::new(prefix, dir, args.index_file.clone()))
+            .collect(),
+        file_dirs: args.file_dirs.clone(),
+    };
+    let mounted = mount_configs(&file, &options)?;
+    let component = component_path(&args).await?;
+    let mut runner = Runner::new(&component, options)?;
+
+    if let Some(fallback) = &args.fallback_component {
+        runner = runner.with_fallback(Runner::new(
+            fallback,
+            Options {
+                allow_precompiled: args.allow_precompiled,
+                guest_config: guest_config.clone(),
+                tcp_egress: args.tcp_allow.clone(),
+                http_egress: args.http_allow.clone(),
+                dns: dns.clone(),
+                proxy: proxy.clone(),
+                max_upstream_body_bytes: args.max_upstream_body_bytes,
+                max_upstream_total_bytes: args.max_upstream_total_bytes,
+                max_response_buffer_bytes: args.max_response_buffer_bytes,
+                ..Options::fallback()
+            },
+        )?);
+    }
+
+    if let Some(shadow) = &args.shadow_component {
+        runner = runner.with_mirror(Mirror::new(
+            Runner::new(
+                shadow,
+                Options {
+                    allow_precompiled: args.allow_precompiled,
+                    guest_config,
+                    ..Options::shadow()
+                },
+            )?,
+            args.shadow_percent,
+            args.shadow_max_body_bytes,
+        ));
+    }
+
+    // Every runner keeps its buckets in the same backend, apart by namespace.
+    let wants_kv =
+        !args.kv_buckets.is_empty() || mounted.iter().any(|mount| !mount.kv_buckets.is_empty());
+    let backend = wants_kv.then(|| kv_backend(&args)).transpose()?;
+    let key_value = |buckets: &[(String, String)]| {
+        let backend = backend.clone()?;
+
+        (!buckets.is_empty()).then(|| {
+            buckets
+                .iter()
+                .fold(KeyValue::shared(backend), |kv, (name, namespace)| {
+                    kv.bucket(name, namespace)
+                })
+        })
+    };
+
+    if let Some(kv) = key_value(&args.kv_buckets) {
+        runner = runner.with_key_value(kv);
+    }
+
+    let runner = Arc::new(runner);
+    let mut mounts = Mounts::new().mount("/", runner.clone());
+    let mut components = vec![(runner.clone(), component.clone())];
+
+    for mount in mounted {
+        let mut mounted = Runner::new(&mount.component, mount.options)?;
+
+        if let Some(kv) = key_value(&mount.kv_buckets) {
+            mounted = mounted.with_key_value(kv);
+        }
+
+        info!(prefix = %mount.prefix, component = %mount.component.display(), "mounted");
+        let mounted = Arc::new(mounted);
+        components.push((mounted.clone(), mount.component));
+        mounts = mounts.mount(&mount.prefix, mounted);
+    }
+
+    let mounts = Arc::new(mounts);
+
+    if args.admin_stdin {
+        tokio::task::spawn(admin(runner.clone()));
+    }
+
+    let workers = args.workers.max(1);
+    let inherited = inherited_listeners()?;
+    let reuse_port = workers > 1 && inherited.is_none() && REUSE_PORT;
+
+    if workers > 1 && !reuse_port {
+        warn!("SO_REUSEPORT is unavailable, so workers share one socket per address");
+    }
+
+    let listeners = match inherited {
+        Some(listeners) => listeners,
+        None => args
+            .addr
+            .iter()
+            .map(|addr| bind(*addr, args.ipv6_only, reuse_port, tuning.backlog))
+            .collect::<std::io::Result<Vec<_>>>()?,
+    };
+
+    let mut worker_listeners = Vec::new();
+
+    for _ in 1..workers {
+        // Bound to the first worker's addresses, in case any asked for port 0.
+        worker_listeners.push(
+            listeners
+                .iter()
+                .map(|listener| {
+                    if reuse_port {
+                        bind(listener.local_addr()?, args.ipv6_only, true, tuning.backlog)
+                    } else {
+                        listener.try_clone()
+                    }
+                })
+                .collect::<std::io::Result<Vec<_>>>()?,
+        );
+    }
+
+    if !args.no_warmup {
+        let started_at = Instant::now();
+        let mounts = mounts.clone();
+
+        tokio::task::spawn_blocking(move || {
+            mounts
+                .iter()
+                .try_for_each(|(_, runner)| runner.warm_up_active())
+        })
+        .await??;
+        info!(
+            elapsed_ms = started_at.elapsed().as_millis() as u64,
+            "warmed up"
+        );
+    }
+
+    if let Some(path) = &args.warmup_requests {
+        send_warmup_requests(&mounts, path, args.warmup_strict).await?;
+    }
+
+    // hyper answers 431 itself once a request head outgrows its read buffer, so leave room for
+    // the runner's own limits to apply first.
+    let max_buf_size = (args.max_uri_bytes + args.max_header_bytes + 1024).max(8192);
+    let header_read_timeout = (args.header_read_timeout_secs > 0)
+        .then(|| Duration::from_secs(args.header_read_timeout_secs));
+    let streams = StreamLimits {
+        max_streams: args.h2_max_streams,
+        per_connection: args.max_connection_concurrency.max(1),
+    };
+
+    let mut accept_loops = JoinSet::new();
+
+    if let Some(addr) = &args.admin_addr {
+        let admin = Arc::new(Admin {
+            mounts: mounts.clone(),
+            components,
+        });
+
+        accept_loops.spawn(serve_admin(AdminListener::bind(addr).await?, admin));
+    }
+
+    for listener in listeners {
+        info!(addr = %listener.local_addr()?, "listening");
+        accept_loops.spawn(serve(
+            TcpListener::from_std(listener)?,
+            mounts.clone(),
+            max_buf_size,
+            header_read_timeout,
+            tuning,
+            streams,
+        ));
+    }
+
+    for (i, listeners) in worker_listeners.into_iter().enumerate() {
+        let worker = i + 1;
+        let mounts = mounts.clone();
+        let (done, finished) = tokio::sync::oneshot::channel();
+
+        // A plain thread rather than a blocking task, so it doesn't hold up the exit.
+        std::thread::Builder::new()
+            .name(format!("worker-{worker}"))
+            .spawn(move || {
+                let run = || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()?;
+
+                    runtime.block_on(async move {
+                        let mut accept_loops = JoinSet::new();
+
+                        for listener in listeners {
+                            accept_loops.spawn(
+                                serve(
+                                    TcpListener::from_std(listener)?,
+                                    mounts.clone(),
+                                    max_buf_size,
+                                    header_read_timeout,
+                                    tuning,
+                                    streams,
+                                )
+                                .instrument(info_span!("worker", worker)),
+                            );
+                        }
+
+                        while let Some(res) = accept_loops.join_next().await {
+                            res??;
+                        }
+
+                        anyhow::Ok(())
+                    })
+                };
+
+                let _ = done.send(run());
+            })?;
+
+        accept_loops.spawn(async move { finished.await? });
+    }
+
+    notify(sd_notify::NotifyState::Ready);
+
+    // Each loop only returns if accepting fails.
+    let res = tokio::select! {
+        res = async {
+            while let Some(res) = accept_loops.join_next().await {
+                res??;
+            }
+
+            anyhow::Ok(())
+        } => res,
+        res = shutdown_signal() => res.map_err(Into::into),
+    };
+
+    notify(sd_notify::NotifyState::Stopping);
+
+    let shutdown = async {
+        for (_, runner) in mounts.iter() {
+            runner.shutdown().await;
+        }
+    };
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown)
+        .await
+        .is_err()
+    {
+        warn!("requests were still running after {SHUTDOWN_TIMEOUT:?}, exiting anyway");
+    }
+
+    #[cfg(feature = "otel")]
+    let _ = tokio::task::spawn_blocking(wasi_http_runner::otel::shutdown).await;
+
+    res
+}
+
+fn method_policy(args: &Args) -> MethodPolicy {
+    if !args.allowed_methods.is_empty() {
+        MethodPolicy::Allow(args.allowed_methods.clone())
+    } else if !args.denied_methods.is_empty() {
+        MethodPolicy::Deny(args.denied_methods.clone())
+    } else {
+        MethodPolicy::Any
+    }
+}
+
+/// The log settings from the flags, or else from the config file's top-level keys.
+fn log_options(args: &Args, file: &toml::Table) -> anyhow::Result<LogOptions> {
+    let setting = |key: &str| {
+        file.get(key)
+            .map(|value| {
+                value.as_str().ok_or_else(|| {
+                    anyhow::Error::msg(format!("{key} in the config file must be a string"))
+                })
+            })
+            .transpose()
+    };
+
+    let mut options = LogOptions::default();
+
+    if let Some(format) = args.log_format {
+        options.format = format;
+    } else if let Some(format) = setting("log-format")? {
+        options.format = format.parse().map_err(anyhow::Error::msg)?;
+    }
+
+    if let Some(level) = &args.log_level {
+        options.level = level.clone();
+    } else if let Some(level) = setting("log-level")? {
+        options.level = level.to_owned();
+    }
+
+    Ok(options)
+}
+
+/// How long requests in the guest get to finish once the server is stopping.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The listening sockets passed in by systemd socket activation (`LISTEN_FDS`), if any.
+fn inherited_listeners() -> anyhow::Result<Option<Vec<std::net::TcpListener>>> {
+    let mut fds = listenfd::ListenFd::from_env();
+
+    if fds.len() == 0 {
+        return Ok(None);
+    }
+
+    let mut listeners = Vec::new();
+
+    for i in 0..fds.len() {
+        let listener = fds.take_tcp_listener(i)?.ok_or_else(|| {
+            anyhow::Error::msg(format!("Inherited socket {i} is not a TCP listener"))
+        })?;
+
+        listener.set_nonblocking(true)?;
+        listeners.push(listener);
+    }
+
+    Ok(Some(listeners))
+}
+
+/// Tells systemd about the service's state. Does nothing when not run by systemd.
+fn notify(state: sd_notify::NotifyState) {
+    if let Err(err) = sd_notify::notify(false, &[state]) {
+        error!(%err, "could not notify systemd");
+    }
+}
+
+/// Waits for Ctrl-C, or for the `SIGTERM` systemd stops the service with.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Whether sockets can share an address with `SO_REUSEPORT`.
+const REUSE_PORT: bool = cfg!(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos"))
+));
+
+fn bind(
+    addr: SocketAddr,
+    ipv6_only: bool,
+    reuse_port: bool,
+    backlog: i32,
+) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    socket.set_reuse_address(true)?;
+
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+
+    #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+    let _ = reuse_port;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+async fn serve(
+    listener: TcpListener,
+    mounts: Arc<Mounts>,
+    max_buf_size: usize,
+    header_read_timeout: Option<Duration>,
+    tuning: SocketTuning,
+    streams: StreamLimits,
+) -> anyhow::Result<()> {
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = listener.accept().await?;
+
+        if let Err(err) = tuning.apply(&stream) {
+            warn!(%err, %peer, "could not tune socket");
+        }
 
         // Use an adapter to access something implementing `tokio::io` traits as if they implement
         // `hyper::rt` IO traits.
         let io = TokioIo::new(stream);
+        let mounts = mounts.clone();
+        let slots = Arc::new(Semaphore::new(streams.per_connection));
 
         // Spawn a tokio task to serve multiple connections concurrently
         tokio::task::spawn(async move {
             info!("Handling connection");
+            let mut builder = auto::Builder::new(TokioExecutor::new());
+            builder.http1().max_buf_size(max_buf_size);
+            builder.http2().max_concurrent_streams(streams.max_streams);
+
+            if let Some(timeout) = header_read_timeout {
+                builder
+                    .http1()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(timeout);
+            }
+
             // Finally, we bind the incoming connection to our `hello` service
-            if let Err(err) = http1::Builder::new()
+            if let Err(err) = builder
                 // `service_fn` converts our function in a `Service`
-                .serve_connection(io, service_fn(wasi_http_runner::service_fn))
+                .serve_connection(
+                    io,
+                    service_fn(move |mut req| {
+                        req.extensions_mut().insert(ClientAddr(peer));
+                        let mounts = mounts.clone();
+                        let slots = slots.clone();
+
+                        async move {
+                            // Held until the response head is ready, by which time the guest
+                            // is done with the request.
+                            let _slot = slots.acquire_owned().await?;
+                            mounts.service_fn(req).await
+                        }
+                    }),
+                )
                 .await
             {
                 println!("Error serving connection: {:?}", err);
@@ -34,3 +1256,1121 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 }
+
+/// Where the admin endpoints are served.
+#[derive(Clone)]
+enum AdminAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for AdminAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("expected <ip>:<port> or unix:<path>, got {s}"))
+    }
+}
+
+enum AdminListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl AdminListener {
+    async fn bind(addr: &AdminAddr) -> anyhow::Result<Self> {
+        match addr {
+            AdminAddr::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                info!(addr = %listener.local_addr()?, "admin listening");
+
+                Ok(Self::Tcp(listener))
+            }
+            #[cfg(unix)]
+            AdminAddr::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                // A socket left behind by an earlier run would make the bind fail. Anything else
+                // at the path is left alone.
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+
+                let listener = tokio::net::UnixListener::bind(path)?;
+                info!(path = %path.display(), "admin listening");
+
+                Ok(Self::Unix(listener))
+            }
+        }
+    }
+}
+
+/// Operator endpoints, answered by the host itself and never by a guest.
+struct Admin {
+    mounts: Arc<Mounts>,
+    /// Each runner with the component `POST /reload` loads it from again.
+    components: Vec<(Arc<Runner>, PathBuf)>,
+}
+
+/// The slot reloaded components are loaded under. Reusing it lets the version it replaces be
+/// dropped once nothing refers to it.
+const RELOAD_SLOT: &str = "reload";
+
+impl Admin {
+    async fn respond(self: Arc<Self>, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        let (status, body) = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/metrics") => {
+                let mut res = Response::new(Full::new(Bytes::from(self.mounts.encode_metrics())));
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; version=0.0.4"),
+                );
+
+                return res;
+            }
+            (&Method::GET, "/healthz") => {
+                if self.mounts.iter().all(|(_, runner)| runner.is_ready()) {
+                    (StatusCode::OK, "ok".to_owned())
+                } else {
+                    (StatusCode::SERVICE_UNAVAILABLE, "unavailable".to_owned())
+                }
+            }
+            (&Method::POST, "/reload") => {
+                // Loading compiles the components, so keep it off the async workers.
+                match tokio::task::spawn_blocking(move || self.reload()).await {
+                    Ok(Ok(())) => (StatusCode::OK, "reloaded".to_owned()),
+                    Ok(Err(err)) => {
+                        error!(error = ?err, "reload failed");
+                        (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+                    }
+                    Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+                }
+            }
+            (_, "/metrics" | "/healthz" | "/reload") => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method not allowed".to_owned(),
+            ),
+            _ => (StatusCode::NOT_FOUND, "not found".to_owned()),
+        };
+
+        let mut res = Response::new(Full::new(Bytes::from(body)));
+        *res.status_mut() = status;
+        res
+    }
+
+    /// Loads every component from disk again and activates it, the way `load` and `activate` do
+    /// on stdin.
+    fn reload(&self) -> anyhow::Result<()> {
+        for (runner, component) in &self.components {
+            runner.load(component, RELOAD_SLOT)?;
+            runner.activate(RELOAD_SLOT)?;
+            info!(component = %component.display(), "reloaded");
+        }
+
+        Ok(())
+    }
+}
+
+async fn serve_admin(listener: AdminListener, admin: Arc<Admin>) -> anyhow::Result<()> {
+    loop {
+        match &listener {
+            AdminListener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                serve_admin_connection(TokioIo::new(stream), admin.clone());
+            }
+            #[cfg(unix)]
+            AdminListener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                serve_admin_connection(TokioIo::new(stream), admin.clone());
+            }
+        }
+    }
+}
+
+fn serve_admin_connection<I>(io: I, admin: Arc<Admin>)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    tokio::task::spawn(async move {
+        let service = service_fn(move |req| {
+            let admin = admin.clone();
+
+            async move { Ok::<_, Infallible>(admin.respond(req).await) }
+        });
+
+        if let Err(err) = auto::Builder::new(TokioExecutor::new())
+            .serve_connection(io, service)
+            .await
+        {
+            warn!(error = ?err, "admin connection failed");
+        }
+    });
+}
+
+/// How much of the server one connection may use at once.
+#[derive(Clone, Copy)]
+struct StreamLimits {
+    max_streams: u32,
+    per_connection: usize,
+}
+
+/// Socket options set on every accepted connection, and the backlog of the listeners that
+/// accept them.
+#[derive(Clone, Copy)]
+struct SocketTuning {
+    nodelay: bool,
+    send_buffer: Option<usize>,
+    recv_buffer: Option<usize>,
+    /// Idle time before the first keepalive probe. No probes are sent if unset.
+    keepalive: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    backlog: i32,
+}
+
+/// `keepalive` with the interval and count of probes set, or `None` on platforms where they can't
+/// be.
+#[allow(unreachable_code)]
+fn keepalive_probes(
+    keepalive: TcpKeepalive,
+    interval: Option<Duration>,
+    retries: Option<u32>,
+) -> Option<TcpKeepalive> {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ))]
+    return Some({
+        let keepalive = match interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+
+        match retries {
+            Some(retries) => keepalive.with_retries(retries),
+            None => keepalive,
+        }
+    });
+
+    let _ = (keepalive, interval, retries);
+    None
+}
+
+impl SocketTuning {
+    fn apply(&self, stream: &tokio::net::TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+
+        let socket = SockRef::from(stream);
+
+        if let Some(time) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(time);
+
+            let keepalive = keepalive_probes(
+                keepalive.clone(),
+                self.keepalive_interval,
+                self.keepalive_retries,
+            )
+            .unwrap_or(keepalive);
+
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_static(value: &str) -> Result<(String, PathBuf), String> {
+    let (prefix, dir) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <prefix>=<dir>, got {value}"))?;
+
+    Ok((prefix.to_owned(), PathBuf::from(dir)))
+}
+
+/// Where the component to serve is on disk, downloading it first if it was given as a URL or an
+/// OCI reference.
+async fn component_path(args: &Args) -> anyhow::Result<PathBuf> {
+    let Some(component) = args
+        .component
+        .to_str()
+        .filter(|component| Fetch::is_url(component) || Fetch::is_oci(component))
+    else {
+        return Ok(args.component.clone());
+    };
+
+    let fetch = Fetch {
+        timeout: Duration::from_secs(args.component_timeout_secs),
+        max_bytes: args.component_max_bytes,
+        sha256: args.component_sha256.clone(),
+        cache_dir: args.component_cache.clone(),
+    };
+
+    let path = if Fetch::is_url(component) {
+        fetch.component(component).await?
+    } else {
+        pull(&fetch, args, component).await?
+    };
+
+    info!(component, path = %path.display(), "downloaded component");
+
+    Ok(path)
+}
+
+#[cfg(feature = "oci")]
+async fn pull(fetch: &Fetch, args: &Args, component: &str) -> anyhow::Result<PathBuf> {
+    let auth =
+        match &args.registry_auth {
+            Some(auth) => Some(auth.split_once(':').ok_or_else(|| {
+                anyhow::Error::msg("--registry-auth must be <username>:<password>")
+            })?),
+            None => None,
+        };
+
+    fetch.oci_component(component, auth).await
+}
+
+#[cfg(not(feature = "oci"))]
+async fn pull(_fetch: &Fetch, _args: &Args, component: &str) -> anyhow::Result<PathBuf> {
+    Err(anyhow::Error::msg(format!(
+        "Can't pull {component}: this build lacks the oci feature"
+    )))
+}
+
+fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected <name>: <value>, got {value}"))?;
+
+    Ok((
+        name.trim()
+            .parse()
+            .map_err(|_| format!("invalid header name {name}"))?,
+        value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid header value {value}"))?,
+    ))
+}
+
+/// The recording at `path`, or every recording in the directory at `path` in the order they were
+/// made.
+fn recordings(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+
+            if file
+                .extension()
+                .is_some_and(|extension| extension == "http")
+            {
+                files.push(file);
+            }
+        }
+    } else {
+        files.push(path.to_owned());
+    }
+
+    // Names start with the time they were recorded.
+    files.sort();
+
+    Ok(files)
+}
+
+/// Sends the recordings at `path` through the mounts, logging how long each took, so guests
+/// build their lazily initialized state before the first real request. Failures only abort
+/// startup when `strict`.
+async fn send_warmup_requests(
+    mounts: &Arc<Mounts>,
+    path: &Path,
+    strict: bool,
+) -> anyhow::Result<()> {
+    for file in recordings(path)? {
+        let recorded = Recorded::read(&file)?;
+        let method = recorded.request.method().clone();
+        let uri = recorded.request.uri().clone();
+        let started_at = Instant::now();
+
+        let res = match mounts
+            .clone()
+            .service_fn(recorded.request.map(Full::new))
+            .await
+        {
+            // The guest may still be writing the body, so wait for all of it.
+            Ok(res) => {
+                let (parts, body) = res.into_parts();
+
+                body.collect().await.map(|_| parts.status).map_err(|err| {
+                    anyhow::Error::msg(format!("Reading the response failed: {err}"))
+                })
+            }
+            Err(err) => Err(err),
+        };
+        let elapsed_ms = started_at.elapsed().as_millis() as u64;
+
+        let failure = match res {
+            Ok(status) if !status.is_server_error() => {
+                info!(
+                    request = %file.display(),
+                    %method,
+                    %uri,
+                    status = status.as_u16(),
+                    elapsed_ms,
+                    "warmup request"
+                );
+                continue;
+            }
+            Ok(status) => anyhow::Error::msg(format!("Returned {status}")),
+            Err(err) => err,
+        };
+
+        warn!(
+            request = %file.display(),
+            %method,
+            %uri,
+            elapsed_ms,
+            error = %failure,
+            "warmup request failed"
+        );
+
+        if strict {
+            return Err(failure.context(format!("Warmup request {} failed", file.display())));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replays the recordings at `path` and prints how each response compares. Returns whether they
+/// all matched.
+async fn replay(runner: Arc<Runner>, path: &Path, timing: bool) -> anyhow::Result<bool> {
+    let mut matched = true;
+    let mut previous = None;
+
+    for file in recordings(path)? {
+        let recorded = Recorded::read(&file)?;
+
+        if let (true, Some(previous)) = (timing, previous) {
+            let gap = recorded.timestamp_ms.saturating_sub(previous);
+            tokio::time::sleep(Duration::from_millis(gap)).await;
+        }
+
+        previous = Some(recorded.timestamp_ms);
+
+        let name = file.display();
+
+        if recorded.body_incomplete {
+            eprintln!("{name}: the recorded request body is incomplete");
+        }
+
+        let invocation = runner
+            .clone()
+            .invoke(recorded.request.map(Full::new))
+            .await?;
+        let res = &invocation.response;
+
+        let Some(expected) = &recorded.response else {
+            println!("{name}: {} (no recorded response)", res.status());
+            continue;
+        };
+
+        if res.status() != expected.status {
+            matched = false;
+            println!("{name}: {}, recorded {}", res.status(), expected.status);
+        } else if expected
+            .body
+            .as_ref()
+            .is_some_and(|body| body != res.body())
+        {
+            matched = false;
+            println!("{name}: {}, body differs from the recording", res.status());
+        } else {
+            println!("{name}: {} as recorded", res.status());
+        }
+    }
+
+    Ok(matched)
+}
+
+fn print_headers(out: &mut dyn Write, prefix: &str, headers: &HeaderMap) -> std::io::Result<()> {
+    for (name, value) in headers {
+        writeln!(
+            out,
+            "{prefix}{name}: {}",
+            String::from_utf8_lossy(value.as_bytes())
+        )?;
+    }
+
+    Ok(())
+}
+
+fn parse_pair(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <key>=<value>, got {value}"))?;
+
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+fn parse_dns_host(value: &str) -> Result<(String, SocketAddr), String> {
+    let (host, addr) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <host>=<ip>:<port>, got {value}"))?;
+    let addr = addr
+        .parse()
+        .map_err(|_| format!("expected <host>=<ip>:<port>, got {value}"))?;
+
+    Ok((host.to_owned(), addr))
+}
+
+fn config_file(args: &Args) -> anyhow::Result<toml::Table> {
+    match &args.config {
+        Some(path) => Ok(std::fs::read_to_string(path)?.parse()?),
+        None => Ok(toml::Table::new()),
+    }
+}
+
+fn guest_config(args: &Args, file: &toml::Table) -> anyhow::Result<GuestConfig> {
+    let mut config = GuestConfig::default();
+
+    for (table, secret) in [("guest-config", false), ("guest-secrets", true)] {
+        let Some(values) = file.get(table) else {
+            continue;
+        };
+
+        let values = values
+            .as_table()
+            .ok_or_else(|| anyhow::Error::msg(format!("[{table}] must be a table")))?;
+
+        for (key, value) in values {
+            let value = value
+                .as_str()
+                .ok_or_else(|| anyhow::Error::msg(format!("{table}.{key} must be a string")))?;
+
+            config = if secret {
+                config.secret(key, value)
+            } else {
+                config.set(key, value)
+            };
+        }
+    }
+
+    for (key, value) in &args.guest_config {
+        config = config.set(key, value);
+    }
+
+    for (key, value) in &args.guest_secret {
+        config = config.secret(key, value);
+    }
+
+    Ok(config)
+}
+
+/// How outbound requests resolve hosts, from the flags and the config file's `[dns-hosts]`
+/// table.
+fn dns_options(args: &Args, file: &toml::Table) -> anyhow::Result<DnsOptions> {
+    let mut options = DnsOptions {
+        resolver: args.dns_resolver.clone().unwrap_or_default(),
+        prefer: args.dns_prefer.unwrap_or_default(),
+        ..Default::default()
+    };
+
+    if let Some(hosts) = file.get("dns-hosts") {
+        let hosts = hosts
+            .as_table()
+            .ok_or_else(|| anyhow::Error::msg("[dns-hosts] must be a table"))?;
+
+        for (host, addr) in hosts {
+            let addr = addr
+                .as_str()
+                .and_then(|addr| addr.parse().ok())
+                .ok_or_else(|| {
+                    anyhow::Error::msg(format!("dns-hosts.{host} must be an <ip>:<port> string"))
+                })?;
+
+            options.hosts.insert(host.clone(), addr);
+        }
+    }
+
+    options.hosts.extend(args.dns_host.iter().cloned());
+
+    Ok(options)
+}
+
+/// Socket options from the config file's `[server.socket]` table, with any given by flags in
+/// their place.
+fn socket_tuning(args: &Args, file: &toml::Table) -> anyhow::Result<SocketTuning> {
+    let mut tuning = SocketTuning {
+        nodelay: true,
+        send_buffer: None,
+        recv_buffer: None,
+        keepalive: None,
+        keepalive_interval: None,
+        keepalive_retries: None,
+        backlog: 1024,
+    };
+
+    if let Some(socket) = file.get("server").and_then(|server| server.get("socket")) {
+        let socket = socket
+            .as_table()
+            .ok_or_else(|| anyhow::Error::msg("[server.socket] must be a table"))?;
+
+        for (key, value) in socket {
+            let invalid =
+                || anyhow::Error::msg(format!("server.socket.{key} must be a positive integer"));
+            let positive = || {
+                value
+                    .as_integer()
+                    .filter(|value| *value > 0)
+                    .ok_or_else(invalid)
+            };
+            let secs = || Ok::<_, anyhow::Error>(Duration::from_secs(positive()? as u64));
+
+            match key.as_str() {
+                "nodelay" => {
+                    tuning.nodelay = value.as_bool().ok_or_else(|| {
+                        anyhow::Error::msg("server.socket.nodelay must be true or false")
+                    })?;
+                }
+                "keepalive-secs" => tuning.keepalive = Some(secs()?),
+                "keepalive-interval-secs" => tuning.keepalive_interval = Some(secs()?),
+                "keepalive-retries" => {
+                    tuning.keepalive_retries =
+                        Some(u32::try_from(positive()?).map_err(|_| invalid())?);
+                }
+                "send-buffer-size" => tuning.send_buffer = Some(positive()? as usize),
+                "recv-buffer-size" => tuning.recv_buffer = Some(positive()? as usize),
+                "backlog" => tuning.backlog = i32::try_from(positive()?).map_err(|_| invalid())?,
+                _ => {
+                    return Err(anyhow::Error::msg(format!(
+                        "server.socket.{key} is not a socket option"
+                    )))
+                }
+            }
+        }
+    }
+
+    if args.no_tcp_nodelay {
+        tuning.nodelay = false;
+    }
+
+    tuning.send_buffer = args.tcp_send_buffer.or(tuning.send_buffer);
+    tuning.recv_buffer = args.tcp_recv_buffer.or(tuning.recv_buffer);
+    tuning.keepalive = args
+        .tcp_keepalive_secs
+        .map(Duration::from_secs)
+        .or(tuning.keepalive);
+    tuning.keepalive_interval = args
+        .tcp_keepalive_interval_secs
+        .map(Duration::from_secs)
+        .or(tuning.keepalive_interval);
+    tuning.keepalive_retries = args.tcp_keepalive_retries.or(tuning.keepalive_retries);
+    tuning.backlog = args.listen_backlog.unwrap_or(tuning.backlog);
+
+    let probes = tuning.keepalive_interval.is_some() || tuning.keepalive_retries.is_some();
+
+    if probes && tuning.keepalive.is_none() {
+        return Err(anyhow::Error::msg(
+            "keepalive-interval-secs and keepalive-retries need keepalive-secs too, in \
+             [server.socket] or as --tcp-keepalive-secs",
+        ));
+    }
+
+    if probes && keepalive_probes(TcpKeepalive::new(), None, None).is_none() {
+        return Err(anyhow::Error::msg(
+            "The interval and count of keepalive probes can't be set on this platform",
+        ));
+    }
+
+    Ok(tuning)
+}
+
+/// The proxies from the environment, with any given by flags in their place.
+fn proxy_options(args: &Args) -> anyhow::Result<ProxyOptions> {
+    let mut options = ProxyOptions::from_env().map_err(anyhow::Error::msg)?;
+
+    if let Some(proxy) = &args.http_proxy {
+        options.http = Some(proxy.clone());
+    }
+
+    if let Some(proxy) = &args.https_proxy {
+        options.https = Some(proxy.clone());
+    }
+
+    if let Some(no_proxy) = &args.no_proxy {
+        options.no_proxy = no_proxy.clone();
+    }
+
+    options.tunnel = args.proxy_tunnel;
+
+    Ok(options)
+}
+
+const CORS_KEYS: &[&str] = &[
+    "origins",
+    "methods",
+    "headers",
+    "credentials",
+    "max-age",
+    "strict",
+];
+
+/// A CORS table from the config file, named `table` in errors.
+fn cors_config(value: &toml::Value, table: &str) -> anyhow::Result<Cors> {
+    let invalid = |key: &str| anyhow::Error::msg(format!("{table}.{key} is invalid"));
+
+    let values = value
+        .as_table()
+        .ok_or_else(|| anyhow::Error::msg(format!("{table} must be a table")))?;
+
+    if let Some(key) = values.keys().find(|key| !CORS_KEYS.contains(&key.as_str())) {
+        return Err(invalid(key));
+    }
+
+    let list = |key: &str| -> anyhow::Result<Option<Vec<&str>>> {
+        values
+            .get(key)
+            .map(|list| {
+                list.as_array()
+                    .ok_or_else(|| invalid(key))?
+                    .iter()
+                    .map(|value| value.as_str().ok_or_else(|| invalid(key)))
+                    .collect()
+            })
+            .transpose()
+    };
+
+    let flag = |key: &str| {
+        values
+            .get(key)
+            .map(|value| value.as_bool().ok_or_else(|| invalid(key)))
+            .transpose()
+    };
+
+    let mut cors = Cors::default();
+
+    if let Some(origins) = list("origins")? {
+        cors.origins = origins
+            .into_iter()
+            .map(|origin| origin.parse().map_err(anyhow::Error::msg))
+            .collect::<anyhow::Result<_>>()?;
+    }
+
+    if let Some(methods) = list("methods")? {
+        cors.methods = methods
+            .into_iter()
+            .map(|method| method.parse().map_err(|_| invalid("methods")))
+            .collect::<anyhow::Result<_>>()?;
+    }
+
+    if let Some(headers) = list("headers")? {
+        cors.headers = headers
+            .into_iter()
+            .map(|header| header.parse().map_err(|_| invalid("headers")))
+            .collect::<anyhow::Result<_>>()?;
+    }
+
+    if let Some(credentials) = flag("credentials")? {
+        cors.credentials = credentials;
+    }
+
+    if let Some(strict) = flag("strict")? {
+        cors.strict = strict;
+    }
+
+    if let Some(max_age) = values.get("max-age") {
+        let max_age = max_age
+            .as_integer()
+            .and_then(|secs| u64::try_from(secs).ok())
+            .ok_or_else(|| invalid("max-age"))?;
+
+        cors.max_age = Some(Duration::from_secs(max_age));
+    }
+
+    cors.validate().map_err(anyhow::Error::msg)?;
+
+    Ok(cors)
+}
+
+/// A table of `request` and `response` header edits from the config file, named `table` in
+/// errors.
+fn header_rules(value: &toml::Value, table: &str) -> anyhow::Result<HeaderRules> {
+    let must_be =
+        |key: &str, kind: &str| anyhow::Error::msg(format!("{table}.{key} must be {kind}"));
+
+    let values = value.as_table().ok_or_else(|| must_be("*", "a table"))?;
+    let mut rules = HeaderRules::default();
+
+    for (direction, edits) in values {
+        let target = match direction.as_str() {
+            "request" => &mut rules.request,
+            "response" => &mut rules.response,
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "{table}.{direction} is invalid"
+                )))
+            }
+        };
+
+        let key = |name: &str| format!("{direction}.{name}");
+        let invalid = |name: &str, error: String| {
+            anyhow::Error::msg(format!("{table}.{}: {error}", key(name)))
+        };
+        let header = |verb: &str, name: &str| {
+            name.parse::<HeaderName>()
+                .map_err(|_| invalid(verb, format!("{name} is not a header name")))
+        };
+
+        let edits = edits
+            .as_table()
+            .ok_or_else(|| must_be(direction, "a table"))?;
+        let mut parsed = HeaderEdits::default();
+
+        for (verb, value) in edits {
+            match verb.as_str() {
+                "remove" => {
+                    let names = value
+                        .as_array()
+                        .ok_or_else(|| must_be(&key(verb), "a list of header names"))?;
+
+                    for name in names {
+                        let name = name
+                            .as_str()
+                            .ok_or_else(|| must_be(&key(verb), "a list of header names"))?;
+                        parsed = parsed
+                            .remove(header(verb, name)?)
+                            .map_err(|error| invalid(verb, error))?;
+                    }
+                }
+                "rename" | "set" => {
+                    let pairs = value
+                        .as_table()
+                        .ok_or_else(|| must_be(&key(verb), "a table of strings"))?;
+
+                    for (name, value) in pairs {
+                        let value = value
+                            .as_str()
+                            .ok_or_else(|| must_be(&key(verb), "a table of strings"))?;
+                        let name = header(verb, name)?;
+
+                        parsed = if verb == "rename" {
+                            parsed.rename(name, header(verb, value)?)
+                        } else {
+                            let value = value.parse().map_err(|error| invalid(verb, error))?;
+                            parsed.set(name, value)
+                        }
+                        .map_err(|error| invalid(verb, error))?;
+                    }
+                }
+                _ => {
+                    return Err(anyhow::Error::msg(format!(
+                        "{table}.{} is invalid",
+                        key(verb)
+                    )))
+                }
+            }
+        }
+
+        *target = parsed;
+    }
+
+    Ok(rules)
+}
+
+/// A `[mounts."<prefix>"]` table from the config file: another component served below `prefix`
+/// with its own limits. Anything not set is taken from the command line.
+struct MountConfig {
+    prefix: String,
+    component: PathBuf,
+    options: Options,
+    kv_buckets: Vec<(String, String)>,
+}
+
+const MOUNT_KEYS: &[&str] = &[
+    "component",
+    "max-concurrency",
+    "queue-depth",
+    "max-body-bytes",
+    "guest-threads",
+    "dump-http",
+    "fuel",
+    "max-memory-bytes",
+    "tcp-allow",
+    "http-allow",
+    "rate-limit",
+    "kv-buckets",
+    "cors",
+    "headers",
+    "allow-methods",
+    "deny-methods",
+    "allow-paths",
+    "deny-paths",
+];
+
+fn mount_configs(file: &toml::Table, base: &Options) -> anyhow::Result<Vec<MountConfig>> {
+    let Some(mounts) = file.get("mounts") else {
+        return Ok(Vec::new());
+    };
+
+    let mounts = mounts
+        .as_table()
+        .ok_or_else(|| anyhow::Error::msg("[mounts] must be a table"))?;
+
+    mounts
+        .iter()
+        .map(|(prefix, mount)| {
+            let invalid =
+                |key: &str| anyhow::Error::msg(format!("mounts.\"{prefix}\".{key} is invalid"));
+
+            let mount = mount.as_table().ok_or_else(|| invalid("*"))?;
+
+            if let Some(key) = mount.keys().find(|key| !MOUNT_KEYS.contains(&key.as_str())) {
+                return Err(invalid(key));
+            }
+
+            let number = |key: &str| {
+                mount
+                    .get(key)
+                    .map(|value| {
+                        value
+                            .as_integer()
+                            .and_then(|value| usize::try_from(value).ok())
+                            .ok_or_else(|| invalid(key))
+                    })
+                    .transpose()
+            };
+
+            let strings = |key: &str| match mount.get(key) {
+                Some(values) => values
+                    .as_array()
+                    .ok_or_else(|| invalid(key))?
+                    .iter()
+                    .map(|value| value.as_str().ok_or_else(|| invalid(key)))
+                    .collect(),
+                None => Ok(Vec::new()),
+            };
+
+            let component = mount
+                .get("component")
+                .and_then(|component| component.as_str())
+                .ok_or_else(|| invalid("component"))?;
+
+            // Static directories, warm-up paths and middleware belong to the main component.
+            let mut options = Options {
+                static_dirs: Vec::new(),
+                warmup: Vec::new(),
+                middleware: Vec::new(),
+                ..base.clone()
+            };
+
+            if let Some(max_concurrency) = number("max-concurrency")? {
+                options.max_concurrency = max_concurrency;
+            }
+
+            if let Some(fuel) = number("fuel")? {
+                options.fuel = Some(fuel as u64);
+            }
+
+            if let Some(max_memory_bytes) = number("max-memory-bytes")? {
+                options.max_memory_bytes = Some(max_memory_bytes);
+            }
+
+            if let Some(guest_threads) = number("guest-threads")? {
+                options.guest_threads = guest_threads;
+            }
+
+            if let Some(queue_depth) = number("queue-depth")? {
+                options.queue_depth = queue_depth;
+            }
+
+            if let Some(max_body_bytes) = number("max-body-bytes")? {
+                options.max_body_bytes = max_body_bytes;
+            }
+
+            if let Some(dump_http) = mount.get("dump-http") {
+                options.dump_heads = dump_http.as_bool().ok_or_else(|| invalid("dump-http"))?;
+
+                if options.dump_heads && options.dump_bodies == 0 {
+                    options.dump_bodies = 4096;
+                }
+            }
+
+            if mount.contains_key("tcp-allow") {
+                options.tcp_egress = strings("tcp-allow")?
+                    .into_iter()
+                    .map(|rule| rule.parse().map_err(anyhow::Error::msg))
+                    .collect::<anyhow::Result<_>>()?;
+            }
+
+            if let Some(rate_limit) = mount.get("rate-limit") {
+                options.rate_limit = Some(
+                    rate_limit
+                        .as_str()
+                        .ok_or_else(|| invalid("rate-limit"))?
+                        .parse()
+                        .map_err(anyhow::Error::msg)?,
+                );
+            }
+
+            let methods = |key: &str| {
+                strings(key)?
+                    .into_iter()
+                    .map(|method| method.parse().map_err(|_| invalid(key)))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            };
+
+            match (
+                mount.contains_key("allow-methods"),
+                mount.contains_key("deny-methods"),
+            ) {
+                (true, true) => return Err(invalid("deny-methods")),
+                (true, false) => options.methods = MethodPolicy::Allow(methods("allow-methods")?),
+                (false, true) => options.methods = MethodPolicy::Deny(methods("deny-methods")?),
+                (false, false) => {}
+            }
+
+            let globs = |key: &str| {
+                strings(key)?
+                    .into_iter()
+                    .map(|glob| glob.parse().map_err(anyhow::Error::msg))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            };
+
+            if mount.contains_key("allow-paths") || mount.contains_key("deny-paths") {
+                options.paths = PathFilter {
+                    allow: globs("allow-paths")?,
+                    deny: globs("deny-paths")?,
+                };
+            }
+
+            if let Some(cors) = mount.get("cors") {
+                options.cors = Some(cors_config(cors, &format!("mounts.\"{prefix}\".cors"))?);
+            }
+
+            if let Some(rules) = mount.get("headers") {
+                options.headers = header_rules(rules, &format!("mounts.\"{prefix}\".headers"))?;
+            }
+
+            if mount.contains_key("http-allow") {
+                options.http_egress = strings("http-allow")?
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect();
+            }
+
+            // Namespaces are scoped to the mount, so tenants can't reach each other's buckets.
+            let kv_buckets = strings("kv-buckets")?
+                .into_iter()
+                .map(|bucket| {
+                    let (name, namespace) = parse_bucket(bucket).map_err(anyhow::Error::msg)?;
+                    Ok((name, format!("{prefix}:{namespace}")))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            Ok(MountConfig {
+                prefix: prefix.clone(),
+                component: PathBuf::from(component),
+                options,
+                kv_buckets,
+            })
+        })
+        .collect()
+}
+
+fn kv_backend(args: &Args) -> anyhow::Result<Arc<dyn KvBackend>> {
+    #[cfg(feature = "redb")]
+    if let Some(path) = &args.kv_path {
+        return Ok(Arc::new(RedbBackend::open(path)?));
+    }
+
+    let _ = args;
+
+    Ok(Arc::new(MemoryBackend::default()))
+}
+
+fn parse_bucket(value: &str) -> Result<(String, String), String> {
+    let (name, namespace) = value.split_once('=').unwrap_or((value, value));
+
+    Ok((name.to_owned(), namespace.to_owned()))
+}
+
+async fn admin(runner: Arc<Runner>) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let runner = runner.clone();
+
+        // Loading compiles the component, so keep it off the async workers.
+        match tokio::task::spawn_blocking(move || run_command(&runner, &line)).await? {
+            Ok(()) => info!("admin command succeeded"),
+            Err(err) => error!(error = ?err, "admin command failed"),
+        }
+    }
+
+    Ok(())
+}
+
+fn run_command(runner: &Runner, line: &str) -> anyhow::Result<()> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["load", path, "as", slot] => runner.load(path, slot),
+        ["activate", slot] => runner.activate(slot),
+        ["rollback"] => runner.rollback(),
+        ["split", slot, weight, sticky @ ..] => {
+            let weight = weight.parse()?;
+
+            let sticky = match sticky {
+                [] => None,
+                ["header", name] => Some(Sticky::Header(name.parse()?)),
+                ["cookie", name] => Some(Sticky::Cookie(name.to_string())),
+                _ => return Err(anyhow::Error::msg(format!("Unknown command: {line}"))),
+            };
+
+            runner.split(slot, weight, sticky)
+        }
+        ["unsplit"] => {
+            runner.clear_split();
+            Ok(())
+        }
+        ["status"] => {
+            info!(active = %runner.active_slot(), "deployment status");
+
+            for version in runner.versions() {
+                let metrics = version.metrics();
+
+                info!(
+                    slot = %version.slot,
+                    requests = metrics.requests.get(),
+                    failures = metrics.failures.get(),
+                    circuit = metrics.circuit_state.get(),
+                    "version status"
+                );
+            }
+
+            Ok(())
+        }
+        [] => Ok(()),
+        _ => Err(anyhow::Error::msg(format!("Unknown command: {line}"))),
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Bucket bounds for durations, in microseconds.
+pub const DURATION_BUCKETS: &[u64] = &[
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000, 10_000_000,
+];
+
+/// Bucket bounds for fuel consumed by a request.
+pub const FUEL_BUCKETS: &[u64] = &[
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+];
+
+/// Bucket bounds for linear memory, in bytes.
+pub const MEMORY_BUCKETS: &[u64] = &[
+    1 << 20,
+    4 << 20,
+    16 << 20,
+    64 << 20,
+    256 << 20,
+    1 << 30,
+    4 << 30,
+];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increments the gauge until the returned guard is dropped.
+    pub fn track(&self) -> Tracked<'_> {
+        self.inc();
+        Tracked(self)
+    }
+}
+
+pub struct Tracked<'a>(&'a Gauge);
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+pub struct Histogram {
+    bounds: &'static [u64],
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        if let Some(index) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[index].fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    /// Returns each bucket bound along with the cumulative number of observations at or below it.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.bounds
+            .iter()
+            .zip(self.counts.iter())
+            .scan(0, |total, (bound, count)| {
+                *total += count.load(Ordering::Relaxed);
+                Some((*bound, *total))
+            })
+    }
+}
+
+pub struct Metrics {
+    pub requests: Counter,
+    pub queue_depth: Gauge,
+    /// Threads of the guest pool running a request.
+    pub guest_threads_busy: Gauge,
+    pub shed: Counter,
+    /// Requests refused because their client exceeded the rate limit.
+    pub rate_limited: Counter,
+    pub failures: Counter,
+    pub retries: Counter,
+    /// 0 when closed, 1 when open and 2 when half-open.
+    pub circuit_state: Gauge,
+    pub circuit_opened: Counter,
+    pub circuit_rejected: Counter,
+    pub mirrored: Counter,
+    /// Sampled requests whose body was too large or not fully read by the primary.
+    pub mirror_skipped: Counter,
+    pub mirror_mismatches: Counter,
+    pub cache_hits: Counter,
+    pub cache_misses: Counter,
+    pub queue_time: Histogram,
+    pub execution_time: Histogram,
+    /// The part of `execution_time` spent instantiating the component.
+    pub instantiation_time: Histogram,
+    /// The part of `execution_time` spent running the guest, less `wait_time`.
+    pub guest_time: Histogram,
+    /// Time the guest spent blocked on the client or the network.
+    pub wait_time: Histogram,
+    /// Time from the guest returning to the last of the response body being sent.
+    pub drain_time: Histogram,
+    /// Fuel consumed by each call into the guest, retries included, when metering is on.
+    pub fuel_used: Histogram,
+    /// Linear memory each instance had when its request finished.
+    pub memory_bytes: Histogram,
+    /// The most linear memory each instance had at once.
+    pub peak_memory_bytes: Histogram,
+    /// Memory grows refused for going over `max_memory_bytes`.
+    pub memory_grows_denied: Counter,
+}
+
+/// Where the time handling one request went, kept in the store's state while the guest runs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    pub instantiation: Duration,
+    /// Time in `call_handle`, less `waited`.
+    pub guest: Duration,
+    /// Time blocked reading the request body, flushing the response body or waiting on a
+    /// pollable.
+    pub waited: Duration,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: Counter::default(),
+            queue_depth: Gauge::default(),
+            guest_threads_busy: Gauge::default(),
+            shed: Counter::default(),
+            rate_limited: Counter::default(),
+            failures: Counter::default(),
+            retries: Counter::default(),
+            circuit_state: Gauge::default(),
+            circuit_opened: Counter::default(),
+            circuit_rejected: Counter::default(),
+            mirrored: Counter::default(),
+            mirror_skipped: Counter::default(),
+            mirror_mismatches: Counter::default(),
+            cache_hits: Counter::default(),
+            cache_misses: Counter::default(),
+            queue_time: Histogram::new(DURATION_BUCKETS),
+            execution_time: Histogram::new(DURATION_BUCKETS),
+            instantiation_time: Histogram::new(DURATION_BUCKETS),
+            guest_time: Histogram::new(DURATION_BUCKETS),
+            wait_time: Histogram::new(DURATION_BUCKETS),
+            drain_time: Histogram::new(DURATION_BUCKETS),
+            fuel_used: Histogram::new(FUEL_BUCKETS),
+            memory_bytes: Histogram::new(MEMORY_BUCKETS),
+            peak_memory_bytes: Histogram::new(MEMORY_BUCKETS),
+            memory_grows_denied: Counter::default(),
+        }
+    }
+}
+
+type Read<T> = fn(&Metrics) -> T;
+
+const COUNTERS: &[(&str, &str, Read<u64>)] = &[
+    ("requests_total", "Requests received.", |m| m.requests.get()),
+    ("shed_total", "Requests shed by the queue.", |m| {
+        m.shed.get()
+    }),
+    (
+        "rate_limited_total",
+        "Requests refused by the rate limit.",
+        |m| m.rate_limited.get(),
+    ),
+    ("failures_total", "Requests the guest failed.", |m| {
+        m.failures.get()
+    }),
+    ("retries_total", "Calls into the guest retried.", |m| {
+        m.retries.get()
+    }),
+    (
+        "circuit_opened_total",
+        "Times the circuit breaker opened.",
+        |m| m.circuit_opened.get(),
+    ),
+    (
+        "circuit_rejected_total",
+        "Requests refused by an open circuit.",
+        |m| m.circuit_rejected.get(),
+    ),
+    ("mirrored_total", "Requests copied to the mirror.", |m| {
+        m.mirrored.get()
+    }),
+    (
+        "mirror_skipped_total",
+        "Sampled requests not mirrored.",
+        |m| m.mirror_skipped.get(),
+    ),
+    (
+        "mirror_mismatches_total",
+        "Mirrored responses that differed.",
+        |m| m.mirror_mismatches.get(),
+    ),
+    (
+        "cache_hits_total",
+        "Responses served from the cache.",
+        |m| m.cache_hits.get(),
+    ),
+    (
+        "cache_misses_total",
+        "Cacheable requests not in the cache.",
+        |m| m.cache_misses.get(),
+    ),
+    (
+        "memory_grows_denied_total",
+        "Memory grows refused by the limit.",
+        |m| m.memory_grows_denied.get(),
+    ),
+];
+
+const GAUGES: &[(&str, &str, Read<i64>)] = &[
+    ("queue_depth", "Requests waiting in the queue.", |m| {
+        m.queue_depth.get()
+    }),
+    (
+        "guest_threads_busy",
+        "Guest threads running a request.",
+        |m| m.guest_threads_busy.get(),
+    ),
+    (
+        "circuit_state",
+        "0 when closed, 1 when open and 2 when half-open.",
+        |m| m.circuit_state.get(),
+    ),
+];
+
+const HISTOGRAMS: &[(&str, &str, for<'a> fn(&'a Metrics) -> &'a Histogram)] = &[
+    ("queue_time_microseconds", "Time spent queued.", |m| {
+        &m.queue_time
+    }),
+    (
+        "execution_time_microseconds",
+        "Time spent handling a request.",
+        |m| &m.execution_time,
+    ),
+    (
+        "instantiation_time_microseconds",
+        "Time spent instantiating the component.",
+        |m| &m.instantiation_time,
+    ),
+    (
+        "guest_time_microseconds",
+        "Time spent running the guest.",
+        |m| &m.guest_time,
+    ),
+    (
+        "wait_time_microseconds",
+        "Time the guest spent blocked.",
+        |m| &m.wait_time,
+    ),
+    (
+        "drain_time_microseconds",
+        "Time spent sending the rest of the body.",
+        |m| &m.drain_time,
+    ),
+    ("fuel_used", "Fuel consumed per call into the guest.", |m| {
+        &m.fuel_used
+    }),
+    (
+        "memory_bytes",
+        "Linear memory at the end of a request.",
+        |m| &m.memory_bytes,
+    ),
+    (
+        "peak_memory_bytes",
+        "Most linear memory during a request.",
+        |m| &m.peak_memory_bytes,
+    ),
+];
+
+/// Renders the metrics of each mount in the Prometheus text format, labelled with its prefix.
+pub fn encode(mounts: &[(&str, &Metrics)]) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+
+    for (name, help, read) in COUNTERS {
+        let _ = writeln!(
+            out,
+            "# HELP wasi_http_{name} {help}\n# TYPE wasi_http_{name} counter"
+        );
+
+        for (mount, metrics) in mounts {
+            let _ = writeln!(
+                out,
+                "wasi_http_{name}{{mount=\"{mount}\"}} {}",
+                read(metrics)
+            );
+        }
+    }
+
+    for (name, help, read) in GAUGES {
+        let _ = writeln!(
+            out,
+            "# HELP wasi_http_{name} {help}\n# TYPE wasi_http_{name} gauge"
+        );
+
+        for (mount, metrics) in mounts {
+            let _ = writeln!(
+                out,
+                "wasi_http_{name}{{mount=\"{mount}\"}} {}",
+                read(metrics)
+            );
+        }
+    }
+
+    for (name, help, read) in HISTOGRAMS {
+        let _ = writeln!(
+            out,
+            "# HELP wasi_http_{name} {help}\n# TYPE wasi_http_{name} histogram"
+        );
+
+        for (mount, metrics) in mounts {
+            let histogram = read(metrics);
+
+            for (bound, count) in histogram.buckets() {
+                let _ = writeln!(
+                    out,
+                    "wasi_http_{name}_bucket{{mount=\"{mount}\",le=\"{bound}\"}} {count}"
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "wasi_http_{name}_bucket{{mount=\"{mount}\",le=\"+Inf\"}} {}\n\
+                 wasi_http_{name}_sum{{mount=\"{mount}\"}} {}\n\
+                 wasi_http_{name}_count{{mount=\"{mount}\"}} {}",
+                histogram.count(),
+                histogram.sum(),
+                histogram.count(),
+            );
+        }
+    }
+
+    out
+}
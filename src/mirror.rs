@@ -0,0 +1,181 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use ::http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use pin_project::pin_project;
+use tokio::sync::oneshot;
+use tracing::{debug, info_span, Instrument};
+
+use crate::{
+    clone_head,
+    http::{BoxError, RequestBody},
+    Runner,
+};
+
+/// Copies a sample of live traffic to a shadow component whose responses are discarded.
+pub struct Mirror {
+    runner: Arc<Runner>,
+    /// Percentage of requests that are mirrored.
+    percent: u64,
+    /// Requests with larger bodies are not mirrored.
+    max_body_bytes: usize,
+    seen: AtomicU64,
+}
+
+impl Mirror {
+    pub fn new(runner: Runner, percent: u8, max_body_bytes: usize) -> Self {
+        Self {
+            runner: Arc::new(runner),
+            percent: percent.min(100).into(),
+            max_body_bytes,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    pub fn runner(&self) -> &Runner {
+        &self.runner
+    }
+
+    /// Spreads the mirrored requests evenly instead of picking them at random.
+    fn sample(&self) -> bool {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed);
+
+        (n + 1) * self.percent / 100 > n * self.percent / 100
+    }
+}
+
+impl Runner {
+    /// Starts mirroring `req` if it is sampled. The body is copied as the primary guest reads it, so
+    /// the shadow only runs once the primary has seen the whole body. The returned sender takes
+    /// the primary's status for comparison.
+    pub(crate) fn mirror(
+        self: &Arc<Self>,
+        req: Request<RequestBody>,
+    ) -> (Request<RequestBody>, Option<oneshot::Sender<StatusCode>>) {
+        let Some(mirror) = self.mirror.as_ref().filter(|mirror| mirror.sample()) else {
+            return (req, None);
+        };
+
+        let (body_tx, body_rx) = oneshot::channel();
+        let (status_tx, status_rx) = oneshot::channel();
+
+        let head = clone_head(&req);
+        let req = req.map(|body| {
+            let mut body = MirrorBody {
+                inner: body,
+                copy: Vec::new(),
+                max_body_bytes: mirror.max_body_bytes,
+                tx: Some(body_tx),
+            };
+
+            // The guest may never poll a body that is already known to be empty.
+            if body.inner.is_end_stream() {
+                finish(&mut body.tx, &mut body.copy);
+            }
+
+            body.boxed_unsync()
+        });
+
+        let runner = mirror.runner.clone();
+        let primary = self.clone();
+        let span = info_span!("shadow", method = %head.method, path = %head.uri.path());
+
+        tokio::task::spawn(
+            async move {
+                let Ok(body) = body_rx.await else {
+                    primary.metrics.mirror_skipped.inc();
+                    return;
+                };
+
+                primary.metrics.mirrored.inc();
+
+                let started_at = Instant::now();
+                let req = Request::from_parts(head, Full::new(Bytes::from(body)));
+                let shadow = match runner.service_fn(req).await {
+                    Ok(res) => res.status(),
+                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                let shadow_us = started_at.elapsed().as_micros() as u64;
+
+                let Ok(status) = status_rx.await else {
+                    return;
+                };
+
+                if status != shadow {
+                    primary.metrics.mirror_mismatches.inc();
+                    debug!(primary = %status, %shadow, shadow_us, "shadow status differs");
+                }
+            }
+            .instrument(span),
+        );
+
+        (req, Some(status_tx))
+    }
+}
+
+#[pin_project]
+struct MirrorBody {
+    #[pin]
+    inner: RequestBody,
+    copy: Vec<u8>,
+    max_body_bytes: usize,
+    /// Dropping this without sending skips the mirrored request.
+    tx: Option<oneshot::Sender<Vec<u8>>>,
+}
+
+fn finish(tx: &mut Option<oneshot::Sender<Vec<u8>>>, copy: &mut Vec<u8>) {
+    if let Some(tx) = tx.take() {
+        let _ = tx.send(std::mem::take(copy));
+    }
+}
+
+impl Body for MirrorBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.project();
+        let res = this.inner.poll_frame(cx);
+
+        match &res {
+            Poll::Ready(Some(Ok(frame))) => match frame.data_ref() {
+                Some(data) if this.tx.is_some() => {
+                    if this.copy.len() + data.len() > *this.max_body_bytes {
+                        *this.tx = None;
+                        *this.copy = Vec::new();
+                    } else {
+                        this.copy.extend_from_slice(data);
+                    }
+                }
+                Some(_) => {}
+                // Trailers end the body; the shadow request goes without them.
+                None => finish(this.tx, this.copy),
+            },
+            Poll::Ready(None) => finish(this.tx, this.copy),
+            Poll::Ready(Some(Err(_))) => *this.tx = None,
+            Poll::Pending => {}
+        }
+
+        res
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
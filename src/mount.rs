@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use ::http::{Request, Response, StatusCode};
+use hyper::body::{Body, Bytes};
+use tracing::{info_span, Instrument};
+
+use crate::{error_response, http::BoxError, metrics, ResponseBody, Runner};
+
+/// Routes requests to one of several runners by path prefix. Each runner keeps its own queue,
+/// limits, egress rules and key-value buckets, so one busy tenant can't take another's slots.
+#[derive(Default)]
+pub struct Mounts {
+    /// Longest prefix first, so the most specific mount wins.
+    mounts: Vec<(String, Arc<Runner>)>,
+}
+
+impl Mounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves paths at and below `prefix` with `runner`. The guest still sees the full path.
+    pub fn mount(mut self, prefix: &str, runner: Arc<Runner>) -> Self {
+        let prefix = prefix.trim_end_matches('/').to_owned();
+        let at = self
+            .mounts
+            .partition_point(|(mounted, _)| mounted.len() >= prefix.len());
+
+        self.mounts.insert(at, (prefix, runner));
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<Runner>)> {
+        self.mounts
+            .iter()
+            .map(|(prefix, runner)| (prefix.as_str(), runner))
+    }
+
+    /// Every mount's metrics in the Prometheus text format.
+    pub fn encode_metrics(&self) -> String {
+        let labels: Vec<_> = self
+            .iter()
+            .map(|(prefix, runner)| {
+                let label = if prefix.is_empty() { "/" } else { prefix };
+                let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+
+                (label, runner.metrics())
+            })
+            .collect();
+
+        let mounts: Vec<_> = labels
+            .iter()
+            .map(|(label, metrics)| (label.as_str(), *metrics))
+            .collect();
+
+        metrics::encode(&mounts)
+    }
+
+    fn route(&self, path: &str) -> Option<(&str, &Arc<Runner>)> {
+        self.iter().find(|(prefix, _)| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    pub async fn service_fn<B>(
+        self: Arc<Self>,
+        req: Request<B>,
+    ) -> anyhow::Result<Response<ResponseBody>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let Some((prefix, runner)) = self.route(req.uri().path()) else {
+            let res = error_response(
+                StatusCode::NOT_FOUND,
+                "no-mount",
+                "No component is mounted at this path",
+            );
+
+            return Ok(res.map(|body| body.into_response_body()));
+        };
+
+        // The mount shows up on every log line of the request, so a noisy tenant stands out.
+        let label = if prefix.is_empty() { "/" } else { prefix };
+        let span = info_span!("mount", mount = %label);
+
+        runner.clone().service_fn(req).instrument(span).await
+    }
+}
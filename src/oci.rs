@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use oci_distribution::{client::ClientConfig, manifest, secrets::RegistryAuth, Client, Reference};
+use sha2::{Digest, Sha256};
+
+use crate::Fetch;
+
+/// Layer media types a component may be published under.
+const COMPONENT_MEDIA_TYPES: &[&str] = &[
+    manifest::WASM_LAYER_MEDIA_TYPE,
+    "application/wasm",
+    "application/vnd.bytecodealliance.wasm.component.layer.v0+wasm",
+];
+
+impl Fetch {
+    /// Pulls the component layer of an OCI artifact into the cache and returns its path there.
+    /// Artifacts are cached by manifest digest, so one pinned by digest is only pulled once.
+    /// Without `auth` as a username and password, credentials come from the Docker config.
+    pub async fn oci_component(
+        &self,
+        component: &str,
+        auth: Option<(&str, &str)>,
+    ) -> anyhow::Result<PathBuf> {
+        let reference: Reference = component
+            .strip_prefix("oci://")
+            .unwrap_or(component)
+            .parse()?;
+
+        let cache_dir = self.cache_dir.join("oci");
+        std::fs::create_dir_all(&cache_dir)?;
+
+        let cached = |digest: &str| cache_dir.join(format!("{}.wasm", digest.replace(':', "-")));
+
+        if let Some(digest) = reference.digest() {
+            let path = cached(digest);
+
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+
+        let auth = match auth {
+            Some((username, password)) => RegistryAuth::Basic(username.into(), password.into()),
+            None => docker_auth(reference.resolve_registry()),
+        };
+
+        let client = Client::new(ClientConfig::default());
+
+        let image = tokio::time::timeout(
+            self.timeout,
+            client.pull(&reference, &auth, COMPONENT_MEDIA_TYPES.to_vec()),
+        )
+        .await
+        .map_err(|_| {
+            anyhow::Error::msg(format!(
+                "Pulling {reference} took longer than {:?}",
+                self.timeout
+            ))
+        })??;
+
+        let descriptors = image
+            .manifest
+            .as_ref()
+            .map(|manifest| manifest.layers.as_slice())
+            .unwrap_or_default();
+
+        let (layer, descriptor) = match (image.layers.as_slice(), descriptors) {
+            ([layer], [descriptor]) => (layer, descriptor),
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "{reference} must have exactly one component layer, found {}",
+                    image.layers.len()
+                )))
+            }
+        };
+
+        if layer.data.len() > self.max_bytes {
+            return Err(anyhow::Error::msg(format!(
+                "{reference} is larger than {} bytes",
+                self.max_bytes
+            )));
+        }
+
+        let digest = format!("sha256:{:x}", Sha256::digest(&layer.data));
+
+        if digest != descriptor.digest {
+            return Err(anyhow::Error::msg(format!(
+                "The layer of {reference} has digest {digest}, but its manifest says {}",
+                descriptor.digest
+            )));
+        }
+
+        let manifest_digest = image
+            .digest
+            .as_deref()
+            .or(reference.digest())
+            .unwrap_or(&digest);
+
+        if let Some(expected) = reference.digest() {
+            if manifest_digest != expected {
+                return Err(anyhow::Error::msg(format!(
+                    "{reference} resolved to manifest {manifest_digest}"
+                )));
+            }
+        }
+
+        let path = cached(manifest_digest);
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, &layer.data)?;
+        std::fs::rename(&partial, &path)?;
+
+        Ok(path)
+    }
+}
+
+/// Credentials for `registry` from the Docker config or its credential helpers, if any.
+fn docker_auth(registry: &str) -> RegistryAuth {
+    match docker_credential::get_credential(registry) {
+        Ok(docker_credential::DockerCredential::UsernamePassword(username, password)) => {
+            RegistryAuth::Basic(username, password)
+        }
+        _ => RegistryAuth::Anonymous,
+    }
+}
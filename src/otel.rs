@@ -0,0 +1,84 @@
+//! Exports spans over OTLP, joining each request to the trace it arrived with.
+
+use std::str::FromStr;
+
+use ::http::HeaderValue;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::trace::TraceContext;
+
+/// A layer sending spans to the collector the standard `OTEL_EXPORTER_OTLP_*` variables name, or
+/// `None` when no endpoint is set. Must be called within the tokio runtime.
+pub fn layer<S>() -> anyhow::Result<Option<OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| std::env::var_os(name).is_some());
+
+    if !configured {
+        return Ok(None);
+    }
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Sends the spans still buffered.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Makes `span` a child of the remote span `trace` names as its parent, and returns `trace` with
+/// the ids `span` is exported under.
+pub fn join(span: &Span, trace: TraceContext, tracestate: Option<&HeaderValue>) -> TraceContext {
+    if let Some(parent_id) = trace.parent_id {
+        let state = tracestate
+            .and_then(|state| state.to_str().ok())
+            .and_then(|state| TraceState::from_str(state).ok())
+            .unwrap_or_default();
+
+        let flags = if trace.sampled {
+            TraceFlags::SAMPLED
+        } else {
+            TraceFlags::default()
+        };
+
+        let parent = SpanContext::new(
+            TraceId::from_bytes(trace.trace_id.to_be_bytes()),
+            SpanId::from_bytes(parent_id.to_be_bytes()),
+            flags,
+            true,
+            state,
+        );
+
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(parent));
+    }
+
+    of_span(span, trace).unwrap_or(trace)
+}
+
+/// `trace` with the ids OpenTelemetry gave `span`, or `None` when it isn't exported.
+pub fn of_span(span: &Span, trace: TraceContext) -> Option<TraceContext> {
+    let context = span.context();
+    let otel_span = context.span();
+    let span_context = otel_span.span_context();
+
+    span_context.is_valid().then(|| TraceContext {
+        trace_id: u128::from_be_bytes(span_context.trace_id().to_bytes()),
+        span_id: u64::from_be_bytes(span_context.span_id().to_bytes()),
+        sampled: span_context.is_sampled(),
+        ..trace
+    })
+}
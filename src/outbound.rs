@@ -0,0 +1,886 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration as StdDuration,
+};
+
+use futures::task::noop_waker_ref;
+use http::{
+    header::{PROXY_AUTHORIZATION, TRANSFER_ENCODING},
+    uri::Authority,
+    HeaderMap, Request, Response, Uri,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use tokio::task::JoinHandle;
+use tracing::{field, info_span, Instrument, Span};
+use wasmtime::component::Resource;
+
+use crate::{
+    dns::{ConnectFailure, Connector, DnsOptions, CONNECT_DEADLINE},
+    http::{method_to_wasi, BodyState, IncomingBodyWrapper, RequestBody, StreamHandle},
+    io::PollableIndividual,
+    pipe::{Pipe, PipeBody},
+    proxy::ProxyOptions,
+    trace::{TRACEPARENT, TRACESTATE},
+    upstream::BetweenBytes,
+    wasi::{
+        self,
+        http::types::{
+            Duration, ErrorCode, FutureIncomingResponse, Headers, IncomingBody, IncomingResponse,
+            Method, OutgoingBody, OutgoingRequest, Pollable, RequestOptions, Scheme, StatusCode,
+            Trailers,
+        },
+    },
+    State,
+};
+
+/// Answers outbound requests in place of the network, or passes them on by returning `None`.
+pub type OutboundMock = Arc<dyn Fn(&Request<Bytes>) -> Option<Response<Bytes>> + Send + Sync>;
+
+/// The client guests send requests through and the authorities they may reach. Cloning is cheap.
+#[derive(Clone)]
+pub struct Outbound {
+    client: Client<Connector, RequestBody>,
+    /// `host` or `host:port`, matched case-insensitively.
+    allow: Vec<String>,
+    /// Consulted before the egress rules, so mocked hosts need not be allowed.
+    mock: Option<OutboundMock>,
+    proxy: Arc<ProxyOptions>,
+}
+
+impl Outbound {
+    pub fn new(allow: Vec<String>, dns: &DnsOptions, proxy: &ProxyOptions) -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build(Connector::new(dns, proxy)),
+            allow,
+            mock: None,
+            proxy: Arc::new(proxy.clone()),
+        }
+    }
+
+    pub fn with_mock(mut self, mock: OutboundMock) -> Self {
+        self.mock = Some(mock);
+        self
+    }
+
+    fn allows(&self, authority: &Authority) -> bool {
+        self.allow.iter().any(|rule| match rule.rsplit_once(':') {
+            Some((host, port)) => {
+                authority.host().eq_ignore_ascii_case(host)
+                    && authority.port_u16().unwrap_or(80).to_string() == port
+            }
+            None => authority.host().eq_ignore_ascii_case(rule),
+        })
+    }
+}
+
+/// A request the guest is building or has handed to `outgoing-handler` but not finished the body
+/// of. The body is buffered until the request is handed over, and then streamed upstream as the
+/// guest writes it. Requests to a mock are buffered until the body is finished.
+pub struct OutboundRequest {
+    method: http::Method,
+    scheme: Option<Scheme>,
+    authority: Option<String>,
+    path_with_query: Option<String>,
+    headers: HeaderMap,
+    body: Arc<Mutex<Pipe>>,
+    /// Whether the guest asked for the body. One it never touched is sent empty.
+    body_taken: bool,
+    pending: Option<Pending>,
+}
+
+/// What `handle` settled on, kept until the body is finished.
+struct Pending {
+    future: u32,
+    uri: Uri,
+    timeouts: Timeouts,
+    /// Covers the request until its response head arrives.
+    span: Span,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Timeouts {
+    connect: Option<StdDuration>,
+    first_byte: Option<StdDuration>,
+    between_bytes: Option<StdDuration>,
+}
+
+pub enum FutureResponse {
+    /// Waiting for the guest to finish the request body.
+    Unsent,
+    InFlight(JoinHandle<Result<Response<RequestBody>, ErrorCode>>),
+    Ready(Result<Response<RequestBody>, ErrorCode>),
+    Taken,
+}
+
+impl FutureResponse {
+    fn settle(&mut self, block: bool) {
+        let FutureResponse::InFlight(handle) = self else {
+            return;
+        };
+
+        let res = if block {
+            futures::executor::block_on(handle)
+        } else {
+            match Pin::new(handle).poll(&mut Context::from_waker(noop_waker_ref())) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return,
+            }
+        };
+
+        *self = FutureResponse::Ready(
+            res.unwrap_or_else(|err| Err(ErrorCode::InternalError(Some(err.to_string())))),
+        );
+    }
+}
+
+fn method_from_wasi(method: Method) -> Option<http::Method> {
+    Some(match method {
+        Method::Get => http::Method::GET,
+        Method::Head => http::Method::HEAD,
+        Method::Post => http::Method::POST,
+        Method::Put => http::Method::PUT,
+        Method::Delete => http::Method::DELETE,
+        Method::Connect => http::Method::CONNECT,
+        Method::Options => http::Method::OPTIONS,
+        Method::Trace => http::Method::TRACE,
+        Method::Patch => http::Method::PATCH,
+        Method::Other(method) => http::Method::from_bytes(method.as_bytes()).ok()?,
+    })
+}
+
+fn client_error(err: hyper_util::client::legacy::Error) -> ErrorCode {
+    let mut source = std::error::Error::source(&err);
+
+    // DNS failures and connect timeouts come from the connector with their code attached.
+    while let Some(error) = source {
+        if let Some(ConnectFailure(code)) = error.downcast_ref() {
+            return code.clone();
+        }
+
+        source = error.source();
+    }
+
+    if err.is_connect() {
+        ErrorCode::ConnectionRefused
+    } else {
+        ErrorCode::InternalError(Some(err.to_string()))
+    }
+}
+
+impl State {
+    fn outbound_request(&mut self, id: u32) -> wasmtime::Result<&mut OutboundRequest> {
+        self.outgoing_requests
+            .get_mut(&id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find request"))
+    }
+
+    /// Works out where a request goes. The egress rules are applied once it is sent, after any
+    /// mock had its say.
+    fn resolve_uri(&self, req: &OutboundRequest) -> Result<Uri, ErrorCode> {
+        if self.outbound.is_none() {
+            return Err(ErrorCode::HttpRequestDenied);
+        }
+
+        let scheme = match &req.scheme {
+            None | Some(Scheme::Http) => "http",
+            Some(Scheme::Https) => "https",
+            Some(Scheme::Other(_)) => return Err(ErrorCode::HttpRequestUriInvalid),
+        };
+
+        let authority: Authority = req
+            .authority
+            .as_deref()
+            .and_then(|authority| authority.parse().ok())
+            .ok_or(ErrorCode::HttpRequestUriInvalid)?;
+
+        Uri::builder()
+            .scheme(scheme)
+            .authority(authority)
+            .path_and_query(req.path_with_query.as_deref().unwrap_or("/"))
+            .build()
+            .map_err(|_| ErrorCode::HttpRequestUriInvalid)
+    }
+
+    /// Sends a request. A finished body is sent as it is and the request forgotten; an unfinished
+    /// one is streamed as the guest goes on writing it.
+    fn send(&mut self, id: u32) -> wasmtime::Result<()> {
+        let req = self.outbound_request(id)?;
+
+        let pending = req
+            .pending
+            .take()
+            .ok_or_else(|| wasmtime::Error::msg("The request was not handled"))?;
+        let method = req.method.clone();
+        let headers = std::mem::take(&mut req.headers);
+        let pipe = req.body.clone();
+        let mut body = pipe.lock().unwrap();
+
+        if body.body.done {
+            self.outgoing_requests.remove(&id);
+        }
+
+        let future = self
+            .outgoing_responses
+            .get_mut(&pending.future)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+
+        if body.aborted {
+            *future = FutureResponse::Ready(Err(ErrorCode::InternalError(Some(
+                "The request body was dropped without being finished".to_owned(),
+            ))));
+
+            return Ok(());
+        }
+
+        let outbound = self
+            .outbound
+            .as_ref()
+            .ok_or_else(|| wasmtime::Error::msg("Outbound requests are not enabled"))?;
+
+        let mut request = Request::new(());
+        *request.method_mut() = method;
+        *request.uri_mut() = pending.uri;
+        *request.headers_mut() = headers;
+
+        // The client frames the body itself and applies no transfer codings, so a guest's
+        // `Transfer-Encoding` would misdescribe what is sent. A body the guest compressed is sent
+        // as written, under its own `Content-Encoding`.
+        request.headers_mut().remove(TRANSFER_ENCODING);
+
+        // `handle` only streams when there is no mock, so a mock always sees the whole body.
+        if let Some(mock) = &outbound.mock {
+            let mut mocked = Request::new(Bytes::from(Vec::from(body.body.buf.clone())));
+            *mocked.method_mut() = request.method().clone();
+            *mocked.uri_mut() = request.uri().clone();
+            *mocked.headers_mut() = request.headers().clone();
+
+            if let Some(res) = mock(&mocked) {
+                pending
+                    .span
+                    .record("http.response.status_code", res.status().as_u16());
+                *future = FutureResponse::Ready(Ok(res.map(|body| {
+                    Full::new(body)
+                        .map_err(|never| match never {})
+                        .boxed_unsync()
+                })));
+
+                return Ok(());
+            }
+        }
+
+        if request.uri().scheme_str() == Some("https") {
+            // A body the guest is still writing has nowhere to go.
+            body.closed = true;
+            *future = FutureResponse::Ready(Err(ErrorCode::InternalError(Some(
+                "HTTPS requests are not supported".to_owned(),
+            ))));
+
+            return Ok(());
+        }
+
+        let allowed = request
+            .uri()
+            .authority()
+            .is_some_and(|authority| outbound.allows(authority));
+
+        if !allowed {
+            body.closed = true;
+            *future = FutureResponse::Ready(Err(ErrorCode::HttpRequestDenied));
+
+            return Ok(());
+        }
+
+        // A proxy that takes requests in absolute form reads its credentials from each one.
+        if let Some((proxy, false)) = outbound.proxy.route(request.uri()) {
+            if let Some(auth) = proxy.auth() {
+                request
+                    .headers_mut()
+                    .insert(PROXY_AUTHORIZATION, auth.clone());
+            }
+        }
+
+        let client = outbound.client.clone();
+        let request = if body.body.done {
+            request.map(|()| std::mem::take(&mut body.body).into_body())
+        } else {
+            body.streaming = true;
+            request.map(|()| PipeBody(pipe.clone()).boxed_unsync())
+        };
+        drop(body);
+
+        // The head has to arrive within the first-byte timeout, or the connect timeout if that
+        // is all the guest set.
+        let limit = pending.timeouts.first_byte.or(pending.timeouts.connect);
+        let deadline = pending
+            .timeouts
+            .connect
+            .map(|connect| tokio::time::Instant::now() + connect);
+
+        // The between-bytes timeout only runs while the guest waits on the body.
+        let between_bytes = pending.timeouts.between_bytes;
+        let span = pending.span;
+
+        *future = FutureResponse::InFlight(tokio::task::spawn(
+            async move {
+                let res = CONNECT_DEADLINE.scope(deadline, client.request(request));
+
+                let res = match limit {
+                    Some(limit) => tokio::time::timeout(limit, res)
+                        .await
+                        .map_err(|_| ErrorCode::ConnectionReadTimeout)?
+                        .map_err(client_error),
+                    None => res.await.map_err(client_error),
+                };
+
+                if let Ok(res) = &res {
+                    Span::current().record("http.response.status_code", res.status().as_u16());
+                }
+
+                res.map(|res| {
+                    res.map(|body| {
+                        BetweenBytes::wrap(body.map_err(Into::into).boxed_unsync(), between_bytes)
+                    })
+                })
+            }
+            .instrument(span),
+        ));
+
+        Ok(())
+    }
+
+    pub fn is_request_body(&self, id: u32) -> bool {
+        self.outgoing_requests.contains_key(&id)
+    }
+
+    pub fn request_body(&self, id: u32) -> Option<Arc<Mutex<Pipe>>> {
+        Some(self.outgoing_requests.get(&id)?.body.clone())
+    }
+
+    /// Ends a request body. A request already handed to `outgoing-handler` is sent now, or ends
+    /// with the trailers if it is already streaming.
+    pub fn request_body_finish(
+        &mut self,
+        id: u32,
+        trailers: Option<Resource<Trailers>>,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        let trailers = trailers
+            .map(|trailers| {
+                self.fields
+                    .remove(&trailers.rep())
+                    .map(|(_, trailers)| trailers)
+                    .ok_or_else(|| wasmtime::Error::msg("Could not find trailers"))
+            })
+            .transpose()?;
+
+        let req = self.outbound_request(id)?;
+        let handled = req.pending.is_some();
+        let mut pipe = req.body.lock().unwrap();
+
+        pipe.body.done = true;
+        pipe.body.trailers = trailers;
+        pipe.body.wake();
+
+        // Once sent, or once sending failed, the request has nothing more to wait for.
+        let sent = pipe.streaming || pipe.closed;
+        drop(pipe);
+
+        if sent {
+            self.outgoing_requests.remove(&id);
+        } else if handled {
+            self.send(id)?;
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// A body dropped without `finish` fails its request, or cuts it off if it is streaming.
+    pub fn request_body_drop(&mut self, id: u32) -> wasmtime::Result<()> {
+        let Some(req) = self.outgoing_requests.get_mut(&id) else {
+            return Ok(());
+        };
+
+        let handled = req.pending.is_some();
+        let mut pipe = req.body.lock().unwrap();
+
+        if pipe.body.done {
+            return Ok(());
+        }
+
+        pipe.abort();
+
+        // Once sent, or once sending failed, the request has nothing more to wait for.
+        let sent = pipe.streaming || pipe.closed;
+        drop(pipe);
+
+        if sent {
+            self.outgoing_requests.remove(&id);
+        } else if handled {
+            self.send(id)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl wasi::http::outgoing_handler::Host for State {
+    fn handle(
+        &mut self,
+        request: Resource<OutgoingRequest>,
+        options: Option<Resource<RequestOptions>>,
+    ) -> wasmtime::Result<Result<Resource<FutureIncomingResponse>, ErrorCode>> {
+        let timeouts = match options {
+            Some(options) => *self
+                .request_options
+                .get(&options.rep())
+                .ok_or_else(|| wasmtime::Error::msg("Could not find request options"))?,
+            None => Timeouts::default(),
+        };
+
+        let req = self
+            .outgoing_requests
+            .get(&request.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find request"))?;
+
+        let uri = match self.resolve_uri(req) {
+            Ok(uri) => uri,
+            Err(code) => {
+                self.outgoing_requests.remove(&request.rep());
+                return Ok(Err(code));
+            }
+        };
+
+        let future = self.new_id();
+        self.outgoing_responses
+            .insert(future, FutureResponse::Unsent);
+
+        let trace = self.trace;
+        let tracestate = self.tracestate.clone();
+        let mocked = self
+            .outbound
+            .as_ref()
+            .is_some_and(|outbound| outbound.mock.is_some());
+        let req = self.outbound_request(request.rep())?;
+
+        let span = info_span!(
+            "outbound request",
+            otel.kind = "client",
+            otel.name = %req.method,
+            http.request.method = %req.method,
+            url.full = %uri,
+            http.response.status_code = field::Empty,
+        );
+
+        // A guest that propagates its own trace context knows better.
+        if let (Some(trace), false) = (trace, req.headers.contains_key(TRACEPARENT)) {
+            let child = trace.child();
+            #[cfg(feature = "otel")]
+            let child = crate::otel::of_span(&span, child).unwrap_or(child);
+
+            req.headers.insert(TRACEPARENT, child.to_header());
+
+            if let Some(tracestate) = tracestate {
+                req.headers.entry(TRACESTATE).or_insert(tracestate);
+            }
+        }
+
+        req.pending = Some(Pending {
+            future,
+            uri,
+            timeouts,
+            span,
+        });
+
+        // A body the guest is still writing is streamed, unless a mock needs all of it first.
+        let mut body = req.body.lock().unwrap();
+
+        if !req.body_taken {
+            body.body.done = true;
+        }
+
+        let send = body.body.done || !mocked;
+        drop(body);
+
+        if send {
+            self.send(request.rep())?;
+        }
+
+        Ok(Ok(Resource::new_own(future)))
+    }
+}
+
+impl wasi::http::types::HostOutgoingRequest for State {
+    fn new(&mut self, headers: Resource<Headers>) -> wasmtime::Result<Resource<OutgoingRequest>> {
+        let (_, headers) = self
+            .fields
+            .remove(&headers.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find headers"))?;
+
+        let id = self.new_id();
+
+        self.outgoing_requests.insert(
+            id,
+            OutboundRequest {
+                method: http::Method::GET,
+                scheme: None,
+                authority: None,
+                path_with_query: None,
+                headers,
+                body: Pipe::shared(self.max_body_bytes),
+                body_taken: false,
+                pending: None,
+            },
+        );
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn body(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+    ) -> wasmtime::Result<Result<Resource<OutgoingBody>, ()>> {
+        let req = self.outbound_request(self_.rep())?;
+
+        if req.body_taken {
+            return Ok(Err(()));
+        }
+
+        req.body_taken = true;
+
+        Ok(Ok(Resource::new_own(self_.rep())))
+    }
+
+    fn method(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Method> {
+        Ok(method_to_wasi(&self.outbound_request(self_.rep())?.method))
+    }
+
+    fn set_method(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+        method: Method,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        let Some(method) = method_from_wasi(method) else {
+            return Ok(Err(()));
+        };
+
+        self.outbound_request(self_.rep())?.method = method;
+
+        Ok(Ok(()))
+    }
+
+    fn path_with_query(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+    ) -> wasmtime::Result<Option<String>> {
+        Ok(self.outbound_request(self_.rep())?.path_with_query.clone())
+    }
+
+    fn set_path_with_query(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+        path_with_query: Option<String>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        if let Some(path_with_query) = &path_with_query {
+            if path_with_query.parse::<http::uri::PathAndQuery>().is_err() {
+                return Ok(Err(()));
+            }
+        }
+
+        self.outbound_request(self_.rep())?.path_with_query = path_with_query;
+
+        Ok(Ok(()))
+    }
+
+    fn scheme(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Option<Scheme>> {
+        Ok(self.outbound_request(self_.rep())?.scheme.clone())
+    }
+
+    fn set_scheme(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+        scheme: Option<Scheme>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        if let Some(Scheme::Other(scheme)) = &scheme {
+            if scheme.parse::<http::uri::Scheme>().is_err() {
+                return Ok(Err(()));
+            }
+        }
+
+        self.outbound_request(self_.rep())?.scheme = scheme;
+
+        Ok(Ok(()))
+    }
+
+    fn authority(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Option<String>> {
+        Ok(self.outbound_request(self_.rep())?.authority.clone())
+    }
+
+    fn set_authority(
+        &mut self,
+        self_: Resource<OutgoingRequest>,
+        authority: Option<String>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        if let Some(authority) = &authority {
+            if authority.parse::<Authority>().is_err() {
+                return Ok(Err(()));
+            }
+        }
+
+        self.outbound_request(self_.rep())?.authority = authority;
+
+        Ok(Ok(()))
+    }
+
+    fn headers(&mut self, self_: Resource<OutgoingRequest>) -> wasmtime::Result<Resource<Headers>> {
+        let headers = self.outbound_request(self_.rep())?.headers.clone();
+
+        Ok(self.new_fields(headers, true))
+    }
+
+    fn drop(&mut self, rep: Resource<OutgoingRequest>) -> wasmtime::Result<()> {
+        // A handled request stays until its body is finished and it is sent.
+        if let Some(req) = self.outgoing_requests.get(&rep.rep()) {
+            if req.pending.is_none() && !req.body_taken {
+                self.outgoing_requests.remove(&rep.rep());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn millis(duration: Option<StdDuration>) -> Option<Duration> {
+    duration.map(|duration| duration.as_millis() as Duration)
+}
+
+impl wasi::http::types::HostRequestOptions for State {
+    fn new(&mut self) -> wasmtime::Result<Resource<RequestOptions>> {
+        let id = self.new_id();
+        self.request_options.insert(id, Timeouts::default());
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn connect_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+    ) -> wasmtime::Result<Option<Duration>> {
+        Ok(millis(self.timeouts(&self_)?.connect))
+    }
+
+    fn set_connect_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+        ms: Option<Duration>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        self.timeouts(&self_)?.connect = ms.map(StdDuration::from_millis);
+
+        Ok(Ok(()))
+    }
+
+    fn first_byte_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+    ) -> wasmtime::Result<Option<Duration>> {
+        Ok(millis(self.timeouts(&self_)?.first_byte))
+    }
+
+    fn set_first_byte_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+        ms: Option<Duration>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        self.timeouts(&self_)?.first_byte = ms.map(StdDuration::from_millis);
+
+        Ok(Ok(()))
+    }
+
+    fn between_bytes_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+    ) -> wasmtime::Result<Option<Duration>> {
+        Ok(millis(self.timeouts(&self_)?.between_bytes))
+    }
+
+    fn set_between_bytes_timeout_ms(
+        &mut self,
+        self_: Resource<RequestOptions>,
+        ms: Option<Duration>,
+    ) -> wasmtime::Result<Result<(), ()>> {
+        self.timeouts(&self_)?.between_bytes = ms.map(StdDuration::from_millis);
+
+        Ok(Ok(()))
+    }
+
+    fn drop(&mut self, rep: Resource<RequestOptions>) -> wasmtime::Result<()> {
+        self.request_options.remove(&rep.rep());
+
+        Ok(())
+    }
+}
+
+impl State {
+    fn timeouts(&mut self, options: &Resource<RequestOptions>) -> wasmtime::Result<&mut Timeouts> {
+        self.request_options
+            .get_mut(&options.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find request options"))
+    }
+}
+
+impl wasi::http::types::HostIncomingResponse for State {
+    fn status(&mut self, self_: Resource<IncomingResponse>) -> wasmtime::Result<StatusCode> {
+        let resource = self
+            .incoming_responses
+            .get(&self_.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+
+        Ok(resource.status().as_u16())
+    }
+
+    fn headers(
+        &mut self,
+        self_: Resource<IncomingResponse>,
+    ) -> wasmtime::Result<Resource<Headers>> {
+        let headers = self
+            .incoming_responses
+            .get(&self_.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?
+            .headers()
+            .clone();
+
+        Ok(self.new_fields(headers, true))
+    }
+
+    fn consume(
+        &mut self,
+        self_: Resource<IncomingResponse>,
+    ) -> wasmtime::Result<Result<Resource<IncomingBody>, ()>> {
+        let Some(resource) = self.incoming_responses.remove(&self_.rep()) else {
+            if self.incoming.contains_key(&self_.rep()) {
+                return Ok(Err(()));
+            }
+
+            return Err(wasmtime::Error::msg("Could not find response"));
+        };
+
+        self.incoming.insert(
+            self_.rep(),
+            IncomingBodyWrapper {
+                incoming: resource.into_body(),
+                state: BodyState::New,
+                stream: StreamHandle::NotTaken,
+                trailers: None,
+                last_frame: None,
+                tee: None,
+                trailers_taken: false,
+            },
+        );
+
+        Ok(Ok(Resource::new_own(self_.rep())))
+    }
+
+    fn drop(&mut self, rep: Resource<IncomingResponse>) -> wasmtime::Result<()> {
+        self.incoming_responses.remove(&rep.rep());
+
+        Ok(())
+    }
+}
+
+struct ResponseReady {
+    id: u32,
+}
+
+impl PollableIndividual for ResponseReady {
+    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
+        let future = state
+            .outgoing_responses
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+
+        future.settle(false);
+
+        Ok(!matches!(
+            future,
+            FutureResponse::Unsent | FutureResponse::InFlight(_)
+        ))
+    }
+
+    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
+        let future = state
+            .outgoing_responses
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+
+        if let FutureResponse::Unsent = future {
+            return Err(wasmtime::Error::msg(
+                "Waited for the response to a request whose body was never finished",
+            ));
+        }
+
+        future.settle(true);
+
+        Ok(())
+    }
+}
+
+impl wasi::http::types::HostFutureIncomingResponse for State {
+    fn subscribe(
+        &mut self,
+        self_: Resource<FutureIncomingResponse>,
+    ) -> wasmtime::Result<Resource<Pollable>> {
+        let id = self.new_id();
+
+        self.pollables
+            .insert(id, Box::new(ResponseReady { id: self_.rep() }));
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn get(
+        &mut self,
+        self_: Resource<FutureIncomingResponse>,
+    ) -> wasmtime::Result<Option<Result<Result<Resource<IncomingResponse>, ErrorCode>, ()>>> {
+        let future = self
+            .outgoing_responses
+            .get_mut(&self_.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find response"))?;
+
+        future.settle(false);
+
+        let res = match std::mem::replace(future, FutureResponse::Taken) {
+            FutureResponse::Ready(res) => res,
+            FutureResponse::Taken => return Ok(Some(Err(()))),
+            pending => {
+                *future = pending;
+                return Ok(None);
+            }
+        };
+
+        // A body already known to be over the cap fails before any of it is read.
+        let res = res.and_then(|res| {
+            let (parts, body) = res.into_parts();
+            let body = self.upstream_limits.cap(body)?;
+
+            Ok(Response::from_parts(parts, body))
+        });
+
+        Ok(Some(Ok(res.map(|res| {
+            let id = self.new_id();
+            self.incoming_responses.insert(id, res);
+
+            Resource::new_own(id)
+        }))))
+    }
+
+    fn drop(&mut self, rep: Resource<FutureIncomingResponse>) -> wasmtime::Result<()> {
+        if let Some(FutureResponse::InFlight(handle)) = self.outgoing_responses.remove(&rep.rep()) {
+            handle.abort();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::str::FromStr;
+
+use crate::static_files::percent_decode;
+
+/// Which request paths reach the guest. A path must match one of `allow`, if there are any, and
+/// none of `deny`; deny wins when both match.
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    pub allow: Vec<PathGlob>,
+    pub deny: Vec<PathGlob>,
+}
+
+impl PathFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn allows(&self, path: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let segments = normalize(path);
+
+        (self.allow.is_empty() || self.allow.iter().any(|glob| glob.matches(&segments)))
+            && !self.deny.iter().any(|glob| glob.matches(&segments))
+    }
+}
+
+/// The segments of `path` with percent-encoding and dot segments resolved, so an encoded or
+/// roundabout spelling of a path is filtered like the plain one.
+fn normalize(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_decode(segment).unwrap_or_else(|| segment.to_owned());
+
+        match segment.as_str() {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    segments
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    /// `**`, any number of segments, including none.
+    Any,
+    /// A segment in which `*` stands for any run of characters.
+    Pattern(String),
+}
+
+/// A path pattern such as `/internal/**` or `/files/*.json`, compiled when the config is loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathGlob(Vec<Segment>);
+
+impl PathGlob {
+    fn matches(&self, path: &[String]) -> bool {
+        fn matches(glob: &[Segment], path: &[String]) -> bool {
+            match glob.split_first() {
+                None => path.is_empty(),
+                Some((Segment::Any, rest)) => (0..=path.len()).any(|at| matches(rest, &path[at..])),
+                Some((Segment::Pattern(pattern), rest)) => {
+                    path.split_first().is_some_and(|(segment, path)| {
+                        wildcard(pattern, segment) && matches(rest, path)
+                    })
+                }
+            }
+        }
+
+        matches(&self.0, path)
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters.
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<_> = parts.collect();
+
+    let Some(last) = parts.pop() else {
+        // No `*` at all.
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl FromStr for PathGlob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with('/') {
+            return Err(format!("expected a path starting with /, got {s}"));
+        }
+
+        let segments = s
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match segment {
+                "**" => Ok(Segment::Any),
+                _ if segment.contains("**") => Err(format!("** must be a whole segment, got {s}")),
+                _ => Ok(Segment::Pattern(segment.to_owned())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(segments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> PathFilter {
+        let globs = |globs: &[&str]| globs.iter().map(|glob| glob.parse().unwrap()).collect();
+
+        PathFilter {
+            allow: globs(allow),
+            deny: globs(deny),
+        }
+    }
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob.parse::<PathGlob>().unwrap().matches(&normalize(path))
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = filter(
+            &["/api/**", "/files/*.json"],
+            &["/api/internal/**", "/api/*/debug"],
+        );
+
+        for (path, allowed) in [
+            ("/api", true),
+            ("/api/users/1", true),
+            ("/files/data.json", true),
+            ("/files/data.txt", false),
+            ("/files/nested/data.json", false),
+            ("/api/internal", false),
+            ("/api/internal/keys", false),
+            ("/api/%69nternal/keys", false),
+            ("/api/public/../internal/keys", false),
+            ("/api/users/debug", false),
+            ("/api/users/debug/more", true),
+            ("/other", false),
+        ] {
+            assert_eq!(filter.allows(path), allowed, "{path}");
+        }
+    }
+
+    #[test]
+    fn without_an_allowlist_everything_not_denied_passes() {
+        assert!(PathFilter::default().allows("/anything/at/all"));
+
+        let filter = filter(&[], &["/internal/*"]);
+        assert!(filter.allows("/anything"));
+        assert!(filter.allows("/internal"));
+        assert!(!filter.allows("/internal/secret"));
+
+        // An allowlist alone turns away whatever it doesn't name.
+        let filter = filter(&["/public/**"], &[]);
+        assert!(filter.allows("/public/index.html"));
+        assert!(!filter.allows("/private"));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_segments() {
+        for (glob, path, matched) in [
+            ("/**", "/", true),
+            ("/**", "/a/b/c", true),
+            ("/a/**", "/a", true),
+            ("/a/**", "/ab", false),
+            ("/a/**/b", "/a/b", true),
+            ("/a/**/b", "/a/x/b", true),
+            ("/a/**/b", "/a/x/y/b", true),
+            ("/a/**/b", "/a/b/c", false),
+            ("/**/*.json", "/data.json", true),
+            ("/**/*.json", "/a/b/data.json", true),
+            ("/**/*.json", "/a/b/data.txt", false),
+            ("/a/**/**/b", "/a/b", true),
+        ] {
+            assert_eq!(matches(glob, path), matched, "{glob} {path}");
+        }
+    }
+
+    #[test]
+    fn single_stars_stay_within_a_segment() {
+        for (glob, path, matched) in [
+            ("/files/*", "/files/a", true),
+            ("/files/*", "/files", false),
+            ("/files/*", "/files/", false),
+            ("/files/*", "/files/a/b", false),
+            ("/a*b*c", "/abc", true),
+            ("/a*b*c", "/axxbyyc", true),
+            ("/a*b*c", "/acb", false),
+            // The prefix and suffix can't share characters.
+            ("/ab*ba", "/aba", false),
+            ("/ab*ba", "/abba", true),
+            ("/exact", "/exact", true),
+            ("/exact", "/exactly", false),
+        ] {
+            assert_eq!(matches(glob, path), matched, "{glob} {path}");
+        }
+    }
+
+    #[test]
+    fn paths_are_normalized_before_matching() {
+        assert_eq!(normalize("//a/./b/../c/"), ["a", "c"]);
+        assert_eq!(normalize("/%61%2Fb"), ["a/b"]);
+        // Climbing above the root stays at the root.
+        assert_eq!(normalize("/../../a"), ["a"]);
+        // A malformed escape is matched as written.
+        assert_eq!(normalize("/%zz"), ["%zz"]);
+    }
+
+    #[test]
+    fn globs_must_be_absolute_with_whole_double_stars() {
+        assert!("relative/**".parse::<PathGlob>().is_err());
+        assert!("/a**".parse::<PathGlob>().is_err());
+        assert!("/**b/c".parse::<PathGlob>().is_err());
+        assert_eq!(
+            "/a/**".parse(),
+            Ok(PathGlob(vec![
+                Segment::Pattern("a".to_owned()),
+                Segment::Any
+            ]))
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    thread,
+    time::Instant,
+};
+
+use hyper::body::{Body, Bytes, Frame};
+use wasmtime::component::Resource;
+
+use crate::{
+    http::{BoxError, Outgoing},
+    io::{PollableIndividual, BUF_LIMIT},
+    wasi::io::{
+        poll::Pollable,
+        streams::{OutputStream, StreamError},
+    },
+    State,
+};
+
+/// A body the guest writes, shared with whoever sends it once it is sent before it is finished.
+pub struct Pipe {
+    pub body: Outgoing,
+    /// The body is being sent, so it is read as it is written and the guest may only get
+    /// `BUF_LIMIT` bytes ahead of it.
+    pub streaming: bool,
+    /// The body was dropped without being finished, so it must not be sent, or must be cut off if
+    /// it already is.
+    pub aborted: bool,
+    /// Whoever sent the body stopped reading it, as when the connection failed.
+    pub closed: bool,
+    /// How much is buffered before the body is sent, after which writes fail.
+    limit: usize,
+}
+
+impl Pipe {
+    pub fn shared(limit: usize) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            body: Outgoing::default(),
+            streaming: false,
+            aborted: false,
+            closed: false,
+            limit,
+        }))
+    }
+
+    /// How much more the guest may write now.
+    pub fn room(&self) -> usize {
+        let limit = if self.streaming {
+            BUF_LIMIT
+        } else {
+            self.limit
+        };
+
+        limit.saturating_sub(self.body.buf.len()).min(BUF_LIMIT)
+    }
+
+    pub fn ended(&self) -> bool {
+        self.body.done || self.closed
+    }
+
+    /// Ends the body unfinished, waking a reader so it can tell.
+    pub fn abort(&mut self) {
+        self.body.done = true;
+        self.aborted = true;
+        self.body.wake();
+    }
+}
+
+/// The sending end of a body that is streamed.
+pub struct PipeBody(pub Arc<Mutex<Pipe>>);
+
+impl Body for PipeBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let mut pipe = self.0.lock().unwrap();
+
+        // An error rather than the end, so the peer can't take what was sent for the whole body.
+        if pipe.aborted {
+            return Poll::Ready(Some(Err(
+                "The body was dropped without being finished".into()
+            )));
+        }
+
+        Pin::new(&mut pipe.body).poll_frame(cx).map(|frame| {
+            frame
+                .map(|frame| frame.map(|frame| frame.map_data(|data| Bytes::from(Vec::from(data)))))
+        })
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let pipe = self.0.lock().unwrap();
+
+        !pipe.aborted && pipe.body.is_end_stream()
+    }
+}
+
+impl Drop for PipeBody {
+    /// Wakes a guest waiting to write, which would otherwise wait forever.
+    fn drop(&mut self) {
+        let mut pipe = self.0.lock().unwrap();
+        pipe.closed = true;
+
+        if let Some(thread) = pipe.body.thread.take() {
+            thread.unpark();
+        }
+    }
+}
+
+/// Parks the guest's thread until `done` holds, the body ends or its reader stops reading. The
+/// reader unparks it whenever it takes from the body.
+pub fn wait_for(pipe: &Mutex<Pipe>, done: impl Fn(&Pipe) -> bool) {
+    loop {
+        let mut locked = pipe.lock().unwrap();
+
+        if done(&locked) || locked.ended() || locked.aborted {
+            return;
+        }
+
+        locked.body.thread = Some(thread::current());
+        locked.body.wake();
+        drop(locked);
+
+        thread::park();
+    }
+}
+
+/// Ready once a body has room to write into.
+struct PipeReady(Arc<Mutex<Pipe>>);
+
+impl PollableIndividual for PipeReady {
+    fn ready(&mut self, _state: &mut State) -> wasmtime::Result<bool> {
+        let pipe = self.0.lock().unwrap();
+
+        Ok(!pipe.streaming || pipe.ended() || pipe.body.buf.len() < BUF_LIMIT)
+    }
+
+    fn block(&mut self, _state: &mut State) -> wasmtime::Result<()> {
+        wait_for(&self.0, |pipe| {
+            !pipe.streaming || pipe.body.buf.len() < BUF_LIMIT
+        });
+
+        Ok(())
+    }
+}
+
+impl State {
+    /// The body of an outgoing request or response.
+    fn body_pipe(&self, id: u32) -> wasmtime::Result<Arc<Mutex<Pipe>>> {
+        self.request_body(id)
+            .or_else(|| self.response_bodies.get(&id).cloned())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))
+    }
+
+    /// The error for a write to a body whose buffer is full before it can be sent. Waiting would
+    /// never end, as nothing reads the body until then.
+    fn buffer_full(&mut self) -> StreamError {
+        let error = std::io::Error::new(
+            std::io::ErrorKind::OutOfMemory,
+            "The body is larger than the runner buffers before sending it",
+        );
+
+        StreamError::LastOperationFailed(self.handle_io_error(error))
+    }
+
+    /// Takes the stream of a body, which may only happen once.
+    pub fn body_write(&mut self, id: u32) -> wasmtime::Result<Result<Resource<OutputStream>, ()>> {
+        let pipe = self.body_pipe(id)?;
+        let mut pipe = pipe.lock().unwrap();
+
+        if !pipe.body.new {
+            return Ok(Err(()));
+        }
+
+        pipe.body.new = false;
+
+        Ok(Ok(Resource::new_own(id)))
+    }
+
+    /// How much more of a body may be written. Until the body is sent the whole of it is
+    /// buffered, up to a limit; once it streams, it is a window its reader opens as it sends.
+    pub fn body_check_write(&mut self, id: u32) -> wasmtime::Result<Result<u64, StreamError>> {
+        let pipe = self.body_pipe(id)?;
+        let pipe = pipe.lock().unwrap();
+
+        if pipe.ended() {
+            return Ok(Err(StreamError::Closed));
+        }
+
+        let room = pipe.room();
+
+        if room == 0 && !pipe.streaming {
+            return Ok(Err(self.buffer_full()));
+        }
+
+        Ok(Ok(room as u64))
+    }
+
+    pub fn body_append(
+        &mut self,
+        id: u32,
+        contents: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), StreamError>> {
+        let pipe = self.body_pipe(id)?;
+        let mut pipe = pipe.lock().unwrap();
+
+        // The body already ended, so anything written now could never be sent.
+        if pipe.ended() {
+            return Ok(Err(StreamError::Closed));
+        }
+
+        if contents.len() > pipe.room() {
+            return Err(wasmtime::Error::msg(
+                "Attempted to write more than check-write permitted",
+            ));
+        }
+
+        pipe.body.buf.extend(contents);
+        pipe.body.wake();
+
+        Ok(Ok(()))
+    }
+
+    /// Waits for room, writes and waits for the reader to take what was written.
+    pub fn body_blocking_write(
+        &mut self,
+        id: u32,
+        contents: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), StreamError>> {
+        if contents.len() > BUF_LIMIT {
+            return Err(wasmtime::Error::msg(
+                "Attempted to write more than 4096 bytes with blocking-write-and-flush",
+            ));
+        }
+
+        let pipe = self.body_pipe(id)?;
+        let waited_at = Instant::now();
+
+        wait_for(&pipe, |pipe| {
+            !pipe.streaming || pipe.room() >= contents.len()
+        });
+        self.timings.waited += waited_at.elapsed();
+
+        let full = {
+            let pipe = pipe.lock().unwrap();
+            !pipe.ended() && pipe.room() < contents.len()
+        };
+
+        if full {
+            return Ok(Err(self.buffer_full()));
+        }
+
+        if let Err(err) = self.body_append(id, contents)? {
+            return Ok(Err(err));
+        }
+
+        self.body_flush(id)
+    }
+
+    /// Waits for the reader to take everything written so far. Nothing is read before a body is
+    /// sent, so there is nothing to wait for until then.
+    pub fn body_flush(&mut self, id: u32) -> wasmtime::Result<Result<(), StreamError>> {
+        let pipe = self.body_pipe(id)?;
+        let waited_at = Instant::now();
+
+        wait_for(&pipe, |pipe| !pipe.streaming || pipe.body.buf.is_empty());
+        self.timings.waited += waited_at.elapsed();
+
+        if pipe.lock().unwrap().closed {
+            return Ok(Err(StreamError::Closed));
+        }
+
+        Ok(Ok(()))
+    }
+
+    pub fn body_subscribe(&mut self, id: u32) -> wasmtime::Result<Resource<Pollable>> {
+        let pipe = self.body_pipe(id)?;
+        let id = self.new_id();
+        self.pollables.insert(id, Box::new(PipeReady(pipe)));
+
+        Ok(Resource::new_own(id))
+    }
+}
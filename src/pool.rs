@@ -0,0 +1,132 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+};
+
+use tokio::{runtime::Handle, sync::oneshot};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads that guest calls run on, so slow guests can't starve the rest of the
+/// process's blocking work and the number of guests running at once is plain to see. The threads
+/// exit once the pool is dropped and their queue is empty.
+pub struct GuestPool {
+    jobs: mpsc::Sender<Job>,
+    threads: usize,
+}
+
+impl GuestPool {
+    pub fn new(threads: usize) -> std::io::Result<Self> {
+        let threads = threads.max(1);
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for i in 0..threads {
+            let queue = queue.clone();
+
+            std::thread::Builder::new()
+                .name(format!("guest-worker-{i}"))
+                .spawn(move || loop {
+                    let Ok(job) = queue.lock().unwrap().recv() else {
+                        break;
+                    };
+
+                    // Jobs catch their own panics; this keeps the thread alive regardless.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                })?;
+        }
+
+        Ok(Self { jobs, threads })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs `f` on one of the pool's threads, inside the caller's tokio runtime. Fails with the
+    /// panic's payload if `f` panicked.
+    pub async fn run<R: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> R + Send + 'static,
+    ) -> std::thread::Result<R> {
+        let (done, result) = oneshot::channel();
+        let runtime = Handle::current();
+
+        let _ = self.jobs.send(Box::new(move || {
+            let _guard = runtime.enter();
+            let _ = done.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        }));
+
+        result
+            .await
+            .unwrap_or_else(|_| Err(Box::new("the guest pool has shut down")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_jobs_on_named_threads_inside_the_runtime() {
+        let pool = GuestPool::new(2).unwrap();
+
+        let (name, in_runtime) = pool
+            .run(|| {
+                let name = std::thread::current().name().map(str::to_owned);
+                (name, Handle::try_current().is_ok())
+            })
+            .await
+            .unwrap();
+
+        assert!(name.unwrap().starts_with("guest-worker-"));
+        assert!(in_runtime);
+    }
+
+    #[tokio::test]
+    async fn runs_no_more_jobs_at_once_than_it_has_threads() {
+        let pool = Arc::new(GuestPool::new(2).unwrap());
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+
+        let jobs: Vec<_> = (0..6)
+            .map(|_| {
+                let pool = pool.clone();
+                let running = running.clone();
+                let most = most.clone();
+
+                tokio::spawn(async move {
+                    pool.run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn threads_survive_panicking_jobs() {
+        let pool = GuestPool::new(0).unwrap();
+        assert_eq!(pool.threads(), 1);
+
+        let payload = pool.run(|| panic!("guest glue")).await.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"guest glue"));
+
+        assert_eq!(pool.run(|| 42).await.unwrap(), 42);
+    }
+}
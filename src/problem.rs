@@ -0,0 +1,176 @@
+use std::{fmt::Write, str::FromStr};
+
+use ::http::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderValue, Response,
+};
+
+use crate::http::Outgoing;
+
+/// How the body of a response the host produces in place of the guest is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The reason phrase as plain text.
+    #[default]
+    Text,
+    /// An RFC 7807 `application/problem+json` document.
+    Problem,
+    Empty,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "problem" | "problem+json" => Ok(Self::Problem),
+            "empty" => Ok(Self::Empty),
+            _ => Err(format!("expected text, problem or empty, got {s}")),
+        }
+    }
+}
+
+/// Attached to every response the host produces in place of the guest.
+#[derive(Clone, Debug)]
+pub struct HostError {
+    /// A short tag for what went wrong, such as `trap` or `circuit-open`.
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+impl ErrorFormat {
+    /// Rewrites the body of `res` if the host produced it. Guest responses are left alone. The
+    /// error stays attached, so in-process callers can still tell why the host answered.
+    pub fn render(self, res: &mut Response<Outgoing>) {
+        let Some(error) = res.extensions().get::<HostError>().cloned() else {
+            return;
+        };
+
+        let body = match self {
+            ErrorFormat::Text => return,
+            ErrorFormat::Empty => Vec::new(),
+            ErrorFormat::Problem => {
+                let status = res.status();
+                let mut json = String::new();
+
+                let _ = write!(
+                    json,
+                    "{{\"type\":\"urn:wasi-http-runner:{}\",\"title\":\"{}\",\"status\":{},\"detail\":\"{}\"}}",
+                    escape(error.kind),
+                    escape(status.canonical_reason().unwrap_or_default()),
+                    status.as_u16(),
+                    escape(&error.detail),
+                );
+
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("application/problem+json"),
+                );
+
+                json.into_bytes()
+            }
+        };
+
+        res.headers_mut().remove(CONTENT_LENGTH);
+        *res.body_mut() = Outgoing::full(body);
+    }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use ::http::StatusCode;
+
+    use crate::error_response;
+
+    use super::*;
+
+    fn timeout() -> Response<Outgoing> {
+        error_response(
+            StatusCode::GATEWAY_TIMEOUT,
+            "timeout",
+            "The guest did not respond within 30s",
+        )
+    }
+
+    fn body(res: &Response<Outgoing>) -> Vec<u8> {
+        res.body().buf.iter().copied().collect()
+    }
+
+    #[test]
+    fn timeouts_render_as_problem_json() {
+        let mut res = timeout();
+        ErrorFormat::Problem.render(&mut res);
+
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/problem+json");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body(&res)).unwrap(),
+            serde_json::json!({
+                "type": "urn:wasi-http-runner:timeout",
+                "title": "Gateway Timeout",
+                "status": 504,
+                "detail": "The guest did not respond within 30s",
+            })
+        );
+        assert_eq!(res.extensions().get::<HostError>().unwrap().kind, "timeout");
+    }
+
+    #[test]
+    fn details_are_escaped() {
+        let mut res = error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "trap",
+            "wasm trap: \"unreachable\"\n\tat \\guest\u{1}",
+        );
+        ErrorFormat::Problem.render(&mut res);
+
+        let json = serde_json::from_slice::<serde_json::Value>(&body(&res)).unwrap();
+        assert_eq!(
+            json["detail"],
+            "wasm trap: \"unreachable\"\n\tat \\guest\u{1}"
+        );
+    }
+
+    #[test]
+    fn text_and_empty_formats() {
+        let mut res = timeout();
+        ErrorFormat::Text.render(&mut res);
+        assert_eq!(body(&res), b"Gateway Timeout");
+
+        let mut res = timeout();
+        ErrorFormat::Empty.render(&mut res);
+        assert!(body(&res).is_empty());
+        assert!(!res.headers().contains_key(CONTENT_TYPE));
+    }
+
+    #[test]
+    fn guest_responses_are_left_alone() {
+        let mut res = Response::new(Outgoing::full(b"guest".to_vec()));
+        *res.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+        ErrorFormat::Problem.render(&mut res);
+
+        assert_eq!(body(&res), b"guest");
+        assert!(!res.headers().contains_key(CONTENT_TYPE));
+    }
+}
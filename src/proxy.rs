@@ -0,0 +1,234 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use ::http::{uri::Authority, HeaderValue, Uri};
+use base64::Engine;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{sockets::EgressRule, static_files::percent_decode, wasi::http::types::ErrorCode};
+
+/// Largest response head a proxy may answer `CONNECT` with.
+const MAX_CONNECT_HEAD: usize = 8 * 1024;
+
+/// A proxy outbound requests go through, from `http://[<user>:<password>@]<host>[:<port>]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    authority: Authority,
+    /// The `Proxy-Authorization` the URL's credentials make.
+    auth: Option<HeaderValue>,
+}
+
+impl Proxy {
+    /// Where the proxy listens, as a URI the connector can resolve.
+    pub fn uri(&self) -> Uri {
+        Uri::builder()
+            .scheme("http")
+            .authority(self.authority.clone())
+            .path_and_query("/")
+            .build()
+            .expect("an authority makes a valid URI")
+    }
+
+    pub fn auth(&self) -> Option<&HeaderValue> {
+        self.auth.as_ref()
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected http://[<user>:<password>@]<host>[:<port>], got {s}");
+
+        let rest = match s.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some(_) => return Err(invalid()),
+            None => s,
+        };
+        let rest = rest.trim_end_matches('/');
+
+        let (auth, host) = match rest.rsplit_once('@') {
+            Some((userinfo, host)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                let credentials = format!(
+                    "{}:{}",
+                    percent_decode(user).ok_or_else(invalid)?,
+                    percent_decode(password).ok_or_else(invalid)?
+                );
+                let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+
+                let mut auth =
+                    HeaderValue::try_from(format!("Basic {encoded}")).map_err(|_| invalid())?;
+                auth.set_sensitive(true);
+
+                (Some(auth), host)
+            }
+            None => (None, rest),
+        };
+
+        let mut authority: Authority = host.parse().map_err(|_| invalid())?;
+
+        // The port curl assumes for proxies too.
+        if authority.port().is_none() {
+            authority = format!("{host}:1080").parse().map_err(|_| invalid())?;
+        }
+
+        Ok(Self { authority, auth })
+    }
+}
+
+/// Hosts reached without the proxy: `*` for all, a domain that also covers its subdomains, or an
+/// `<ip>[/<prefix>][:<port>]` matched against hosts given as addresses.
+#[derive(Clone, Debug)]
+enum Bypass {
+    All,
+    Domain(String),
+    Addr(EgressRule),
+}
+
+/// A `NO_PROXY` list, separated by commas.
+#[derive(Clone, Debug, Default)]
+pub struct NoProxy(Vec<Bypass>);
+
+impl FromStr for NoProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules = s
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| match rule.parse() {
+                _ if rule == "*" => Bypass::All,
+                Ok(addr) => Bypass::Addr(addr),
+                Err(_) => Bypass::Domain(
+                    rule.trim_start_matches("*.")
+                        .trim_start_matches('.')
+                        .to_ascii_lowercase(),
+                ),
+            })
+            .collect();
+
+        Ok(Self(rules))
+    }
+}
+
+impl NoProxy {
+    fn bypasses(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = host.parse::<IpAddr>().ok();
+
+        self.0.iter().any(|rule| match rule {
+            Bypass::All => true,
+            Bypass::Domain(domain) => {
+                host.eq_ignore_ascii_case(domain)
+                    || host.len() > domain.len()
+                        && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                        && host[..host.len() - domain.len()].ends_with('.')
+            }
+            Bypass::Addr(rule) => ip.is_some_and(|ip| rule.allows(SocketAddr::new(ip, port))),
+        })
+    }
+}
+
+/// The proxies outbound requests go through, by the scheme of the request.
+#[derive(Clone, Debug, Default)]
+pub struct ProxyOptions {
+    pub http: Option<Proxy>,
+    pub https: Option<Proxy>,
+    pub no_proxy: NoProxy,
+    /// Tunnels plain HTTP requests through `CONNECT` too, for proxies that only tunnel, instead
+    /// of sending them to the proxy in absolute form.
+    pub tunnel: bool,
+}
+
+impl ProxyOptions {
+    /// Reads `http_proxy`, `https_proxy` and `no_proxy`, or their uppercase forms.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var(name.to_ascii_uppercase()))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+
+        Ok(Self {
+            http: var("http_proxy").map(|proxy| proxy.parse()).transpose()?,
+            https: var("https_proxy").map(|proxy| proxy.parse()).transpose()?,
+            no_proxy: var("no_proxy")
+                .map(|hosts| hosts.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            tunnel: false,
+        })
+    }
+
+    /// The proxy a request to `uri` goes through, and whether through a `CONNECT` tunnel.
+    pub fn route(&self, uri: &Uri) -> Option<(&Proxy, bool)> {
+        let https = uri.scheme_str() == Some("https");
+        let proxy = if https { &self.https } else { &self.http }.as_ref()?;
+
+        let host = uri.host()?;
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        (!self.no_proxy.bypasses(host, port)).then_some((proxy, https || self.tunnel))
+    }
+}
+
+/// Asks the proxy on the other end of `stream` to open a tunnel to `target`, leaving `stream`
+/// connected to it on success.
+pub async fn tunnel(
+    stream: &mut TcpStream,
+    target: &Authority,
+    auth: Option<&HeaderValue>,
+) -> Result<(), ErrorCode> {
+    let mut head = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n").into_bytes();
+
+    if let Some(auth) = auth {
+        head.extend_from_slice(b"Proxy-Authorization: ");
+        head.extend_from_slice(auth.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+
+    head.extend_from_slice(b"\r\n");
+
+    stream
+        .write_all(&head)
+        .await
+        .map_err(|_| ErrorCode::ConnectionTerminated)?;
+
+    // The proxy sends nothing past the head until the client speaks through the tunnel.
+    let mut answer = Vec::new();
+
+    while !answer.ends_with(b"\r\n\r\n") {
+        if answer.len() >= MAX_CONNECT_HEAD {
+            return Err(ErrorCode::HttpProtocolError);
+        }
+
+        let mut byte = [0];
+
+        match stream.read(&mut byte).await {
+            Ok(1) => answer.push(byte[0]),
+            _ => return Err(ErrorCode::ConnectionTerminated),
+        }
+    }
+
+    let status = answer
+        .strip_prefix(b"HTTP/1.")
+        .and_then(|rest| rest.get(2..5))
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or(ErrorCode::HttpProtocolError)?;
+
+    match status {
+        200..=299 => Ok(()),
+        403 | 407 => Err(ErrorCode::HttpRequestDenied),
+        504 => Err(ErrorCode::ConnectionTimeout),
+        _ => Err(ErrorCode::DestinationUnavailable),
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+use crate::metrics::Gauge;
+
+pub struct Queue {
+    permits: Arc<Semaphore>,
+    concurrency: usize,
+    queued: AtomicUsize,
+    depth: usize,
+    max_wait: Duration,
+}
+
+#[derive(Debug)]
+pub enum Shed {
+    QueueFull,
+    Timeout,
+    ShuttingDown,
+}
+
+impl Display for Shed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shed::QueueFull => write!(f, "queue full"),
+            Shed::Timeout => write!(f, "queue wait exceeded"),
+            Shed::ShuttingDown => write!(f, "shutting down"),
+        }
+    }
+}
+
+impl Queue {
+    pub fn new(concurrency: usize, depth: usize, max_wait: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+            queued: AtomicUsize::new(0),
+            depth,
+            max_wait,
+        }
+    }
+
+    /// Waits in FIFO order for a free execution slot.
+    ///
+    /// If the returned future is dropped while waiting (for example because the client went away)
+    /// the entry leaves the queue and never consumes a permit.
+    pub async fn admit(&self, gauge: &Gauge) -> Result<OwnedSemaphorePermit, Shed> {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => return Ok(permit),
+            Err(TryAcquireError::Closed) => return Err(Shed::ShuttingDown),
+            Err(TryAcquireError::NoPermits) => {}
+        }
+
+        let entry = QueueEntry::new(&self.queued, gauge);
+
+        if entry.position > self.depth {
+            return Err(Shed::QueueFull);
+        }
+
+        match tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(Shed::ShuttingDown),
+            Err(_) => Err(Shed::Timeout),
+        }
+    }
+
+    /// Waits for every admitted request to finish, then turns away all others, including those
+    /// still queued.
+    pub async fn close(&self) {
+        if let Ok(permits) = self.permits.acquire_many(self.concurrency as u32).await {
+            permits.forget();
+        }
+
+        self.permits.close();
+    }
+}
+
+struct QueueEntry<'a> {
+    queued: &'a AtomicUsize,
+    gauge: &'a Gauge,
+    position: usize,
+}
+
+impl<'a> QueueEntry<'a> {
+    fn new(queued: &'a AtomicUsize, gauge: &'a Gauge) -> Self {
+        let position = queued.fetch_add(1, Ordering::Relaxed) + 1;
+        gauge.inc();
+
+        Self {
+            queued,
+            gauge,
+            position,
+        }
+    }
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.gauge.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sheds_requests_beyond_its_depth() {
+        let queue = Arc::new(Queue::new(1, 2, Duration::from_secs(5)));
+        let gauge = Arc::new(Gauge::default());
+        let running = queue.admit(&gauge).await.unwrap();
+
+        let waiting: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                let gauge = gauge.clone();
+
+                tokio::spawn(async move { queue.admit(&gauge).await.map(drop) })
+            })
+            .collect();
+
+        while gauge.get() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(matches!(queue.admit(&gauge).await, Err(Shed::QueueFull)));
+        assert_eq!(gauge.get(), 2);
+
+        drop(running);
+
+        for waiting in waiting {
+            waiting.await.unwrap().unwrap();
+        }
+
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_that_wait_too_long() {
+        let queue = Queue::new(1, 1, Duration::from_millis(50));
+        let gauge = Gauge::default();
+        let _running = queue.admit(&gauge).await.unwrap();
+
+        assert!(matches!(queue.admit(&gauge).await, Err(Shed::Timeout)));
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn abandoned_entries_leave_the_queue() {
+        let queue = Queue::new(1, 1, Duration::from_secs(5));
+        let gauge = Gauge::default();
+        let running = queue.admit(&gauge).await.unwrap();
+
+        let mut waiting = Box::pin(queue.admit(&gauge));
+        assert!(futures::poll!(&mut waiting).is_pending());
+        assert_eq!(gauge.get(), 1);
+
+        drop(waiting);
+        assert_eq!(gauge.get(), 0);
+
+        // The freed slot goes to the next request rather than the one that left.
+        drop(running);
+        assert!(queue.admit(&gauge).now_or_never().unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn closing_turns_away_queued_requests() {
+        let queue = Queue::new(1, 1, Duration::from_secs(5));
+        let gauge = Gauge::default();
+        let running = queue.admit(&gauge).await.unwrap();
+
+        let mut closing = Box::pin(queue.close());
+        assert!(futures::poll!(&mut closing).is_pending());
+
+        let mut waiting = Box::pin(queue.admit(&gauge));
+        assert!(futures::poll!(&mut waiting).is_pending());
+
+        drop(running);
+        closing.await;
+
+        assert!(matches!(waiting.await, Err(Shed::ShuttingDown)));
+    }
+}
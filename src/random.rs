@@ -0,0 +1,47 @@
+use rand::{Rng, RngCore};
+
+use crate::{wasi, State};
+
+/// Largest buffer a guest can ask for in one call; anything bigger traps.
+const MAX_RANDOM_BYTES: u64 = 1024 * 1024;
+
+fn random_bytes(rng: &mut impl RngCore, len: u64) -> wasmtime::Result<Vec<u8>> {
+    if len > MAX_RANDOM_BYTES {
+        return Err(wasmtime::Error::msg(format!(
+            "Asked for {len} random bytes, more than the limit of {MAX_RANDOM_BYTES}"
+        )));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    rng.fill_bytes(&mut bytes);
+
+    Ok(bytes)
+}
+
+// `thread_rng` is a CSPRNG seeded from the OS, so it serves both interfaces.
+
+impl wasi::random::random::Host for State {
+    fn get_random_bytes(&mut self, len: u64) -> wasmtime::Result<Vec<u8>> {
+        random_bytes(&mut rand::thread_rng(), len)
+    }
+
+    fn get_random_u64(&mut self) -> wasmtime::Result<u64> {
+        Ok(rand::thread_rng().gen())
+    }
+}
+
+impl wasi::random::insecure::Host for State {
+    fn get_insecure_random_bytes(&mut self, len: u64) -> wasmtime::Result<Vec<u8>> {
+        random_bytes(&mut rand::thread_rng(), len)
+    }
+
+    fn get_insecure_random_u64(&mut self) -> wasmtime::Result<u64> {
+        Ok(rand::thread_rng().gen())
+    }
+}
+
+impl wasi::random::insecure_seed::Host for State {
+    fn insecure_seed(&mut self) -> wasmtime::Result<(u64, u64)> {
+        Ok(rand::thread_rng().gen())
+    }
+}
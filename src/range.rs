@@ -0,0 +1,352 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use ::http::{
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    HeaderValue, Method, Request, Response, StatusCode,
+};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame};
+use pin_project::pin_project;
+
+use crate::http::{BoxError, Outgoing, RequestBody};
+
+/// The `Range` a `GET` request carried.
+pub struct RangeRequest {
+    range: HeaderValue,
+    if_range: Option<HeaderValue>,
+}
+
+impl RangeRequest {
+    pub fn from_request<B>(req: &Request<B>) -> Option<Self> {
+        if *req.method() != Method::GET {
+            return None;
+        }
+
+        Some(Self {
+            range: req.headers().get(RANGE)?.clone(),
+            if_range: req.headers().get(IF_RANGE).cloned(),
+        })
+    }
+
+    /// Cuts a full `200 OK` response of known length down to the requested range. Anything this
+    /// does not understand, including requests for several ranges, gets the full response.
+    pub fn apply(&self, res: &mut Response<Outgoing>) {
+        let Some(len) = sliceable_len(res) else {
+            return;
+        };
+
+        res.headers_mut()
+            .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        if !self.still_current(res) {
+            return;
+        }
+
+        let Some(range) = self.range.to_str().ok().and_then(parse_range) else {
+            return;
+        };
+
+        let Some((start, end)) = range.resolve(len) else {
+            *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            *res.body_mut() = Outgoing::full(Vec::new());
+
+            let headers = res.headers_mut();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(0));
+            headers.insert(CONTENT_RANGE, content_range(format!("bytes */{len}")));
+
+            return;
+        };
+
+        let body = res.body_mut();
+
+        match body.source.take() {
+            Some(source) => {
+                body.source = Some(SliceBody::new(source, start, end - start + 1).boxed_unsync());
+
+                if let Some(length) = &mut body.length {
+                    length.redeclare(end - start + 1);
+                }
+            }
+            None => {
+                body.buf.truncate(end as usize + 1);
+                body.buf.drain(..start as usize);
+            }
+        }
+
+        *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+
+        let headers = res.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+        headers.insert(
+            CONTENT_RANGE,
+            content_range(format!("bytes {start}-{end}/{len}")),
+        );
+    }
+
+    /// `If-Range` only allows a partial response while the representation has not changed, by
+    /// strong comparison.
+    fn still_current(&self, res: &Response<Outgoing>) -> bool {
+        let Some(if_range) = &self.if_range else {
+            return true;
+        };
+
+        if if_range.as_bytes().starts_with(b"W/") {
+            return false;
+        }
+
+        let headers = res.headers();
+
+        if if_range.as_bytes().starts_with(b"\"") {
+            headers.get(ETAG) == Some(if_range)
+        } else {
+            headers.get(LAST_MODIFIED) == Some(if_range)
+        }
+    }
+}
+
+/// The length of a complete `200 OK` body, if it is known.
+fn sliceable_len(res: &Response<Outgoing>) -> Option<u64> {
+    if res.status() != StatusCode::OK || res.headers().contains_key(CONTENT_RANGE) {
+        return None;
+    }
+
+    let len = res
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()?;
+
+    let body = res.body();
+
+    // A guest body that disagrees with its own Content-Length can't be sliced safely.
+    let complete = body.done
+        && body.trailers.is_none()
+        && (body.source.is_some() || body.buf.len() as u64 == len);
+
+    complete.then_some(len)
+}
+
+fn content_range(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).expect("Content-Range values are plain ASCII")
+}
+
+enum ByteRange {
+    /// `start-` or `start-end`, inclusive.
+    FromTo(u64, Option<u64>),
+    /// `-len`, the last `len` bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// The inclusive bounds within a body of `len` bytes, or `None` if none of it is covered.
+    fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(start, end) => {
+                (start < len).then(|| (start, end.map_or(len - 1, |end| end.min(len - 1))))
+            }
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(suffix) => (len > 0).then(|| (len.saturating_sub(suffix), len - 1)),
+        }
+    }
+}
+
+fn parse_range(value: &str) -> Option<ByteRange> {
+    // Range units are case-insensitive.
+    let (unit, spec) = value.trim().split_once('=')?;
+
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let spec = spec.trim();
+
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        return end.parse().ok().map(ByteRange::Suffix);
+    }
+
+    let start = start.parse().ok()?;
+
+    let end = match end {
+        "" => None,
+        end => Some(end.parse().ok().filter(|end| *end >= start)?),
+    };
+
+    Some(ByteRange::FromTo(start, end))
+}
+
+/// Passes on `len` bytes of a body, starting `skip` bytes in.
+#[pin_project]
+struct SliceBody {
+    #[pin]
+    inner: RequestBody,
+    skip: u64,
+    len: u64,
+}
+
+impl SliceBody {
+    fn new(inner: RequestBody, skip: u64, len: u64) -> Self {
+        Self { inner, skip, len }
+    }
+}
+
+impl Body for SliceBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let mut this = self.project();
+
+        while *this.len > 0 {
+            let frame = match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                other => return Poll::Ready(other),
+            };
+
+            let Ok(mut data) = frame.into_data() else {
+                continue;
+            };
+
+            let skipped = (*this.skip).min(data.len() as u64);
+            *this.skip -= skipped;
+            let _ = data.split_to(skipped as usize);
+
+            data.truncate((*this.len).min(data.len() as u64) as usize);
+            *this.len -= data.len() as u64;
+
+            if !data.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(data))));
+            }
+        }
+
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn bounds(value: &str, len: u64) -> Option<(u64, u64)> {
+        parse_range(value)?.resolve(len)
+    }
+
+    fn request(range: &str) -> RangeRequest {
+        let req = Request::get("/").header(RANGE, range).body(()).unwrap();
+
+        RangeRequest::from_request(&req).unwrap()
+    }
+
+    fn response(body: &str) -> Response<Outgoing> {
+        let mut res = Response::new(Outgoing::full(body.as_bytes().to_vec()));
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        res
+    }
+
+    fn body(res: &Response<Outgoing>) -> Vec<u8> {
+        res.body().buf.iter().copied().collect()
+    }
+
+    #[test]
+    fn ranges_parse() {
+        assert_eq!(bounds("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(bounds("bytes=5-", 10), Some((5, 9)));
+        assert_eq!(bounds("bytes=5-100", 10), Some((5, 9)));
+        assert_eq!(bounds("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(bounds("bytes=-30", 10), Some((0, 9)));
+        assert_eq!(bounds(" Bytes = 1 - 2 ", 10), Some((1, 2)));
+        assert_eq!(bounds("BYTES=1-2", 10), Some((1, 2)));
+
+        for unsupported in ["items=0-4", "bytes=0-1,3-4", "bytes=4-2", "bytes=x-", "0-4"] {
+            assert!(parse_range(unsupported).is_none(), "{unsupported}");
+        }
+    }
+
+    #[test]
+    fn ranges_outside_the_body_are_unsatisfiable() {
+        assert_eq!(bounds("bytes=10-", 10), None);
+        assert_eq!(bounds("bytes=-0", 10), None);
+        assert_eq!(bounds("bytes=-5", 0), None);
+        assert_eq!(bounds("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn single_ranges_are_sliced() {
+        let mut res = response("hello world");
+        request("bytes=6-").apply(&mut res);
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 6-10/11");
+        assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(body(&res), b"world");
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_get_416() {
+        let mut res = response("hello");
+        request("bytes=5-").apply(&mut res);
+
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes */5");
+        assert!(body(&res).is_empty());
+    }
+
+    #[test]
+    fn other_responses_pass_through() {
+        let req = Request::get("/").body(()).unwrap();
+        assert!(RangeRequest::from_request(&req).is_none());
+
+        // Several ranges get the whole body.
+        let mut res = response("hello");
+        request("bytes=0-1,3-4").apply(&mut res);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(body(&res), b"hello");
+
+        // As does a body that disagrees with its length.
+        let mut res = response("hello");
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(4));
+        request("bytes=0-1").apply(&mut res);
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // And a representation that changed since the client's copy.
+        let mut res = response("hello");
+        res.headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"v2\""));
+        let req = Request::get("/")
+            .header(RANGE, "bytes=0-1")
+            .header(IF_RANGE, "\"v1\"")
+            .body(())
+            .unwrap();
+        RangeRequest::from_request(&req).unwrap().apply(&mut res);
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn streamed_bodies_are_sliced_across_frames() {
+        let frames =
+            ["hel", "lo w", "orld"].map(|chunk| Ok::<_, BoxError>(Frame::data(Bytes::from(chunk))));
+        let source = http_body_util::StreamBody::new(futures::stream::iter(frames)).boxed_unsync();
+
+        let collected = block_on(SliceBody::new(source, 2, 6).collect()).unwrap();
+        assert_eq!(collected.to_bytes(), "llo wo");
+    }
+}
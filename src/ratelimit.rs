@@ -0,0 +1,81 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota,
+};
+
+/// Idle clients' buckets are dropped every this many checks, so the table doesn't grow forever.
+const PRUNE_EVERY: u64 = 4096;
+
+/// The address a request came from, attached by the server as a request extension. Requests
+/// without one, such as warmup requests, are never rate limited.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// How many requests each client IP may send: `per_second` on average, and up to `burst` at once.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub per_second: NonZeroU32,
+    pub burst: NonZeroU32,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses `<per-second>[/<burst>]`. The burst defaults to the per-second rate.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (per_second, burst) = match s.split_once('/') {
+            Some((per_second, burst)) => (per_second, Some(burst)),
+            None => (s, None),
+        };
+
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<NonZeroU32>()
+                .map_err(|_| format!("expected <per-second>[/<burst>], got {s}"))
+        };
+
+        let per_second = parse(per_second)?;
+        let burst = burst.map(parse).transpose()?.unwrap_or(per_second);
+
+        Ok(Self { per_second, burst })
+    }
+}
+
+/// A token bucket per client IP, shared by every request to a runner.
+pub struct Limiter {
+    limiter: DefaultKeyedRateLimiter<IpAddr>,
+    clock: DefaultClock,
+    checks: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limiter: DefaultKeyedRateLimiter::keyed(
+                Quota::per_second(limit.per_second).allow_burst(limit.burst),
+            ),
+            clock: DefaultClock::default(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a token for `ip`, or tells how long until the next one is available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        if self.checks.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            self.limiter.retain_recent();
+        }
+
+        self.limiter
+            .check_key(&ip)
+            .map_err(|not_until| not_until.wait_time_from(self.clock.now()))
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ::http::{request::Parts, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use pin_project::pin_project;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::{
+    clone_head, dump,
+    http::{BoxError, Outgoing, RequestBody},
+    ratelimit::ClientAddr,
+    Runner,
+};
+
+const REDACTED: HeaderValue = HeaderValue::from_static("[redacted]");
+
+/// Numbers recordings made in the same millisecond, across every runner sharing a directory.
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Where requests are recorded for [`Recorded::read`] to replay, one file each.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub dir: PathBuf,
+    /// Bodies are cut off after this many bytes, which the recording notes.
+    pub max_body_bytes: usize,
+    /// Headers whose values are not recorded, in requests and responses.
+    pub redact: Vec<HeaderName>,
+}
+
+impl Recording {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_body_bytes: 1024 * 1024,
+            redact: dump::sensitive_headers(),
+        }
+    }
+}
+
+pub(crate) struct Recorder {
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(recording: Recording) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&recording.dir)?;
+
+        Ok(Self { recording })
+    }
+
+    fn redact(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+
+        for name in &self.recording.redact {
+            if let ::http::header::Entry::Occupied(mut entry) = headers.entry(name) {
+                for value in entry.iter_mut() {
+                    *value = REDACTED;
+                }
+            }
+        }
+
+        headers
+    }
+
+    fn write(
+        &self,
+        head: &Parts,
+        body: Option<CapturedBody>,
+        res: Option<ResponseCopy>,
+    ) -> std::io::Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed) % 1_000_000;
+
+        let mut out = Vec::new();
+        writeln!(out, "# wasi-http-runner recording")?;
+        writeln!(out, "# timestamp-ms: {timestamp_ms}")?;
+
+        if let Some(ClientAddr(addr)) = head.extensions.get() {
+            writeln!(out, "# remote: {addr}")?;
+        }
+
+        match &body {
+            Some(body) if body.truncated => writeln!(out, "# body-truncated")?,
+            Some(_) => {}
+            None => writeln!(out, "# body-missing")?,
+        }
+
+        let body = body.map(|body| body.data).unwrap_or_default();
+        writeln!(out, "# body-length: {}", body.len())?;
+        writeln!(out, "{} {} {:?}", head.method, head.uri, head.version)?;
+        write_headers(&mut out, &self.redact(&head.headers))?;
+        out.extend_from_slice(&body);
+
+        if let Some(res) = res {
+            writeln!(out)?;
+
+            match &res.body {
+                Some(body) => writeln!(out, "# body-length: {}", body.len())?,
+                None => writeln!(out, "# body-missing")?,
+            }
+
+            writeln!(out, "HTTP/1.1 {}", res.status.as_u16())?;
+            write_headers(&mut out, &self.redact(&res.headers))?;
+            out.extend_from_slice(res.body.as_deref().unwrap_or_default());
+        }
+
+        let name = format!("{timestamp_ms:015}-{seq:06}.http");
+        let partial = self.recording.dir.join(format!("{name}.partial"));
+        std::fs::write(&partial, out)?;
+        std::fs::rename(&partial, self.recording.dir.join(name))
+    }
+}
+
+fn write_headers(out: &mut Vec<u8>, headers: &HeaderMap) -> std::io::Result<()> {
+    for (name, value) in headers {
+        write!(out, "{name}: ")?;
+        out.extend_from_slice(value.as_bytes());
+        writeln!(out)?;
+    }
+
+    writeln!(out)
+}
+
+struct CapturedBody {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+/// What is recorded of a response. Bodies the host doesn't hold in full when the response is
+/// returned are left out.
+pub(crate) struct ResponseCopy {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Option<Bytes>,
+}
+
+impl ResponseCopy {
+    pub fn new(res: &Response<Outgoing>) -> Self {
+        let body = res.body();
+        let complete = body.done && body.source.is_none();
+
+        Self {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: complete.then(|| Bytes::from(Vec::from(body.buf.clone()))),
+        }
+    }
+}
+
+impl Runner {
+    /// Starts recording `req`. The body is copied as the guest reads it, and the recording is
+    /// written once the returned sender gets the response; one the guest never read is recorded
+    /// without its body.
+    pub(crate) fn record(
+        self: &Arc<Self>,
+        req: Request<RequestBody>,
+    ) -> (Request<RequestBody>, Option<oneshot::Sender<ResponseCopy>>) {
+        let Some(recorder) = &self.recorder else {
+            return (req, None);
+        };
+
+        let (body_tx, body_rx) = oneshot::channel();
+        let (res_tx, res_rx) = oneshot::channel();
+
+        let head = clone_head(&req);
+        let req = req.map(|body| {
+            let mut body = RecordBody {
+                inner: body,
+                copy: Some(CapturedBody {
+                    data: Vec::new(),
+                    truncated: false,
+                }),
+                max_body_bytes: recorder.recording.max_body_bytes,
+                tx: Some(body_tx),
+            };
+
+            if body.inner.is_end_stream() {
+                body.finish();
+            }
+
+            body.boxed_unsync()
+        });
+
+        let runner = self.clone();
+
+        tokio::task::spawn(async move {
+            let res = res_rx.await.ok();
+            let body = body_rx.await.ok();
+
+            if let Some(recorder) = &runner.recorder {
+                if let Err(err) = recorder.write(&head, body, res) {
+                    warn!(%err, "could not record request");
+                }
+            }
+        });
+
+        (req, Some(res_tx))
+    }
+}
+
+#[pin_project]
+struct RecordBody {
+    #[pin]
+    inner: RequestBody,
+    copy: Option<CapturedBody>,
+    max_body_bytes: usize,
+    tx: Option<oneshot::Sender<CapturedBody>>,
+}
+
+impl RecordBody {
+    fn finish(&mut self) {
+        if let (Some(tx), Some(copy)) = (self.tx.take(), self.copy.take()) {
+            let _ = tx.send(copy);
+        }
+    }
+}
+
+impl Body for RecordBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let mut this = self.project();
+        let res = this.inner.as_mut().poll_frame(cx);
+
+        let done = match &res {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Some(copy)) = (frame.data_ref(), this.copy.as_mut()) {
+                    let room = this.max_body_bytes.saturating_sub(copy.data.len());
+                    copy.truncated |= data.len() > room;
+                    copy.data.extend_from_slice(&data[..data.len().min(room)]);
+                }
+
+                frame.is_trailers()
+            }
+            Poll::Ready(None) => true,
+            // The body as far as it was read is still worth having.
+            Poll::Ready(Some(Err(_))) => {
+                if let Some(copy) = this.copy.as_mut() {
+                    copy.truncated = true;
+                }
+
+                true
+            }
+            Poll::Pending => false,
+        };
+
+        if done {
+            if let (Some(tx), Some(copy)) = (this.tx.take(), this.copy.take()) {
+                let _ = tx.send(copy);
+            }
+        }
+
+        res
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A request read back from a recording, with the response it got if that was recorded.
+pub struct Recorded {
+    pub timestamp_ms: u64,
+    pub remote: Option<String>,
+    pub request: Request<Bytes>,
+    /// The body was cut off at the size cap, or the guest didn't read all of it.
+    pub body_incomplete: bool,
+    pub response: Option<RecordedResponse>,
+}
+
+pub struct RecordedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Left out when the body was streamed.
+    pub body: Option<Bytes>,
+}
+
+impl Recorded {
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        let mut rest = data.as_slice();
+
+        let invalid =
+            |what: &str| anyhow::Error::msg(format!("{} has an invalid {what}", path.display()));
+
+        let request = Section::parse(&mut rest)?.ok_or_else(|| invalid("request"))?;
+        let response = Section::parse(&mut rest)?;
+
+        let mut start = request.start.split(' ');
+        let (Some(method), Some(uri)) = (start.next(), start.next()) else {
+            return Err(invalid("request line"));
+        };
+
+        let timestamp_ms = request
+            .meta("timestamp-ms")
+            .and_then(|timestamp| timestamp.parse().ok())
+            .unwrap_or_default();
+        let remote = request.meta("remote").map(str::to_owned);
+        let body_incomplete = request.has("body-truncated") || request.has("body-missing");
+
+        let mut req = Request::new(request.body);
+        *req.method_mut() = method.parse().map_err(|_| invalid("method"))?;
+        *req.uri_mut() = uri.parse().map_err(|_| invalid("request target"))?;
+        *req.headers_mut() = request.headers;
+
+        let response = response
+            .map(|response| {
+                let status = response
+                    .start
+                    .split(' ')
+                    .nth(1)
+                    .and_then(|status| status.parse().ok())
+                    .ok_or_else(|| invalid("status line"))?;
+
+                let body = (!response.has("body-missing")).then_some(response.body);
+
+                anyhow::Ok(RecordedResponse {
+                    status,
+                    headers: response.headers,
+                    body,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            timestamp_ms,
+            remote,
+            request: req,
+            body_incomplete,
+            response,
+        })
+    }
+}
+
+/// A recorded message: `# ` metadata lines, the start line, the header fields, a blank line and
+/// `body-length` bytes of body.
+struct Section {
+    meta: Vec<(String, String)>,
+    start: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Section {
+    fn parse(data: &mut &[u8]) -> anyhow::Result<Option<Self>> {
+        let mut meta = Vec::new();
+
+        let start = loop {
+            let Some(next) = line(data) else {
+                return Ok(None);
+            };
+
+            match next.strip_prefix(b"# ") {
+                Some(comment) => {
+                    let comment = String::from_utf8_lossy(comment);
+                    let (key, value) = comment.split_once(": ").unwrap_or((&comment, ""));
+                    meta.push((key.to_owned(), value.to_owned()));
+                }
+                // Separates the request's body from the response.
+                None if next.is_empty() => {}
+                None => break String::from_utf8_lossy(next).into_owned(),
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+
+        loop {
+            let next =
+                line(data).ok_or_else(|| anyhow::Error::msg("Unterminated header section"))?;
+
+            if next.is_empty() {
+                break;
+            }
+
+            let colon = next
+                .iter()
+                .position(|byte| *byte == b':')
+                .ok_or_else(|| anyhow::Error::msg("Header field without a colon"))?;
+
+            headers.append(
+                HeaderName::from_bytes(&next[..colon])?,
+                HeaderValue::from_bytes(next[colon + 1..].trim_ascii_start())?,
+            );
+        }
+
+        let mut section = Self {
+            meta,
+            start,
+            headers,
+            body: Bytes::new(),
+        };
+
+        let len: usize = section
+            .meta("body-length")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+
+        if data.len() < len {
+            return Err(anyhow::Error::msg(
+                "Recorded body is shorter than its length",
+            ));
+        }
+
+        section.body = Bytes::copy_from_slice(&data[..len]);
+        *data = &data[len..];
+
+        Ok(Some(section))
+    }
+
+    fn meta(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.meta(key).is_some()
+    }
+}
+
+fn line<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let all = *data;
+    let end = all.iter().position(|byte| *byte == b'\n')?;
+    *data = &all[end + 1..];
+
+    let line = &all[..end];
+    Some(line.strip_suffix(b"\r").unwrap_or(line))
+}
@@ -0,0 +1,316 @@
+use std::str::FromStr;
+
+use ::http::{
+    header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderMap, HeaderName, HeaderValue, Request,
+};
+
+use crate::{trace::TraceContext, ClientAddr};
+
+/// Header edits the host makes on a component's behalf: to each request before the guest sees it,
+/// and to each response once the guest has set it.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderRules {
+    pub request: HeaderEdits,
+    pub response: HeaderEdits,
+}
+
+/// Edits to one set of headers, applied in the order `remove`, `rename`, `set`.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderEdits {
+    remove: Vec<HeaderName>,
+    rename: Vec<(HeaderName, HeaderName)>,
+    set: Vec<(HeaderName, HeaderTemplate)>,
+}
+
+/// Headers the server frames bodies with. Rules touching them are refused.
+const FRAMING: [HeaderName; 2] = [CONTENT_LENGTH, TRANSFER_ENCODING];
+
+fn check_framing(name: &HeaderName) -> Result<(), String> {
+    if FRAMING.contains(name) {
+        return Err(format!("{name} frames the body and can't be rewritten"));
+    }
+
+    Ok(())
+}
+
+impl HeaderEdits {
+    pub fn remove(mut self, name: HeaderName) -> Result<Self, String> {
+        check_framing(&name)?;
+        self.remove.push(name);
+        Ok(self)
+    }
+
+    /// Moves every value of `from` to `to`, after any values `to` already has.
+    pub fn rename(mut self, from: HeaderName, to: HeaderName) -> Result<Self, String> {
+        check_framing(&from)?;
+        check_framing(&to)?;
+        self.rename.push((from, to));
+        Ok(self)
+    }
+
+    /// Replaces `name` with `value`, expanded for each request.
+    pub fn set(mut self, name: HeaderName, value: HeaderTemplate) -> Result<Self, String> {
+        check_framing(&name)?;
+        self.set.push((name, value));
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.rename.is_empty() && self.set.is_empty()
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap, vars: &Substitutions) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (from, to) in &self.rename {
+            let values: Vec<_> = headers.get_all(from).iter().cloned().collect();
+            headers.remove(from);
+
+            for value in values {
+                headers.append(to, value);
+            }
+        }
+
+        for (name, template) in &self.set {
+            // A value naming something the request doesn't have is left out rather than sent
+            // half-filled.
+            match template.expand(vars) {
+                Some(value) => headers.insert(name, value),
+                None => headers.remove(name),
+            };
+        }
+    }
+}
+
+/// What `${...}` in a header value can stand for.
+#[derive(Clone, Debug, Default)]
+pub struct Substitutions {
+    /// The client's IP, for `${remote_addr}`.
+    pub remote_addr: Option<String>,
+    /// The id of the request's trace, which its logs and outbound requests also carry, for
+    /// `${request_id}`.
+    pub request_id: Option<String>,
+}
+
+impl Substitutions {
+    pub fn for_request<B>(req: &Request<B>) -> Self {
+        Self {
+            remote_addr: req
+                .extensions()
+                .get::<ClientAddr>()
+                .map(|ClientAddr(addr)| addr.ip().to_string()),
+            request_id: req
+                .extensions()
+                .get::<TraceContext>()
+                .map(|trace| format!("{:032x}", trace.trace_id)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    RemoteAddr,
+    RequestId,
+}
+
+/// A header value with `${remote_addr}` and `${request_id}` substituted per request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderTemplate(Vec<Part>);
+
+impl HeaderTemplate {
+    pub fn expand(&self, vars: &Substitutions) -> Option<HeaderValue> {
+        let mut value = String::new();
+
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => value.push_str(literal),
+                Part::RemoteAddr => value.push_str(vars.remote_addr.as_deref()?),
+                Part::RequestId => value.push_str(vars.request_id.as_deref()?),
+            }
+        }
+
+        HeaderValue::try_from(value).ok()
+    }
+}
+
+impl FromStr for HeaderTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find("${") {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed ${{ in {s}"))?;
+
+            parts.push(match &rest[start + 2..start + end] {
+                "remote_addr" => Part::RemoteAddr,
+                "request_id" => Part::RequestId,
+                name => {
+                    return Err(format!(
+                        "expected ${{remote_addr}} or ${{request_id}}, got ${{{name}}}"
+                    ))
+                }
+            });
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        let template = Self(parts);
+
+        // Catch values that could never be sent, such as ones with newlines, up front.
+        let sample = Substitutions {
+            remote_addr: Some("127.0.0.1".to_owned()),
+            request_id: Some("0".repeat(32)),
+        };
+
+        template
+            .expand(&sample)
+            .ok_or_else(|| format!("{s} is not a valid header value"))?;
+
+        Ok(template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &'static str) -> HeaderName {
+        HeaderName::from_static(name)
+    }
+
+    fn vars() -> Substitutions {
+        Substitutions {
+            remote_addr: Some("192.0.2.7".to_owned()),
+            request_id: Some("0af7651916cd43dd8448eb211c80319c".to_owned()),
+        }
+    }
+
+    #[test]
+    fn templates_split_into_literals_and_substitutions() {
+        let template = "ip=${remote_addr}; id=${request_id}".parse::<HeaderTemplate>();
+        assert_eq!(
+            template,
+            Ok(HeaderTemplate(vec![
+                Part::Literal("ip=".to_owned()),
+                Part::RemoteAddr,
+                Part::Literal("; id=".to_owned()),
+                Part::RequestId,
+            ]))
+        );
+
+        // A `$` or braces that don't open a substitution are kept as they are.
+        assert_eq!(
+            "$ {}".parse::<HeaderTemplate>(),
+            Ok(HeaderTemplate(vec![Part::Literal("$ {}".to_owned())]))
+        );
+        assert_eq!("".parse::<HeaderTemplate>(), Ok(HeaderTemplate(Vec::new())));
+
+        for value in ["${remote_host}", "${request_id", "line\nbreak"] {
+            assert!(value.parse::<HeaderTemplate>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn templates_expand_per_request() {
+        let template = "ip=${remote_addr}".parse::<HeaderTemplate>().unwrap();
+        assert_eq!(template.expand(&vars()).unwrap(), "ip=192.0.2.7");
+
+        // A request without a client address, such as a warmup request, can't fill it in.
+        assert!(template.expand(&Substitutions::default()).is_none());
+
+        let mut req = Request::new(());
+        req.extensions_mut()
+            .insert(ClientAddr("192.0.2.7:4000".parse().unwrap()));
+        req.extensions_mut().insert(TraceContext {
+            trace_id: 0x0af7651916cd43dd8448eb211c80319c,
+            span_id: 1,
+            parent_id: None,
+            sampled: true,
+        });
+
+        let vars = Substitutions::for_request(&req);
+        assert_eq!(vars.remote_addr.as_deref(), Some("192.0.2.7"));
+        assert_eq!(
+            vars.request_id.as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+    }
+
+    #[test]
+    fn edits_remove_then_rename_then_set() {
+        let edits = HeaderEdits::default()
+            .remove(header("x-old"))
+            .and_then(|edits| edits.rename(header("x-old"), header("x-gone")))
+            .and_then(|edits| edits.rename(header("x-from"), header("x-to")))
+            .and_then(|edits| edits.set(header("x-to"), "set".parse().unwrap()))
+            .and_then(|edits| edits.rename(header("x-a"), header("x-b")))
+            .and_then(|edits| edits.set(header("x-id"), "${request_id}".parse().unwrap()))
+            .and_then(|edits| edits.set(header("x-ip"), "${remote_addr}".parse().unwrap()))
+            .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-old", HeaderValue::from_static("old"));
+        headers.insert("x-from", HeaderValue::from_static("from"));
+        headers.insert("x-a", HeaderValue::from_static("a"));
+        headers.insert("x-b", HeaderValue::from_static("b"));
+        headers.insert("x-ip", HeaderValue::from_static("spoofed"));
+
+        let vars = Substitutions {
+            remote_addr: None,
+            ..vars()
+        };
+        edits.apply(&mut headers, &vars);
+
+        // Removed before it could be renamed.
+        assert!(!headers.contains_key("x-old"));
+        assert!(!headers.contains_key("x-gone"));
+
+        // Renamed, then replaced.
+        assert!(!headers.contains_key("x-from"));
+        assert_eq!(headers["x-to"], "set");
+
+        // Renamed values go after those already there.
+        let values: Vec<_> = headers.get_all("x-b").iter().collect();
+        assert_eq!(values, ["b", "a"]);
+
+        assert_eq!(headers["x-id"], "0af7651916cd43dd8448eb211c80319c");
+
+        // Nothing to fill it in with, so the value the client sent doesn't get through either.
+        assert!(!headers.contains_key("x-ip"));
+    }
+
+    #[test]
+    fn edits_refuse_framing_headers() {
+        for name in [CONTENT_LENGTH, TRANSFER_ENCODING] {
+            let edits = HeaderEdits::default();
+            assert!(edits.clone().remove(name.clone()).is_err());
+            assert!(edits
+                .clone()
+                .rename(header("x-length"), name.clone())
+                .is_err());
+            assert!(edits
+                .clone()
+                .rename(name.clone(), header("x-length"))
+                .is_err());
+            assert!(edits.set(name, "1".parse().unwrap()).is_err());
+        }
+
+        assert!(HeaderEdits::default().is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+
+use ::http::{header::CONTENT_LENGTH, HeaderValue};
+use futures::TryStreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use wasmtime::component::Resource;
+
+use crate::{bluezeeking, http::BoxError, io::BUF_LIMIT, wasi::http::types::OutgoingBody, State};
+
+/// The file at `path`, as long as it is a regular file inside one of `dirs` once symlinks are
+/// followed.
+fn resolve(dirs: &[PathBuf], path: &str) -> Result<PathBuf, String> {
+    let path =
+        std::fs::canonicalize(Path::new(path)).map_err(|error| format!("{path}: {error}"))?;
+
+    let allowed = dirs
+        .iter()
+        .filter_map(|dir| std::fs::canonicalize(dir).ok())
+        .any(|dir| path.starts_with(dir));
+
+    if !allowed {
+        return Err(format!(
+            "{} is outside the directories files may be sent from",
+            path.display()
+        ));
+    }
+
+    if !path.is_file() {
+        return Err(format!("{} is not a regular file", path.display()));
+    }
+
+    Ok(path)
+}
+
+impl bluezeeking::service::files::Host for State {
+    fn send_file(
+        &mut self,
+        body: Resource<OutgoingBody>,
+        path: String,
+    ) -> wasmtime::Result<Result<u64, String>> {
+        let dirs = self.file_dirs.clone();
+
+        let pipe = self
+            .response_bodies
+            .get(&body.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find body"))?;
+        let mut pipe = pipe.lock().unwrap();
+        let outgoing = &mut pipe.body;
+
+        if outgoing.done || !outgoing.buf.is_empty() || outgoing.source.is_some() {
+            return Ok(Err("The body already has contents".to_owned()));
+        }
+
+        let file = match resolve(&dirs, &path).and_then(|path| {
+            std::fs::File::open(&path).map_err(|error| format!("{}: {error}", path.display()))
+        }) {
+            Ok(file) => file,
+            Err(error) => return Ok(Err(error)),
+        };
+
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(error) => return Ok(Err(format!("{path}: {error}"))),
+        };
+
+        // Read in the same slices guests write in, so a large file is never held in memory.
+        let stream = ReaderStream::with_capacity(File::from_std(file), BUF_LIMIT)
+            .map_ok(Frame::data)
+            .map_err(BoxError::from);
+
+        // The head of a response already set is out of reach, so the file goes without a length.
+        if let Some(res) = self.responses.get_mut(&body.rep()) {
+            res.headers_mut()
+                .entry(CONTENT_LENGTH)
+                .or_insert_with(|| HeaderValue::from(len));
+        }
+
+        outgoing.source = Some(StreamBody::new(stream).boxed_unsync());
+        outgoing.new = false;
+        outgoing.done = true;
+        outgoing.wake();
+
+        Ok(Ok(len))
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use ::http::{Request, Response, StatusCode};
+use hyper::body::{Body, Bytes};
+use tower::Service;
+use tracing::error;
+
+use crate::{
+    error_response,
+    http::{BoxError, Outgoing},
+    ResponseBody, Runner,
+};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, Infallible>> + Send>>;
+
+/// A runner as a `tower::Service`, so host-side middleware can be layered in front of the
+/// component. It never fails: whatever goes wrong becomes a 500 response. Its responses have a
+/// plain `Bytes` body, so it also mounts in an axum `Router` with `any_service`.
+#[derive(Clone)]
+pub struct WasiHttpService {
+    runner: Arc<Runner>,
+}
+
+impl WasiHttpService {
+    pub fn new(runner: Arc<Runner>) -> Self {
+        Self { runner }
+    }
+
+    async fn respond<B>(runner: Arc<Runner>, req: Request<B>) -> Response<ResponseBody>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        match runner.clone().service_fn(req).await {
+            Ok(res) => res,
+            Err(error) => {
+                error!(?error, "could not handle request");
+
+                let mut res = error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "host-error",
+                    error.to_string(),
+                );
+                runner.options.error_format.render(&mut res);
+                res.map(Outgoing::into_response_body)
+            }
+        }
+    }
+}
+
+impl From<Arc<Runner>> for WasiHttpService {
+    fn from(runner: Arc<Runner>) -> Self {
+        Self::new(runner)
+    }
+}
+
+impl<B> Service<Request<B>> for WasiHttpService
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The runner queues and sheds requests itself.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let respond = Self::respond(self.runner.clone(), req);
+
+        Box::pin(async move { Ok(respond.await) })
+    }
+}
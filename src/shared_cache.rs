@@ -0,0 +1,99 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{bluezeeking, State};
+
+/// The values behind `bluezeeking:service/cache`, shared by every request to a runner.
+pub struct SharedCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    max_entries: usize,
+}
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+impl SharedCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_entries,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+
+        if entry.live(Instant::now()) {
+            return Some(entry.value.clone());
+        }
+
+        entries.remove(key);
+        None
+    }
+
+    pub fn set(&self, key: String, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), String> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.live(now));
+
+            if entries.len() >= self.max_entries {
+                return Err(format!("The cache is full at {} entries", self.max_entries));
+            }
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                value,
+                expires_at: ttl.map(|ttl| now + ttl),
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+impl bluezeeking::service::cache::Host for State {
+    fn get(&mut self, key: String) -> wasmtime::Result<Option<Vec<u8>>> {
+        Ok(self.shared_cache.get(&key))
+    }
+
+    fn set(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        ttl_ms: Option<u64>,
+    ) -> wasmtime::Result<Result<(), String>> {
+        Ok(self
+            .shared_cache
+            .set(key, value, ttl_ms.map(Duration::from_millis)))
+    }
+
+    fn delete(&mut self, key: String) -> wasmtime::Result<()> {
+        self.shared_cache.delete(&key);
+
+        Ok(())
+    }
+
+    fn exists(&mut self, key: String) -> wasmtime::Result<bool> {
+        Ok(self.shared_cache.get(&key).is_some())
+    }
+}
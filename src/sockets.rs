@@ -0,0 +1,988 @@
+// Egress rules and the stream plumbing stay compiled without the `sockets` feature, since
+// options and wasi:io refer to them.
+#![cfg_attr(not(feature = "sockets"), allow(dead_code, unused_imports))]
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use futures::task::noop_waker_ref;
+use socket2::SockRef;
+use tokio::{
+    io::ReadBuf,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    task::JoinHandle,
+};
+use wasmtime::component::Resource;
+
+use crate::{
+    io::{PollableIndividual, BUF_LIMIT},
+    wasi::{
+        self,
+        clocks::monotonic_clock::Duration,
+        io::{
+            poll::Pollable,
+            streams::{InputStream, OutputStream, StreamError},
+        },
+        sockets::{
+            ip_name_lookup::ResolveAddressStream,
+            network::{
+                ErrorCode, IpAddress, IpAddressFamily, IpSocketAddress, Ipv4SocketAddress,
+                Ipv6SocketAddress, Network,
+            },
+            tcp::{ShutdownType, TcpSocket},
+        },
+    },
+    State,
+};
+
+/// Reads are cut off at this size, like reads from request bodies are by the frame size.
+const READ_LIMIT: usize = 64 * 1024;
+
+/// Addresses guests may connect to, as `<ip>[/<prefix>][:<port>]`, with IPv6 addresses in
+/// brackets when a port follows. A missing port or `*` allows every port.
+#[derive(Clone, Debug)]
+pub struct EgressRule {
+    network: IpAddr,
+    prefix: u8,
+    port: Option<u16>,
+}
+
+impl FromStr for EgressRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <ip>[/<prefix>][:<port>], got {s}");
+
+        let (network, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (network, rest) = rest.split_once(']').ok_or_else(invalid)?;
+
+                match rest {
+                    "" => (network, None),
+                    rest => (network, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            }
+            None => match s.rsplit_once(':') {
+                Some((network, port)) if !network.contains(':') => (network, Some(port)),
+                _ => (s, None),
+            },
+        };
+
+        let (network, prefix) = match network.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (network, None),
+        };
+
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(invalid)?,
+            None => max,
+        };
+
+        let port = match port {
+            None | Some("*") => None,
+            Some(port) => Some(port.parse().map_err(|_| invalid())?),
+        };
+
+        Ok(Self {
+            network,
+            prefix,
+            port,
+        })
+    }
+}
+
+impl EgressRule {
+    pub fn allows(&self, addr: SocketAddr) -> bool {
+        if self.port.is_some_and(|port| port != addr.port()) {
+            return false;
+        }
+
+        match (self.network, addr.ip()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct TcpConn {
+    family: IpAddressFamily,
+    state: TcpState,
+}
+
+enum TcpState {
+    Unbound,
+    Connecting(JoinHandle<std::io::Result<TcpStream>>),
+    /// The connection attempt ended and waits for `finish-connect`.
+    Connected(std::io::Result<TcpStream>),
+    Open {
+        local: SocketAddr,
+        remote: SocketAddr,
+        reader: u32,
+        writer: u32,
+    },
+    Closed,
+}
+
+impl TcpState {
+    /// Moves a finished connection attempt on to `Connected`, waiting for it if `block` is set.
+    fn settle(&mut self, block: bool) {
+        let TcpState::Connecting(handle) = self else {
+            return;
+        };
+
+        let res = if block {
+            futures::executor::block_on(handle)
+        } else {
+            match Pin::new(handle).poll(&mut Context::from_waker(noop_waker_ref())) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return,
+            }
+        };
+
+        *self = TcpState::Connected(
+            res.unwrap_or_else(|err| Err(std::io::Error::other(err.to_string()))),
+        );
+    }
+}
+
+pub struct TcpReader {
+    half: OwnedReadHalf,
+    closed: bool,
+}
+
+pub struct TcpWriter {
+    half: OwnedWriteHalf,
+    buf: Vec<u8>,
+}
+
+impl TcpWriter {
+    /// Writes as much of the buffer as the socket takes without blocking.
+    fn flush_some(&mut self) -> std::io::Result<()> {
+        while !self.buf.is_empty() {
+            match self.half.try_write(&self.buf) {
+                Ok(written) => {
+                    self.buf.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush_all(&mut self) -> std::io::Result<()> {
+        loop {
+            self.flush_some()?;
+
+            if self.buf.is_empty() {
+                return Ok(());
+            }
+
+            futures::executor::block_on(self.half.writable())?;
+        }
+    }
+}
+
+pub enum Resolver {
+    Pending(JoinHandle<std::io::Result<Vec<IpAddr>>>),
+    Ready(VecDeque<IpAddr>),
+    Failed(ErrorCode),
+}
+
+impl Resolver {
+    fn settle(&mut self, block: bool) {
+        let Resolver::Pending(handle) = self else {
+            return;
+        };
+
+        let res = if block {
+            futures::executor::block_on(handle)
+        } else {
+            match Pin::new(handle).poll(&mut Context::from_waker(noop_waker_ref())) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return,
+            }
+        };
+
+        *self = match res {
+            Ok(Ok(addresses)) => Resolver::Ready(addresses.into()),
+            Ok(Err(_)) => Resolver::Failed(ErrorCode::NameUnresolvable),
+            Err(_) => Resolver::Failed(ErrorCode::Unknown),
+        };
+    }
+}
+
+fn error_code(err: &std::io::Error) -> ErrorCode {
+    match err.kind() {
+        ErrorKind::ConnectionRefused => ErrorCode::ConnectionRefused,
+        ErrorKind::ConnectionReset => ErrorCode::ConnectionReset,
+        ErrorKind::ConnectionAborted => ErrorCode::ConnectionAborted,
+        ErrorKind::PermissionDenied => ErrorCode::AccessDenied,
+        ErrorKind::TimedOut => ErrorCode::Timeout,
+        ErrorKind::AddrInUse => ErrorCode::AddressInUse,
+        ErrorKind::AddrNotAvailable => ErrorCode::AddressNotBindable,
+        ErrorKind::InvalidInput => ErrorCode::InvalidArgument,
+        ErrorKind::OutOfMemory => ErrorCode::OutOfMemory,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+fn to_socket_addr(addr: IpSocketAddress) -> SocketAddr {
+    match addr {
+        IpSocketAddress::Ipv4(addr) => {
+            let (a, b, c, d) = addr.address;
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), addr.port))
+        }
+        IpSocketAddress::Ipv6(addr) => {
+            let (a, b, c, d, e, f, g, h) = addr.address;
+            SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(a, b, c, d, e, f, g, h),
+                addr.port,
+                addr.flow_info,
+                addr.scope_id,
+            ))
+        }
+    }
+}
+
+fn from_socket_addr(addr: SocketAddr) -> IpSocketAddress {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let [a, b, c, d] = addr.ip().octets();
+            IpSocketAddress::Ipv4(Ipv4SocketAddress {
+                port: addr.port(),
+                address: (a, b, c, d),
+            })
+        }
+        SocketAddr::V6(addr) => {
+            let [a, b, c, d, e, f, g, h] = addr.ip().segments();
+            IpSocketAddress::Ipv6(Ipv6SocketAddress {
+                port: addr.port(),
+                flow_info: addr.flowinfo(),
+                address: (a, b, c, d, e, f, g, h),
+                scope_id: addr.scope_id(),
+            })
+        }
+    }
+}
+
+fn from_ip(ip: IpAddr) -> IpAddress {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            IpAddress::Ipv4((a, b, c, d))
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, e, f, g, h] = ip.segments();
+            IpAddress::Ipv6((a, b, c, d, e, f, g, h))
+        }
+    }
+}
+
+impl State {
+    fn tcp_socket(&mut self, socket: &Resource<TcpSocket>) -> wasmtime::Result<&mut TcpConn> {
+        self.tcp_sockets
+            .get_mut(&socket.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find socket"))
+    }
+
+    pub fn tcp_read(
+        &mut self,
+        stream: u32,
+        len: u64,
+        block: bool,
+    ) -> wasmtime::Result<Result<Vec<u8>, StreamError>> {
+        let reader = self
+            .tcp_readers
+            .get_mut(&stream)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        if reader.closed {
+            return Ok(Err(StreamError::Closed));
+        }
+
+        let mut buf = vec![0; (len as usize).min(READ_LIMIT)];
+
+        if buf.is_empty() {
+            return Ok(Ok(buf));
+        }
+
+        let res = loop {
+            match reader.half.try_read(&mut buf) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock && block => {
+                    if let Err(err) = futures::executor::block_on(reader.half.readable()) {
+                        break Err(err);
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(Ok(Vec::new())),
+                res => break res,
+            }
+        };
+
+        match res {
+            Ok(0) => {
+                reader.closed = true;
+                Ok(Err(StreamError::Closed))
+            }
+            Ok(read) => {
+                buf.truncate(read);
+                Ok(Ok(buf))
+            }
+            Err(err) => {
+                reader.closed = true;
+                Ok(Err(StreamError::LastOperationFailed(
+                    self.handle_io_error(err),
+                )))
+            }
+        }
+    }
+
+    pub fn tcp_write(
+        &mut self,
+        stream: u32,
+        contents: Vec<u8>,
+        block: bool,
+    ) -> wasmtime::Result<Result<(), StreamError>> {
+        let writer = self
+            .tcp_writers
+            .get_mut(&stream)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        let limit = if block {
+            BUF_LIMIT
+        } else {
+            BUF_LIMIT.saturating_sub(writer.buf.len())
+        };
+
+        if contents.len() > limit {
+            return Err(wasmtime::Error::msg(
+                "Attempted to write more than check-write permitted",
+            ));
+        }
+
+        writer.buf.extend(contents);
+
+        let res = if block {
+            writer.flush_all()
+        } else {
+            writer.flush_some()
+        };
+
+        Ok(res.map_err(|err| StreamError::LastOperationFailed(self.handle_io_error(err))))
+    }
+
+    pub fn tcp_check_write(&mut self, stream: u32) -> wasmtime::Result<Result<u64, StreamError>> {
+        let writer = self
+            .tcp_writers
+            .get_mut(&stream)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        match writer.flush_some() {
+            Ok(()) => Ok(Ok(BUF_LIMIT.saturating_sub(writer.buf.len()) as u64)),
+            Err(err) => Ok(Err(StreamError::LastOperationFailed(
+                self.handle_io_error(err),
+            ))),
+        }
+    }
+
+    pub fn tcp_flush(
+        &mut self,
+        stream: u32,
+        block: bool,
+    ) -> wasmtime::Result<Result<(), StreamError>> {
+        let writer = self
+            .tcp_writers
+            .get_mut(&stream)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        let res = if block {
+            writer.flush_all()
+        } else {
+            writer.flush_some()
+        };
+
+        Ok(res.map_err(|err| StreamError::LastOperationFailed(self.handle_io_error(err))))
+    }
+
+    pub fn tcp_subscribe(&mut self, stream: u32) -> Resource<Pollable> {
+        let id = self.new_id();
+
+        self.pollables
+            .insert(id, Box::new(TcpStreamReady { id: stream }));
+
+        Resource::new_own(id)
+    }
+}
+
+/// Ready once a socket stream can be read from or written to without blocking.
+struct TcpStreamReady {
+    id: u32,
+}
+
+impl PollableIndividual for TcpStreamReady {
+    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
+        if let Some(reader) = state.tcp_readers.get_mut(&self.id) {
+            if reader.closed {
+                return Ok(true);
+            }
+
+            let mut byte = [0];
+            let res = reader.half.poll_peek(
+                &mut Context::from_waker(noop_waker_ref()),
+                &mut ReadBuf::new(&mut byte),
+            );
+
+            return Ok(res.is_ready());
+        }
+
+        let writer = state
+            .tcp_writers
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        // A write error is also readiness; the next operation reports it.
+        Ok(writer.flush_some().is_err() || writer.buf.len() < BUF_LIMIT)
+    }
+
+    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
+        if let Some(reader) = state.tcp_readers.get_mut(&self.id) {
+            if !reader.closed {
+                let _ = futures::executor::block_on(reader.half.readable());
+            }
+
+            return Ok(());
+        }
+
+        let writer = state
+            .tcp_writers
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find stream"))?;
+
+        while writer.flush_some().is_ok() && writer.buf.len() >= BUF_LIMIT {
+            if futures::executor::block_on(writer.half.writable()).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Ready once a connection attempt ended.
+struct TcpConnectReady {
+    id: u32,
+}
+
+impl PollableIndividual for TcpConnectReady {
+    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
+        let Some(socket) = state.tcp_sockets.get_mut(&self.id) else {
+            return Ok(true);
+        };
+
+        socket.state.settle(false);
+
+        Ok(!matches!(socket.state, TcpState::Connecting(_)))
+    }
+
+    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
+        if let Some(socket) = state.tcp_sockets.get_mut(&self.id) {
+            socket.state.settle(true);
+        }
+
+        Ok(())
+    }
+}
+
+struct ResolverReady {
+    id: u32,
+}
+
+impl PollableIndividual for ResolverReady {
+    fn ready(&mut self, state: &mut State) -> wasmtime::Result<bool> {
+        let resolver = state
+            .resolvers
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find resolver"))?;
+
+        resolver.settle(false);
+
+        Ok(!matches!(resolver, Resolver::Pending(_)))
+    }
+
+    fn block(&mut self, state: &mut State) -> wasmtime::Result<()> {
+        let resolver = state
+            .resolvers
+            .get_mut(&self.id)
+            .ok_or_else(|| wasmtime::Error::msg("Could not find resolver"))?;
+
+        resolver.settle(true);
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::network::Host for State {}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::network::HostNetwork for State {
+    fn drop(&mut self, _rep: Resource<Network>) -> wasmtime::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::instance_network::Host for State {
+    fn instance_network(&mut self) -> wasmtime::Result<Resource<Network>> {
+        Ok(Resource::new_own(self.new_id()))
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::tcp_create_socket::Host for State {
+    fn create_tcp_socket(
+        &mut self,
+        address_family: IpAddressFamily,
+    ) -> wasmtime::Result<Result<Resource<TcpSocket>, ErrorCode>> {
+        let id = self.new_id();
+
+        self.tcp_sockets.insert(
+            id,
+            TcpConn {
+                family: address_family,
+                state: TcpState::Unbound,
+            },
+        );
+
+        Ok(Ok(Resource::new_own(id)))
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::tcp::Host for State {}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::tcp::HostTcpSocket for State {
+    fn start_bind(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _network: Resource<Network>,
+        _local_address: IpSocketAddress,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        // Only outbound connections are supported.
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn finish_bind(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotInProgress))
+    }
+
+    fn start_connect(
+        &mut self,
+        self_: Resource<TcpSocket>,
+        _network: Resource<Network>,
+        remote_address: IpSocketAddress,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        let remote = to_socket_addr(remote_address);
+        let allowed = self.tcp_egress.iter().any(|rule| rule.allows(remote));
+        let socket = self.tcp_socket(&self_)?;
+
+        if !matches!(socket.state, TcpState::Unbound) {
+            return Ok(Err(ErrorCode::InvalidState));
+        }
+
+        let family_matches = match socket.family {
+            IpAddressFamily::Ipv4 => remote.is_ipv4(),
+            IpAddressFamily::Ipv6 => remote.is_ipv6(),
+        };
+
+        if !family_matches || remote.port() == 0 || remote.ip().is_unspecified() {
+            return Ok(Err(ErrorCode::InvalidArgument));
+        }
+
+        if !allowed {
+            return Ok(Err(ErrorCode::AccessDenied));
+        }
+
+        socket.state = TcpState::Connecting(tokio::task::spawn(TcpStream::connect(remote)));
+
+        Ok(Ok(()))
+    }
+
+    fn finish_connect(
+        &mut self,
+        self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<(Resource<InputStream>, Resource<OutputStream>), ErrorCode>> {
+        let socket = self.tcp_socket(&self_)?;
+        socket.state.settle(false);
+
+        let stream = match std::mem::replace(&mut socket.state, TcpState::Closed) {
+            TcpState::Connected(Ok(stream)) => stream,
+            TcpState::Connected(Err(err)) => return Ok(Err(error_code(&err))),
+            TcpState::Connecting(handle) => {
+                socket.state = TcpState::Connecting(handle);
+                return Ok(Err(ErrorCode::WouldBlock));
+            }
+            state => {
+                socket.state = state;
+                return Ok(Err(ErrorCode::NotInProgress));
+            }
+        };
+
+        let (local, remote) = match (stream.local_addr(), stream.peer_addr()) {
+            (Ok(local), Ok(remote)) => (local, remote),
+            (Err(err), _) | (_, Err(err)) => return Ok(Err(error_code(&err))),
+        };
+
+        let reader = self.new_id();
+        let writer = self.new_id();
+        let (read_half, write_half) = stream.into_split();
+
+        self.tcp_readers.insert(
+            reader,
+            TcpReader {
+                half: read_half,
+                closed: false,
+            },
+        );
+        self.tcp_writers.insert(
+            writer,
+            TcpWriter {
+                half: write_half,
+                buf: Vec::new(),
+            },
+        );
+
+        self.tcp_socket(&self_)?.state = TcpState::Open {
+            local,
+            remote,
+            reader,
+            writer,
+        };
+
+        Ok(Ok((Resource::new_own(reader), Resource::new_own(writer))))
+    }
+
+    fn start_listen(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn finish_listen(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotInProgress))
+    }
+
+    fn accept(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<
+        Result<
+            (
+                Resource<TcpSocket>,
+                Resource<InputStream>,
+                Resource<OutputStream>,
+            ),
+            ErrorCode,
+        >,
+    > {
+        Ok(Err(ErrorCode::InvalidState))
+    }
+
+    fn local_address(
+        &mut self,
+        self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<IpSocketAddress, ErrorCode>> {
+        Ok(match self.tcp_socket(&self_)?.state {
+            TcpState::Open { local, .. } => Ok(from_socket_addr(local)),
+            _ => Err(ErrorCode::InvalidState),
+        })
+    }
+
+    fn remote_address(
+        &mut self,
+        self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<IpSocketAddress, ErrorCode>> {
+        Ok(match self.tcp_socket(&self_)?.state {
+            TcpState::Open { remote, .. } => Ok(from_socket_addr(remote)),
+            _ => Err(ErrorCode::InvalidState),
+        })
+    }
+
+    fn is_listening(&mut self, _self_: Resource<TcpSocket>) -> wasmtime::Result<bool> {
+        Ok(false)
+    }
+
+    fn address_family(&mut self, self_: Resource<TcpSocket>) -> wasmtime::Result<IpAddressFamily> {
+        Ok(self.tcp_socket(&self_)?.family)
+    }
+
+    fn ipv6_only(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<bool, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_ipv6_only(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: bool,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_listen_backlog_size(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: u64,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn keep_alive_enabled(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<bool, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_keep_alive_enabled(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: bool,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn keep_alive_idle_time(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<Duration, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_keep_alive_idle_time(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: Duration,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn keep_alive_interval(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<Duration, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_keep_alive_interval(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: Duration,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn keep_alive_count(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<u32, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_keep_alive_count(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: u32,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn hop_limit(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<u8, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_hop_limit(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: u8,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn receive_buffer_size(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<u64, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_receive_buffer_size(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: u64,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn send_buffer_size(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+    ) -> wasmtime::Result<Result<u64, ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn set_send_buffer_size(
+        &mut self,
+        _self_: Resource<TcpSocket>,
+        _value: u64,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        Ok(Err(ErrorCode::NotSupported))
+    }
+
+    fn subscribe(&mut self, self_: Resource<TcpSocket>) -> wasmtime::Result<Resource<Pollable>> {
+        let id = self.new_id();
+
+        self.pollables
+            .insert(id, Box::new(TcpConnectReady { id: self_.rep() }));
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn shutdown(
+        &mut self,
+        self_: Resource<TcpSocket>,
+        shutdown_type: ShutdownType,
+    ) -> wasmtime::Result<Result<(), ErrorCode>> {
+        let TcpState::Open { reader, writer, .. } = self.tcp_socket(&self_)?.state else {
+            return Ok(Err(ErrorCode::InvalidState));
+        };
+
+        let how = match shutdown_type {
+            ShutdownType::Receive => std::net::Shutdown::Read,
+            ShutdownType::Send => std::net::Shutdown::Write,
+            ShutdownType::Both => std::net::Shutdown::Both,
+        };
+
+        // Either half reaches the socket; the guest may have dropped one of them already.
+        let res = match (self.tcp_readers.get(&reader), self.tcp_writers.get(&writer)) {
+            (_, Some(writer)) => SockRef::from(writer.half.as_ref()).shutdown(how),
+            (Some(reader), None) => SockRef::from(reader.half.as_ref()).shutdown(how),
+            (None, None) => Ok(()),
+        };
+
+        Ok(res.map_err(|err| error_code(&err)))
+    }
+
+    fn drop(&mut self, rep: Resource<TcpSocket>) -> wasmtime::Result<()> {
+        self.tcp_sockets.remove(&rep.rep());
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::ip_name_lookup::Host for State {
+    fn resolve_addresses(
+        &mut self,
+        _network: Resource<Network>,
+        name: String,
+    ) -> wasmtime::Result<Result<Resource<ResolveAddressStream>, ErrorCode>> {
+        let resolver = match name.parse::<IpAddr>() {
+            Ok(ip) => Resolver::Ready(VecDeque::from([ip])),
+            Err(_) if name.is_empty() || name.contains([':', '/', ' ']) => {
+                return Ok(Err(ErrorCode::InvalidArgument));
+            }
+            Err(_) => Resolver::Pending(tokio::task::spawn(async move {
+                let mut addresses: Vec<IpAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                    .await?
+                    .map(|addr| addr.ip())
+                    .collect();
+                addresses.dedup();
+
+                Ok(addresses)
+            })),
+        };
+
+        let id = self.new_id();
+        self.resolvers.insert(id, resolver);
+
+        Ok(Ok(Resource::new_own(id)))
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl wasi::sockets::ip_name_lookup::HostResolveAddressStream for State {
+    fn resolve_next_address(
+        &mut self,
+        self_: Resource<ResolveAddressStream>,
+    ) -> wasmtime::Result<Result<Option<IpAddress>, ErrorCode>> {
+        let resolver = self
+            .resolvers
+            .get_mut(&self_.rep())
+            .ok_or_else(|| wasmtime::Error::msg("Could not find resolver"))?;
+
+        resolver.settle(false);
+
+        Ok(match resolver {
+            Resolver::Pending(_) => Err(ErrorCode::WouldBlock),
+            Resolver::Ready(addresses) => Ok(addresses.pop_front().map(from_ip)),
+            Resolver::Failed(code) => Err(*code),
+        })
+    }
+
+    fn subscribe(
+        &mut self,
+        self_: Resource<ResolveAddressStream>,
+    ) -> wasmtime::Result<Resource<Pollable>> {
+        let id = self.new_id();
+
+        self.pollables
+            .insert(id, Box::new(ResolverReady { id: self_.rep() }));
+
+        Ok(Resource::new_own(id))
+    }
+
+    fn drop(&mut self, rep: Resource<ResolveAddressStream>) -> wasmtime::Result<()> {
+        self.resolvers.remove(&rep.rep());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::ErrorKind,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+};
+
+use ::http::HeaderMap;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::io::AsyncWriteExt;
+
+use crate::http::{BoxError, RequestBody};
+
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A request body that is copied to an anonymous temp file as fast as the client sends it, with
+/// reads served from the file at whatever pace the guest keeps.
+///
+/// The file is unlinked as soon as it is created, so it disappears with the last handle no matter
+/// how the request ends.
+pub struct SpoolBody {
+    file: File,
+    shared: Arc<Mutex<Shared>>,
+    read: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    written: u64,
+    /// Set once the body ended, with its trailers, or with the reason it could not be spooled.
+    done: Option<Result<Option<HeaderMap>, String>>,
+    waker: Option<Waker>,
+}
+
+impl SpoolBody {
+    /// Whether a body of this size should be spooled rather than passed through.
+    pub fn wanted(body: &RequestBody, threshold: usize) -> bool {
+        !body.is_end_stream()
+            && body
+                .size_hint()
+                .upper()
+                .is_none_or(|upper| upper > threshold as u64)
+    }
+
+    pub fn spool(mut inner: RequestBody) -> std::io::Result<Self> {
+        let file = tempfile::tempfile()?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let mut writer = tokio::fs::File::from_std(file.try_clone()?);
+        let progress = Arc::downgrade(&shared);
+
+        tokio::task::spawn(async move {
+            let done = loop {
+                let frame = match inner.frame().await {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(err)) => break Err(err.to_string()),
+                    None => break Ok(None),
+                };
+
+                let data = match frame.into_data() {
+                    Ok(data) => data,
+                    Err(frame) => break Ok(frame.into_trailers().ok()),
+                };
+
+                // A full disk surfaces to the guest as a failed read.
+                if let Err(err) = async {
+                    writer.write_all(&data).await?;
+                    writer.flush().await
+                }
+                .await
+                {
+                    break Err(err.to_string());
+                }
+
+                // Nobody is left to read the body.
+                if !update(&progress, |shared| shared.written += data.len() as u64) {
+                    return;
+                }
+            };
+
+            update(&progress, |shared| shared.done = Some(done));
+        });
+
+        Ok(Self {
+            file,
+            shared,
+            read: 0,
+        })
+    }
+}
+
+fn update(shared: &Weak<Mutex<Shared>>, f: impl FnOnce(&mut Shared)) -> bool {
+    let Some(shared) = shared.upgrade() else {
+        return false;
+    };
+
+    let mut shared = shared.lock().unwrap();
+    f(&mut shared);
+
+    if let Some(waker) = shared.waker.take() {
+        waker.wake();
+    }
+
+    true
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+impl Body for SpoolBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+
+        if this.read < shared.written {
+            let len = (shared.written - this.read).min(READ_CHUNK as u64) as usize;
+            drop(shared);
+
+            let mut buf = vec![0; len];
+
+            return Poll::Ready(Some(match read_at(&this.file, &mut buf, this.read) {
+                Ok(0) => Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(read) => {
+                    buf.truncate(read);
+                    this.read += read as u64;
+                    Ok(Frame::data(Bytes::from(buf)))
+                }
+                Err(err) => Err(err.into()),
+            }));
+        }
+
+        match shared.done.take() {
+            Some(Ok(trailers)) => {
+                shared.done = Some(Ok(None));
+                Poll::Ready(trailers.map(|trailers| Ok(Frame::trailers(trailers))))
+            }
+            Some(Err(err)) => {
+                shared.done = Some(Ok(None));
+                Poll::Ready(Some(Err(std::io::Error::other(err).into())))
+            }
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+
+        self.read == shared.written && matches!(shared.done, Some(Ok(None)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let shared = self.shared.lock().unwrap();
+        let buffered = shared.written - self.read;
+
+        match shared.done {
+            Some(_) => SizeHint::with_exact(buffered),
+            None => {
+                let mut hint = SizeHint::new();
+                hint.set_lower(buffered);
+                hint
+            }
+        }
+    }
+}
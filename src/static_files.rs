@@ -0,0 +1,288 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use ::http::{
+    header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED},
+    HeaderValue, Method, Response, StatusCode,
+};
+use futures::TryStreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error_response,
+    http::{BoxError, Outgoing},
+};
+
+/// Serves files below `root` for request paths starting with `prefix`.
+#[derive(Clone, Debug)]
+pub struct StaticDir {
+    prefix: String,
+    root: PathBuf,
+    index: Option<String>,
+}
+
+impl StaticDir {
+    pub fn new(prefix: &str, root: impl Into<PathBuf>, index: Option<String>) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            root: root.into(),
+            index,
+        }
+    }
+
+    /// Returns the part of `path` below the prefix, if the prefix covers it.
+    pub fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&self.prefix)?;
+
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+
+    pub async fn serve(&self, method: &Method, rest: &str) -> Response<Outgoing> {
+        if !matches!(*method, Method::GET | Method::HEAD) {
+            let mut res = error_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method-not-allowed",
+                "Static files only support GET and HEAD",
+            );
+            res.headers_mut()
+                .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+
+            return res;
+        }
+
+        let Some(path) = self.resolve(rest).await else {
+            return not_found();
+        };
+
+        let Ok(file) = File::open(&path).await else {
+            return not_found();
+        };
+
+        let Ok(metadata) = file.metadata().await else {
+            return not_found();
+        };
+
+        let mut res = if *method == Method::HEAD {
+            Response::new(Outgoing::full(Vec::new()))
+        } else {
+            let stream = ReaderStream::new(file)
+                .map_ok(Frame::data)
+                .map_err(BoxError::from);
+
+            Response::new(Outgoing::stream(StreamBody::new(stream).boxed_unsync()))
+        };
+
+        let headers = res.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(metadata.len()));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type(&path)));
+
+        if let Ok(modified) = metadata.modified() {
+            let secs = modified
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default();
+
+            // Size and modification time identify a version well enough for a weak validator.
+            if let Ok(etag) = HeaderValue::try_from(format!("W/\"{:x}-{secs:x}\"", metadata.len()))
+            {
+                headers.insert(ETAG, etag);
+            }
+
+            if let Ok(last_modified) = HeaderValue::try_from(httpdate::fmt_http_date(modified)) {
+                headers.insert(LAST_MODIFIED, last_modified);
+            }
+        }
+
+        res
+    }
+
+    /// Maps the request path below the prefix to a regular file inside `root`. Symlinks are
+    /// followed only as long as they stay inside `root`.
+    async fn resolve(&self, rest: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+
+        for segment in rest.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode(segment)?;
+
+            if segment == "."
+                || segment == ".."
+                || segment.contains(['/', '\\', '\0'])
+                || Path::new(&segment).is_absolute()
+            {
+                return None;
+            }
+
+            path.push(segment);
+        }
+
+        let root = tokio::fs::canonicalize(&self.root).await.ok()?;
+        let mut path = tokio::fs::canonicalize(&path).await.ok()?;
+
+        if tokio::fs::metadata(&path).await.ok()?.is_dir() {
+            path.push(self.index.as_ref()?);
+            path = tokio::fs::canonicalize(&path).await.ok()?;
+        }
+
+        (path.starts_with(&root) && tokio::fs::metadata(&path).await.ok()?.is_file())
+            .then_some(path)
+    }
+}
+
+fn not_found() -> Response<Outgoing> {
+    error_response(StatusCode::NOT_FOUND, "not-found", "No such file")
+}
+
+pub fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A root holding `app.js` and `docs/index.html`, beside a `secret.txt` outside of it.
+    fn site() -> (tempfile::TempDir, StaticDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("public");
+
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("app.js"), "run()").unwrap();
+        std::fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "hunter2").unwrap();
+
+        let site = StaticDir::new("/assets/", root, Some("index.html".to_owned()));
+
+        (dir, site)
+    }
+
+    #[test]
+    fn prefixes_cover_whole_segments() {
+        let (_dir, site) = site();
+
+        assert_eq!(site.strip("/assets"), Some(""));
+        assert_eq!(site.strip("/assets/app.js"), Some("/app.js"));
+        assert_eq!(site.strip("/assetsx/app.js"), None);
+        assert_eq!(site.strip("/other"), None);
+    }
+
+    #[tokio::test]
+    async fn serves_files_and_index_pages() {
+        let (_dir, site) = site();
+
+        let res = site.serve(&Method::GET, "/app.js").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+        assert!(res.headers().contains_key(ETAG));
+        assert!(res.headers().contains_key(LAST_MODIFIED));
+
+        assert!(site
+            .resolve("/docs")
+            .await
+            .unwrap()
+            .ends_with("docs/index.html"));
+        assert!(site.resolve("/d%6fcs/").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn traversal_is_rejected() {
+        let (_dir, site) = site();
+
+        for rest in [
+            "/../secret.txt",
+            "/docs/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/.%2E/secret.txt",
+            "/docs/..%2f..%2fsecret.txt",
+            "/%2fetc%2fpasswd",
+            "/..%5csecret.txt",
+            "/app.js%00.png",
+            "/%zz",
+        ] {
+            assert_eq!(site.resolve(rest).await, None, "{rest}");
+
+            let res = site.serve(&Method::GET, rest).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{rest}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_must_stay_inside_the_root() {
+        let (dir, site) = site();
+        let root = dir.path().join("public");
+
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("leak.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("app.js"), root.join("alias.js")).unwrap();
+
+        assert_eq!(site.resolve("/leak.txt").await, None);
+        assert!(site.resolve("/alias.js").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn only_get_and_head_are_allowed() {
+        let (_dir, site) = site();
+        let res = site.serve(&Method::POST, "/app.js").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET, HEAD");
+
+        let res = site.serve(&Method::HEAD, "/app.js").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+        assert!(res.body().buf.is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{path::Path, sync::Arc};
+
+use ::http::{HeaderMap, Method, Request, Response};
+use http_body_util::{BodyExt, Collected, Full};
+use hyper::body::Bytes;
+
+use crate::{Options, Runner};
+
+/// A component loaded for `cargo test`, sent requests without a server. Each request gets a
+/// fresh instance, as it would when served.
+pub struct TestRunner {
+    runner: Arc<Runner>,
+}
+
+impl TestRunner {
+    pub fn new(component: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::with_options(component, Options::default())
+    }
+
+    pub fn with_options(component: impl AsRef<Path>, options: Options) -> anyhow::Result<Self> {
+        Ok(Self::from_runner(Runner::new(component, options)?))
+    }
+
+    /// Tests a runner set up beyond what [`Options`] covers, such as one with an outbound mock.
+    pub fn from_runner(runner: Runner) -> Self {
+        Self {
+            runner: Arc::new(runner),
+        }
+    }
+
+    pub fn runner(&self) -> &Arc<Runner> {
+        &self.runner
+    }
+
+    /// Sends `req` along the same path the server uses and reads the whole response, trailers
+    /// included.
+    pub async fn request(
+        &self,
+        req: Request<Full<Bytes>>,
+    ) -> anyhow::Result<Response<Collected<Bytes>>> {
+        let res = self.runner.clone().service_fn(req).await?;
+        let (parts, body) = res.into_parts();
+
+        let body = body.collect().await.map_err(anyhow::Error::msg)?;
+
+        Ok(Response::from_parts(parts, body))
+    }
+
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: HeaderMap,
+        body: impl Into<Bytes>,
+    ) -> anyhow::Result<Response<Collected<Bytes>>> {
+        let mut req = Request::new(Full::new(body.into()));
+        *req.method_mut() = method;
+        *req.uri_mut() = uri.parse()?;
+        *req.headers_mut() = headers;
+
+        self.request(req).await
+    }
+
+    pub async fn get(&self, uri: &str) -> anyhow::Result<Response<Collected<Bytes>>> {
+        self.send(Method::GET, uri, HeaderMap::new(), Bytes::new())
+            .await
+    }
+
+    pub async fn post(
+        &self,
+        uri: &str,
+        body: impl Into<Bytes>,
+    ) -> anyhow::Result<Response<Collected<Bytes>>> {
+        self.send(Method::POST, uri, HeaderMap::new(), body).await
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use ::http::{HeaderMap, HeaderName, HeaderValue};
+
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+/// Vendor data for the trace, passed on unchanged alongside `traceparent`.
+pub const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// A W3C trace context: the trace a request belongs to and the span handling it. Attached to
+/// requests as an extension, and passed on to the guest's outbound requests as `traceparent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    /// The span the request was sent from, if it came with a `traceparent`.
+    pub parent_id: Option<u64>,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Continues the trace from the request's `traceparent`, or starts a new one when it has none
+    /// or it is malformed.
+    pub fn for_request(headers: &HeaderMap) -> Self {
+        match headers.get(TRACEPARENT).and_then(parse) {
+            Some(parent) => parent.child(),
+            None => Self {
+                trace_id: nonzero(rand::random()),
+                span_id: nonzero(rand::random()),
+                parent_id: None,
+                sampled: true,
+            },
+        }
+    }
+
+    /// A new span in the same trace, parented to this one.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: nonzero(rand::random()),
+            parent_id: Some(self.span_id),
+            sampled: self.sampled,
+        }
+    }
+
+    /// The `traceparent` header naming this span as the parent.
+    pub fn to_header(self) -> HeaderValue {
+        HeaderValue::try_from(self.to_string()).expect("trace contexts are plain ASCII")
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+}
+
+/// Parses `<version>-<trace-id>-<parent-id>-<flags>`. Later versions may add fields, which are
+/// ignored, but version `ff` and all-zero ids are invalid.
+fn parse(value: &HeaderValue) -> Option<TraceContext> {
+    let mut parts = value.to_str().ok()?.trim().split('-');
+
+    let version = parts.next().filter(|version| version.len() == 2)?;
+    let trace_id = parts.next().filter(|id| id.len() == 32)?;
+    let span_id = parts.next().filter(|id| id.len() == 16)?;
+    let flags = parts.next().filter(|flags| flags.len() == 2)?;
+
+    if version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+
+    let hex = |value: &str| value.bytes().all(|byte| byte.is_ascii_hexdigit());
+
+    if !hex(version) || !hex(trace_id) || !hex(span_id) || !hex(flags) {
+        return None;
+    }
+
+    let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+    let span_id = u64::from_str_radix(span_id, 16).ok()?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    if trace_id == 0 || span_id == 0 {
+        return None;
+    }
+
+    Some(TraceContext {
+        trace_id,
+        span_id,
+        parent_id: None,
+        sampled: flags & 1 == 1,
+    })
+}
+
+fn nonzero<T: Default + PartialEq + From<u8>>(id: T) -> T {
+    if id == T::default() {
+        T::from(1)
+    } else {
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn headers(traceparent: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, traceparent.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn requests_continue_the_trace_they_arrive_with() {
+        let trace = TraceContext::for_request(&headers(PARENT));
+
+        assert_eq!(trace.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(trace.parent_id, Some(0x00f067aa0ba902b7));
+        assert_ne!(trace.span_id, 0x00f067aa0ba902b7);
+        assert!(trace.sampled);
+
+        let unsampled = PARENT.replace("-01", "-00");
+        assert!(!TraceContext::for_request(&headers(&unsampled)).sampled);
+    }
+
+    #[test]
+    fn children_are_parented_to_their_span() {
+        let trace = TraceContext::for_request(&headers(PARENT));
+        let child = trace.child();
+
+        assert_eq!(child.trace_id, trace.trace_id);
+        assert_eq!(child.parent_id, Some(trace.span_id));
+        assert_ne!(child.span_id, trace.span_id);
+
+        // The header names the span it is sent from, so the next hop can parent to it.
+        let next = TraceContext::for_request(&headers(child.to_header().to_str().unwrap()));
+        assert_eq!(next.trace_id, trace.trace_id);
+        assert_eq!(next.parent_id, Some(child.span_id));
+    }
+
+    #[test]
+    fn malformed_traceparents_start_a_new_trace() {
+        for traceparent in [
+            "",
+            "garbage",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01",
+        ] {
+            let trace = TraceContext::for_request(&headers(traceparent));
+
+            assert_eq!(trace.parent_id, None, "{traceparent:?}");
+            assert_ne!(trace.trace_id, 0, "{traceparent:?}");
+            assert_ne!(trace.span_id, 0, "{traceparent:?}");
+        }
+    }
+
+    #[test]
+    fn later_versions_may_add_fields() {
+        let trace = TraceContext::for_request(&headers(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ));
+
+        assert_eq!(trace.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(trace.parent_id, Some(0x00f067aa0ba902b7));
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures::task::noop_waker_ref;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::time::{Instant, Sleep};
+
+use crate::{
+    http::{BoxError, RequestBody},
+    wasi::http::types::ErrorCode,
+};
+
+/// Why an upstream response body was cut off, as the guest is told it through
+/// `http-error-code`.
+#[derive(Debug)]
+pub struct BodyFailure(pub ErrorCode);
+
+impl fmt::Display for BodyFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::error::Error for BodyFailure {}
+
+/// Caps on the upstream response bodies one guest request receives.
+#[derive(Clone, Default)]
+pub struct UpstreamLimits {
+    /// Bytes each response body may have.
+    per_body: Option<u64>,
+    /// Bytes all of them may have together, counted down as they arrive.
+    remaining: Option<Arc<AtomicU64>>,
+}
+
+impl UpstreamLimits {
+    pub fn new(per_body: Option<u64>, total: Option<u64>) -> Self {
+        Self {
+            per_body,
+            remaining: total.map(|total| Arc::new(AtomicU64::new(total))),
+        }
+    }
+
+    /// The most the next response body may have.
+    fn allowance(&self) -> Option<u64> {
+        let remaining = self
+            .remaining
+            .as_ref()
+            .map(|remaining| remaining.load(Ordering::Relaxed));
+
+        match (self.per_body, remaining) {
+            (Some(per_body), Some(remaining)) => Some(per_body.min(remaining)),
+            (per_body, remaining) => per_body.or(remaining),
+        }
+    }
+
+    /// Caps `body`, or fails right away if it is known to be over the cap, as when its
+    /// `Content-Length` is.
+    pub fn cap(&self, body: RequestBody) -> Result<RequestBody, ErrorCode> {
+        let Some(allowance) = self.allowance() else {
+            return Ok(body);
+        };
+
+        let len = body.size_hint().lower();
+
+        if len > allowance {
+            return Err(ErrorCode::HttpResponseBodySize(Some(len)));
+        }
+
+        Ok(CappedBody {
+            inner: Some(body),
+            limits: self.clone(),
+            received: 0,
+        }
+        .boxed_unsync())
+    }
+}
+
+/// An upstream response body that fails once it goes over its cap. The response is dropped
+/// then, closing the connection so no more of it is downloaded.
+struct CappedBody {
+    inner: Option<RequestBody>,
+    limits: UpstreamLimits,
+    received: u64,
+}
+
+impl CappedBody {
+    fn take(&mut self, len: u64) -> bool {
+        self.received += len;
+
+        let within_body = self
+            .limits
+            .per_body
+            .is_none_or(|per_body| self.received <= per_body);
+
+        within_body
+            && self.limits.remaining.as_ref().is_none_or(|remaining| {
+                remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                        left.checked_sub(len)
+                    })
+                    .is_ok()
+            })
+    }
+}
+
+impl Body for CappedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+
+        let Some(inner) = &mut this.inner else {
+            return Poll::Ready(None);
+        };
+
+        let frame = ready!(Pin::new(inner).poll_frame(cx));
+
+        let len = match &frame {
+            Some(Ok(frame)) => frame.data_ref().map_or(0, |data| data.len() as u64),
+            _ => 0,
+        };
+
+        if !this.take(len) {
+            this.inner = None;
+
+            return Poll::Ready(Some(Err(BodyFailure(ErrorCode::HttpResponseBodySize(
+                Some(this.received),
+            ))
+            .into())));
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+/// An upstream response body that fails with `connection-read-timeout` once the guest has been
+/// blocked on it for `timeout` without a frame arriving. Time the guest spends on anything else,
+/// even while the body is idle, doesn't count.
+pub struct BetweenBytes {
+    inner: Option<RequestBody>,
+    timeout: Duration,
+    /// Time spent blocked since the last frame arrived, not counting the current wait.
+    waited: Duration,
+    /// When the current wait began, if the guest is blocked on the body.
+    blocked_since: Option<Instant>,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl BetweenBytes {
+    pub fn wrap(body: RequestBody, timeout: Option<Duration>) -> RequestBody {
+        let Some(timeout) = timeout else {
+            return body;
+        };
+
+        Self {
+            inner: Some(body),
+            timeout,
+            waited: Duration::ZERO,
+            blocked_since: None,
+            timer: None,
+        }
+        .boxed_unsync()
+    }
+
+    fn stop_waiting(&mut self) {
+        if let Some(since) = self.blocked_since.take() {
+            self.waited += since.elapsed();
+        }
+
+        self.timer = None;
+    }
+}
+
+impl Body for BetweenBytes {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+
+        let Some(inner) = &mut this.inner else {
+            return Poll::Ready(None);
+        };
+
+        if let Poll::Ready(frame) = Pin::new(inner).poll_frame(cx) {
+            // Any frame starts the gap over, whatever it holds.
+            this.waited = Duration::ZERO;
+            this.blocked_since = None;
+            this.timer = None;
+
+            return Poll::Ready(frame);
+        }
+
+        // Reads and readiness checks that don't block poll with a waker that does nothing.
+        if cx.waker().will_wake(noop_waker_ref()) {
+            this.stop_waiting();
+            return Poll::Pending;
+        }
+
+        let since = *this.blocked_since.get_or_insert_with(Instant::now);
+        let deadline = since + this.timeout.saturating_sub(this.waited);
+        let timer = this
+            .timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+
+        ready!(timer.as_mut().poll(cx));
+
+        // Dropping the body closes the connection, as for a body over its cap.
+        this.inner = None;
+        this.stop_waiting();
+
+        Poll::Ready(Some(Err(
+            BodyFailure(ErrorCode::ConnectionReadTimeout).into()
+        )))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
@@ -0,0 +1,4318 @@
+//! Serves the fixture guest built by `scripts/build-fixtures.sh` through the real listener. A
+//! test fails when its fixture hasn't been built, unless `SKIP_E2E` is set to skip it.
+
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpListener, TcpStream},
+    ops::ControlFlow,
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+        ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, CONNECTION, CONTENT_TYPE,
+        DATE, ETAG, IF_NONE_MATCH, ORIGIN, RETRY_AFTER, SERVER, STRICT_TRANSPORT_SECURITY, VARY,
+    },
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+};
+use http_body_util::{BodyExt, Collected, Full, StreamBody};
+use hyper::body::{Body, Bytes, Frame};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+};
+use wasi_http_runner::{
+    bench, testing::TestRunner, BenchOptions, ClientAddr, ClockSource, Cors, ErrorFormat,
+    GuestConfig, HeaderEdits, HeaderRules, KeyValue, ManualClock, MemoryBackend, Metrics, Mounts,
+    Options, ProxyOptions, RequestBody, Runner, SystemClock, WasiHttpService,
+};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/guest.wasm");
+const MIDDLEWARE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/middleware.wasm"
+);
+const STD_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/std.wasm");
+
+/// Whether `fixture` has been built. A missing fixture fails the test unless `SKIP_E2E` is set, in
+/// which case the test is skipped.
+fn built(fixture: &str) -> bool {
+    if Path::new(fixture).exists() {
+        return true;
+    }
+
+    assert!(
+        std::env::var_os("SKIP_E2E").is_some(),
+        "{fixture} is missing, build it with scripts/build-fixtures.sh or set SKIP_E2E to skip"
+    );
+    eprintln!("skipping: {fixture} is missing");
+
+    false
+}
+
+/// A runner process serving the fixture. Its output is printed if the test fails.
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+    output: Arc<Mutex<String>>,
+}
+
+impl Server {
+    fn start() -> Option<Self> {
+        Self::with_args(&[])
+    }
+
+    fn with_args(args: &[&str]) -> Option<Self> {
+        Self::on("127.0.0.1:0", args)
+    }
+
+    /// Listens on `bind`, with port 0 replaced by a free one.
+    fn on(bind: &str, args: &[&str]) -> Option<Self> {
+        Self::serving(FIXTURE, bind, args)
+    }
+
+    fn serving(component: &str, bind: &str, args: &[&str]) -> Option<Self> {
+        if !built(component) {
+            return None;
+        }
+
+        // Taken and released again, so the runner can bind it.
+        let addr = TcpListener::bind(bind).unwrap().local_addr().unwrap();
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"));
+        command
+            .arg("--component")
+            .arg(component)
+            .arg("--addr")
+            .arg(addr.to_string())
+            .args(args);
+
+        Some(Self::spawn(command, addr))
+    }
+
+    /// Runs `command`, which starts a runner that will accept connections on `addr`.
+    fn spawn(mut command: Command, addr: SocketAddr) -> Self {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let output = Arc::new(Mutex::new(String::new()));
+
+        for mut stream in [
+            Box::new(child.stdout.take().unwrap()) as Box<dyn Read + Send>,
+            Box::new(child.stderr.take().unwrap()),
+        ] {
+            let output = output.clone();
+
+            std::thread::spawn(move || {
+                let mut buf = [0; 4096];
+
+                while let Ok(read @ 1..) = stream.read(&mut buf) {
+                    output
+                        .lock()
+                        .unwrap()
+                        .push_str(&String::from_utf8_lossy(&buf[..read]));
+                }
+            });
+        }
+
+        let server = Self {
+            child,
+            addr,
+            output,
+        };
+
+        let started_at = Instant::now();
+
+        while TcpStream::connect(addr).is_err() {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(30),
+                "the runner did not start listening"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        server
+    }
+
+    fn uri(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+
+        if std::thread::panicking() {
+            eprintln!("runner output:\n{}", self.output.lock().unwrap());
+        }
+    }
+}
+
+fn client() -> Client<HttpConnector, Full<Bytes>> {
+    Client::builder(TokioExecutor::new()).build_http()
+}
+
+async fn send(
+    server: &Server,
+    method: Method,
+    path: &str,
+    body: impl Into<Bytes>,
+) -> Response<Collected<Bytes>> {
+    let req = Request::builder()
+        .method(method)
+        .uri(server.uri(path))
+        .body(Full::new(body.into()))
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(10), client().request(req))
+        .await
+        .expect("the request timed out")
+        .unwrap();
+    let (parts, body) = res.into_parts();
+
+    Response::from_parts(parts, body.collect().await.unwrap())
+}
+
+#[tokio::test]
+async fn hello() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+}
+
+#[tokio::test]
+#[cfg(unix)]
+async fn serves_sockets_passed_by_systemd() {
+    use std::os::{fd::AsRawFd, unix::net::UnixDatagram};
+
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let listener =
+        socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+    listener
+        .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+        .unwrap();
+    listener.listen(128).unwrap();
+    listener.set_cloexec(false).unwrap();
+    let addr = listener.local_addr().unwrap().as_socket().unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let notify_path = dir.path().join("notify");
+    let notify = UnixDatagram::bind(&notify_path).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+
+    // Socket activation hands the socket over as fd 3, naming the process it is meant for. The
+    // shell keeps its pid when it execs the runner.
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!(
+            "exec 3<&{} && LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\"",
+            listener.as_raw_fd()
+        ))
+        .arg(env!("CARGO_BIN_EXE_wasi-http-runner"))
+        .arg("--component")
+        .arg(FIXTURE)
+        // Ignored in favour of the inherited socket.
+        .args(["--addr", "127.0.0.1:1"])
+        .env("NOTIFY_SOCKET", &notify_path);
+
+    let server = Server::spawn(command, addr);
+    drop(listener);
+
+    let mut buf = [0; 64];
+    let read = notify.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..read], b"READY=1\n");
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+
+    // systemd stops the service with SIGTERM.
+    let status = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let read = notify.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..read], b"STOPPING=1\n");
+}
+
+#[tokio::test]
+#[cfg(unix)]
+async fn sigterm_drains_requests_in_the_guest() {
+    let Some(mut server) = Server::start() else {
+        return;
+    };
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    std::io::Write::write_all(
+        &mut stream,
+        b"GET /sleep/1000 HTTP/1.1\r\nhost: localhost\r\n\r\n",
+    )
+    .unwrap();
+
+    // Gives the request time to reach the guest.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let signalled_at = Instant::now();
+    let status = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = loop {
+        if let Some(status) = server.child.try_wait().unwrap() {
+            break status;
+        }
+
+        assert!(
+            signalled_at.elapsed() < Duration::from_secs(10),
+            "the runner never exited"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    // The runner shuts down rather than being killed by the signal, and only once the guest has
+    // finished with the request.
+    assert!(status.success(), "{status}");
+    assert!(signalled_at.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn serves_ipv6_and_dual_stack_listeners() {
+    // Hosts without an IPv6 loopback can't run this.
+    if TcpListener::bind("[::1]:0").is_err() {
+        return;
+    }
+
+    let Some(server) = Server::on("[::1]:0", &[]) else {
+        return;
+    };
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+
+    // An unspecified IPv6 address takes IPv4 connections as well.
+    let Some(server) = Server::on("[::]:0", &[]) else {
+        return;
+    };
+    let req = Request::get(format!("http://127.0.0.1:{}/", server.addr.port()))
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = client().request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.into_body().collect().await.unwrap().to_bytes(),
+        "Hello, World!"
+    );
+}
+
+#[tokio::test]
+async fn echoes_request_body() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let res = send(&server, Method::POST, "/echo", "ping").await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "ping");
+}
+
+#[tokio::test]
+async fn large_bodies() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let body = vec![b'a'; 1024 * 1024];
+    let res = send(&server, Method::POST, "/echo", body.clone()).await;
+    assert_eq!(res.into_body().to_bytes(), body);
+
+    let res = send(&server, Method::GET, "/stream/1048576", Bytes::new()).await;
+    assert_eq!(res.into_body().to_bytes().len(), 1024 * 1024);
+}
+
+#[tokio::test]
+async fn writes_past_the_write_buffer_before_the_response_is_sent() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // Nothing drains the body while the guest runs, so neither way of writing may wait for it.
+    let res = send(&server, Method::GET, "/write-loop", Bytes::new()).await;
+    let body = res.into_body().to_bytes();
+
+    assert_eq!(body.len(), 128 * 1024);
+    assert!(body[..64 * 1024].iter().all(|&byte| byte == b'a'));
+    assert!(body[64 * 1024..].iter().all(|&byte| byte == b'b'));
+}
+
+#[tokio::test]
+async fn ends_streamed_responses_over_h1_and_h2() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // The guest never says how long the body is, so HTTP/1.1 gets it chunked.
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    std::io::Write::write_all(
+        &mut stream,
+        b"GET /stream/3000 HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+    )
+    .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    let raw = String::from_utf8(raw).unwrap();
+    let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+    let head = head.to_ascii_lowercase();
+
+    assert!(head.starts_with("http/1.1 200"), "{head}");
+    assert!(head.contains("\r\ntransfer-encoding: chunked"), "{head}");
+    assert!(!head.contains("\r\ncontent-length:"), "{head}");
+    assert!(body.ends_with("\r\n0\r\n\r\n"), "{body:?}");
+    assert_eq!(body.matches('x').count(), 3000);
+
+    // HTTP/2 has no chunking; the stream simply ends after the last frame.
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http::<Full<Bytes>>();
+    let req = Request::get(server.uri("/stream/3000"))
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("the request timed out")
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key("transfer-encoding"));
+
+    let mut body = res.into_body();
+    let mut received = 0;
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.expect("the stream was reset instead of ended");
+        received += frame.into_data().expect("no trailers were sent").len();
+    }
+
+    assert_eq!(received, 3000);
+    assert!(body.is_end_stream());
+}
+
+#[tokio::test]
+async fn reads_a_whole_body_in_one_blocking_read() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // About 1MB of JSON, which arrives from the connection in many frames.
+    let json = format!("[{}0]", "1234567,".repeat(128 * 1024));
+    let res = send(&server, Method::POST, "/read-all", json.clone()).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.into_body().to_bytes(),
+        format!("{} bytes in one read, closed: true", json.len())
+    );
+}
+
+#[tokio::test]
+async fn sends_custom_reason_phrases_over_h1_only() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let status_line = |path: &str| {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        std::io::Write::write_all(
+            &mut stream,
+            format!("GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+
+        // The header the guest asked with is not sent on.
+        assert!(
+            !res.to_ascii_lowercase().contains("x-reason-phrase"),
+            "{res}"
+        );
+
+        res.lines().next().unwrap().to_owned()
+    };
+
+    assert_eq!(
+        status_line("/reason/Still%20Fine"),
+        "HTTP/1.1 200 Still Fine"
+    );
+    assert_eq!(status_line("/"), "HTTP/1.1 200 OK");
+
+    // HTTP/2 has no reason phrase at all.
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http::<Full<Bytes>>();
+    let req = Request::get(server.uri("/reason/Still%20Fine"))
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key("x-reason-phrase"));
+    assert!(res.extensions().get::<hyper::ext::ReasonPhrase>().is_none());
+}
+
+#[tokio::test]
+async fn guests_see_the_content_length_of_fixed_size_bodies() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let Some(server) = Server::start() else {
+        return;
+    };
+    let body = "x".repeat(12345);
+
+    let res = send(
+        &server,
+        Method::POST,
+        "/header/content-length",
+        body.clone(),
+    )
+    .await;
+    assert_eq!(res.into_body().to_bytes(), "12345");
+
+    // Without the header, as HTTP/2 clients may send, the length comes from the body.
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let res = runner
+        .post("/header/content-length", body.clone())
+        .await
+        .unwrap();
+    assert_eq!(res.into_body().to_bytes(), "12345");
+
+    // A chunked body has no length to show.
+    let chunks = futures::stream::iter([Ok::<_, std::convert::Infallible>(Frame::data(
+        Bytes::from(body),
+    ))]);
+    let req = Request::post(server.uri("/header/content-length"))
+        .body(StreamBody::new(chunks))
+        .unwrap();
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await
+        .unwrap();
+    assert_eq!(res.into_body().collect().await.unwrap().to_bytes(), "");
+}
+
+#[tokio::test]
+async fn echoes_trailers() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // HTTP/1 responses can't carry trailers here, so this goes over HTTP/2.
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-checksum", "abc".parse().unwrap());
+
+    let frames = futures::stream::iter([
+        Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from("ping"))),
+        Ok(Frame::trailers(trailers)),
+    ]);
+
+    let req = Request::post(server.uri("/echo"))
+        .body(StreamBody::new(frames))
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("the request timed out")
+        .unwrap();
+    let body = res.into_body().collect().await.unwrap();
+
+    assert_eq!(body.trailers().unwrap()["x-checksum"], "abc");
+    assert_eq!(body.to_bytes(), "ping");
+}
+
+#[tokio::test]
+async fn test_runners_send_header_maps() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-test", "sent".parse().unwrap());
+    let res = runner
+        .send(Method::POST, "/header/x-test", headers, "ignored")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "sent");
+
+    let res = runner.get("/header/x-test").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "");
+
+    let res = runner.post("/echo", "ping").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "ping");
+}
+
+#[tokio::test]
+async fn test_runners_collect_response_trailers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+
+    let req = Request::get("/trailers")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    // The fixture flushes its body before it returns, which must not wait for the response to be
+    // sent.
+    let res = tokio::time::timeout(Duration::from_secs(10), runner.request(req))
+        .await
+        .expect("the guest never finished writing its body");
+    let body = res.unwrap().into_body();
+
+    assert_eq!(body.trailers().unwrap()["x-checksum"], "abc");
+    assert_eq!(body.to_bytes(), "body");
+}
+
+#[test]
+fn drops_connections_that_send_their_head_slowly() {
+    let Some(server) = Server::with_args(&["--header-read-timeout-secs", "1"]) else {
+        return;
+    };
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    std::io::Write::write_all(&mut stream, b"GET / HTTP/1.1\r\nhost: localhost\r\n").unwrap();
+
+    let started_at = Instant::now();
+    let mut buf = [0; 1024];
+
+    // A header line at a time, never finishing the head.
+    let closed_after = loop {
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "the slow connection was kept open"
+        );
+
+        if std::io::Write::write_all(&mut stream, b"x-slow: 1\r\n").is_err() {
+            break started_at.elapsed();
+        }
+
+        match stream.read(&mut buf) {
+            Ok(0) => break started_at.elapsed(),
+            Ok(read) => assert!(
+                !buf[..read].starts_with(b"HTTP/1.1 200"),
+                "the incomplete request was answered"
+            ),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(_) => break started_at.elapsed(),
+        }
+    };
+
+    assert!(
+        closed_after >= Duration::from_millis(800),
+        "closed after {closed_after:?}"
+    );
+
+    // Clients that send their head in time are unaffected.
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    std::io::Write::write_all(
+        &mut stream,
+        b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+    )
+    .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let mut res = String::new();
+    stream.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+}
+
+#[tokio::test]
+async fn survives_early_termination() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // Announces more body than it sends, then hangs up.
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    std::io::Write::write_all(
+        &mut stream,
+        b"POST /echo HTTP/1.1\r\nhost: localhost\r\ncontent-length: 1000\r\n\r\npartial",
+    )
+    .unwrap();
+    drop(stream);
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn traps_become_server_errors() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/trap", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // The next request gets a fresh instance.
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn concurrent_requests() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let responses =
+        futures::future::join_all((0..32).map(|_| send(&server, Method::GET, "/", Bytes::new())))
+            .await;
+
+    for res in responses {
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+    }
+}
+
+#[tokio::test]
+async fn sheds_load_when_the_queue_is_full() {
+    let Some(server) = Server::with_args(&[
+        "--max-concurrency",
+        "1",
+        "--guest-threads",
+        "1",
+        "--queue-depth",
+        "1",
+    ]) else {
+        return;
+    };
+
+    // Occupies the only slot before the rest arrive.
+    let running = send(&server, Method::GET, "/sleep/1000", Bytes::new());
+
+    let started_at = Instant::now();
+    let overload = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        futures::future::join_all(
+            (0..5).map(|_| send(&server, Method::GET, "/sleep/1000", Bytes::new())),
+        )
+        .await
+    };
+
+    let (running, responses) = tokio::join!(running, overload);
+
+    let shed: Vec<_> = responses
+        .iter()
+        .filter(|res| res.status() == StatusCode::SERVICE_UNAVAILABLE)
+        .collect();
+
+    // One waits in the queue, the others are turned away at once.
+    assert_eq!(shed.len(), 4);
+    assert!(shed
+        .iter()
+        .all(|res| res.headers().contains_key(RETRY_AFTER)));
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+    assert_eq!(running.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn queues_load_beyond_capacity() {
+    // Twice as many requests as the runner can run at once.
+    async fn overload(server: &Server) -> Vec<StatusCode> {
+        futures::future::join_all(
+            (0..4).map(|_| send(server, Method::GET, "/sleep/300", Bytes::new())),
+        )
+        .await
+        .iter()
+        .map(|res| res.status())
+        .collect()
+    }
+
+    let args = ["--max-concurrency", "2", "--guest-threads", "2"];
+
+    let Some(unqueued) = Server::with_args(&[&args[..], &["--queue-depth", "0"]].concat()) else {
+        return;
+    };
+    let statuses = overload(&unqueued).await;
+    let shed = statuses
+        .iter()
+        .filter(|status| **status == StatusCode::SERVICE_UNAVAILABLE)
+        .count();
+    assert_eq!(shed, 2, "{statuses:?}");
+
+    let Some(queued) = Server::with_args(&[&args[..], &["--queue-depth", "2"]].concat()) else {
+        return;
+    };
+    let statuses = overload(&queued).await;
+    assert!(
+        statuses.iter().all(|status| *status == StatusCode::OK),
+        "{statuses:?}"
+    );
+}
+
+/// Instantiation, guest and wait time of the requests handled since `before`, and their total
+/// execution time, in microseconds.
+fn breakdown(metrics: &Metrics, before: [u64; 4]) -> [u64; 4] {
+    let now = [
+        metrics.instantiation_time.sum(),
+        metrics.guest_time.sum(),
+        metrics.wait_time.sum(),
+        metrics.execution_time.sum(),
+    ];
+
+    std::array::from_fn(|i| now[i] - before[i])
+}
+
+#[tokio::test]
+async fn a_saturated_mount_does_not_slow_the_others() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = || Options {
+        max_concurrency: 1,
+        guest_threads: 1,
+        queue_depth: 16,
+        ..Default::default()
+    };
+    let busy = Arc::new(Runner::new(FIXTURE, options()).unwrap());
+    let quiet = Arc::new(Runner::new(FIXTURE, options()).unwrap());
+    let mounts = Arc::new(
+        Mounts::new()
+            .mount("/", quiet.clone())
+            .mount("/sleep", busy.clone()),
+    );
+
+    let get = |path: &'static str| {
+        let mounts = mounts.clone();
+
+        tokio::spawn(async move {
+            let req = Request::get(path).body(Full::new(Bytes::new())).unwrap();
+            let res = mounts.service_fn(req).await.unwrap();
+            let status = res.status();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+
+            (status, body)
+        })
+    };
+
+    // Enough work to keep the busy mount's only slot taken for two seconds.
+    let saturating: Vec<_> = (0..4).map(|_| get("/sleep/500")).collect();
+
+    let started_at = Instant::now();
+
+    while busy.metrics().queue_depth.get() < 3 {
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "the busy mount never queued"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    for _ in 0..5 {
+        let started_at = Instant::now();
+        let (status, body) = get("/").await.unwrap();
+        let elapsed = started_at.elapsed();
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "Hello, World!");
+        assert!(
+            elapsed < Duration::from_millis(300),
+            "the quiet mount took {elapsed:?}"
+        );
+    }
+
+    for request in saturating {
+        let (status, _) = request.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn benches_a_component_in_process() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    // Without the circuit breaker, so every trap reaches the guest.
+    let runner = Options {
+        failure_threshold: 0,
+        ..Default::default()
+    };
+    let runner = Arc::new(Runner::new(FIXTURE, runner).unwrap());
+    let options = BenchOptions {
+        requests: 50,
+        duration: None,
+        concurrency: 4,
+        path: "/".to_owned(),
+        body: None,
+    };
+
+    let report = bench(runner.clone(), options.clone()).await.unwrap();
+    assert_eq!(report.requests, 50);
+    assert_eq!(report.server_errors, 0);
+    assert_eq!(report.traps, 0);
+    assert!(report.p50 <= report.p90 && report.p90 <= report.p99 && report.p99 <= report.max);
+    assert!(report.instantiation > 0);
+
+    let json = report.to_json();
+    assert!(json.starts_with("{\"requests\":50,"), "{json}");
+    assert!(json.contains("\"traps\":0,"), "{json}");
+
+    // Traps are told apart from other server errors.
+    let report = bench(
+        runner.clone(),
+        BenchOptions {
+            requests: 5,
+            path: "/trap".to_owned(),
+            ..options.clone()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.requests, 5);
+    assert_eq!(report.server_errors, 5);
+    assert_eq!(report.traps, 5);
+
+    // A body turns the requests into POSTs.
+    let report = bench(
+        runner.clone(),
+        BenchOptions {
+            requests: 5,
+            path: "/echo".to_owned(),
+            body: Some(Bytes::from_static(b"benched")),
+            ..options.clone()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.requests, 5);
+    assert_eq!(report.server_errors, 0);
+
+    // With a duration, the request count is whatever fit.
+    let report = bench(
+        runner,
+        BenchOptions {
+            requests: 0,
+            duration: Some(Duration::from_millis(500)),
+            ..options
+        },
+    )
+    .await
+    .unwrap();
+    assert!(report.requests > 0);
+    assert!(report.elapsed >= Duration::from_millis(500));
+    assert!(report.elapsed < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn invokes_requests_in_process() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = Arc::new(Runner::new(FIXTURE, Options::default()).unwrap());
+
+    let req = Request::post("/echo")
+        .body(Full::new(Bytes::from("invoked")))
+        .unwrap();
+    let invocation = runner.clone().invoke(req).await.unwrap();
+    assert_eq!(invocation.response.status(), StatusCode::OK);
+    assert_eq!(invocation.response.body(), "invoked");
+    assert!(!invocation.trapped);
+
+    let req = Request::get("/trap").body(Full::new(Bytes::new())).unwrap();
+    let invocation = runner.invoke(req).await.unwrap();
+    assert_eq!(
+        invocation.response.status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert!(invocation.trapped);
+}
+
+#[test]
+fn invoke_prints_the_response_like_curl() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let invoke = |args: &[&str], stdin: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+            // The log shares stdout with the response.
+            .args(["--log-level", "off", "invoke", "--component"])
+            .arg(FIXTURE)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), stdin).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+        (output.status.code(), stdout, stderr)
+    };
+
+    let (code, stdout, _) = invoke(&["POST", "/echo", "--body", "inline"], b"");
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "inline");
+
+    let (code, stdout, _) = invoke(&["POST", "/echo", "--body", "@-"], b"from stdin");
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "from stdin");
+
+    let dir = tempfile::tempdir().unwrap();
+    let payload = dir.path().join("payload.json");
+    let output = dir.path().join("output");
+    std::fs::write(&payload, "{\"from\":\"file\"}").unwrap();
+
+    let (code, stdout, _) = invoke(
+        &[
+            "POST",
+            "/echo",
+            "--body",
+            &format!("@{}", payload.display()),
+            "--output",
+            output.to_str().unwrap(),
+        ],
+        b"",
+    );
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "");
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "{\"from\":\"file\"}"
+    );
+
+    // -i puts the head before the body, -v sends both heads to stderr.
+    let (code, stdout, _) = invoke(&["GET", "/header/x-test", "-H", "x-test: sent", "-i"], b"");
+    assert_eq!(code, Some(0));
+    assert!(stdout.starts_with("HTTP/1.1 200 OK\n"), "{stdout}");
+    assert!(stdout.ends_with("\n\nsent"), "{stdout}");
+
+    let (code, stdout, stderr) =
+        invoke(&["GET", "/header/x-test", "-H", "x-test: sent", "-v"], b"");
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "sent");
+    assert!(
+        stderr.contains("> GET /header/x-test\n> x-test: sent\n"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("< HTTP/1.1 200 OK\n"), "{stderr}");
+
+    // The exit code follows the status class, or tells of a trap.
+    let (code, _, _) = invoke(&["GET", "/missing"], b"");
+    assert_eq!(code, Some(4));
+
+    let (code, _, _) = invoke(&["GET", "/trap"], b"");
+    assert_eq!(code, Some(6));
+}
+
+#[test]
+fn bench_prints_a_report() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+        // The log shares stdout with the report.
+        .args(["--log-level", "off", "bench", "--requests", "20"])
+        .args(["--concurrency", "2", "--component"])
+        .arg(FIXTURE)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.starts_with("requests      20 in "), "{stdout}");
+    assert!(stdout.contains("traps         0\n"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+        .args(["--log-level", "off", "bench", "--requests", "20"])
+        .args(["--json", "--component"])
+        .arg(FIXTURE)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["requests"], 20);
+    assert_eq!(report["server_errors"], 0);
+    assert!(report["latency_us"]["p99"].is_u64(), "{stdout}");
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_guests_to_drop_their_stores() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        guest_threads: 3,
+        ..Default::default()
+    };
+    let runner = Arc::new(Runner::new(FIXTURE, options).unwrap());
+
+    let in_flight: Vec<_> = (0..3)
+        .map(|_| {
+            let runner = runner.clone();
+            let req = Request::get("/sleep/300")
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+
+            tokio::spawn(async move { runner.invoke(req).await.unwrap().response.status() })
+        })
+        .collect();
+
+    let started_at = Instant::now();
+
+    while runner.metrics().guest_threads_busy.get() < 3 {
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "the requests never reached the guest"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    runner.shutdown().await;
+
+    // Each store, with its linear memory, is dropped before its guest thread is released.
+    assert_eq!(runner.metrics().guest_threads_busy.get(), 0);
+
+    for request in in_flight {
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+    }
+
+    // Nothing is instantiated again afterwards.
+    let instantiations = runner.metrics().instantiation_time.count();
+    let req = Request::get("/").body(Full::new(Bytes::new())).unwrap();
+    let res = runner.clone().invoke(req).await.unwrap();
+
+    assert_eq!(res.response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(runner.metrics().instantiation_time.count(), instantiations);
+}
+
+#[tokio::test]
+async fn timings_add_up() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let metrics = runner.runner().metrics();
+
+    let assert_adds_up = |[instantiation, guest, wait, total]: [u64; 4]| {
+        let parts = instantiation + guest + wait;
+        assert!(parts <= total, "{parts}us of parts in {total}us");
+        assert!(total - parts < 50_000, "{parts}us of parts in {total}us");
+    };
+
+    // A client that takes its time sending the body.
+    let before = breakdown(metrics, [0; 4]);
+    let chunks = futures::stream::iter(["slow", "client"]).then(|chunk| async move {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from(chunk)))
+    });
+    let req = Request::post("/echo")
+        .body(StreamBody::new(chunks))
+        .unwrap();
+
+    let res = runner.runner().clone().service_fn(req).await.unwrap();
+    assert_eq!(
+        res.into_body().collect().await.unwrap().to_bytes(),
+        "slowclient"
+    );
+
+    let slow_client = breakdown(metrics, before);
+    assert!(slow_client[2] >= 400_000, "waited {}us", slow_client[2]);
+    assert_adds_up(slow_client);
+    assert_eq!(metrics.drain_time.count(), 1);
+
+    // A guest that takes its time answering.
+    let before = breakdown(metrics, [0; 4]);
+    let res = runner.get("/sleep/300").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let slow_guest = breakdown(metrics, before);
+    assert!(slow_guest[1] >= 300_000, "ran for {}us", slow_guest[1]);
+    assert_adds_up(slow_guest);
+}
+
+/// POSTs a 64 KiB body to a route that never reads it, then sends another request on the same
+/// connection. Returns the first response and whether the second one got through.
+async fn ignored_body_then_reuse(server: &Server) -> (Response<Collected<Bytes>>, bool) {
+    let stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(conn);
+
+    let req = Request::post(server.uri("/ignore"))
+        .body(Full::new(Bytes::from(vec![b'a'; 64 * 1024])))
+        .unwrap();
+    let res = tokio::time::timeout(Duration::from_secs(10), sender.send_request(req))
+        .await
+        .expect("the response waited for the unread body")
+        .unwrap();
+    let (parts, body) = res.into_parts();
+    let res = Response::from_parts(parts, body.collect().await.unwrap());
+
+    let reused = match sender.ready().await {
+        Ok(()) => {
+            let req = Request::get(server.uri("/"))
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            sender.send_request(req).await.is_ok()
+        }
+        Err(_) => false,
+    };
+
+    (res, reused)
+}
+
+#[tokio::test]
+async fn drains_unread_bodies() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let (res, reused) = ignored_body_then_reuse(&server).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(reused, "the connection was not kept alive");
+}
+
+#[tokio::test]
+async fn closes_after_unread_bodies() {
+    let Some(server) = Server::with_args(&["--unread-body", "close"]) else {
+        return;
+    };
+
+    let (res, reused) = ignored_body_then_reuse(&server).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["connection"], "close");
+    assert!(!reused, "the connection was kept alive");
+}
+
+#[tokio::test]
+async fn reports_fuel_per_request() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        fuel: Some(u64::MAX / 2),
+        fuel_header: true,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let fuel_used = |res: &Response<Collected<Bytes>>| -> u64 {
+        res.headers()["x-wasm-fuel-used"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+
+    let light = runner.get("/spin/10").await.unwrap();
+    let heavy = runner.get("/spin/1000000").await.unwrap();
+
+    assert!(
+        fuel_used(&heavy) > 10 * fuel_used(&light),
+        "{} against {}",
+        fuel_used(&heavy),
+        fuel_used(&light)
+    );
+    assert_eq!(runner.runner().metrics().fuel_used.count(), 2);
+}
+
+#[tokio::test]
+async fn shared_cache_outlives_requests() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let res = send(&server, Method::PUT, "/cache/greeting", "hello").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Each request gets a fresh instance, so this comes from the host.
+    let res = send(&server, Method::GET, "/cache/greeting", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "hello");
+
+    let res = send(&server, Method::PUT, "/cache/brief?ttl=100", "gone soon").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let res = send(&server, Method::GET, "/cache/brief", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn trailers_can_only_be_taken_once() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // The fixture asks for the trailers a second time, which fails without trapping.
+    let res = send(&server, Method::POST, "/trailers-twice", "body").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.into_body().to_bytes(),
+        "taken then trailers already taken"
+    );
+}
+
+/// Waits for the JSON log line whose `message` is `message`.
+async fn json_log(server: &Server, message: &str) -> serde_json::Map<String, serde_json::Value> {
+    let started_at = Instant::now();
+
+    loop {
+        let line = server
+            .output
+            .lock()
+            .unwrap()
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .find(|line| line["message"] == message);
+
+        if let Some(serde_json::Value::Object(line)) = line {
+            return line;
+        }
+
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "nothing logged {message:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Checks `line` against `snapshot`. `"<number>"` and `"<string>"` stand for values that change
+/// from run to run, and keys missing from the snapshot are ignored.
+fn assert_snapshot(line: &serde_json::Map<String, serde_json::Value>, snapshot: serde_json::Value) {
+    for (key, expected) in snapshot.as_object().unwrap() {
+        let actual = line
+            .get(key)
+            .unwrap_or_else(|| panic!("{key} is missing from {line:?}"));
+
+        match expected.as_str() {
+            Some("<number>") => assert!(actual.is_u64(), "{key} is {actual} in {line:?}"),
+            Some("<string>") => assert!(actual.is_string(), "{key} is {actual} in {line:?}"),
+            _ => assert_eq!(actual, expected, "{key} in {line:?}"),
+        }
+    }
+
+    // Span fields sit beside the event's own rather than nested under the span.
+    assert!(line.values().all(|value| !value.is_object()), "{line:?}");
+}
+
+#[tokio::test]
+async fn logs_requests_as_json() {
+    let Some(server) = Server::with_args(&["--log-format", "json"]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let line = json_log(&server, "request handled").await;
+    assert_snapshot(
+        &line,
+        serde_json::json!({
+            "timestamp": "<string>",
+            "level": "INFO",
+            "target": "wasi_http_runner::access",
+            "message": "request handled",
+            "otel.kind": "server",
+            "http.request.method": "GET",
+            "url.path": "/",
+            "http.response.status_code": 200,
+            "trace_id": "<string>",
+            "span_id": "<string>",
+            "version": "default",
+            "queue_us": "<number>",
+            "exec_us": "<number>",
+            "instantiation_us": "<number>",
+            "guest_us": "<number>",
+            "wait_us": "<number>",
+        }),
+    );
+}
+
+#[tokio::test]
+async fn links_request_spans_to_incoming_traceparents() {
+    let Some(server) = Server::with_args(&["--log-format", "json"]) else {
+        return;
+    };
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    let req = Request::get(server.uri("/header/traceparent"))
+        .header("traceparent", traceparent)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = client().request(req).await.unwrap();
+
+    // The guest sees the header it was sent, not the runner's span.
+    assert_eq!(
+        res.into_body().collect().await.unwrap().to_bytes(),
+        traceparent
+    );
+
+    let line = json_log(&server, "request handled").await;
+    assert_eq!(line["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(line["parent_id"], "00f067aa0ba902b7");
+    assert_ne!(line["span_id"], "00f067aa0ba902b7");
+}
+
+#[tokio::test]
+async fn outbound_requests_carry_a_child_traceparent() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .with_outbound_mock({
+            let seen = seen.clone();
+
+            move |req| {
+                seen.lock()
+                    .unwrap()
+                    .push(req.headers()["traceparent"].clone());
+                Some(Response::new(Bytes::new()))
+            }
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse()
+            .unwrap(),
+    );
+    let res = runner
+        .send(Method::GET, "/get/mocked.test", headers, Bytes::new())
+        .await
+        .unwrap();
+    assert_eq!(res.into_body().to_bytes(), "200");
+
+    // Same trace, sampled, but sent from a span of the runner's rather than the caller's.
+    let sent = seen.lock().unwrap()[0].to_str().unwrap().to_owned();
+    let parts = sent.split('-').collect::<Vec<_>>();
+    assert_eq!(parts[..2], ["00", "4bf92f3577b34da6a3ce929d0e0e4736"]);
+    assert_ne!(parts[2], "00f067aa0ba902b7");
+    assert_eq!(parts[3], "01");
+
+    // Without one, the runner starts a trace of its own and still passes it on.
+    runner.get("/get/mocked.test").await.unwrap();
+    let sent = seen.lock().unwrap()[1].to_str().unwrap().to_owned();
+    assert_eq!(sent.len(), 55);
+    assert!(!sent.contains("4bf92f3577b34da6a3ce929d0e0e4736"));
+}
+
+/// Sends a request with credentials to `server` and returns the headers its request head dump
+/// shows.
+async fn dumped_headers(server: &Server) -> String {
+    let req = Request::get(server.uri("/"))
+        .header("authorization", "Bearer secret")
+        .header("x-api-key", "key")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = client().request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let line = json_log(server, "head dump").await;
+    line["headers"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn dumps_leave_out_redacted_header_values() {
+    let args = [
+        "--log-format",
+        "json",
+        "--log-level",
+        "info,wasi_http_runner=debug",
+        "--dump-http",
+    ];
+
+    let Some(server) = Server::with_args(&args) else {
+        return;
+    };
+    let headers = dumped_headers(&server).await;
+    assert!(headers.contains("authorization: [redacted]"), "{headers}");
+    assert!(headers.contains("x-api-key: key"), "{headers}");
+    assert!(!headers.contains("secret"), "{headers}");
+
+    // Naming headers to redact replaces the defaults.
+    let Some(server) = Server::with_args(&[&args[..], &["--dump-redact", "x-api-key"]].concat())
+    else {
+        return;
+    };
+    let headers = dumped_headers(&server).await;
+    assert!(
+        headers.contains("authorization: Bearer secret"),
+        "{headers}"
+    );
+    assert!(headers.contains("x-api-key: [redacted]"), "{headers}");
+}
+
+#[tokio::test]
+async fn logs_traps_as_json() {
+    let Some(server) = Server::with_args(&[
+        "--log-format",
+        "json",
+        "--log-level",
+        "info,wasi_http_runner::access=off",
+    ]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/trap", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let line = json_log(&server, "guest failed to handle request").await;
+    assert_snapshot(
+        &line,
+        serde_json::json!({
+            "timestamp": "<string>",
+            "level": "ERROR",
+            "target": "wasi_http_runner",
+            "message": "guest failed to handle request",
+            "http.request.method": "GET",
+            "url.path": "/trap",
+            "trace_id": "<string>",
+            "reason": "<string>",
+            "error": "<string>",
+        }),
+    );
+
+    // The access log was turned off on its own.
+    let output = server.output.lock().unwrap().clone();
+    assert!(!output.contains("request handled"), "{output}");
+}
+
+/// The mean round trip of small requests sent one after another on a single connection.
+async fn mean_round_trip(server: &Server) -> Duration {
+    let stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(conn);
+
+    const ROUNDS: u32 = 20;
+    let started_at = Instant::now();
+
+    for _ in 0..ROUNDS {
+        sender.ready().await.unwrap();
+
+        let req = Request::post(server.uri("/echo"))
+            .body(Full::new(Bytes::from("ping")))
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        res.into_body().collect().await.unwrap();
+    }
+
+    started_at.elapsed() / ROUNDS
+}
+
+#[tokio::test]
+async fn small_responses_are_not_delayed() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // Warms up the connection path once.
+    mean_round_trip(&server).await;
+
+    let round_trip = mean_round_trip(&server).await;
+
+    // A delayed acknowledgement holds a write back for 40ms on Linux.
+    assert!(
+        round_trip < Duration::from_millis(40),
+        "{round_trip:?} with TCP_NODELAY"
+    );
+}
+
+#[tokio::test]
+async fn measures_guest_memory() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        max_memory_bytes: Some(32 << 20),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let metrics = runner.runner().metrics();
+
+    let res = runner.get("/alloc/16").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(
+        metrics.peak_memory_bytes.sum() >= 16 << 20,
+        "peaked at {} bytes",
+        metrics.peak_memory_bytes.sum()
+    );
+    assert!(metrics.memory_bytes.sum() <= metrics.peak_memory_bytes.sum());
+    assert_eq!(metrics.memory_grows_denied.get(), 0);
+
+    // Past the cap, the allocation fails and the guest traps.
+    let res = runner.get("/alloc/64").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(metrics.memory_grows_denied.get() >= 1);
+    assert_eq!(metrics.peak_memory_bytes.count(), 2);
+}
+
+#[tokio::test]
+async fn mounts_inside_axum() {
+    use tower::ServiceExt;
+
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let service = WasiHttpService::new(runner.runner().clone());
+    let router = axum::Router::new().route("/", axum::routing::any_service(service));
+
+    let req = Request::get("/").body(axum::body::Body::empty()).unwrap();
+    let res = router.oneshot(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.into_body().collect().await.unwrap().to_bytes(),
+        "Hello, World!"
+    );
+}
+
+#[cfg(feature = "host-log")]
+#[tokio::test]
+async fn guest_logs_join_the_request() {
+    let Some(server) = Server::with_args(&["--log-format", "json"]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/log", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let line = json_log(&server, "disk almost full").await;
+    assert_snapshot(
+        &line,
+        serde_json::json!({
+            "level": "WARN",
+            "target": "guest",
+            "message": "disk almost full",
+            "fields": "free=\"3%\"",
+            "url.path": "/log",
+            "trace_id": "<string>",
+            "span_id": "<string>",
+        }),
+    );
+}
+
+#[tokio::test]
+async fn guests_can_close_the_connection() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .unwrap();
+    let conn = tokio::spawn(conn);
+
+    let req = Request::get(server.uri("/close"))
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = sender.send_request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["connection"], "close");
+    res.into_body().collect().await.unwrap();
+
+    // The server hangs up, which ends the client's side of the connection too.
+    tokio::time::timeout(Duration::from_secs(10), conn)
+        .await
+        .expect("the connection was kept alive")
+        .unwrap()
+        .unwrap();
+    assert!(sender.ready().await.is_err());
+}
+
+#[tokio::test]
+async fn request_hooks_rewrite_requests() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .on_request(|req| {
+            req.headers_mut()
+                .insert("x-hook", HeaderValue::from_static("first"));
+            ControlFlow::Continue(())
+        })
+        .on_request_async(|req| {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+
+                let first = req.headers()["x-hook"].to_str().unwrap().to_owned();
+                req.headers_mut()
+                    .insert("x-hook", format!("{first},second").parse().unwrap());
+                ControlFlow::Continue(())
+            })
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    let res = runner.get("/header/x-hook").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "first,second");
+}
+
+#[tokio::test]
+async fn request_hooks_short_circuit() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let hook = |name: &'static str, reject: bool| {
+        let ran = ran.clone();
+
+        move |_: &mut Request<RequestBody>| {
+            ran.lock().unwrap().push(name);
+
+            if reject {
+                let mut res = Response::new(Bytes::from("denied"));
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                ControlFlow::Break(res)
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    };
+
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .on_request(hook("first", false))
+        .on_request(hook("second", true))
+        .on_request(hook("third", false));
+    let runner = TestRunner::from_runner(runner);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.into_body().to_bytes(), "denied");
+
+    assert_eq!(*ran.lock().unwrap(), ["first", "second"]);
+    assert_eq!(runner.runner().metrics().instantiation_time.count(), 0);
+}
+
+#[tokio::test]
+async fn response_hooks_see_every_response() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let counted = Arc::new(AtomicUsize::new(0));
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .on_response(|parts, _| {
+            parts.headers.insert(
+                STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static("max-age=63072000"),
+            );
+        })
+        .wrap_response_body({
+            let counted = counted.clone();
+
+            move |body, _| {
+                let counted = counted.clone();
+
+                body.map_frame(move |frame| {
+                    if let Some(data) = frame.data_ref() {
+                        counted.fetch_add(data.len(), Ordering::Relaxed);
+                    }
+
+                    frame
+                })
+                .boxed_unsync()
+            }
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[STRICT_TRANSPORT_SECURITY], "max-age=63072000");
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+    assert_eq!(counted.load(Ordering::Relaxed), "Hello, World!".len());
+
+    // Responses the host makes up itself pass through the hooks too.
+    let res = runner.get("/trap").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(res.headers().contains_key(STRICT_TRANSPORT_SECURITY));
+}
+
+/// Requests the version loaded under `slot` has handled.
+fn handled_by(runner: &Runner, slot: &str) -> u64 {
+    runner
+        .versions()
+        .iter()
+        .find(|version| version.slot == slot)
+        .unwrap()
+        .metrics()
+        .requests
+        .get()
+}
+
+#[tokio::test]
+async fn activation_leaves_requests_in_flight_on_their_version() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = Arc::new(TestRunner::new(FIXTURE).unwrap());
+    runner.runner().load(FIXTURE, "next").unwrap();
+
+    let in_flight = tokio::spawn({
+        let runner = runner.clone();
+        async move { runner.get("/sleep/1000").await.unwrap() }
+    });
+
+    while handled_by(runner.runner(), "default") == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    runner.runner().activate("next").unwrap();
+    assert_eq!(runner.runner().active_slot(), "next");
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!in_flight.is_finished());
+
+    let res = in_flight.await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    assert_eq!(handled_by(runner.runner(), "default"), 1);
+    assert_eq!(handled_by(runner.runner(), "next"), 1);
+}
+
+#[tokio::test]
+async fn activation_is_refused_when_warmup_fails() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        warmup: vec!["/trap".to_owned()],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    runner.runner().load(FIXTURE, "broken").unwrap();
+
+    assert!(runner.runner().activate("broken").is_err());
+    assert_eq!(runner.runner().active_slot(), "default");
+    assert!(runner.runner().activate("missing").is_err());
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(handled_by(runner.runner(), "default"), 1);
+}
+
+#[tokio::test]
+async fn rolls_back_to_the_previous_version() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let versions = runner.runner();
+
+    // Nothing was activated yet, so there is nothing to go back to.
+    assert!(versions.rollback().is_err());
+
+    versions.load(FIXTURE, "next").unwrap();
+    versions.activate("next").unwrap();
+
+    versions.rollback().unwrap();
+    assert_eq!(versions.active_slot(), "default");
+    runner.get("/").await.unwrap();
+
+    // Rolling back again undoes the rollback.
+    versions.rollback().unwrap();
+    assert_eq!(versions.active_slot(), "next");
+    runner.get("/").await.unwrap();
+
+    assert_eq!(handled_by(versions, "default"), 1);
+    assert_eq!(handled_by(versions, "next"), 1);
+}
+
+#[tokio::test]
+async fn hooks_strip_connection_headers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .on_request(|req| {
+            req.headers_mut().remove(CONNECTION);
+            ControlFlow::Continue(())
+        })
+        .on_response(|parts, _| {
+            parts.headers.remove(CONNECTION);
+            parts
+                .headers
+                .insert("x-served-by", HeaderValue::from_static("runner"));
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+
+    let res = runner
+        .send(Method::GET, "/header/connection", headers, Bytes::new())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "");
+
+    // The guest asks for `connection: close`, which the hook takes out again.
+    let res = runner.get("/close").await.unwrap();
+    assert!(!res.headers().contains_key(CONNECTION));
+    assert_eq!(res.headers()["x-served-by"], "runner");
+}
+
+#[tokio::test]
+async fn falls_back_when_the_primary_fails() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let Some(server) = Server::with_args(&["--fallback-component", FIXTURE]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/trap", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.into_body().to_bytes(), "We're having trouble (trap)");
+
+    // Requests the primary handles never reach the fallback.
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+}
+
+#[tokio::test]
+async fn refuses_bodies_that_miss_their_length() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    for path in ["/length/100/50", "/length/10/50"] {
+        let res = send(&server, Method::GET, path, Bytes::new()).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR, "{path}");
+    }
+
+    let res = send(&server, Method::GET, "/length/50/50", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes().len(), 50);
+}
+
+#[tokio::test]
+async fn corrects_bodies_that_miss_their_length() {
+    let Some(server) = Server::with_args(&["--content-length-mismatch", "correct"]) else {
+        return;
+    };
+
+    for path in ["/length/100/50", "/length/10/50"] {
+        let res = send(&server, Method::GET, path, Bytes::new()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{path}");
+        assert_eq!(res.headers()["content-length"], "50", "{path}");
+        assert_eq!(res.into_body().to_bytes().len(), 50, "{path}");
+    }
+}
+
+/// Asks `server` to send `file` under a `Content-Length` of `declared`, returning the body or the
+/// error reading it failed with.
+async fn send_declaring(server: &Server, file: &Path, declared: usize) -> hyper::Result<Bytes> {
+    let req = Request::get(server.uri("/send-file"))
+        .header("x-file", file.to_str().unwrap())
+        .header("x-declared-length", declared)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let res = client().request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-length"], declared.to_string());
+
+    Ok(res.into_body().collect().await?.to_bytes())
+}
+
+#[tokio::test]
+async fn cuts_off_streamed_bodies_that_miss_their_length() {
+    let dir = std::env::temp_dir().join(format!("streamed-length-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("ten.txt");
+    std::fs::write(&file, "0123456789").unwrap();
+    let file_dir = dir.to_str().unwrap();
+
+    // The head is gone by the time the file streams, so a body missing its length can only be
+    // cut off, which aborts the connection.
+    let Some(server) = Server::with_args(&["--file-dir", file_dir]) else {
+        return;
+    };
+    assert_eq!(
+        send_declaring(&server, &file, 10).await.unwrap(),
+        "0123456789"
+    );
+    assert!(send_declaring(&server, &file, 20).await.is_err());
+    assert!(send_declaring(&server, &file, 5).await.is_err());
+
+    // Correcting it sends the declared length of an over-write, and still can't make up for an
+    // under-write.
+    let Some(server) = Server::with_args(&[
+        "--file-dir",
+        file_dir,
+        "--content-length-mismatch",
+        "correct",
+    ]) else {
+        return;
+    };
+    assert_eq!(send_declaring(&server, &file, 5).await.unwrap(), "01234");
+    assert!(send_declaring(&server, &file, 20).await.is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn streams_responses_while_the_guest_writes_them() {
+    use futures::SinkExt;
+
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let (mut chunks, frames) = futures::channel::mpsc::channel(1);
+    let req = Request::post(server.uri("/relay"))
+        .body(StreamBody::new(frames))
+        .unwrap();
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let res = tokio::time::timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("the head only came once the guest returned")
+        .unwrap();
+    let mut body = res.into_body();
+
+    // The guest is still reading the request, so each chunk only comes back if it is sent as
+    // the guest writes it.
+    for chunk in ["first", "second"] {
+        let frame = Frame::data(Bytes::from(chunk));
+        chunks
+            .send(Ok::<_, std::convert::Infallible>(frame))
+            .await
+            .unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(10), body.frame())
+            .await
+            .expect("the chunk only came once the guest returned")
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.into_data().unwrap(), chunk);
+    }
+
+    drop(chunks);
+    let rest = tokio::time::timeout(Duration::from_secs(10), body.collect())
+        .await
+        .expect("the response never ended")
+        .unwrap();
+    assert!(rest.to_bytes().is_empty());
+}
+
+#[tokio::test]
+async fn unfinished_bodies_become_server_errors() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // No more of the body can come once the guest returns, so it isn't sent at all.
+    let res = send(&server, Method::GET, "/unfinished", Bytes::new());
+    let res = tokio::time::timeout(Duration::from_secs(10), res)
+        .await
+        .expect("the response never ended");
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_ne!(res.into_body().to_bytes(), "partial");
+}
+
+#[tokio::test]
+async fn denied_methods_get_405() {
+    let Some(server) = Server::with_args(&["--deny-method", "TRACE"]) else {
+        return;
+    };
+
+    let res = send(&server, Method::TRACE, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(
+        res.headers()["allow"],
+        "GET, HEAD, POST, PUT, DELETE, CONNECT, OPTIONS, PATCH"
+    );
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn cors() -> Cors {
+    Cors {
+        origins: vec![
+            "https://app.example.com".parse().unwrap(),
+            "https://*.example.org".parse().unwrap(),
+        ],
+        methods: vec![Method::GET, Method::PUT],
+        headers: vec!["content-type".parse().unwrap()],
+        credentials: true,
+        max_age: Some(Duration::from_secs(600)),
+        strict: false,
+    }
+}
+
+fn cors_headers(origin: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ORIGIN, origin.parse().unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn answers_cors_preflights() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        cors: Some(cors()),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    // (origin, requested method, requested headers, allowed). The rules themselves are tested
+    // in the cors module.
+    let matrix = [
+        ("https://app.example.com", "PUT", "content-type", true),
+        ("https://api.example.org", "GET", "", true),
+        ("https://evil.com", "GET", "", false),
+        ("https://app.example.com", "DELETE", "", false),
+    ];
+
+    for (origin, method, request_headers, allowed) in matrix {
+        let mut headers = cors_headers(origin);
+        headers.insert(ACCESS_CONTROL_REQUEST_METHOD, method.parse().unwrap());
+
+        if !request_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_REQUEST_HEADERS,
+                request_headers.parse().unwrap(),
+            );
+        }
+
+        let res = runner
+            .send(Method::OPTIONS, "/", headers, Bytes::new())
+            .await
+            .unwrap();
+        let case = format!("{origin} {method} {request_headers}");
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT, "{case}");
+        assert_eq!(res.headers()[VARY], "Origin", "{case}");
+
+        if allowed {
+            assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], origin, "{case}");
+            assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+            assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+            assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+            assert_eq!(res.headers()[ACCESS_CONTROL_MAX_AGE], "600");
+        } else {
+            assert!(
+                !res.headers().contains_key(ACCESS_CONTROL_ALLOW_METHODS),
+                "{case}"
+            );
+        }
+    }
+
+    // Preflights never reach the guest.
+    assert_eq!(runner.runner().metrics().instantiation_time.count(), 0);
+
+    let res = runner
+        .send(
+            Method::GET,
+            "/",
+            cors_headers("https://app.example.com"),
+            Bytes::new(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://app.example.com"
+    );
+    assert_eq!(res.headers()[VARY], "Origin");
+
+    // Other origins get the response as is, for the browser to withhold.
+    let res = runner
+        .send(
+            Method::GET,
+            "/",
+            cors_headers("https://evil.com"),
+            Bytes::new(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!(res.headers()[VARY], "Origin");
+}
+
+#[tokio::test]
+async fn strict_cors_rejects_other_origins() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        cors: Some(Cors {
+            strict: true,
+            ..cors()
+        }),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner
+        .send(
+            Method::GET,
+            "/",
+            cors_headers("https://evil.com"),
+            Bytes::new(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let mut headers = cors_headers("https://evil.com");
+    headers.insert(ACCESS_CONTROL_REQUEST_METHOD, "GET".parse().unwrap());
+    let res = runner
+        .send(Method::OPTIONS, "/", headers, Bytes::new())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Requests without an origin aren't cross-origin.
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rate_limits_each_client_ip() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        rate_limit: Some("1/2".parse().unwrap()),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let get = |client: &str| {
+        let mut req = Request::new(Full::new(Bytes::new()));
+        *req.uri_mut() = "/".parse().unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr(client.parse().unwrap()));
+
+        runner.request(req)
+    };
+
+    // The burst goes through at once.
+    for _ in 0..2 {
+        let res = get("192.0.2.7:4000").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let res = get("192.0.2.7:4001").await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()[RETRY_AFTER], "1");
+    assert_eq!(runner.runner().metrics().rate_limited.get(), 1);
+
+    // Other clients have their own buckets, and requests with no client aren't limited.
+    let res = get("192.0.2.8:4000").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // A token comes back after a second.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let res = get("192.0.2.7:4000").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(runner.runner().metrics().rate_limited.get(), 1);
+}
+
+#[tokio::test]
+async fn rewrites_headers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let header = |name: &str| name.parse().unwrap();
+    let template = |value: &str| value.parse().unwrap();
+
+    let request = HeaderEdits::default()
+        .remove(header("x-internal-secret"))
+        .and_then(|edits| edits.rename(header("x-old"), header("x-new")))
+        .and_then(|edits| edits.set(header("x-api-version"), template("2")))
+        .and_then(|edits| edits.set(header("x-client"), template("ip=${remote_addr}")))
+        .and_then(|edits| edits.set(header("x-id"), template("${request_id}")))
+        .unwrap();
+    let response = HeaderEdits::default()
+        .remove(header("date"))
+        .and_then(|edits| edits.rename(header("content-type"), header("x-original-type")))
+        .and_then(|edits| edits.set(header("x-served-by"), template("runner")))
+        .unwrap();
+
+    let options = Options {
+        headers: HeaderRules { request, response },
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let get = |path: &str| {
+        let mut req = Request::new(Full::new(Bytes::new()));
+        *req.uri_mut() = path.parse().unwrap();
+
+        let headers = req.headers_mut();
+        headers.insert("x-internal-secret", HeaderValue::from_static("hunter2"));
+        headers.insert("x-old", HeaderValue::from_static("old"));
+        headers.insert("x-api-version", HeaderValue::from_static("1"));
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+        );
+        req.extensions_mut()
+            .insert(ClientAddr("192.0.2.7:4000".parse().unwrap()));
+
+        runner.request(req)
+    };
+
+    // The fixture answers with an empty body for headers it didn't get.
+    for (name, expected) in [
+        ("x-internal-secret", ""),
+        ("x-old", ""),
+        ("x-new", "old"),
+        ("x-api-version", "2"),
+        ("x-client", "ip=192.0.2.7"),
+        ("x-id", "0af7651916cd43dd8448eb211c80319c"),
+    ] {
+        let res = get(&format!("/header/{name}")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK, "{name}");
+        assert_eq!(res.into_body().to_bytes(), expected, "{name}");
+    }
+
+    let res = get("/").await.unwrap();
+    assert!(!res.headers().contains_key("date"));
+    assert!(!res.headers().contains_key("content-type"));
+    assert_eq!(
+        res.headers()["x-original-type"],
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(res.headers()["x-served-by"], "runner");
+}
+
+#[tokio::test]
+async fn one_h2_connection_cannot_take_every_guest_thread() {
+    let Some(server) = Server::with_args(&[
+        "--guest-threads",
+        "4",
+        "--max-concurrency",
+        "4",
+        "--max-connection-concurrency",
+        "2",
+    ]) else {
+        return;
+    };
+
+    let h2 = || -> Client<HttpConnector, Full<Bytes>> {
+        Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http()
+    };
+    let get = |client: Client<HttpConnector, Full<Bytes>>, path: &str| {
+        let uri = format!("http://{}{path}", server.addr);
+
+        async move {
+            let started_at = Instant::now();
+            let res = client.get(uri.parse().unwrap()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            started_at.elapsed()
+        }
+    };
+
+    // One greedy connection queues far more work than the guest threads can take at once.
+    let greedy = h2();
+    let flood = tokio::spawn(futures::future::join_all(
+        (0..16)
+            .map(|_| get(greedy.clone(), "/sleep/500"))
+            .collect::<Vec<_>>(),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Without the per-connection limit this would wait behind the flood for about two seconds.
+    let polite = get(h2(), "/sleep/500").await;
+    assert!(polite < Duration::from_millis(1500), "took {polite:?}");
+
+    // The greedy connection still gets all of its requests answered, two at a time.
+    let flood = flood.await.unwrap();
+    assert_eq!(flood.len(), 16);
+    assert!(flood.iter().max().unwrap() >= &Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn manual_clock_fires_pollables_when_advanced() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let clock = Arc::new(ManualClock::default());
+    let options = Options {
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let runner = Arc::new(TestRunner::with_options(FIXTURE, options).unwrap());
+
+    let waiting = tokio::spawn({
+        let runner = runner.clone();
+        async move { runner.get("/wait/1000").await.unwrap() }
+    });
+
+    while clock.sleepers() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // However long the test takes in real time, the guest only wakes once the clock moves far
+    // enough.
+    clock.advance(Duration::from_millis(999));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!waiting.is_finished());
+    assert_eq!(clock.sleepers(), 1);
+
+    clock.advance(Duration::from_millis(1));
+    let res = tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .expect("the pollable fired")
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "waited");
+}
+
+fn path_filter(allow: &[&str], deny: &[&str]) -> PathFilter {
+    let globs = |globs: &[&str]| globs.iter().map(|glob| glob.parse().unwrap()).collect();
+
+    PathFilter {
+        allow: globs(allow),
+        deny: globs(deny),
+    }
+}
+
+#[tokio::test]
+async fn filters_methods_and_paths_before_the_guest() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        methods: MethodPolicy::Allow(vec![Method::GET, Method::HEAD]),
+        paths: path_filter(&["/", "/header/**"], &["/header/x-secret*"]),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    for path in ["/trap", "/header/x-secret-key", "/header/%78-secret"] {
+        let res = runner.get(path).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+
+    // The method is checked first, so a denied method on a denied path still gets its `Allow`.
+    for (method, path) in [(Method::POST, "/"), (Method::DELETE, "/trap")] {
+        let res = runner
+            .send(method.clone(), path, HeaderMap::new(), Bytes::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            res.status(),
+            StatusCode::METHOD_NOT_ALLOWED,
+            "{method} {path}"
+        );
+        assert_eq!(res.headers()["allow"], "GET, HEAD");
+    }
+
+    assert_eq!(runner.runner().metrics().instantiation_time.count(), 0);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = runner.get("/header/x-visible").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[cfg(all(feature = "cli", feature = "clocks", feature = "filesystem"))]
+#[tokio::test]
+async fn serves_guests_built_on_the_standard_library() {
+    let Some(server) = Server::serving(STD_FIXTURE, "127.0.0.1:0", &["--log-format", "json"])
+    else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap();
+    let report: std::collections::HashMap<_, _> = body
+        .lines()
+        .map(|line| line.split_once('=').unwrap())
+        .collect();
+
+    assert_eq!(report["env"], "0");
+    assert_eq!(report["preopens"], "none");
+    assert_eq!(report["map"], "value");
+    assert_eq!(report["elapsed"], "true");
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let unix: u64 = report["unix"].parse().unwrap();
+    assert!(now.abs_diff(unix) < 60, "{unix} is not close to {now}");
+
+    // Each line printed becomes a log event.
+    let line = json_log(&server, "handling /").await;
+    assert_eq!(line["context"], "stdout");
+    assert_eq!(line["level"], "INFO");
+
+    let line = json_log(&server, "to stderr").await;
+    assert_eq!(line["context"], "stderr");
+    assert_eq!(line["level"], "WARN");
+
+    // Request bodies reach the response through blocking-splice, across several chunks.
+    let body = "spliced ".repeat(2048);
+    let res = send(&server, Method::POST, "/echo", body.clone()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), body);
+}
+
+#[cfg(feature = "host-log")]
+#[tokio::test]
+async fn middleware_wraps_the_component() {
+    if !built(MIDDLEWARE) {
+        return;
+    }
+
+    let Some(server) = Server::with_args(&["--compose", MIDDLEWARE, "--log-format", "json"]) else {
+        return;
+    };
+
+    // The request and its body pass through the middleware's instance to the echo route.
+    let res = send(&server, Method::POST, "/echo", "through the middle").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "through the middle");
+
+    let line = json_log(&server, "passed through middleware").await;
+    assert_eq!(line["target"], "guest");
+    assert_eq!(line["url.path"], "/echo");
+    assert_eq!(line["fields"], "path=\"/echo\"");
+}
+
+#[cfg(feature = "host-log")]
+#[tokio::test]
+async fn middleware_chains_pass_requests_through_every_layer() {
+    if !built(MIDDLEWARE) {
+        return;
+    }
+
+    let Some(server) = Server::with_args(&[
+        "--compose",
+        MIDDLEWARE,
+        "--compose",
+        MIDDLEWARE,
+        "--log-format",
+        "json",
+    ]) else {
+        return;
+    };
+
+    let res = send(&server, Method::POST, "/echo", "through both").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "through both");
+
+    // Each layer is its own instance, and logs the request once.
+    json_log(&server, "passed through middleware").await;
+    let started_at = Instant::now();
+
+    loop {
+        let layers = server
+            .output
+            .lock()
+            .unwrap()
+            .matches("passed through middleware")
+            .count();
+
+        if layers == 2 {
+            break;
+        }
+
+        assert!(layers < 2, "logged by {layers} layers");
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "logged by {layers} layers"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(feature = "host-log")]
+#[tokio::test]
+async fn middleware_chains_run_in_process() {
+    if !built(FIXTURE) || !built(MIDDLEWARE) {
+        return;
+    }
+
+    let options = Options {
+        middleware: vec![MIDDLEWARE.into(), MIDDLEWARE.into()],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner.post("/echo", "through both").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "through both");
+}
+
+#[tokio::test]
+async fn read_loops_end_at_trailers() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    // Trailers only make it to the runner over HTTP/2.
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-checksum", "abc".parse().unwrap());
+
+    // Each frame is sent on its own, so the guest's pollable becomes ready for the trailers
+    // frame by itself.
+    let frames = futures::stream::iter([
+        Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from("ping"))),
+        Ok(Frame::trailers(trailers)),
+    ])
+    .then(|frame| async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        frame
+    });
+
+    let req = Request::post(server.uri("/read-loop"))
+        .body(StreamBody::new(Box::pin(frames)))
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("the guest's read loop never ended")
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "4 bytes, 0 empty reads, trailers: x-checksum");
+}
+
+#[tokio::test]
+async fn retries_traps_with_a_fresh_instance() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        max_retries: 1,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let metrics = runner.runner().metrics();
+
+    let res = runner.get("/flaky/unread").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "recovered");
+    assert_eq!(metrics.retries.get(), 1);
+
+    // The body went into the instance that trapped, so there is nothing left to retry with.
+    let res = runner
+        .send(
+            Method::GET,
+            "/flaky-read/read",
+            HeaderMap::new(),
+            "consumed",
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(metrics.retries.get(), 1);
+}
+
+#[tokio::test]
+async fn traps_are_not_retried_by_default() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+
+    let res = runner.get("/flaky/once").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(runner.runner().metrics().retries.get(), 0);
+
+    // The trap was the only one, as the next request finds.
+    let res = runner.get("/flaky/once").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_and_recovers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let cooldown = Duration::from_millis(500);
+    let options = Options {
+        failure_threshold: 2,
+        circuit_cooldown: cooldown,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let metrics = runner.runner().metrics();
+
+    // The fixture's backend is down until the entry expires.
+    let outage = Duration::from_millis(1500);
+    let down_at = Instant::now();
+    let res = runner
+        .send(
+            Method::PUT,
+            &format!("/cache/backend?ttl={}", outage.as_millis()),
+            HeaderMap::new(),
+            "down",
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    for _ in 0..2 {
+        let res = runner.get("/outage/backend").await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    assert_eq!(metrics.circuit_state.get(), 1);
+
+    // Open, so not even a request the guest would answer reaches it. The body tells this apart
+    // from a shed request's.
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(RETRY_AFTER));
+    assert_eq!(
+        res.into_body().to_bytes(),
+        "Service Unavailable: circuit open"
+    );
+
+    // Half-open after the cooldown: the probe fails while the backend is still down, which opens
+    // the circuit again.
+    tokio::time::sleep(cooldown).await;
+    let res = runner.get("/outage/backend").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(metrics.circuit_opened.get(), 2);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Once the backend is back, the next probe closes the circuit.
+    tokio::time::sleep_until((down_at + outage + cooldown).into()).await;
+    let res = runner.get("/outage/backend").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "up");
+    assert_eq!(metrics.circuit_state.get(), 0);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn fallback_sheds_by_its_own_limits_while_the_circuit_is_open() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let fallback = Options {
+        max_concurrency: 1,
+        queue_depth: 0,
+        ..Options::fallback()
+    };
+    let options = Options {
+        failure_threshold: 1,
+        circuit_cooldown: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let runner = Runner::new(FIXTURE, options)
+        .unwrap()
+        .with_fallback(Runner::new(FIXTURE, fallback).unwrap());
+    let runner = Arc::new(runner);
+    let get = |path: &str, render_ms: u64| {
+        Request::get(path)
+            .header("x-render-ms", render_ms)
+            .body(Full::new(Bytes::new()))
+            .unwrap()
+    };
+
+    // The trap opens the circuit.
+    let res = runner.clone().invoke(get("/trap", 0)).await.unwrap();
+    assert_eq!(res.response.into_body(), "We're having trouble (trap)");
+
+    // Open, so the fallback answers in place of the primary, one request at a time.
+    let slow = tokio::spawn(runner.clone().invoke(get("/", 500)));
+    let fallback = runner.fallback().unwrap().metrics();
+    let started_at = Instant::now();
+
+    while fallback.guest_threads_busy.get() < 1 {
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "the request never reached the fallback"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // With no room to queue, the next is shed rather than run on the primary's threads.
+    let res = runner.clone().invoke(get("/", 0)).await.unwrap().response;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.into_body(), "Service Unavailable");
+    assert_eq!(fallback.shed.get(), 1);
+
+    let res = slow.await.unwrap().unwrap().response;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.into_body(), "We're having trouble (circuit-open)");
+}
+
+#[tokio::test]
+async fn read_loops_progress_through_h2_stalls() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+
+    // Four times the default flow-control window, with pauses, so the guest's reads outrun the
+    // client and have to wait on the pollable rather than spin on empty reads.
+    let frames = futures::stream::iter(0..16).then(|i| async move {
+        if i % 4 == 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from(vec![b'a'; 16 * 1024])))
+    });
+
+    let req = Request::post(server.uri("/read-loop"))
+        .body(StreamBody::new(Box::pin(frames)))
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("the guest's read loop stalled")
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "262144 bytes, 0 empty reads, trailers: ");
+}
+
+#[tokio::test]
+async fn tee_reads_the_body_twice() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let body: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+    let res = send(&server, Method::POST, "/tee", body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "262144 262144 true");
+}
+
+#[tokio::test]
+async fn serves_admin_endpoints_apart_from_guests() {
+    let admin = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let Some(server) = Server::with_args(&["--admin-addr", &admin.to_string()]) else {
+        return;
+    };
+
+    let admin_request = |method: Method, path: &str| {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("http://{admin}{path}"))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+
+        async move {
+            let res = client().request(req).await.unwrap();
+            let status = res.status();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The public listener only ever reaches the guest.
+    let res = send(&server, Method::GET, "/metrics", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let (status, body) = admin_request(Method::GET, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        body.contains("wasi_http_requests_total{mount=\"/\"} 2"),
+        "{body}"
+    );
+    assert!(body.contains("# TYPE wasi_http_execution_time_microseconds histogram"));
+
+    let (status, body) = admin_request(Method::GET, "/healthz").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "ok"));
+
+    let (status, _) = admin_request(Method::GET, "/reload").await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+
+    let (status, _) = admin_request(Method::GET, "/").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = admin_request(Method::POST, "/reload").await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+}
+
+/// A directory of recordings, one per `(method, path)`, in that order.
+fn warmup_recordings(name: &str, requests: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (i, (method, path)) in requests.iter().enumerate() {
+        std::fs::write(
+            dir.join(format!("{i:03}.http")),
+            format!("# body-length: 0\n{method} {path} HTTP/1.1\nhost: localhost\n\n"),
+        )
+        .unwrap();
+    }
+
+    dir
+}
+
+#[tokio::test]
+async fn sends_warmup_requests_before_serving() {
+    let dir = warmup_recordings("warmup", &[("GET", "/"), ("GET", "/trap")]);
+    let Some(server) = Server::with_args(&[
+        "--log-format",
+        "json",
+        "--warmup-requests",
+        dir.to_str().unwrap(),
+    ]) else {
+        return;
+    };
+
+    let line = json_log(&server, "warmup request").await;
+    assert_eq!(line["uri"], "/");
+    assert_eq!(line["status"], 200);
+    assert!(line["elapsed_ms"].is_u64(), "{line:?}");
+
+    // Without --warmup-strict a failure is only logged.
+    let line = json_log(&server, "warmup request failed").await;
+    assert_eq!(line["uri"], "/trap");
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[test]
+fn validates_components_without_serving_them() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let validate = |component: &Path, json: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"));
+        command.arg("validate").arg(component);
+
+        if json {
+            command.arg("--json");
+        }
+
+        command.output().unwrap()
+    };
+
+    let output = validate(Path::new(FIXTURE), true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("\"handler\":true,\"servable\":true"),
+        "{stdout}"
+    );
+
+    let output = validate(Path::new(FIXTURE), false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("ok       wasi:http/types@"), "{stdout}");
+    assert!(
+        stdout.ends_with("the component can be served\n"),
+        "{stdout}"
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let component = |name: &str, wat: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        path
+    };
+
+    // A command exports wasi:cli/run rather than a handler.
+    let command = component(
+        "command.wasm",
+        "(component (instance $run) \
+         (export \"wasi:cli/run@0.2.0-rc-2023-11-10\" (instance $run)))",
+    );
+    let output = validate(&command, false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("does not export wasi:http/incoming-handler"),
+        "{stdout}"
+    );
+
+    let unimplemented = component(
+        "unimplemented.wasm",
+        "(component (import \"example:unknown/thing@1.0.0\" (instance)) (instance $h) \
+         (export \"wasi:http/incoming-handler@0.2.0-rc-2023-11-10\" (instance $h)))",
+    );
+    let output = validate(&unimplemented, true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("{\"name\":\"example:unknown/thing@1.0.0\",\"provided\":false}"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("\"handler\":true,\"servable\":false"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn strict_warmup_failures_abort_startup() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let dir = warmup_recordings("warmup-strict", &[("GET", "/trap")]);
+    let output = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+        .arg("--component")
+        .arg(FIXTURE)
+        .arg("--addr")
+        .arg("127.0.0.1:0")
+        .arg("--warmup-requests")
+        .arg(&dir)
+        .arg("--warmup-strict")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warmup request"), "{stderr}");
+}
+
+#[tokio::test]
+async fn from_list_set_and_append_refuse_the_same_fields() {
+    let Some(server) = Server::start() else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/fields", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap();
+    let results: Vec<_> = body
+        .lines()
+        .map(|line| {
+            let (name, results) = line.split_once(": ").unwrap();
+            let results: Vec<_> = results.split(' ').collect();
+
+            // The three ways of adding a field agree on every one.
+            assert!(results.iter().all(|result| *result == results[0]), "{line}");
+
+            (name, results[0].to_owned())
+        })
+        .collect();
+
+    assert_eq!(results.len(), 5, "{body}");
+    assert!(results[0].1.contains("Forbidden"), "{body}");
+    assert!(results[1].1.contains("Forbidden"), "{body}");
+    assert!(results[2].1.contains("InvalidSyntax"), "{body}");
+    assert!(results[3].1.contains("InvalidSyntax"), "{body}");
+    assert_eq!(results[4], ("x-fine", "Ok(())".to_owned()));
+}
+
+/// An upstream answering each request with `<length> <fnv-1a hash> <x-checksum trailer>` of its
+/// body, computed as the body arrives.
+async fn checksum_upstream() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let service = hyper::service::service_fn(|req: Request<hyper::body::Incoming>| async {
+                let mut body = req.into_body();
+                let (mut len, mut hash) = (0, 0xcbf2_9ce4_8422_2325_u64);
+                let mut trailer = String::new();
+
+                while let Some(frame) = body.frame().await {
+                    match frame?.into_data() {
+                        Ok(data) => {
+                            len += data.len();
+
+                            for byte in data {
+                                hash = (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+                            }
+                        }
+                        Err(frame) => {
+                            if let Some(value) = frame
+                                .trailers_ref()
+                                .and_then(|trailers| trailers.get("x-checksum"))
+                            {
+                                trailer = value.to_str().unwrap().to_owned();
+                            }
+                        }
+                    }
+                }
+
+                let answer = format!("{len} {hash:016x} {trailer}");
+                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(answer))))
+            });
+
+            tokio::spawn(
+                hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service),
+            );
+        }
+    });
+
+    addr
+}
+
+/// The resident set size of a process, in bytes.
+fn rss(pid: u32) -> u64 {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap();
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .unwrap()
+        .trim()
+        .parse::<u64>()
+        .unwrap();
+
+    kib * 1024
+}
+
+#[tokio::test]
+#[cfg(target_os = "linux")]
+async fn streams_outbound_request_bodies() {
+    let upstream = checksum_upstream().await;
+    let Some(server) = Server::with_args(&["--http-allow", &upstream.to_string()]) else {
+        return;
+    };
+
+    let upload = |bytes: usize| {
+        let req = Request::get(server.uri(&format!("/upload/{upstream}/{bytes}")))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+
+        async move {
+            let res = tokio::time::timeout(Duration::from_secs(120), client().request(req))
+                .await
+                .expect("the upload timed out")
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+
+    // A small upload first, so the baseline includes the compiled component and the client.
+    let answer = upload(1 << 20).await;
+    assert!(answer.starts_with("1048576 "), "{answer}");
+    let before = rss(server.child.id());
+
+    let answer = upload(50 << 20).await;
+    let [len, hash, trailer] = answer.split(' ').collect::<Vec<_>>()[..] else {
+        panic!("unexpected answer {answer:?}");
+    };
+
+    assert_eq!(len, (50 << 20).to_string());
+    // The trailer the guest sent matches what arrived.
+    assert_eq!(hash, trailer);
+
+    let grown = rss(server.child.id()).saturating_sub(before);
+    assert!(grown < 16 << 20, "the runner grew by {grown} bytes");
+}
+
+#[tokio::test]
+#[cfg(target_os = "linux")]
+async fn spools_large_uploads_without_holding_them_in_memory() {
+    let Some(server) = Server::with_args(&["--spool-threshold", "1048576"]) else {
+        return;
+    };
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let upload = |bytes: usize| {
+        let chunks = futures::stream::iter((0..bytes).step_by(64 * 1024).map(move |start| {
+            let len = (bytes - start).min(64 * 1024);
+            Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from(vec![b'x'; len])))
+        }));
+        let req = Request::post(server.uri("/read-loop"))
+            .body(StreamBody::new(chunks))
+            .unwrap();
+        let res = client.request(req);
+
+        async move {
+            let res = tokio::time::timeout(Duration::from_secs(120), res)
+                .await
+                .expect("the upload timed out")
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+
+    // A small upload first, so the baseline includes the compiled component and the client.
+    let answer = upload(2 << 20).await;
+    assert!(answer.starts_with("2097152 bytes"), "{answer}");
+    let before = rss(server.child.id());
+
+    let answer = upload(200 << 20).await;
+    assert!(answer.starts_with("209715200 bytes"), "{answer}");
+
+    let grown = rss(server.child.id()).saturating_sub(before);
+    assert!(grown < 32 << 20, "the runner grew by {grown} bytes");
+}
+
+#[tokio::test]
+async fn sends_host_files_without_guest_buffering() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("send-file-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("served")).unwrap();
+
+    let large = dir.join("served/large.bin");
+    let contents: Vec<u8> = (0..64 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(&large, &contents).unwrap();
+
+    let outside = dir.join("secret.txt");
+    std::fs::write(&outside, "secret").unwrap();
+
+    let options = Options {
+        file_dirs: vec![dir.join("served")],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let metrics = runner.runner().metrics();
+
+    let send_file = |path: &Path| {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-file",
+            HeaderValue::from_str(path.to_str().unwrap()).unwrap(),
+        );
+        runner.send(Method::GET, "/send-file", headers, Bytes::new())
+    };
+
+    let res = send_file(&large).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-length"], (64 << 20).to_string());
+    assert!(res.into_body().to_bytes() == contents);
+
+    // The file never entered the guest, so its memory stayed far below the file's size.
+    assert!(
+        metrics.peak_memory_bytes.sum() < 16 << 20,
+        "peaked at {} bytes",
+        metrics.peak_memory_bytes.sum()
+    );
+
+    for path in [
+        outside,
+        dir.join("served/../secret.txt"),
+        dir.join("served"),
+    ] {
+        let res = send_file(&path).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", path.display());
+        assert!(!res.into_body().to_bytes().starts_with(b"secret"));
+    }
+}
+
+#[tokio::test]
+async fn outbound_request_trailers_reach_the_upstream() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let upstream = checksum_upstream().await;
+    let options = Options {
+        http_egress: vec![upstream.to_string()],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    // Over HTTP/1.1, trailers can only follow a chunked body.
+    for bytes in [0, 10, 10_000] {
+        let res = runner
+            .get(&format!("/upload/{upstream}/{bytes}"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let answer = String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap();
+        let [len, hash, trailer] = answer.split(' ').collect::<Vec<_>>()[..] else {
+            panic!("unexpected answer {answer:?}");
+        };
+
+        assert_eq!(len, bytes.to_string());
+        assert_eq!(hash, trailer);
+    }
+}
+
+#[tokio::test]
+async fn compressed_outbound_bodies_reach_the_upstream_as_written() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    // `compressed by the guest\n`, gzipped.
+    const GZIPPED: [u8; 44] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x4b, 0xce, 0xcf, 0x2d, 0x28,
+        0x4a, 0x2d, 0x2e, 0x4e, 0x4d, 0x51, 0x48, 0xaa, 0x54, 0x28, 0xc9, 0x48, 0x55, 0x48, 0x2f,
+        0x4d, 0x2d, 0x2e, 0xe1, 0x02, 0x00, 0xf3, 0x43, 0xad, 0x03, 0x18, 0x00, 0x00, 0x00,
+    ];
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+
+    // Answers with the codings the request arrived under and whether its body is untouched.
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let service = hyper::service::service_fn(|req: Request<hyper::body::Incoming>| async {
+                let header = |name: &str| {
+                    req.headers()
+                        .get(name)
+                        .map_or("-".to_owned(), |value| value.to_str().unwrap().to_owned())
+                };
+                let (encoding, transfer) =
+                    (header("content-encoding"), header("transfer-encoding"));
+                let body = req.into_body().collect().await?.to_bytes();
+
+                let answer = format!("{encoding} {transfer} {}", body[..] == GZIPPED);
+                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(answer))))
+            });
+
+            tokio::spawn(
+                hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service),
+            );
+        }
+    });
+
+    let options = Options {
+        http_egress: vec![upstream.to_string()],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner
+        .post(
+            &format!("/compressed/{upstream}"),
+            Bytes::from_static(&GZIPPED),
+        )
+        .await
+        .unwrap();
+
+    // The guest's coding stays, and the client frames the body itself rather than claiming a
+    // gzip transfer coding it never applied.
+    let answer = res.into_body().to_bytes();
+    assert!(
+        matches!(&answer[..], b"gzip chunked true" | b"gzip - true"),
+        "{answer:?}"
+    );
+}
+
+#[tokio::test]
+async fn answers_outbound_requests_from_a_mock() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .with_outbound_mock({
+            let seen = seen.clone();
+
+            move |req| {
+                seen.lock().unwrap().push(req.uri().to_string());
+
+                if req.uri().host() != Some("mocked.test") {
+                    return None;
+                }
+
+                let status = if req.method() == Method::GET {
+                    StatusCode::IM_A_TEAPOT
+                } else {
+                    StatusCode::OK
+                };
+                let answer = format!("{} {} {}", req.method(), req.uri().path(), req.body().len());
+
+                let mut res = Response::new(Bytes::from(answer));
+                *res.status_mut() = status;
+                Some(res)
+            }
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    // The guest sees the canned status, and the canned body for a request with one.
+    let res = runner.get("/get/mocked.test").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "418");
+
+    let res = runner.get("/upload/mocked.test/10").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "POST / 10");
+
+    // Requests the mock passes on meet the egress rules, which allow nothing here.
+    let res = runner.get("/get/elsewhere.test").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "ErrorCode::HttpRequestDenied");
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "http://mocked.test/",
+            "http://mocked.test/",
+            "http://elsewhere.test/"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resolves_outbound_hosts_through_overrides() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let upstream = checksum_upstream().await;
+
+    let mut options = Options {
+        http_egress: vec!["api.example.com".to_owned(), "nowhere.invalid".to_owned()],
+        ..Default::default()
+    };
+    options
+        .dns
+        .hosts
+        .insert("api.example.com".to_owned(), upstream);
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    // The override carries the port, so the request reaches the mock wherever it listens.
+    let res = runner.get("/upload/api.example.com/10").await.unwrap();
+    assert!(res.into_body().to_bytes().starts_with(b"10 "));
+
+    let res = runner.get("/get/nowhere.invalid").await.unwrap();
+    let body = String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap();
+    assert!(body.starts_with("ErrorCode::DnsError"), "{body}");
+}
+
+/// Host code that panics whenever a guest waits, standing in for a bug in the runner.
+#[derive(Debug)]
+struct PanickingClock;
+
+impl ClockSource for PanickingClock {
+    fn monotonic_now(&self) -> u64 {
+        SystemClock.monotonic_now()
+    }
+
+    fn wall_now(&self) -> Duration {
+        SystemClock.wall_now()
+    }
+
+    fn sleep_until(&self, _deadline: u64) {
+        panic!("the clock broke");
+    }
+}
+
+#[tokio::test]
+async fn host_panics_become_500s() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        clock: Arc::new(PanickingClock),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner.get("/wait/10").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(runner.runner().metrics().failures.get(), 1);
+
+    // The panic cost that request only.
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+}
+
+/// A proxy that tunnels `CONNECT` requests to their target and answers anything else itself
+/// with `200 proxied`. Returns its address and every request head it was sent.
+async fn connect_proxy() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let heads = Arc::new(Mutex::new(Vec::new()));
+
+    tokio::spawn({
+        let heads = heads.clone();
+
+        async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let heads = heads.clone();
+
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+
+                    while !head.ends_with(b"\r\n\r\n") {
+                        let mut byte = [0];
+                        if client.read(&mut byte).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+
+                    let head = String::from_utf8(head).unwrap();
+                    heads.lock().unwrap().push(head.clone());
+
+                    match head.strip_prefix("CONNECT ") {
+                        Some(rest) => {
+                            let target = rest.split(' ').next().unwrap();
+                            let mut upstream =
+                                tokio::net::TcpStream::connect(target).await.unwrap();
+
+                            client
+                                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                                .await
+                                .unwrap();
+                            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                        }
+                        None => {
+                            let _ = client
+                                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 7\r\n\r\nproxied")
+                                .await;
+                        }
+                    }
+                });
+            }
+        }
+    });
+
+    (addr, heads)
+}
+
+#[tokio::test]
+async fn sends_outbound_requests_through_proxies() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let upstream = checksum_upstream().await;
+    let (proxy, heads) = connect_proxy().await;
+    let origin = format!("127.0.0.1:{}", upstream.port());
+
+    let runner = |proxy: ProxyOptions| {
+        TestRunner::with_options(
+            FIXTURE,
+            Options {
+                http_egress: vec![origin.clone(), "upstream.test".to_owned()],
+                proxy,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    // Tunneled: the proxy only sees the CONNECT, with the credentials from its URL.
+    let tunneled = runner(ProxyOptions {
+        http: Some(format!("http://user:pa%20ss@{proxy}").parse().unwrap()),
+        tunnel: true,
+        ..Default::default()
+    });
+    let res = tunneled.get(&format!("/upload/{origin}/10")).await.unwrap();
+    assert!(res.into_body().to_bytes().starts_with(b"10 "));
+    {
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.len(), 1);
+        assert!(heads[0].starts_with(&format!("CONNECT {origin} HTTP/1.1\r\n")));
+        assert!(heads[0].contains("Proxy-Authorization: Basic dXNlcjpwYSBzcw==\r\n"));
+    }
+
+    // In absolute form, the proxy answers for a host that doesn't even resolve.
+    let forwarded = runner(ProxyOptions {
+        http: Some(format!("http://{proxy}").parse().unwrap()),
+        ..Default::default()
+    });
+    let res = forwarded.get("/get/upstream.test").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "200");
+    assert!(heads.lock().unwrap()[1].starts_with("GET http://upstream.test/ HTTP/1.1\r\n"));
+
+    // Bypassed: addresses listed in NO_PROXY are reached directly.
+    let bypassed = runner(ProxyOptions {
+        http: Some(format!("http://{proxy}").parse().unwrap()),
+        no_proxy: "example.com, 127.0.0.0/8".parse().unwrap(),
+        tunnel: true,
+        ..Default::default()
+    });
+    let res = bypassed.get(&format!("/upload/{origin}/10")).await.unwrap();
+    assert!(res.into_body().to_bytes().starts_with(b"10 "));
+    assert_eq!(heads.lock().unwrap().len(), 2);
+
+    // A proxy that isn't there is a refused connection, not a failure of the origin's.
+    let unreachable = runner(ProxyOptions {
+        http: Some("http://127.0.0.1:1".parse().unwrap()),
+        ..Default::default()
+    });
+    let res = unreachable.get("/get/upstream.test").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "ErrorCode::ConnectionRefused");
+}
+
+#[tokio::test]
+async fn normalizes_method_casing_unless_preserved() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let lowercase = Method::from_bytes(b"get").unwrap();
+    let extension = Method::from_bytes(b"purge").unwrap();
+
+    // The fixture flushes its body before it returns, which must not wait for the response to be
+    // sent.
+    async fn send(runner: &TestRunner, method: &Method) -> Response<Collected<Bytes>> {
+        let res = runner.send(method.clone(), "/method", HeaderMap::new(), Bytes::new());
+
+        tokio::time::timeout(Duration::from_secs(10), res)
+            .await
+            .expect("the guest never finished writing its body")
+            .unwrap()
+    }
+
+    let normalized = TestRunner::new(FIXTURE).unwrap();
+    let res = send(&normalized, &lowercase).await;
+    assert_eq!(res.into_body().to_bytes(), "Method::Get");
+    let res = send(&normalized, &extension).await;
+    assert_eq!(res.into_body().to_bytes(), "Method::Other(\"purge\")");
+
+    let options = Options {
+        preserve_method_case: true,
+        ..Default::default()
+    };
+    let preserved = TestRunner::with_options(FIXTURE, options).unwrap();
+    let res = send(&preserved, &lowercase).await;
+    assert_eq!(res.into_body().to_bytes(), "Method::Other(\"get\")");
+    let res = send(&preserved, &Method::GET).await;
+    assert_eq!(res.into_body().to_bytes(), "Method::Get");
+}
+
+/// An upstream that answers `/small` with 1 KiB, `/length` with a 10 GiB `Content-Length`,
+/// `/stall/{ms}` with 1 KiB, then another after `ms` milliseconds, and `/chunked` with a chunked
+/// body that never ends. Counts the connections it stopped writing to because the client went
+/// away.
+async fn streaming_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let dropped = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn({
+        let dropped = dropped.clone();
+
+        async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let dropped = dropped.clone();
+
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+
+                    while !head.ends_with(b"\r\n\r\n") {
+                        let mut byte = [0];
+                        if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+
+                    let head = String::from_utf8(head).unwrap();
+                    let path = head.split(' ').nth(1).unwrap();
+                    let chunk = vec![b'x'; 16 * 1024];
+
+                    let (answer, chunked) = match path {
+                        "/small" => {
+                            // Closed, so the client never reuses a connection this side is done with.
+                            let head = "connection: close\r\ncontent-length: 1024\r\n\r\n";
+                            let mut answer = format!("HTTP/1.1 200 OK\r\n{head}").into_bytes();
+                            answer.extend_from_slice(&chunk[..1024]);
+                            stream.write_all(&answer).await.unwrap();
+                            return;
+                        }
+                        path if path.starts_with("/stall/") => {
+                            let ms = path["/stall/".len()..].parse().unwrap();
+                            let half = format!("400\r\n{}\r\n", "x".repeat(1024));
+                            let head = "connection: close\r\ntransfer-encoding: chunked\r\n\r\n";
+                            let answer = format!("HTTP/1.1 200 OK\r\n{head}{half}");
+
+                            let _ = stream.write_all(answer.as_bytes()).await;
+                            tokio::time::sleep(Duration::from_millis(ms)).await;
+                            let _ = stream
+                                .write_all(format!("{half}0\r\n\r\n").as_bytes())
+                                .await;
+                            return;
+                        }
+                        "/length" => (
+                            "HTTP/1.1 200 OK\r\ncontent-length: 10737418240\r\n\r\n",
+                            false,
+                        ),
+                        _ => (
+                            "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n",
+                            true,
+                        ),
+                    };
+
+                    let mut body = Vec::new();
+                    if chunked {
+                        body.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    }
+                    body.extend_from_slice(&chunk);
+                    if chunked {
+                        body.extend_from_slice(b"\r\n");
+                    }
+
+                    let mut res = stream.write_all(answer.as_bytes()).await;
+
+                    while res.is_ok() {
+                        res = stream.write_all(&body).await;
+                    }
+
+                    dropped.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        }
+    });
+
+    (addr, dropped)
+}
+
+#[tokio::test]
+async fn caps_upstream_response_bodies() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let (upstream, dropped) = streaming_upstream().await;
+
+    let mut options = Options {
+        http_egress: vec!["big.test".to_owned()],
+        max_upstream_body_bytes: Some(64 * 1024),
+        ..Default::default()
+    };
+    options.dns.hosts.insert("big.test".to_owned(), upstream);
+    let runner = TestRunner::with_options(FIXTURE, options.clone()).unwrap();
+
+    let download = |path: &'static str| {
+        let runner = &runner;
+
+        async move {
+            let res = runner.get(path).await.unwrap();
+            String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap()
+        }
+    };
+
+    assert_eq!(download("/download/big.test/small").await, "1024");
+
+    // The declared length is refused before any of the body is read.
+    assert_eq!(
+        download("/download/big.test/length").await,
+        "ErrorCode::HttpResponseBodySize(Some(10737418240))"
+    );
+
+    let body = download("/download/big.test/chunked").await;
+    assert!(
+        body.starts_with("ErrorCode::HttpResponseBodySize(Some("),
+        "{body}"
+    );
+
+    // Both oversized bodies stopped downloading.
+    let waited = Instant::now();
+    while dropped.load(Ordering::SeqCst) < 2 {
+        assert!(
+            waited.elapsed() < Duration::from_secs(5),
+            "the upstream kept sending"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The total covers every response of a request, so one small body can use it all up.
+    let runner = TestRunner::with_options(
+        FIXTURE,
+        Options {
+            max_upstream_body_bytes: None,
+            max_upstream_total_bytes: Some(512),
+            ..options
+        },
+    )
+    .unwrap();
+
+    let res = runner.get("/download/big.test/small").await.unwrap();
+    let body = String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap();
+    assert_eq!(body, "ErrorCode::HttpResponseBodySize(Some(1024))");
+}
+
+#[tokio::test]
+async fn times_out_upstream_bodies_that_stall() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let (upstream, _) = streaming_upstream().await;
+
+    let mut options = Options {
+        http_egress: vec!["slow.test".to_owned()],
+        ..Default::default()
+    };
+    options.dns.hosts.insert("slow.test".to_owned(), upstream);
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let download = |path: &'static str, between_bytes: &'static str| {
+        let runner = &runner;
+
+        async move {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "x-between-bytes-ms",
+                HeaderValue::from_static(between_bytes),
+            );
+
+            let res = runner
+                .send(Method::GET, path, headers, Bytes::new())
+                .await
+                .unwrap();
+            String::from_utf8(res.into_body().to_bytes().to_vec()).unwrap()
+        }
+    };
+
+    // The stall comes after the head and the first chunk, so only the body's timer can catch it.
+    assert_eq!(
+        download("/download/slow.test/stall/1000", "200").await,
+        "ErrorCode::ConnectionReadTimeout"
+    );
+
+    assert_eq!(
+        download("/download/slow.test/stall/100", "2000").await,
+        "2048"
+    );
+}
+
+#[tokio::test]
+async fn applies_socket_options_from_the_config_file() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("socket-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let config = dir.join("valid.toml");
+    std::fs::write(
+        &config,
+        "[server.socket]\nnodelay = true\nkeepalive-secs = 30\nkeepalive-interval-secs = 5\n\
+         keepalive-retries = 3\nrecv-buffer-size = 65536\nbacklog = 16\n",
+    )
+    .unwrap();
+
+    let Some(server) = Server::with_args(&["--config", config.to_str().unwrap()]) else {
+        return;
+    };
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Mistakes stop the runner before it listens.
+    for (name, option) in [
+        ("zero", "backlog = 0"),
+        ("typo", "keepalive = 30"),
+        ("probes", "keepalive-retries = 3"),
+        ("nodelay", "nodelay = 1"),
+    ] {
+        let config = dir.join(format!("{name}.toml"));
+        std::fs::write(&config, format!("[server.socket]\n{option}\n")).unwrap();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+            .arg("--component")
+            .arg(FIXTURE)
+            .arg("--addr")
+            .arg("127.0.0.1:0")
+            .arg("--config")
+            .arg(&config)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert!(!output.status.success(), "{option} was accepted");
+        assert!(stderr.contains("server.socket"), "{option}: {stderr}");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn answers_current_validators_with_not_modified() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        cache_max_bytes: 1 << 20,
+        cache_max_entry_bytes: 1 << 16,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let metrics = runner.runner().metrics();
+
+    let get = |if_none_match: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static(if_none_match));
+        runner.send(Method::GET, "/etag/v1", headers, Bytes::new())
+    };
+
+    // A miss still comes back as 304 once the guest's tag matches.
+    let res = get("\"v0\", W/\"v1\"").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers()[ETAG], "\"v1\"");
+    assert!(res.into_body().to_bytes().is_empty());
+    assert_eq!(metrics.cache_misses.get(), 1);
+
+    // Revalidating against the cached entry doesn't reach the guest.
+    let res = get("*").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert!(res.into_body().to_bytes().is_empty());
+    assert_eq!(metrics.cache_hits.get(), 1);
+
+    let res = get("\"v2\"").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "tagged");
+    assert_eq!(metrics.cache_hits.get(), 2);
+}
+
+#[tokio::test]
+async fn warms_up_before_accepting_requests() {
+    let Some(server) = Server::with_args(&["--log-format", "json"]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    json_log(&server, "request handled").await;
+
+    let output = server.output.lock().unwrap().clone();
+    let position = |message: &str| output.find(&format!("\"message\":\"{message}\""));
+    let warmed_up = position("warmed up").expect("nothing logged \"warmed up\"");
+
+    assert!(warmed_up < position("listening").unwrap(), "{output}");
+    assert!(warmed_up < position("request handled").unwrap(), "{output}");
+}
+
+#[tokio::test]
+async fn warmup_can_be_skipped() {
+    let Some(server) = Server::with_args(&["--log-format", "json", "--no-warmup"]) else {
+        return;
+    };
+
+    let res = send(&server, Method::GET, "/", Bytes::new()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    json_log(&server, "request handled").await;
+
+    assert!(!server.output.lock().unwrap().contains("warmed up"));
+}
+
+#[tokio::test]
+async fn serves_components_compiled_ahead_of_time() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let compiled = dir.path().join("guest.cwasm");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_wasi-http-runner"))
+        .arg("--compile")
+        .arg(FIXTURE)
+        .arg(&compiled)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let Err(err) = Runner::new(&compiled, Options::default()) else {
+        panic!("loaded a precompiled component without it being allowed");
+    };
+    assert!(
+        err.to_string().contains("must be allowed explicitly"),
+        "{err}"
+    );
+
+    let allowed = || Options {
+        allow_precompiled: true,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(&compiled, allowed()).unwrap();
+    let res = runner.get("/").await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+
+    // Code compiled without fuel metering doesn't fit an engine that meters it.
+    let metered = Options {
+        fuel: Some(1_000_000),
+        ..allowed()
+    };
+    assert!(Runner::new(&compiled, metered).is_err());
+}
+
+#[tokio::test]
+async fn renders_host_errors_as_problem_json() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        error_format: ErrorFormat::Problem,
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner.get("/trap").await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/problem+json");
+
+    let problem: serde_json::Value = serde_json::from_slice(&res.into_body().to_bytes()).unwrap();
+    assert_eq!(problem["type"], "urn:wasi-http-runner:trap");
+    assert_eq!(problem["title"], "Internal Server Error");
+    assert_eq!(problem["status"], 500);
+    assert!(problem["detail"].is_string(), "{problem}");
+
+    // What the guest answers itself is its own business.
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+}
+
+#[tokio::test]
+async fn counts_requests_in_a_shared_bucket() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let kv = KeyValue::new(MemoryBackend::default()).bucket("counters", "e2e");
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .with_key_value(kv);
+    let runner = &TestRunner::from_runner(runner);
+
+    let count = |key: &str| {
+        let uri = format!("/count/{key}");
+
+        async move {
+            let body = runner.get(&uri).await.unwrap().into_body().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+
+    // Each request runs in a fresh instance, so the count lives in the host.
+    for expected in ["1", "2", "3"] {
+        assert_eq!(count("visits").await, expected);
+    }
+
+    assert_eq!(count("other").await, "1");
+
+    // Without a store, the guest can't open the bucket.
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let res = runner.get("/count/visits").await.unwrap();
+    let body = res.into_body().to_bytes();
+    assert!(
+        String::from_utf8_lossy(&body).contains("NoSuchStore"),
+        "{body:?}"
+    );
+}
+
+#[tokio::test]
+async fn serves_a_component_from_memory() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let bytes = std::fs::read(FIXTURE).unwrap();
+    let runner = Runner::from_bytes(&bytes, Options::default()).unwrap();
+    let runner = TestRunner::from_runner(runner);
+
+    let res = runner.get("/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "Hello, World!");
+
+    assert!(Runner::from_bytes(b"not a component", Options::default()).is_err());
+}
+
+#[tokio::test]
+async fn guests_read_their_configuration() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let options = Options {
+        guest_config: GuestConfig::default()
+            .set("api-base", "https://api.example.com")
+            .secret("api-token", "hunter2"),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner.get("/config/api-base").await.unwrap();
+    assert_eq!(res.headers()["x-config-value"], "https://api.example.com");
+    assert_eq!(res.into_body().to_bytes(), "api-base,api-token");
+
+    // Secrets are only hidden from the log, not from the guest.
+    let res = runner.get("/config/api-token").await.unwrap();
+    assert_eq!(res.headers()["x-config-value"], "hunter2");
+
+    let res = runner.get("/config/missing").await.unwrap();
+    assert_eq!(res.headers()["x-config-value"], "<unset>");
+}
+
+#[tokio::test]
+async fn guests_connect_to_allowed_tcp_servers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let options = Options {
+        tcp_egress: vec![echo.to_string().parse().unwrap()],
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+
+    let res = runner.get(&format!("/tcp-echo/{echo}")).await.unwrap();
+    assert_eq!(res.into_body().to_bytes(), "ping");
+
+    // Nothing outside the allow-list can be reached.
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let res = runner.get(&format!("/tcp-echo/{echo}")).await.unwrap();
+    let body = res.into_body().to_bytes();
+    assert!(
+        String::from_utf8_lossy(&body).contains("AccessDenied"),
+        "{body:?}"
+    );
+}
+
+#[tokio::test]
+async fn adds_date_and_server_headers() {
+    if !built(FIXTURE) {
+        return;
+    }
+
+    let runner = TestRunner::new(FIXTURE).unwrap();
+    let res = runner.get("/").await.unwrap();
+
+    let date = res.headers()[DATE].to_str().unwrap();
+    let date = httpdate::parse_http_date(date).unwrap();
+    let skew = match date.duration_since(std::time::SystemTime::now()) {
+        Ok(ahead) => ahead,
+        Err(behind) => behind.duration(),
+    };
+    assert!(skew < Duration::from_secs(5), "the Date is {skew:?} off");
+    assert!(!res.headers().contains_key(SERVER));
+
+    let options = Options {
+        date_header: false,
+        server_header: Some(HeaderValue::from_static("wasi-http-runner")),
+        ..Default::default()
+    };
+    let runner = TestRunner::with_options(FIXTURE, options).unwrap();
+    let res = runner.get("/").await.unwrap();
+
+    assert!(!res.headers().contains_key(DATE));
+    assert_eq!(res.headers()[SERVER], "wasi-http-runner");
+}
+
+#[tokio::test]
+async fn rejects_oversized_request_heads() {
+    let Some(server) = Server::with_args(&[
+        "--max-header-bytes",
+        "1024",
+        "--max-headers",
+        "8",
+        "--max-uri-bytes",
+        "256",
+    ]) else {
+        return;
+    };
+
+    let status = |path: String, headers: Vec<(String, String)>| {
+        let mut req = Request::get(server.uri(&path));
+
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+
+        let res = client().request(req.body(Full::new(Bytes::new())).unwrap());
+
+        async move {
+            tokio::time::timeout(Duration::from_secs(10), res)
+                .await
+                .expect("the request timed out")
+                .unwrap()
+                .status()
+        }
+    };
+
+    let large = vec![("x-large".to_owned(), "a".repeat(2048))];
+    assert_eq!(
+        status("/".to_owned(), large).await,
+        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+    );
+
+    let many = (0..16)
+        .map(|i| (format!("x-header-{i}"), "a".to_owned()))
+        .collect();
+    assert_eq!(
+        status("/".to_owned(), many).await,
+        StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+    );
+
+    assert_eq!(
+        status(format!("/{}", "a".repeat(512)), Vec::new()).await,
+        StatusCode::URI_TOO_LONG
+    );
+
+    // The limits leave ordinary requests alone.
+    assert_eq!(status("/".to_owned(), Vec::new()).await, StatusCode::OK);
+}
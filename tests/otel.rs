@@ -0,0 +1,96 @@
+//! Checks that exported spans join the caller's trace and that the fixture guest's outbound
+//! requests continue it. Needs the `otel` feature and the fixture from
+//! `scripts/build-fixtures.sh`.
+#![cfg(feature = "otel")]
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
+use hyper::body::Bytes;
+use opentelemetry::trace::{SpanId, SpanKind, TraceId, TracerProvider as _};
+use opentelemetry_sdk::{testing::trace::InMemorySpanExporter, trace::TracerProvider};
+use tracing_subscriber::layer::SubscriberExt;
+use wasi_http_runner::{testing::TestRunner, Options, Runner};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/guest.wasm");
+
+const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+const PARENT_ID: &str = "b7ad6b7169203331";
+
+#[tokio::test]
+async fn outbound_requests_continue_the_callers_trace() {
+    if !Path::new(FIXTURE).exists() {
+        assert!(
+            std::env::var_os("SKIP_E2E").is_some(),
+            "{FIXTURE} is missing, build it with scripts/build-fixtures.sh or set SKIP_E2E to skip"
+        );
+        eprintln!("skipping: {FIXTURE} is missing");
+        return;
+    }
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+
+    // The guest runs on the pool's threads, so this can't be a thread-local default.
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test"))),
+    )
+    .unwrap();
+
+    let sent = Arc::new(Mutex::new(None));
+    let runner = Runner::new(FIXTURE, Options::default())
+        .unwrap()
+        .with_outbound_mock({
+            let sent = sent.clone();
+
+            move |req| {
+                *sent.lock().unwrap() = req.headers().get("traceparent").cloned();
+                Some(Response::new(Bytes::from("upstream")))
+            }
+        });
+    let runner = TestRunner::from_runner(runner);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "traceparent",
+        HeaderValue::from_str(&format!("00-{TRACE_ID}-{PARENT_ID}-01")).unwrap(),
+    );
+
+    let res = runner
+        .send(Method::GET, "/fetch", headers, Bytes::new())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.into_body().to_bytes(), "200");
+
+    drop(runner);
+    provider.force_flush();
+    let spans = exporter.get_finished_spans().unwrap();
+
+    let server = spans
+        .iter()
+        .find(|span| span.span_kind == SpanKind::Server)
+        .expect("no server span was exported");
+    let client = spans
+        .iter()
+        .find(|span| span.span_kind == SpanKind::Client)
+        .expect("no client span was exported");
+
+    let trace_id = TraceId::from_hex(TRACE_ID).unwrap();
+    assert_eq!(server.span_context.trace_id(), trace_id);
+    assert_eq!(server.parent_span_id, SpanId::from_hex(PARENT_ID).unwrap());
+    assert_eq!(client.span_context.trace_id(), trace_id);
+    assert_eq!(client.parent_span_id, server.span_context.span_id());
+
+    let client_id = u64::from_be_bytes(client.span_context.span_id().to_bytes());
+    assert_eq!(
+        sent.lock().unwrap().as_ref().unwrap(),
+        &format!("00-{TRACE_ID}-{client_id:016x}-01")
+    );
+}
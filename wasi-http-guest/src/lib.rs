@@ -6,7 +6,12 @@ use std::{
 };
 
 use anyhow::anyhow;
-use axum::{routing::get, Router};
+use axum::{
+    body::Body as AxumBody,
+    extract::Path,
+    routing::{get, post},
+    Router,
+};
 use bytes::{Buf, Bytes};
 use exports::wasi::http::incoming_handler::Guest;
 use futures::{future::poll_fn, task::noop_waker_ref};
@@ -32,10 +37,895 @@ fn service() -> impl Service<
     Response = Response<impl Body<Data = Bytes, Error = impl Into<anyhow::Error>>>,
     Error = impl Into<anyhow::Error>,
 > {
-    Router::new().route("/", get("Hello, World!"))
+    Router::new()
+        .route("/", get("Hello, World!"))
+        // Fixtures for the runner's end-to-end tests.
+        .route("/echo", post(echo))
+        .route("/stream/:bytes", get(stream))
+        .route("/trap", get(trap))
+        .route("/flaky/:key", get(flaky))
+        .route("/flaky-read/:key", get(flaky_read))
+        .route("/outage/:key", get(outage))
+        .route("/sleep/:ms", get(sleep))
+        .route("/wait/:ms", get(wait))
+        .route("/ignore", post("ignored"))
+        .route("/spin/:rounds", get(spin))
+        .route("/alloc/:mib", get(alloc))
+        .route("/log", get(log))
+        .route("/header/:name", get(header).post(header))
+        .route("/etag/:tag", get(etag))
+        .route("/reason/:phrase", get(reason))
+        .route("/length/:declared/:written", get(length))
+        .route(
+            "/close",
+            get(([(http::header::CONNECTION, "close")], "closing")),
+        )
+        .route("/cache/:key", get(cache_get).put(cache_set))
+        .route("/count/:key", get(count))
+        .route("/config/:key", get(config))
+        .route("/tcp-echo/:addr", get(tcp_echo))
+        .route("/fetch", get(fetch))
+        .route("/fields", get(fields))
+        .route("/upload/:authority/:bytes", get(upload))
+        .route("/compressed/:authority", post(compressed))
+        .route("/get/:authority", get(get_upstream))
+        .route("/download/:authority/*path", get(download))
+    // `/trailers-twice`, `/trailers` and the other raw handlers are answered before routing, see
+    // `handle`.
+}
+
+/// Counts the requests made for `key` in the `counters` bucket of `wasi:keyvalue`.
+async fn count(Path(key): Path<String>) -> Result<String, String> {
+    use wasi::keyvalue::{atomics, store};
+
+    let bucket = store::open("counters").map_err(|err| format!("{err:?}"))?;
+    let count = atomics::increment(&bucket, &key, 1).map_err(|err| format!("{err:?}"))?;
+
+    Ok(count.to_string())
+}
+
+/// Reflects the `wasi:config` value of `key` in the `x-config-value` header, and lists every key
+/// in the body.
+async fn config(Path(key): Path<String>) -> Result<([(HeaderName, String); 1], String), String> {
+    use wasi::config::store;
+
+    let value = store::get(&key).map_err(|err| format!("{err:?}"))?;
+    let keys = store::get_all()
+        .map_err(|err| format!("{err:?}"))?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    Ok((
+        [(
+            HeaderName::from_static("x-config-value"),
+            value.unwrap_or_else(|| "<unset>".to_owned()),
+        )],
+        keys.join(","),
+    ))
+}
+
+/// Sends `ping` to the TCP echo server at the IPv4 address `addr` through `wasi:sockets`, and
+/// answers with what came back.
+async fn tcp_echo(Path(addr): Path<String>) -> Result<String, String> {
+    use wasi::sockets::{
+        instance_network::instance_network,
+        network::{ErrorCode, IpAddressFamily, IpSocketAddress, Ipv4SocketAddress},
+        tcp_create_socket::create_tcp_socket,
+    };
+
+    let addr = addr
+        .parse::<std::net::SocketAddrV4>()
+        .map_err(|err| err.to_string())?;
+    let [a, b, c, d] = addr.ip().octets();
+
+    let socket = create_tcp_socket(IpAddressFamily::Ipv4).map_err(|err| format!("{err:?}"))?;
+    socket
+        .start_connect(
+            &instance_network(),
+            IpSocketAddress::Ipv4(Ipv4SocketAddress {
+                port: addr.port(),
+                address: (a, b, c, d),
+            }),
+        )
+        .map_err(|err| format!("{err:?}"))?;
+
+    let (input, output) = loop {
+        match socket.finish_connect() {
+            Ok(streams) => break streams,
+            Err(ErrorCode::WouldBlock) => socket.subscribe().block(),
+            Err(err) => return Err(format!("{err:?}")),
+        }
+    };
+
+    output
+        .blocking_write_and_flush(b"ping")
+        .map_err(|err| format!("{err:?}"))?;
+
+    let mut reply = Vec::new();
+
+    while reply.len() < 4 {
+        let read = input
+            .blocking_read(4 - reply.len() as u64)
+            .map_err(|err| format!("{err:?}"))?;
+        reply.extend(read);
+    }
+
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Sends the request body back, trailers included.
+async fn echo(request: Request<AxumBody>) -> Response<AxumBody> {
+    Response::new(request.into_body())
+}
+
+/// Writes `bytes` bytes in chunks of up to 16 KiB.
+async fn stream(Path(bytes): Path<usize>) -> Response<AxumBody> {
+    let chunks = (0..bytes).step_by(16 * 1024).map(move |start| {
+        let len = (bytes - start).min(16 * 1024);
+        Ok::<_, std::convert::Infallible>(Bytes::from(vec![b'x'; len]))
+    });
+
+    Response::new(AxumBody::from_stream(futures::stream::iter(chunks)))
+}
+
+async fn trap() -> &'static str {
+    panic!("trap requested")
+}
+
+/// Traps the first time it's called for `key`, like a component whose lazy init races, and
+/// answers every time after. The shared cache remembers the first call across instances.
+async fn flaky(Path(key): Path<String>) -> &'static str {
+    if !bluezeeking::service::cache::exists(&key) {
+        bluezeeking::service::cache::set(&key, b"tripped", None).unwrap();
+        panic!("first call for {key}");
+    }
+
+    "recovered"
+}
+
+/// Like `flaky`, but reads the request body first.
+async fn flaky_read(key: Path<String>, _body: String) -> &'static str {
+    flaky(key).await
+}
+
+/// Traps while the shared cache holds `key`, like a component whose backend is down, and answers
+/// every time after it is gone.
+async fn outage(Path(key): Path<String>) -> &'static str {
+    if bluezeeking::service::cache::exists(&key) {
+        panic!("{key} is down");
+    }
+
+    "up"
+}
+
+async fn cache_get(Path(key): Path<String>) -> Response<AxumBody> {
+    match bluezeeking::service::cache::get(&key) {
+        Some(value) => Response::new(AxumBody::from(value)),
+        None => Response::builder()
+            .status(404)
+            .body(AxumBody::empty())
+            .unwrap(),
+    }
+}
+
+/// Stores the request body, for `ttl` milliseconds if the query gives one.
+async fn cache_set(Path(key): Path<String>, request: Request<AxumBody>) -> Response<AxumBody> {
+    let ttl = request
+        .uri()
+        .query()
+        .and_then(|query| query.strip_prefix("ttl="))
+        .and_then(|ttl| ttl.parse().ok());
+    let value = axum::body::to_bytes(request.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    match bluezeeking::service::cache::set(&key, &value, ttl) {
+        Ok(()) => Response::new(AxumBody::empty()),
+        Err(error) => Response::builder()
+            .status(507)
+            .body(AxumBody::from(error))
+            .unwrap(),
+    }
+}
+
+/// Tries a few invalid fields with `from-list`, `set` and `append`, one line per field with what
+/// each returned.
+async fn fields() -> String {
+    let cases: [(&str, &[u8]); 5] = [
+        (":path", b"/"),
+        (":path", b"/again"),
+        ("bad name", b"value"),
+        ("x-bad-value", b"line\nbreak"),
+        ("x-fine", b"value"),
+    ];
+
+    cases
+        .iter()
+        .map(|(name, value)| {
+            let name = name.to_string();
+            let value = value.to_vec();
+
+            let from_list = Fields::from_list(&[(name.clone(), value.clone())]).map(|_| ());
+            // Fields from `from-list` are mutable, so these only fail on the field itself.
+            let set = Fields::from_list(&[]).unwrap().set(&name, &[value.clone()]);
+            let append = Fields::from_list(&[]).unwrap().append(&name, &value);
+
+            format!("{name}: {from_list:?} {set:?} {append:?}\n")
+        })
+        .collect()
+}
+
+/// Sends a request to `http://upstream.test/` and answers with the status it got.
+async fn fetch() -> String {
+    use wasi::http::{
+        outgoing_handler,
+        types::{OutgoingRequest, Scheme},
+    };
+
+    let request = OutgoingRequest::new(Fields::new());
+    request.set_scheme(Some(&Scheme::Http)).unwrap();
+    request.set_authority(Some("upstream.test")).unwrap();
+    request.set_path_with_query(Some("/")).unwrap();
+
+    let response = outgoing_handler::handle(request, None).unwrap();
+    response.subscribe().block();
+
+    match response.get() {
+        Some(Ok(Ok(response))) => response.status().to_string(),
+        _ => "failed".to_owned(),
+    }
+}
+
+/// POSTs `bytes` generated bytes to `http://{authority}/`, writing them after handing the request
+/// over so they stream, with their FNV-1a hash as the `x-checksum` trailer. Answers with the
+/// upstream's response body.
+async fn upload(Path((authority, bytes)): Path<(String, usize)>) -> String {
+    use wasi::http::{
+        outgoing_handler,
+        types::{Method, OutgoingRequest, Scheme},
+    };
+
+    let headers = Fields::from_list(&[("trailer".to_owned(), b"x-checksum".to_vec())]).unwrap();
+    let request = OutgoingRequest::new(headers);
+    request.set_method(&Method::Post).unwrap();
+    request.set_scheme(Some(&Scheme::Http)).unwrap();
+    request.set_authority(Some(&authority)).unwrap();
+    request.set_path_with_query(Some("/")).unwrap();
+
+    let body = request.body().unwrap();
+    let response = outgoing_handler::handle(request, None).unwrap();
+
+    let stream = body.write().unwrap();
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut chunk = [0; 4096];
+
+    for start in (0..bytes).step_by(chunk.len()) {
+        let len = (bytes - start).min(chunk.len());
+
+        for (i, byte) in chunk[..len].iter_mut().enumerate() {
+            *byte = ((start + i) % 251) as u8;
+            hash = (hash ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+        }
+
+        stream.blocking_write_and_flush(&chunk[..len]).unwrap();
+    }
+
+    drop(stream);
+
+    let trailers =
+        Fields::from_list(&[("x-checksum".to_owned(), format!("{hash:016x}").into_bytes())])
+            .unwrap();
+    OutgoingBody::finish(body, Some(trailers)).unwrap();
+
+    response.subscribe().block();
+
+    let Some(Ok(Ok(response))) = response.get() else {
+        return "failed".to_owned();
+    };
+
+    let body = response.consume().unwrap();
+    let stream = body.stream().unwrap();
+    let mut answer = Vec::new();
+
+    while let Ok(read) = stream.blocking_read(4096) {
+        answer.extend(read);
+    }
+
+    String::from_utf8_lossy(&answer).into_owned()
+}
+
+/// POSTs the request body to `http://{authority}/` as one the guest compressed itself, under
+/// `Content-Encoding: gzip` and a `Transfer-Encoding: gzip` the host must not pass on. Answers
+/// with the upstream's response body.
+async fn compressed(Path(authority): Path<String>, body: Bytes) -> String {
+    use wasi::http::{
+        outgoing_handler,
+        types::{Method, OutgoingRequest, Scheme},
+    };
+
+    let headers = Fields::from_list(&[
+        ("content-encoding".to_owned(), b"gzip".to_vec()),
+        ("transfer-encoding".to_owned(), b"gzip".to_vec()),
+    ])
+    .unwrap();
+    let request = OutgoingRequest::new(headers);
+    request.set_method(&Method::Post).unwrap();
+    request.set_scheme(Some(&Scheme::Http)).unwrap();
+    request.set_authority(Some(&authority)).unwrap();
+    request.set_path_with_query(Some("/")).unwrap();
+
+    let outgoing_body = request.body().unwrap();
+    let response = outgoing_handler::handle(request, None).unwrap();
+
+    let stream = outgoing_body.write().unwrap();
+
+    for chunk in body.chunks(4096) {
+        stream.blocking_write_and_flush(chunk).unwrap();
+    }
+
+    drop(stream);
+    OutgoingBody::finish(outgoing_body, None).unwrap();
+
+    response.subscribe().block();
+
+    let Some(Ok(Ok(response))) = response.get() else {
+        return "failed".to_owned();
+    };
+
+    let body = response.consume().unwrap();
+    let stream = body.stream().unwrap();
+    let mut answer = Vec::new();
+
+    while let Ok(read) = stream.blocking_read(4096) {
+        answer.extend(read);
+    }
+
+    String::from_utf8_lossy(&answer).into_owned()
+}
+
+/// GETs `http://{authority}/`, answering with the upstream's status, or the error code the
+/// request failed with.
+async fn get_upstream(Path(authority): Path<String>) -> String {
+    use wasi::http::{
+        outgoing_handler,
+        types::{OutgoingRequest, Scheme},
+    };
+
+    let request = OutgoingRequest::new(Fields::new());
+    request.set_scheme(Some(&Scheme::Http)).unwrap();
+    request.set_authority(Some(&authority)).unwrap();
+    request.set_path_with_query(Some("/")).unwrap();
+
+    let response = match outgoing_handler::handle(request, None) {
+        Ok(response) => response,
+        Err(code) => return format!("{code:?}"),
+    };
+    response.subscribe().block();
+
+    match response.get() {
+        Some(Ok(Ok(response))) => response.status().to_string(),
+        Some(Ok(Err(code))) => format!("{code:?}"),
+        _ => "failed".to_owned(),
+    }
+}
+
+/// GETs `http://{authority}/{path}` and reads the whole body, answering with how many bytes
+/// arrived, or the error code the request or a read failed with. `x-between-bytes-ms` sets the
+/// request's between-bytes timeout.
+async fn download(Path((authority, path)): Path<(String, String)>, headers: HeaderMap) -> String {
+    use wasi::{
+        http::{
+            outgoing_handler,
+            types::{http_error_code, OutgoingRequest, RequestOptions, Scheme},
+        },
+        io::streams::StreamError,
+    };
+
+    let request = OutgoingRequest::new(Fields::new());
+    request.set_scheme(Some(&Scheme::Http)).unwrap();
+    request.set_authority(Some(&authority)).unwrap();
+    request
+        .set_path_with_query(Some(&format!("/{path}")))
+        .unwrap();
+
+    let options = headers.get("x-between-bytes-ms").map(|ms| {
+        let options = RequestOptions::new();
+        let ms = ms.to_str().unwrap().parse().unwrap();
+        options.set_between_bytes_timeout_ms(Some(ms)).unwrap();
+        options
+    });
+
+    let response = match outgoing_handler::handle(request, options) {
+        Ok(response) => response,
+        Err(code) => return format!("{code:?}"),
+    };
+    response.subscribe().block();
+
+    let response = match response.get() {
+        Some(Ok(Ok(response))) => response,
+        Some(Ok(Err(code))) => return format!("{code:?}"),
+        _ => return "failed".to_owned(),
+    };
+
+    let body = response.consume().unwrap();
+    let stream = body.stream().unwrap();
+    let mut read = 0;
+
+    loop {
+        match stream.blocking_read(64 * 1024) {
+            Ok(bytes) => read += bytes.len(),
+            Err(StreamError::Closed) => return read.to_string(),
+            Err(StreamError::LastOperationFailed(error)) => {
+                return match http_error_code(&error) {
+                    Some(code) => format!("{code:?}"),
+                    None => error.to_debug_string(),
+                }
+            }
+        }
+    }
+}
+
+/// Does `rounds` rounds of busy work.
+async fn spin(Path(rounds): Path<u64>) -> String {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+
+    for round in 0..rounds {
+        hash = (hash ^ round).wrapping_mul(0x100_0000_01b3);
+    }
+
+    std::hint::black_box(hash).to_string()
+}
+
+/// Declares a `declared` byte body but writes `written` bytes.
+async fn length(Path((declared, written)): Path<(usize, usize)>) -> Response<AxumBody> {
+    Response::builder()
+        .header(http::header::CONTENT_LENGTH, declared)
+        .body(AxumBody::from(vec![b'x'; written]))
+        .unwrap()
+}
+
+/// Answers with the value of the request header `name`.
+async fn header(Path(name): Path<String>, headers: HeaderMap) -> String {
+    headers
+        .get(&name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// A cacheable response with the strong entity tag `"<tag>"`.
+async fn etag(Path(tag): Path<String>) -> ([(HeaderName, String); 2], &'static str) {
+    (
+        [
+            (http::header::ETAG, format!("\"{tag}\"")),
+            (http::header::CACHE_CONTROL, "max-age=60".to_owned()),
+        ],
+        "tagged",
+    )
+}
+
+/// Asks the runner to send `phrase` as the HTTP/1 reason phrase.
+async fn reason(Path(phrase): Path<String>) -> ([(HeaderName, String); 1], &'static str) {
+    (
+        [(HeaderName::from_static("x-reason-phrase"), phrase)],
+        "reasoned",
+    )
+}
+
+/// Logs a warning through `bluezeeking:service/log`.
+async fn log() -> &'static str {
+    use bluezeeking::service::log::{log, Level};
+
+    log(
+        Level::Warn,
+        "disk almost full",
+        &[("free".to_owned(), "3%".to_owned())],
+    );
+    "logged"
+}
+
+/// Allocates and touches `mib` MiB, growing linear memory by at least that much.
+async fn alloc(Path(mib): Path<usize>) -> String {
+    let buf = vec![1_u8; mib << 20];
+    std::hint::black_box(&buf).len().to_string()
+}
+
+/// Holds the guest thread for `ms` milliseconds.
+async fn sleep(Path(ms): Path<u64>) -> &'static str {
+    thread::sleep(std::time::Duration::from_millis(ms));
+    "slept"
+}
+
+/// Blocks on a `subscribe-duration` pollable, so the host's clock decides when it fires.
+async fn wait(Path(ms): Path<u64>) -> &'static str {
+    wasi::clocks::monotonic_clock::subscribe_duration(ms * 1_000_000).block();
+    "waited"
+}
+
+/// Reads the request's trailers, then asks for them again, which the host must refuse. Answers
+/// with what each `get` returned.
+fn trailers_twice(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let body = request
+        .consume()
+        .map_err(|_| anyhow!("Could not get request body"))?;
+    drop(request);
+
+    let trailers = IncomingBody::finish(body);
+    trailers.subscribe().block();
+
+    let describe = |got: Option<Result<Option<Fields>, ErrorCode>>| match got {
+        None => "pending".to_owned(),
+        Some(Ok(_)) => "taken".to_owned(),
+        Some(Err(ErrorCode::InternalError(Some(message)))) => message,
+        Some(Err(_)) => "failed".to_owned(),
+    };
+    let first = describe(trailers.get());
+    let second = describe(trailers.get());
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(format!("{first} then {second}").as_bytes())?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Answers with `body` and an `x-checksum: abc` trailer.
+fn trailers(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    drop(request);
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(b"body")?;
+    drop(output);
+
+    let trailers = Fields::from_list(&[("x-checksum".to_owned(), b"abc".to_vec())])
+        .map_err(|_| anyhow!("Could not build trailers"))?;
+    OutgoingBody::finish(outgoing_body, Some(trailers))?;
+
+    Ok(response)
+}
+
+/// Answers with the method exactly as the host presented it.
+fn method(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let method = format!("{:?}", request.method());
+    drop(request);
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(method.as_bytes())?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Tees the request body and reads all of it through the original stream, then through the
+/// branch. Answers with how many bytes each read and whether they saw the same ones.
+fn tee(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let body = request
+        .consume()
+        .map_err(|_| anyhow!("Could not get request body"))?;
+    drop(request);
+
+    let source = body
+        .stream()
+        .map_err(|_| anyhow!("Could not get request stream"))?;
+    let branch = bluezeeking::service::body::tee(&source)
+        .map_err(|()| anyhow!("Could not tee the request stream"))?;
+
+    let read_all = |stream: &InputStream| -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        loop {
+            match stream.blocking_read(64 * 1024) {
+                Ok(chunk) => bytes.extend(chunk),
+                Err(wasi::io::streams::StreamError::Closed) => return Ok(bytes),
+                Err(wasi::io::streams::StreamError::LastOperationFailed(err)) => {
+                    return Err(anyhow!(err.to_debug_string()))
+                }
+            }
+        }
+    };
+
+    let first = read_all(&source)?;
+    let second = read_all(&branch)?;
+    drop(branch);
+    drop(source);
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(
+        format!("{} {} {}", first.len(), second.len(), first == second).as_bytes(),
+    )?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Has the host send the file named by the `x-file` header as the body, answering 403 with the
+/// host's reason when it refuses. `x-declared-length` sets a `Content-Length` of the guest's own.
+fn send_file(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let path = request
+        .headers()
+        .get(&"x-file".to_owned())
+        .pop()
+        .ok_or(anyhow!("Missing x-file header"))?;
+    let path = String::from_utf8(path)?;
+
+    let headers = Fields::new();
+
+    if let Some(length) = request.headers().get(&"x-declared-length".to_owned()).pop() {
+        headers
+            .set(&"content-length".to_owned(), &[length])
+            .map_err(|_| anyhow!("Could not set content-length"))?;
+    }
+
+    drop(request);
+
+    let response = OutgoingResponse::new(headers);
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+
+    let Err(error) = bluezeeking::service::files::send_file(outgoing_body, &path) else {
+        return Ok(response);
+    };
+
+    let response = OutgoingResponse::new(Fields::new());
+    response
+        .set_status_code(403)
+        .map_err(|_| anyhow!("Could not set status code"))?;
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(error.as_bytes())?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Reads the request body the way a guest without a blocking read would: wait on the stream's
+/// pollable, then read, until the stream is closed. Answers with how many bytes it read, how many
+/// reads came back empty after the pollable was ready, and the names of the trailers.
+fn read_loop(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let body = request
+        .consume()
+        .map_err(|_| anyhow!("Could not get request body"))?;
+    drop(request);
+
+    let stream = body
+        .stream()
+        .map_err(|_| anyhow!("Could not get request stream"))?;
+    let (mut read, mut empty) = (0, 0);
+
+    loop {
+        stream.subscribe().block();
+
+        match stream.read(4096) {
+            Ok(bytes) if bytes.is_empty() => empty += 1,
+            Ok(bytes) => read += bytes.len(),
+            Err(wasi::io::streams::StreamError::Closed) => break,
+            Err(wasi::io::streams::StreamError::LastOperationFailed(err)) => {
+                return Err(anyhow!(err.to_debug_string()))
+            }
+        }
+    }
+    drop(stream);
+
+    let trailers = IncomingBody::finish(body);
+    trailers.subscribe().block();
+
+    let names = match trailers.get() {
+        Some(Ok(Some(trailers))) => trailers
+            .entries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => String::new(),
+    };
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(
+        format!("{read} bytes, {empty} empty reads, trailers: {names}").as_bytes(),
+    )?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Reads the whole request body with a single `blocking-read`, the way a guest that only wants a
+/// small JSON payload would. Answers with how many bytes that read returned, and whether the
+/// stream was closed afterwards.
+fn read_all(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    let body = request
+        .consume()
+        .map_err(|_| anyhow!("Could not get request body"))?;
+    drop(request);
+
+    let stream = body
+        .stream()
+        .map_err(|_| anyhow!("Could not get request stream"))?;
+
+    let read = match stream.blocking_read(u64::MAX) {
+        Ok(bytes) => bytes.len(),
+        Err(wasi::io::streams::StreamError::Closed) => 0,
+        Err(wasi::io::streams::StreamError::LastOperationFailed(err)) => {
+            return Err(anyhow!(err.to_debug_string()))
+        }
+    };
+    let closed = matches!(
+        stream.blocking_read(u64::MAX),
+        Err(wasi::io::streams::StreamError::Closed)
+    );
+    drop(stream);
+    drop(body);
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(
+        format!("{read} bytes in one read, closed: {closed}").as_bytes(),
+    )?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Writes 64 KiB with `blocking-write-and-flush`, 4 KiB at a time, then another 64 KiB with
+/// `write`, as much as `check-write` permits each time, waiting on the stream's pollable in
+/// between. All of it is written before the host sends the response.
+fn write_loop(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    drop(request);
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+
+    for _ in 0..16 {
+        output.blocking_write_and_flush(&[b'a'; 4096])?;
+    }
+
+    let mut left = 64 * 1024;
+
+    while left > 0 {
+        output.subscribe().block();
+
+        let len = (output.check_write()? as usize).min(left);
+        output.write(&vec![b'b'; len])?;
+        left -= len;
+    }
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
+}
+
+/// Writes part of a body under a `Content-Length` covering more, then returns without finishing
+/// it.
+fn unfinished(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    drop(request);
+
+    let headers = Fields::from_list(&[("content-length".to_owned(), b"100".to_vec())])
+        .map_err(|_| anyhow!("Could not build headers"))?;
+    let response = OutgoingResponse::new(headers);
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(b"partial")?;
+    drop(output);
+    drop(outgoing_body);
+
+    Ok(response)
+}
+
+/// Sets its response before writing any of the body, then echoes the request body a read at a
+/// time, flushing each chunk before reading the next. A client only gets a chunk before it sends
+/// the next if the host sends the response while the guest is still writing it.
+fn relay(request: IncomingRequest, response_out: ResponseOutparam) -> anyhow::Result<()> {
+    let body = request
+        .consume()
+        .map_err(|_| anyhow!("Could not get request body"))?;
+    drop(request);
+
+    let input = body
+        .stream()
+        .map_err(|_| anyhow!("Could not get request stream"))?;
+
+    let response = OutgoingResponse::new(Fields::new());
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    ResponseOutparam::set(response_out, Ok(response));
+
+    loop {
+        match input.blocking_read(4096) {
+            Ok(bytes) => output.blocking_write_and_flush(&bytes)?,
+            Err(wasi::io::streams::StreamError::Closed) => break,
+            Err(wasi::io::streams::StreamError::LastOperationFailed(err)) => {
+                return Err(anyhow!(err.to_debug_string()))
+            }
+        }
+    }
+    drop(output);
+    drop(input);
+    drop(body);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(())
+}
+
+/// The branded error page served when this guest runs as a fallback, for any request the primary
+/// failed on. Takes `render_ms` to render, standing in for a slow fallback.
+fn trouble_page(reason: &str, render_ms: u64) -> anyhow::Result<OutgoingResponse> {
+    thread::sleep(std::time::Duration::from_millis(render_ms));
+
+    let response = OutgoingResponse::new(Fields::new());
+    response
+        .set_status_code(503)
+        .map_err(|_| anyhow!("Could not set status code"))?;
+    let outgoing_body = response.body().map_err(|_| anyhow!("Could not get body"))?;
+    let output = outgoing_body
+        .write()
+        .map_err(|_| anyhow!("Could not get stream"))?;
+    output.blocking_write_and_flush(format!("We're having trouble ({reason})").as_bytes())?;
+    drop(output);
+    OutgoingBody::finish(outgoing_body, None)?;
+
+    Ok(response)
 }
 
 fn handle(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
+    if let Some(reason) = request.headers().get(&"x-fallback-reason".to_owned()).pop() {
+        let render_ms = match request.headers().get(&"x-render-ms".to_owned()).pop() {
+            Some(ms) => String::from_utf8(ms)?.parse()?,
+            None => 0,
+        };
+
+        return trouble_page(&String::from_utf8(reason)?, render_ms);
+    }
+
+    match request.path_with_query().as_deref() {
+        Some("/trailers-twice") => return trailers_twice(request),
+        Some("/read-loop") => return read_loop(request),
+        Some("/read-all") => return read_all(request),
+        Some("/send-file") => return send_file(request),
+        Some("/method") => return method(request),
+        Some("/trailers") => return trailers(request),
+        Some("/tee") => return tee(request),
+        Some("/write-loop") => return write_loop(request),
+        Some("/unfinished") => return unfinished(request),
+        _ => {}
+    }
+
     let mut uri = Uri::builder();
 
     if let Some(scheme) = request.scheme() {
@@ -162,6 +1052,13 @@ fn handle(request: IncomingRequest) -> anyhow::Result<OutgoingResponse> {
 
 impl Guest for MyHost {
     fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        // Answers on its own, as it sets the response before it is done writing it.
+        if request.path_with_query().as_deref() == Some("/relay") {
+            // Past `set`, a failure can only cut the body off, which the host does.
+            let _ = relay(request, response_out);
+            return;
+        }
+
         let res = handle(request);
         ResponseOutparam::set(
             response_out,
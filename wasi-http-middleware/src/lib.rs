@@ -0,0 +1,29 @@
+//! Logs every request through `bluezeeking:service/log`, then hands it to the component it wraps.
+
+use bluezeeking::service::log::{log, Level};
+use exports::wasi::http::incoming_handler::Guest;
+use wasi::http::types::{IncomingRequest, ResponseOutparam};
+
+wit_bindgen::generate!({
+    path: "../wasi-http-guest/wit",
+    world: "middleware",
+    exports: {
+        "wasi:http/incoming-handler": Logger
+    }
+});
+
+struct Logger;
+
+impl Guest for Logger {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        let path = request.path_with_query().unwrap_or_default();
+
+        log(
+            Level::Info,
+            "passed through middleware",
+            &[("path".to_owned(), path)],
+        );
+
+        wasi::http::incoming_handler::handle(request, response_out);
+    }
+}
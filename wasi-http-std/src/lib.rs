@@ -0,0 +1,74 @@
+//! Answers every request through the standard library the way a plain `fn main` program would:
+//! printing, reading the environment, the clocks and the filesystem, and hashing with random
+//! keys. Request bodies are echoed back with `blocking-splice`.
+
+use std::{
+    collections::HashMap,
+    time::{Instant, SystemTime},
+};
+
+use exports::wasi::http::incoming_handler::Guest;
+use wasi::http::types::{
+    Fields, IncomingRequest, OutgoingBody, OutgoingResponse, ResponseOutparam,
+};
+
+wit_bindgen::generate!({
+    path: "../wasi-http-guest/wit",
+    world: "std",
+    exports: {
+        "wasi:http/incoming-handler": Std
+    }
+});
+
+struct Std;
+
+impl Guest for Std {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        let started_at = Instant::now();
+        let path = request.path_with_query().unwrap_or_default();
+
+        println!("handling {path}");
+        eprintln!("to stderr");
+
+        let response = OutgoingResponse::new(Fields::new());
+        let body = response.body().unwrap();
+        ResponseOutparam::set(response_out, Ok(response));
+
+        let output = body.write().unwrap();
+
+        if path == "/echo" {
+            let incoming = request.consume().unwrap();
+            let input = incoming.stream().unwrap();
+
+            while output.blocking_splice(&input, 4096).is_ok() {}
+
+            drop(input);
+        } else {
+            let unix = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+
+            let mut map = HashMap::new();
+            map.insert("key", "value");
+
+            let report = format!(
+                "env={}\nargs={}\nunix={}\npreopens={}\nmap={}\nelapsed={}\n",
+                std::env::vars().count(),
+                std::env::args().count(),
+                unix.as_secs(),
+                if std::fs::read_dir("/").is_ok() {
+                    "some"
+                } else {
+                    "none"
+                },
+                map["key"],
+                started_at.elapsed() < std::time::Duration::from_secs(1),
+            );
+
+            output.blocking_write_and_flush(report.as_bytes()).unwrap();
+        }
+
+        drop(output);
+        OutgoingBody::finish(body, None).unwrap();
+    }
+}